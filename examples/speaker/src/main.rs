@@ -16,7 +16,7 @@
  */
 
 mod dev_att;
-use matter::core::{self, CommissioningData};
+use matter::core::{self, CommissioningData, MatterConfig};
 use matter::data_model::cluster_basic_information::BasicInfoConfig;
 use matter::data_model::cluster_media_playback::{Commands, MediaPlaybackCluster};
 use matter::data_model::device_types::DEV_TYPE_ON_SMART_SPEAKER;
@@ -42,7 +42,7 @@ fn main() {
     };
     let dev_att = Box::new(dev_att::HardCodedDevAtt::new());
 
-    let mut matter = core::Matter::new(dev_info, dev_att, comm_data).unwrap();
+    let mut matter = core::Matter::new(dev_info, dev_att, MatterConfig::new(comm_data)).unwrap();
     let dm = matter.get_data_model();
     {
         let mut node = dm.node.write().unwrap();
@@ -185,6 +185,15 @@ impl Accessor {
             acl_mgr,
         }
     }
+
+    /// The node ID of the accessor, if it is on a CASE session
+    pub fn node_id(&self) -> Option<u64> {
+        if self.auth_mode == AuthMode::Case {
+            Some(self.subjects.0[0])
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -426,37 +435,29 @@ pub struct AclMgr {
 }
 
 impl AclMgr {
-    pub fn new() -> Result<Self, Error> {
-        AclMgr::new_with(true)
-    }
-
-    pub fn new_with(psm_support: bool) -> Result<Self, Error> {
+    /// The entries are only kept in memory
+    pub fn new() -> Self {
         const INIT: Option<AclEntry> = None;
-        let mut psm = None;
-
-        let inner = if !psm_support {
-            AclMgrInner {
+        Self {
+            inner: RwLock::new(AclMgrInner {
                 entries: [INIT; MAX_ACL_ENTRIES],
+            }),
+            psm: None,
+        }
+    }
+
+    /// The entries are loaded from, and stored to, this storage
+    pub fn new_with_psm(psm: Arc<Mutex<Psm>>) -> Self {
+        let mut mgr = AclMgr::new();
+        {
+            let psm_lock = psm.lock().unwrap();
+            // Error loading from PSM, start afresh
+            if let Ok(inner) = AclMgrInner::load(&psm_lock) {
+                mgr.inner = RwLock::new(inner);
             }
-        } else {
-            let psm_handle = Psm::get()?;
-            let inner = {
-                let psm_lock = psm_handle.lock().unwrap();
-                AclMgrInner::load(&psm_lock)
-            };
-
-            psm = Some(psm_handle);
-            inner.unwrap_or({
-                // Error loading from PSM
-                AclMgrInner {
-                    entries: [INIT; MAX_ACL_ENTRIES],
-                }
-            })
-        };
-        Ok(Self {
-            inner: RwLock::new(inner),
-            psm,
-        })
+        }
+        mgr.psm = Some(psm);
+        mgr
     }
 
     pub fn erase_all(&self) {
@@ -576,6 +577,12 @@ impl AclMgr {
     }
 }
 
+impl Default for AclMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl std::fmt::Display for AclMgr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let inner = self.inner.read().unwrap();
@@ -601,7 +608,7 @@ mod tests {
 
     #[test]
     fn test_basic_empty_subject_target() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -629,7 +636,7 @@ mod tests {
 
     #[test]
     fn test_subject() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -651,7 +658,7 @@ mod tests {
 
     #[test]
     fn test_cat() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
 
         let allow_cat = 0xABCD;
@@ -689,7 +696,7 @@ mod tests {
 
     #[test]
     fn test_cat_version() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
 
         let allow_cat = 0xABCD;
@@ -721,7 +728,7 @@ mod tests {
 
     #[test]
     fn test_target() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
         let path = GenericPath::new(Some(1), Some(1234), None);
@@ -782,7 +789,7 @@ mod tests {
 
     #[test]
     fn test_privilege() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
 
         let accessor = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
@@ -823,7 +830,7 @@ mod tests {
 
     #[test]
     fn test_delete_for_fabric() {
-        let am = Arc::new(AclMgr::new_with(false).unwrap());
+        let am = Arc::new(AclMgr::new());
         am.erase_all();
         let path = GenericPath::new(Some(1), Some(1234), None);
         let accessor2 = Accessor::new(2, AccessorSubjects::new(112233), AuthMode::Case, am.clone());
@@ -286,4 +286,9 @@ impl<'a> CertConsumer for ASN1Writer<'a> {
             self.write_str(0x17, time_str.as_bytes())
         }
     }
+
+    fn no_expiry(&mut self, _tag: &str) -> Result<(), Error> {
+        // As per RFC 5280, this is the GeneralizedTime 99991231235959Z
+        self.write_str(0x18, b"99991231235959Z")
+    }
 }
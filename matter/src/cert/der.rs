@@ -0,0 +1,463 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The DER encoded X.509 certificates and PKCS#10 CSRs, as a commissionee presents them
+//!
+//! Only as much is parsed, as is needed to verify their signatures and chains, unlike the
+//! Matter certificates in [crate::cert], which are TLV encoded.
+
+use std::str;
+
+use chrono::{TimeZone, Utc};
+use log::error;
+
+use crate::{
+    crypto::{self, CryptoKeyPair, KeyPair},
+    error::Error,
+};
+
+// The DER encoding of a P-256 SubjectPublicKeyInfo, till the public key itself
+const EC_PUB_KEY_DER_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+// The DER encoding of the ecdsa-with-SHA256 AlgorithmIdentifier
+const ECDSA_WITH_SHA256_DER: [u8; 12] = [
+    0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02,
+];
+// The Matter specific attributes in the subject of a DAC or PAI
+const OID_MATTER_VENDOR_ID: [u8; 10] = [0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01];
+const OID_MATTER_PRODUCT_ID: [u8; 10] =
+    [0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x02];
+
+const DER_INTEGER: u8 = 0x02;
+const DER_BIT_STRING: u8 = 0x03;
+const DER_OID: u8 = 0x06;
+const DER_UTF8_STRING: u8 = 0x0c;
+const DER_UTC_TIME: u8 = 0x17;
+const DER_GENERALIZED_TIME: u8 = 0x18;
+const DER_SEQUENCE: u8 = 0x30;
+const DER_SET: u8 = 0x31;
+// The explicit tag of the version in a certificate
+const DER_CERT_VERSION: u8 = 0xa0;
+
+// A DER element: its whole encoding, and its contents
+struct DerElement<'a> {
+    raw: &'a [u8],
+    contents: &'a [u8],
+}
+
+// Read the next DER element, which has to have this tag, off the buffer
+fn next_der<'a>(buf: &mut &'a [u8], tag: u8) -> Result<DerElement<'a>, Error> {
+    let der: &'a [u8] = buf;
+    let (len, hdr_len) = match der {
+        [t, len, ..] if *t == tag && *len < 0x80 => (*len as usize, 2),
+        [t, 0x81, len, ..] if *t == tag => (*len as usize, 3),
+        [t, 0x82, len1, len2, ..] if *t == tag => (((*len1 as usize) << 8) | *len2 as usize, 4),
+        _ => return Err(Error::Invalid),
+    };
+    let raw = der.get(..hdr_len + len).ok_or(Error::Invalid)?;
+    *buf = &der[raw.len()..];
+    Ok(DerElement {
+        raw,
+        contents: &raw[hdr_len..],
+    })
+}
+
+// Read the next UTCTime or GeneralizedTime off the buffer, as seconds since the UNIX epoch
+fn next_der_time(buf: &mut &[u8]) -> Result<i64, Error> {
+    let (time, year_len) = match buf.first() {
+        Some(&DER_UTC_TIME) => (next_der(buf, DER_UTC_TIME)?.contents, 2),
+        Some(&DER_GENERALIZED_TIME) => (next_der(buf, DER_GENERALIZED_TIME)?.contents, 4),
+        _ => return Err(Error::Invalid),
+    };
+    // The year, followed by MMDDhhmmss, in UTC
+    let digits = time
+        .strip_suffix(b"Z")
+        .filter(|d| d.len() == year_len + 10 && d.iter().all(u8::is_ascii_digit))
+        .ok_or(Error::InvalidTime)?;
+    let num = |d: &[u8]| d.iter().fold(0, |n, c| n * 10 + (c - b'0') as u32);
+    let (year, rest) = digits.split_at(year_len);
+    let mut year = num(year) as i32;
+    if year_len == 2 {
+        year += if year >= 50 { 1900 } else { 2000 };
+    }
+    let time = Utc
+        .with_ymd_and_hms(
+            year,
+            num(&rest[0..2]),
+            num(&rest[2..4]),
+            num(&rest[4..6]),
+            num(&rest[6..8]),
+            num(&rest[8..10]),
+        )
+        .single()
+        .ok_or(Error::InvalidTime)?;
+    Ok(time.timestamp())
+}
+
+// The P-256 public key from a DER encoded SubjectPublicKeyInfo
+fn get_spki_pub_key<'a>(spki: &DerElement<'a>) -> Result<&'a [u8], Error> {
+    spki.raw
+        .strip_prefix(&EC_PUB_KEY_DER_PREFIX[..])
+        .filter(|pub_key| pub_key.len() == crypto::EC_POINT_LEN_BYTES)
+        .ok_or(Error::Invalid)
+}
+
+// The value of one of the Matter specific attributes in a DN, which is 4 uppercase hex
+// digits
+fn get_dn_attr(dn: &[u8], oid: &[u8]) -> Result<Option<u16>, Error> {
+    let mut dn = dn;
+    let mut rdns = next_der(&mut dn, DER_SEQUENCE)?.contents;
+    while !rdns.is_empty() {
+        let mut attrs = next_der(&mut rdns, DER_SET)?.contents;
+        while !attrs.is_empty() {
+            let mut attr = next_der(&mut attrs, DER_SEQUENCE)?.contents;
+            if next_der(&mut attr, DER_OID)?.contents != oid {
+                continue;
+            }
+            let value = next_der(&mut attr, DER_UTF8_STRING)?.contents;
+            let value = str::from_utf8(value)
+                .ok()
+                .filter(|v| v.len() == 4 && v.bytes().all(|b| b.is_ascii_hexdigit()))
+                .filter(|v| !v.bytes().any(|b| b.is_ascii_lowercase()))
+                .ok_or(Error::Invalid)?;
+            return u16::from_str_radix(value, 16)
+                .map(Some)
+                .map_err(|_| Error::Invalid);
+        }
+    }
+    Ok(None)
+}
+
+// A DER encoded certificate or CSR: the part that is signed, and the signature over it
+struct DerSigned<'a> {
+    tbs: DerElement<'a>,
+    signature: [u8; crypto::EC_SIGNATURE_LEN_BYTES],
+}
+
+impl<'a> DerSigned<'a> {
+    fn parse(der: &'a [u8]) -> Result<Self, Error> {
+        let mut buf = der;
+        let mut outer = next_der(&mut buf, DER_SEQUENCE)?.contents;
+        if !buf.is_empty() {
+            return Err(Error::Invalid);
+        }
+        let tbs = next_der(&mut outer, DER_SEQUENCE)?;
+        if next_der(&mut outer, DER_SEQUENCE)?.raw != ECDSA_WITH_SHA256_DER {
+            return Err(Error::Invalid);
+        }
+        // There are no unused bits in the signature
+        let mut sig = match next_der(&mut outer, DER_BIT_STRING)?.contents {
+            [0, sig @ ..] => sig,
+            _ => return Err(Error::Invalid),
+        };
+
+        // The r and s integers of the signature, as the crypto backend takes them
+        let mut ints = next_der(&mut sig, DER_SEQUENCE)?.contents;
+        let mut signature = [0u8; crypto::EC_SIGNATURE_LEN_BYTES];
+        for out in signature.chunks_mut(crypto::BIGNUM_LEN_BYTES) {
+            let int = match next_der(&mut ints, DER_INTEGER)?.contents {
+                // The leading zero that keeps the integer positive
+                [0, int @ ..] => int,
+                int => int,
+            };
+            if int.len() > out.len() {
+                return Err(Error::Invalid);
+            }
+            let start = out.len() - int.len();
+            out[start..].copy_from_slice(int);
+        }
+        Ok(Self { tbs, signature })
+    }
+
+    fn verify(&self, pub_key: &[u8]) -> Result<(), Error> {
+        KeyPair::new_from_public(pub_key)?.verify_msg(self.tbs.raw, &self.signature)
+    }
+}
+
+/// A DER encoded X.509 certificate, like a DAC or a PAI
+pub struct DerCert<'a> {
+    signed: DerSigned<'a>,
+    issuer: &'a [u8],
+    // The validity, in seconds since the UNIX epoch
+    not_before: i64,
+    not_after: i64,
+    subject: &'a [u8],
+    pub_key: &'a [u8],
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+}
+
+impl<'a> DerCert<'a> {
+    pub fn parse(der: &'a [u8]) -> Result<Self, Error> {
+        let signed = DerSigned::parse(der)?;
+        let mut fields = signed.tbs.contents;
+        if fields.first() == Some(&DER_CERT_VERSION) {
+            next_der(&mut fields, DER_CERT_VERSION)?;
+        }
+        // The serial number, and the signature algorithm
+        next_der(&mut fields, DER_INTEGER)?;
+        next_der(&mut fields, DER_SEQUENCE)?;
+        let issuer = next_der(&mut fields, DER_SEQUENCE)?.raw;
+        let mut validity = next_der(&mut fields, DER_SEQUENCE)?.contents;
+        let not_before = next_der_time(&mut validity)?;
+        let not_after = next_der_time(&mut validity)?;
+        let subject = next_der(&mut fields, DER_SEQUENCE)?.raw;
+        let pub_key = get_spki_pub_key(&next_der(&mut fields, DER_SEQUENCE)?)?;
+        Ok(Self {
+            signed,
+            issuer,
+            not_before,
+            not_after,
+            subject,
+            pub_key,
+            vendor_id: get_dn_attr(subject, &OID_MATTER_VENDOR_ID)?,
+            product_id: get_dn_attr(subject, &OID_MATTER_PRODUCT_ID)?,
+        })
+    }
+
+    pub fn get_pubkey(&self) -> &[u8] {
+        self.pub_key
+    }
+
+    /// The Vendor ID in the subject, if any
+    pub fn get_vendor_id(&self) -> Option<u16> {
+        self.vendor_id
+    }
+
+    /// The Product ID in the subject, if any
+    pub fn get_product_id(&self) -> Option<u16> {
+        self.product_id
+    }
+
+    /// Whether this time, in seconds since the UNIX epoch, is in the certificate's validity
+    pub fn is_valid_at(&self, time: i64) -> bool {
+        (self.not_before..=self.not_after).contains(&time)
+    }
+
+    /// Verify that this certificate is signed by the issuer
+    pub fn verify_issuer(&self, issuer: &DerCert) -> Result<(), Error> {
+        if self.issuer != issuer.subject {
+            return Err(Error::Invalid);
+        }
+        self.signed.verify(issuer.pub_key)
+    }
+}
+
+/// Get the P-256 public key from a DER encoded PKCS#10 CSR, once the CSR's signature is
+/// verified with it. This proves that the node has the private key.
+pub fn verify_csr(csr: &[u8]) -> Result<[u8; crypto::EC_POINT_LEN_BYTES], Error> {
+    let signed = DerSigned::parse(csr)?;
+    let mut fields = signed.tbs.contents;
+    // The version, and the subject
+    next_der(&mut fields, DER_INTEGER)?;
+    next_der(&mut fields, DER_SEQUENCE)?;
+    let pub_key = get_spki_pub_key(&next_der(&mut fields, DER_SEQUENCE)?)?;
+    if let Err(e) = signed.verify(pub_key) {
+        error!("Invalid CSR signature");
+        return Err(e);
+    }
+
+    let mut out = [0u8; crypto::EC_POINT_LEN_BYTES];
+    out.copy_from_slice(pub_key);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_csr() {
+        let key = KeyPair::new().unwrap();
+        let mut buf = [0u8; 300];
+        let csr = key.get_csr(&mut buf).unwrap().to_vec();
+        let mut pub_key = [0u8; crypto::EC_POINT_LEN_BYTES];
+        key.get_public_key(&mut pub_key).unwrap();
+        assert_eq!(verify_csr(&csr), Ok(pub_key));
+
+        // The CSR has been tampered with
+        let mut tampered = csr.clone();
+        let subject = tampered.windows(3).position(|w| w == b"CSR").unwrap();
+        tampered[subject] = b'X';
+        assert_eq!(verify_csr(&tampered), Err(Error::InvalidSignature));
+
+        assert_eq!(verify_csr(&csr[..csr.len() - 1]), Err(Error::Invalid));
+        assert_eq!(verify_csr(&pub_key), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn test_verify_dac() {
+        let dac = DerCert::parse(&DAC_CERT).unwrap();
+        let pai = DerCert::parse(&PAI_CERT).unwrap();
+        dac.verify_issuer(&pai).unwrap();
+        assert_eq!(pai.verify_issuer(&dac), Err(Error::Invalid));
+        assert_eq!(pai.verify_issuer(&pai), Err(Error::Invalid));
+
+        // The DAC has been tampered with
+        let mut tampered = DAC_CERT;
+        tampered[20] ^= 0x01;
+        let tampered = DerCert::parse(&tampered).unwrap();
+        assert_eq!(tampered.verify_issuer(&pai), Err(Error::InvalidSignature));
+    }
+
+    #[test]
+    fn test_dac_fields() {
+        let dac = DerCert::parse(&DAC_CERT).unwrap();
+        assert_eq!(dac.get_vendor_id(), Some(0xFFF1));
+        assert_eq!(dac.get_product_id(), Some(0x8002));
+        // The PAI is for all the products of the vendor
+        let pai = DerCert::parse(&PAI_CERT).unwrap();
+        assert_eq!(pai.get_vendor_id(), Some(0xFFF1));
+        assert_eq!(pai.get_product_id(), None);
+
+        // Valid from 2022-02-05, with no well-defined expiration
+        let not_before = Utc
+            .with_ymd_and_hms(2022, 2, 5, 0, 0, 0)
+            .unwrap()
+            .timestamp();
+        assert!(!dac.is_valid_at(not_before - 1));
+        assert!(dac.is_valid_at(not_before));
+        let far = Utc
+            .with_ymd_and_hms(9999, 12, 31, 23, 59, 59)
+            .unwrap()
+            .timestamp();
+        assert!(dac.is_valid_at(far));
+    }
+
+    #[test]
+    fn test_der_time() {
+        let time = |der: &[u8]| next_der_time(&mut &der[..]);
+        let ts = |y, m, d| Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap().timestamp();
+        assert_eq!(time(b"\x17\x0d220205000000Z"), Ok(ts(2022, 2, 5)));
+        assert_eq!(time(b"\x17\x0d500101000000Z"), Ok(ts(1950, 1, 1)));
+        assert_eq!(time(b"\x18\x0f20500101000000Z"), Ok(ts(2050, 1, 1)));
+        assert_eq!(time(b"\x17\x0d221305000000Z"), Err(Error::InvalidTime));
+        assert_eq!(time(b"\x17\x0d2202050000000"), Err(Error::InvalidTime));
+        assert_eq!(time(b"\x17\x0d22020500000+Z"), Err(Error::InvalidTime));
+        assert_eq!(time(b"\x18\x0d220205000000Z"), Err(Error::InvalidTime));
+        assert_eq!(time(b"\x17\x0e220205000000Z"), Err(Error::Invalid));
+        assert_eq!(time(b"\x04\x0d220205000000Z"), Err(Error::Invalid));
+    }
+
+    #[test]
+    fn test_parse_bounds() {
+        // No prefix of a certificate is one
+        for len in 0..DAC_CERT.len() {
+            assert!(DerCert::parse(&DAC_CERT[..len]).is_err());
+        }
+        assert!(DerCert::parse(&[DAC_CERT.as_slice(), &[0]].concat()).is_err());
+
+        // Whatever is in the certificate, parsing it doesn't panic
+        let key = KeyPair::new().unwrap();
+        let mut buf = [0u8; 300];
+        let csr = key.get_csr(&mut buf).unwrap();
+        for der in [&DAC_CERT[..], &PAI_CERT[..], csr] {
+            for pos in 0..der.len() {
+                for flip in [0x01, 0x7f, 0x80, 0xff] {
+                    let mut fuzzed = der.to_vec();
+                    fuzzed[pos] ^= flip;
+                    let _ = DerCert::parse(&fuzzed);
+                    let _ = verify_csr(&fuzzed);
+                }
+                // The lengths that run past the end of their container
+                let mut fuzzed = der.to_vec();
+                fuzzed[pos] = 0x82;
+                let _ = DerCert::parse(&fuzzed);
+                let _ = verify_csr(&fuzzed);
+            }
+        }
+
+        // The length forms that are larger than what we take
+        assert!(DerCert::parse(&[0x30, 0x83, 0x00, 0x00, 0x01, 0x00]).is_err());
+        assert!(DerCert::parse(&[0x30, 0x80]).is_err());
+        assert!(DerCert::parse(&[0x30, 0x82, 0xff]).is_err());
+    }
+
+    // credentials/examples/ExamplePAI.cpp FFF1
+    const PAI_CERT: [u8; 463] = [
+        0x30, 0x82, 0x01, 0xcb, 0x30, 0x82, 0x01, 0x71, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x08,
+        0x56, 0xad, 0x82, 0x22, 0xad, 0x94, 0x5b, 0x64, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+        0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x30, 0x31, 0x18, 0x30, 0x16, 0x06, 0x03, 0x55, 0x04,
+        0x03, 0x0c, 0x0f, 0x4d, 0x61, 0x74, 0x74, 0x65, 0x72, 0x20, 0x54, 0x65, 0x73, 0x74, 0x20,
+        0x50, 0x41, 0x41, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82,
+        0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32,
+        0x32, 0x30, 0x32, 0x30, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39,
+        0x39, 0x39, 0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30,
+        0x3d, 0x31, 0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74,
+        0x74, 0x65, 0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x50, 0x41, 0x49, 0x20, 0x30, 0x78, 0x46,
+        0x46, 0x46, 0x31, 0x20, 0x6e, 0x6f, 0x20, 0x50, 0x49, 0x44, 0x31, 0x14, 0x30, 0x12, 0x06,
+        0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46,
+        0x46, 0x31, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+        0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0x41,
+        0x9a, 0x93, 0x15, 0xc2, 0x17, 0x3e, 0x0c, 0x8c, 0x87, 0x6d, 0x03, 0xcc, 0xfc, 0x94, 0x48,
+        0x52, 0x64, 0x7f, 0x7f, 0xec, 0x5e, 0x50, 0x82, 0xf4, 0x05, 0x99, 0x28, 0xec, 0xa8, 0x94,
+        0xc5, 0x94, 0x15, 0x13, 0x09, 0xac, 0x63, 0x1e, 0x4c, 0xb0, 0x33, 0x92, 0xaf, 0x68, 0x4b,
+        0x0b, 0xaf, 0xb7, 0xe6, 0x5b, 0x3b, 0x81, 0x62, 0xc2, 0xf5, 0x2b, 0xf9, 0x31, 0xb8, 0xe7,
+        0x7a, 0xaa, 0x82, 0xa3, 0x66, 0x30, 0x64, 0x30, 0x12, 0x06, 0x03, 0x55, 0x1d, 0x13, 0x01,
+        0x01, 0xff, 0x04, 0x08, 0x30, 0x06, 0x01, 0x01, 0xff, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x06,
+        0x03, 0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x1d,
+        0x06, 0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0x63, 0x54, 0x0e, 0x47, 0xf6, 0x4b,
+        0x1c, 0x38, 0xd1, 0x38, 0x84, 0xa4, 0x62, 0xd1, 0x6c, 0x19, 0x5d, 0x8f, 0xfb, 0x3c, 0x30,
+        0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x6a, 0xfd, 0x22,
+        0x77, 0x1f, 0x51, 0x1f, 0xec, 0xbf, 0x16, 0x41, 0x97, 0x67, 0x10, 0xdc, 0xdc, 0x31, 0xa1,
+        0x71, 0x7e, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03,
+        0x48, 0x00, 0x30, 0x45, 0x02, 0x21, 0x00, 0xb2, 0xef, 0x27, 0xf4, 0x9a, 0xe9, 0xb5, 0x0f,
+        0xb9, 0x1e, 0xea, 0xc9, 0x4c, 0x4d, 0x0b, 0xdb, 0xb8, 0xd7, 0x92, 0x9c, 0x6c, 0xb8, 0x8f,
+        0xac, 0xe5, 0x29, 0x36, 0x8d, 0x12, 0x05, 0x4c, 0x0c, 0x02, 0x20, 0x65, 0x5d, 0xc9, 0x2b,
+        0x86, 0xbd, 0x90, 0x98, 0x82, 0xa6, 0xc6, 0x21, 0x77, 0xb8, 0x25, 0xd7, 0xd0, 0x5e, 0xdb,
+        0xe7, 0xc2, 0x2f, 0x9f, 0xea, 0x71, 0x22, 0x0e, 0x7e, 0xa7, 0x03, 0xf8, 0x91,
+    ];
+
+    // credentials/examples/ExampleDACs.cpp FFF1-8000-0002-Cert
+    const DAC_CERT: [u8; 492] = [
+        0x30, 0x82, 0x01, 0xe8, 0x30, 0x82, 0x01, 0x8e, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x08,
+        0x52, 0x72, 0x4d, 0x21, 0xe2, 0xc1, 0x74, 0xaf, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48,
+        0xce, 0x3d, 0x04, 0x03, 0x02, 0x30, 0x3d, 0x31, 0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04,
+        0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74, 0x74, 0x65, 0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x50,
+        0x41, 0x49, 0x20, 0x30, 0x78, 0x46, 0x46, 0x46, 0x31, 0x20, 0x6e, 0x6f, 0x20, 0x50, 0x49,
+        0x44, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c,
+        0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x32, 0x30,
+        0x32, 0x30, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x18, 0x0f, 0x39, 0x39, 0x39,
+        0x39, 0x31, 0x32, 0x33, 0x31, 0x32, 0x33, 0x35, 0x39, 0x35, 0x39, 0x5a, 0x30, 0x53, 0x31,
+        0x25, 0x30, 0x23, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x1c, 0x4d, 0x61, 0x74, 0x74, 0x65,
+        0x72, 0x20, 0x44, 0x65, 0x76, 0x20, 0x44, 0x41, 0x43, 0x20, 0x30, 0x78, 0x46, 0x46, 0x46,
+        0x31, 0x2f, 0x30, 0x78, 0x38, 0x30, 0x30, 0x32, 0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b,
+        0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02, 0x01, 0x0c, 0x04, 0x46, 0x46, 0x46, 0x31,
+        0x31, 0x14, 0x30, 0x12, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0xa2, 0x7c, 0x02,
+        0x02, 0x0c, 0x04, 0x38, 0x30, 0x30, 0x32, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86,
+        0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07,
+        0x03, 0x42, 0x00, 0x04, 0xda, 0x93, 0xf1, 0x67, 0x36, 0x25, 0x67, 0x50, 0xd9, 0x03, 0xb0,
+        0x34, 0xba, 0x45, 0x88, 0xab, 0xaf, 0x58, 0x95, 0x4f, 0x77, 0xaa, 0x9f, 0xd9, 0x98, 0x9d,
+        0xfd, 0x40, 0x0d, 0x7a, 0xb3, 0xfd, 0xc9, 0x75, 0x3b, 0x3b, 0x92, 0x1b, 0x29, 0x4c, 0x95,
+        0x0f, 0xd9, 0xd2, 0x80, 0xd1, 0x4c, 0x43, 0x86, 0x2f, 0x16, 0xdc, 0x85, 0x4b, 0x00, 0xed,
+        0x39, 0xe7, 0x50, 0xba, 0xbf, 0x1d, 0xc4, 0xca, 0xa3, 0x60, 0x30, 0x5e, 0x30, 0x0c, 0x06,
+        0x03, 0x55, 0x1d, 0x13, 0x01, 0x01, 0xff, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0e, 0x06, 0x03,
+        0x55, 0x1d, 0x0f, 0x01, 0x01, 0xff, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x1d, 0x06,
+        0x03, 0x55, 0x1d, 0x0e, 0x04, 0x16, 0x04, 0x14, 0xef, 0x06, 0x56, 0x11, 0x9c, 0x1c, 0x91,
+        0xa7, 0x9a, 0x94, 0xe6, 0xdc, 0xf3, 0x79, 0x79, 0xdb, 0xd0, 0x7f, 0xf8, 0xa3, 0x30, 0x1f,
+        0x06, 0x03, 0x55, 0x1d, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x63, 0x54, 0x0e, 0x47,
+        0xf6, 0x4b, 0x1c, 0x38, 0xd1, 0x38, 0x84, 0xa4, 0x62, 0xd1, 0x6c, 0x19, 0x5d, 0x8f, 0xfb,
+        0x3c, 0x30, 0x0a, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02, 0x03, 0x48,
+        0x00, 0x30, 0x45, 0x02, 0x20, 0x46, 0x86, 0x81, 0x07, 0x33, 0xbf, 0x0d, 0xc8, 0xff, 0x4c,
+        0xb5, 0x14, 0x5a, 0x6b, 0xfa, 0x1a, 0xec, 0xff, 0xa8, 0xb6, 0xda, 0xb6, 0xc3, 0x51, 0xaa,
+        0xee, 0xcd, 0xaf, 0xb8, 0xbe, 0x95, 0x7d, 0x02, 0x21, 0x00, 0xe8, 0xc2, 0x8d, 0x6b, 0xfc,
+        0xc8, 0x7a, 0x7d, 0x54, 0x2e, 0xad, 0x6e, 0xda, 0xca, 0x14, 0x8d, 0x5f, 0xa5, 0x06, 0x1e,
+        0x51, 0x7c, 0xbe, 0x4f, 0x24, 0xa7, 0x20, 0xe1, 0xc0, 0x59, 0xde, 0x1a,
+    ];
+}
@@ -0,0 +1,409 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use super::{
+    BasicConstraints, Cert, CertRole, DistNameValue, DistNames, DnTags, EcCurveIdValue, Extensions,
+    PubKeyAlgoValue, SignAlgoValue, ValidityTime, BUILD_TIME, EXT_KEY_USAGE_CLIENT_AUTH,
+    EXT_KEY_USAGE_SERVER_AUTH, KEY_ID_LEN, KEY_USAGE_CRL_SIGN, KEY_USAGE_DIGITAL_SIGN,
+    KEY_USAGE_KEY_CERT_SIGN, MAX_ASN1_CERT_SIZE,
+};
+use crate::{
+    crypto::{
+        CryptoKeyPair, KeyPair, Sha256, EC_POINT_LEN_BYTES, EC_SIGNATURE_LEN_BYTES,
+        SHA256_HASH_LEN_BYTES,
+    },
+    error::Error,
+    tlv::TLVArrayOwned,
+};
+use log::error;
+use rand::prelude::*;
+
+// A NOC may have at most 3 CASE Authenticated Tags
+const MAX_CATS: usize = 3;
+const SERIAL_NO_LEN: usize = 8;
+
+/// The CA that signs the operational certificates of the nodes a commissioner brings on
+/// to its fabric
+pub trait NocIssuer: Send {
+    /// Issue a NOC, in the Matter TLV encoding, for the node with this node id on our
+    /// fabric, with the public key from the node's CSR
+    ///
+    /// The NOC must chain to the root and the ICAC (if any) of our fabric.
+    fn issue_noc(&mut self, pub_key: &[u8], node_id: u64, noc: &mut [u8]) -> Result<usize, Error>;
+}
+
+/// The details of an operational certificate, to be issued by a [`CertIssuer`]
+pub struct CertBuilder {
+    role: CertRole,
+    // The RCAC id, the ICAC id or the node id, as per the role
+    id: u64,
+    cats: Vec<u32>,
+    serial_no: Vec<u8>,
+    not_before: u32,
+    not_after: u32,
+}
+
+impl CertBuilder {
+    /// A self-signed RCAC, with this RCAC id
+    pub fn rcac(rcac_id: u64) -> Self {
+        Self::new(CertRole::Rcac, rcac_id)
+    }
+
+    /// An ICAC, with this ICAC id
+    pub fn icac(icac_id: u64) -> Self {
+        Self::new(CertRole::Icac, icac_id)
+    }
+
+    /// A NOC, for the node with this node id
+    pub fn noc(node_id: u64) -> Self {
+        Self::new(CertRole::Noc, node_id)
+    }
+
+    fn new(role: CertRole, id: u64) -> Self {
+        let mut serial_no = [0u8; SERIAL_NO_LEN];
+        rand::thread_rng().fill_bytes(&mut serial_no);
+        // Keep the integer positive, and minimally encoded
+        serial_no[0] = (serial_no[0] & 0x7f) | 0x40;
+
+        let now = match ValidityTime::new(BUILD_TIME) {
+            ValidityTime::Current(t) | ValidityTime::LastKnownGood(t) => t,
+        };
+        Self {
+            role,
+            id,
+            cats: Vec::new(),
+            serial_no: serial_no.to_vec(),
+            not_before: now,
+            not_after: 0,
+        }
+    }
+
+    /// Add a CASE Authenticated Tag to the subject, only a NOC may have these
+    pub fn add_cat(mut self, cat: u32) -> Self {
+        self.cats.push(cat);
+        self
+    }
+
+    /// Set the serial number, a random one is used otherwise
+    pub fn set_serial_no(mut self, serial_no: &[u8]) -> Self {
+        self.serial_no = serial_no.to_vec();
+        self
+    }
+
+    /// Set the validity period, in seconds since the Matter epoch
+    ///
+    /// By default, the certificate is valid from now on, with no well-defined expiry (a Not
+    /// After of 0).
+    pub fn set_validity(mut self, not_before: u32, not_after: u32) -> Self {
+        self.not_before = not_before;
+        self.not_after = not_after;
+        self
+    }
+
+    fn subject(&self, fabric_id: u64) -> DistNames {
+        let id_tag = match self.role {
+            CertRole::Rcac => DnTags::RootCaId,
+            CertRole::Icac => DnTags::IcaId,
+            CertRole::Noc => DnTags::NodeId,
+        };
+        let mut dn = vec![
+            (id_tag as u8, DistNameValue::Uint(self.id)),
+            (DnTags::FabricId as u8, DistNameValue::Uint(fabric_id)),
+        ];
+        for cat in &self.cats {
+            dn.push((DnTags::NocCat as u8, DistNameValue::Uint(*cat as u64)));
+        }
+        DistNames { dn }
+    }
+
+    fn extensions(&self, subj_key_id: Vec<u8>, auth_key_id: Vec<u8>) -> Extensions {
+        let (is_ca, key_usage, ext_key_usage) = match self.role {
+            CertRole::Rcac | CertRole::Icac => {
+                (true, KEY_USAGE_KEY_CERT_SIGN | KEY_USAGE_CRL_SIGN, None)
+            }
+            CertRole::Noc => (
+                false,
+                KEY_USAGE_DIGITAL_SIGN,
+                Some(TLVArrayOwned::new(vec![
+                    EXT_KEY_USAGE_CLIENT_AUTH,
+                    EXT_KEY_USAGE_SERVER_AUTH,
+                ])),
+            ),
+        };
+        Extensions {
+            basic_const: Some(BasicConstraints { is_ca, path: None }),
+            key_usage: Some(key_usage),
+            ext_key_usage,
+            subj_key_id: Some(subj_key_id),
+            auth_key_id: Some(auth_key_id),
+            future_extensions: None,
+        }
+    }
+
+    fn build(
+        &self,
+        fabric_id: u64,
+        pub_key: &[u8],
+        issuer: Option<&Cert>,
+        key_pair: &KeyPair,
+    ) -> Result<Cert, Error> {
+        if pub_key.len() != EC_POINT_LEN_BYTES {
+            error!("Invalid public key for the certificate");
+            return Err(Error::Invalid);
+        }
+        if !self.cats.is_empty() && (self.role != CertRole::Noc || self.cats.len() > MAX_CATS) {
+            error!("Invalid CASE Authenticated Tags for the certificate");
+            return Err(Error::Invalid);
+        }
+
+        let subject = self.subject(fabric_id);
+        let subj_key_id = key_id(pub_key)?;
+        let (issuer, auth_key_id) = match issuer {
+            Some(issuer) => (
+                issuer.subject.clone(),
+                issuer.get_subject_key_id()?.to_vec(),
+            ),
+            // Self-signed
+            None => (subject.clone(), subj_key_id.clone()),
+        };
+        let mut cert = Cert {
+            serial_no: self.serial_no.clone(),
+            sign_algo: SignAlgoValue::ECDSAWithSHA256 as u8,
+            issuer,
+            not_before: self.not_before,
+            not_after: self.not_after,
+            subject,
+            pubkey_algo: PubKeyAlgoValue::EcPubKey as u8,
+            ec_curve_id: EcCurveIdValue::Prime256V1 as u8,
+            pubkey: pub_key.to_vec(),
+            extensions: self.extensions(subj_key_id, auth_key_id),
+            signature: Vec::new(),
+        };
+
+        let mut asn1 = [0u8; MAX_ASN1_CERT_SIZE];
+        let len = cert.as_asn1(&mut asn1)?;
+        let mut signature = [0u8; EC_SIGNATURE_LEN_BYTES];
+        let len = key_pair.sign_msg(&asn1[..len], &mut signature)?;
+        cert.signature = signature[..len].to_vec();
+        Ok(cert)
+    }
+}
+
+// The Key ID is the leftmost 160 bits of the SHA-256 hash of the public key (RFC 7093)
+fn key_id(pub_key: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::new()?;
+    hasher.update(pub_key)?;
+    let mut hash = [0u8; SHA256_HASH_LEN_BYTES];
+    hasher.finish(&mut hash)?;
+    Ok(hash[..KEY_ID_LEN].to_vec())
+}
+
+/// A Certificate Authority of a fabric, that issues its operational certificates
+///
+/// This is either the root CA, with a self-signed RCAC, or an intermediate CA, with an
+/// ICAC issued by the root CA. The certificates are in the Matter TLV encoding with
+/// `Cert::as_tlv()`, and in the X.509 DER encoding with `Cert::as_x509()`.
+pub struct CertIssuer {
+    key_pair: KeyPair,
+    cert: Cert,
+    fabric_id: u64,
+}
+
+impl CertIssuer {
+    /// Create the root CA of the fabric with this fabric id, the RCAC is signed by its own
+    /// key pair
+    pub fn new_root(key_pair: KeyPair, rcac: CertBuilder, fabric_id: u64) -> Result<Self, Error> {
+        if rcac.role != CertRole::Rcac {
+            return Err(Error::Invalid);
+        }
+        let mut pub_key = [0u8; EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pub_key)?;
+        let cert = rcac.build(fabric_id, &pub_key[..len], None, &key_pair)?;
+        Ok(Self {
+            key_pair,
+            cert,
+            fabric_id,
+        })
+    }
+
+    /// Create an intermediate CA, with its ICAC issued by this (root) CA
+    pub fn new_intermediate(&self, key_pair: KeyPair, icac: CertBuilder) -> Result<Self, Error> {
+        let mut pub_key = [0u8; EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pub_key)?;
+        let cert = self.issue(icac, &pub_key[..len])?;
+        Ok(Self {
+            key_pair,
+            cert,
+            fabric_id: self.fabric_id,
+        })
+    }
+
+    /// Issue an ICAC or a NOC, for this public key
+    ///
+    /// Only the root CA may issue an ICAC.
+    pub fn issue(&self, builder: CertBuilder, pub_key: &[u8]) -> Result<Cert, Error> {
+        match builder.role {
+            CertRole::Noc => (),
+            CertRole::Icac if self.cert.get_role()? == CertRole::Rcac => (),
+            _ => {
+                error!("A {:?} cannot be issued by this CA", builder.role);
+                return Err(Error::Invalid);
+            }
+        }
+        builder.build(self.fabric_id, pub_key, Some(&self.cert), &self.key_pair)
+    }
+
+    /// The certificate of this CA
+    pub fn get_cert(&self) -> &Cert {
+        &self.cert
+    }
+
+    pub fn get_fabric_id(&self) -> u64 {
+        self.fabric_id
+    }
+}
+
+impl NocIssuer for CertIssuer {
+    fn issue_noc(&mut self, pub_key: &[u8], node_id: u64, noc: &mut [u8]) -> Result<usize, Error> {
+        self.issue(CertBuilder::noc(node_id), pub_key)?.as_tlv(noc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CertBuilder, CertIssuer, NocIssuer};
+    use crate::cert::{Cert, BUILD_TIME};
+    use crate::crypto::{CryptoKeyPair, KeyPair, EC_POINT_LEN_BYTES};
+    use crate::error::Error;
+
+    fn pub_key(key_pair: &KeyPair) -> Vec<u8> {
+        let mut pub_key = [0u8; EC_POINT_LEN_BYTES];
+        let len = key_pair.get_public_key(&mut pub_key).unwrap();
+        pub_key[..len].to_vec()
+    }
+
+    #[test]
+    fn test_issue_chain() {
+        let root = CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), 5).unwrap();
+        let ica = root
+            .new_intermediate(KeyPair::new().unwrap(), CertBuilder::icac(2))
+            .unwrap();
+        let node_key = KeyPair::new().unwrap();
+        let noc = ica
+            .issue(
+                CertBuilder::noc(0x1122).add_cat(0x0001_0001),
+                &pub_key(&node_key),
+            )
+            .unwrap();
+
+        assert_eq!(noc.get_node_id(), Ok(0x1122));
+        assert_eq!(noc.get_fabric_id(), Ok(5));
+        let mut cats = [0u32; 3];
+        noc.get_cat_ids(&mut cats);
+        assert_eq!(cats, [0x0001_0001, 0, 0]);
+        assert_eq!(noc.get_pubkey(), pub_key(&node_key).as_slice());
+        noc.verify_chain_start(BUILD_TIME)
+            .add_cert(ica.get_cert())
+            .unwrap()
+            .add_cert(root.get_cert())
+            .unwrap()
+            .finalise()
+            .unwrap();
+
+        // The Matter TLV encoding round-trips
+        let mut tlv = [0u8; 400];
+        let len = noc.as_tlv(&mut tlv).unwrap();
+        let parsed = Cert::new(&tlv[..len]).unwrap();
+        let mut tlv2 = [0u8; 400];
+        assert_eq!(parsed.as_tlv(&mut tlv2), Ok(len));
+        assert_eq!(&tlv[..len], &tlv2[..len]);
+    }
+
+    #[test]
+    fn test_issue_noc_without_icac() {
+        let mut root =
+            CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), 5).unwrap();
+        let mut tlv = [0u8; 400];
+        let len = root
+            .issue_noc(&pub_key(&KeyPair::new().unwrap()), 0x1122, &mut tlv)
+            .unwrap();
+        let noc = Cert::new(&tlv[..len]).unwrap();
+        noc.verify_chain_start(BUILD_TIME)
+            .add_cert(root.get_cert())
+            .unwrap()
+            .finalise()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_issue_invalid() {
+        let root = CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), 5).unwrap();
+        let ica = root
+            .new_intermediate(KeyPair::new().unwrap(), CertBuilder::icac(2))
+            .unwrap();
+        let key = pub_key(&KeyPair::new().unwrap());
+
+        // Only the root issues ICACs, and nobody issues RCACs
+        assert!(matches!(
+            ica.issue(CertBuilder::icac(3), &key),
+            Err(Error::Invalid)
+        ));
+        assert!(matches!(
+            root.issue(CertBuilder::rcac(3), &key),
+            Err(Error::Invalid)
+        ));
+        // Too many CATs, and CATs outside of a NOC
+        let noc = (1..=4).fold(CertBuilder::noc(3), |b, cat| b.add_cat(cat));
+        assert!(matches!(root.issue(noc, &key), Err(Error::Invalid)));
+        assert!(matches!(
+            root.issue(CertBuilder::icac(3).add_cat(1), &key),
+            Err(Error::Invalid)
+        ));
+    }
+
+    #[test]
+    fn test_x509_encoding() {
+        let root = CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), 5).unwrap();
+        let rcac = root.get_cert();
+        let mut tbs = [0u8; 1000];
+        let tbs_len = rcac.as_asn1(&mut tbs).unwrap();
+        let mut der = [0u8; 1000];
+        let len = rcac.as_x509(&mut der).unwrap();
+        let der = &der[..len];
+
+        // There is no well-defined expiry
+        assert!(tbs[..tbs_len]
+            .windows(17)
+            .any(|t| t == b"\x18\x0f99991231235959Z"));
+
+        // SEQUENCE { TBSCertificate, AlgorithmIdentifier, BIT STRING }
+        assert_eq!(&der[..2], &[0x30, 0x82]);
+        assert_eq!(((der[2] as usize) << 8) | der[3] as usize, len - 4);
+        assert_eq!(&der[4..(4 + tbs_len)], &tbs[..tbs_len]);
+        let rest = &der[(4 + tbs_len)..];
+        assert_eq!(
+            &rest[..12],
+            &[0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02]
+        );
+        // The bit string has the DER ECDSA-Sig-Value
+        assert_eq!(rest[12], 0x03);
+        assert_eq!(rest[13] as usize, rest.len() - 14);
+        assert_eq!(rest[14], 0x00);
+        assert_eq!(rest[15], 0x30);
+        assert_eq!(rest[16] as usize, rest.len() - 17);
+    }
+}
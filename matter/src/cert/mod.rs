@@ -15,10 +15,14 @@
  *    limitations under the License.
  */
 
-use std::fmt;
+use std::{
+    convert::TryFrom,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use crate::{
-    crypto::{CryptoKeyPair, KeyPair},
+    crypto::{CryptoKeyPair, KeyPair, BIGNUM_LEN_BYTES, EC_SIGNATURE_LEN_BYTES},
     error::Error,
     tlv::{self, FromTLV, TLVArrayOwned, TLVElement, TLVWriter, TagType, ToTLV},
     utils::writebuf::WriteBuf,
@@ -27,6 +31,8 @@ use log::error;
 use num_derive::FromPrimitive;
 
 pub use self::asn1_writer::ASN1Writer;
+pub use self::der::{verify_csr, DerCert};
+pub use self::issuer::{CertBuilder, CertIssuer, NocIssuer};
 use self::printer::CertPrinter;
 
 // As per https://datatracker.ietf.org/doc/html/rfc5280
@@ -87,6 +93,55 @@ const KEY_USAGE_CRL_SIGN: u16 = 0x0040;
 const KEY_USAGE_ENCIPHER_ONLY: u16 = 0x0080;
 const KEY_USAGE_DECIPHER_ONLY: u16 = 0x0100;
 
+// The Extended Key Usage values, as they are encoded in the Matter Certificate
+const EXT_KEY_USAGE_SERVER_AUTH: u8 = 1;
+const EXT_KEY_USAGE_CLIENT_AUTH: u8 = 2;
+
+const KEY_ID_LEN: usize = 20;
+
+// The Matter epoch, 2000-01-01 00:00:00 UTC, in seconds since the Unix epoch
+const MATTER_EPOCH_SECS: u64 = 946684800;
+
+/// The time at which this version of the stack was released, 2023-01-01 00:00:00 UTC, in
+/// seconds since the Matter epoch
+///
+/// The Last Known Good UTC Time is never behind this.
+pub const BUILD_TIME: u32 = 725846400;
+
+// The time against which the validity periods are checked
+#[derive(Clone, Copy)]
+enum ValidityTime {
+    // The system time, it is at least the Last Known Good UTC Time
+    Current(u32),
+    // The Last Known Good UTC Time, the actual time may well be later than this, so
+    // only the Not After can be checked against it
+    LastKnownGood(u32),
+}
+
+impl ValidityTime {
+    // The system time, or the Last Known Good UTC Time, if the system time isn't set or is
+    // behind it
+    fn new(last_known_good: u32) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|d| d.as_secs().checked_sub(MATTER_EPOCH_SECS))
+            .and_then(|t| u32::try_from(t).ok());
+        match now {
+            Some(now) if now >= last_known_good => ValidityTime::Current(now),
+            _ => ValidityTime::LastKnownGood(last_known_good),
+        }
+    }
+}
+
+// The role of the certificate in the operational chain, as its subject says
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CertRole {
+    Rcac,
+    Icac,
+    Noc,
+}
+
 fn reverse_byte(byte: u8) -> u8 {
     const LOOKUP: [u8; 16] = [
         0x00, 0x08, 0x04, 0x0c, 0x02, 0x0a, 0x06, 0x0e, 0x01, 0x09, 0x05, 0x0d, 0x03, 0x0b, 0x07,
@@ -293,13 +348,14 @@ enum DnTags {
     NocCat = 22,
 }
 
+#[derive(Clone)]
 enum DistNameValue {
     Uint(u64),
     Utf8Str(Vec<u8>),
     PrintableStr(Vec<u8>),
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 struct DistNames {
     // The order in which the DNs arrive is important, as the signing
     // requires that the ASN1 notation retains the same order
@@ -569,6 +625,11 @@ impl Cert {
         self.pubkey.as_slice()
     }
 
+    /// The start of the validity period, in seconds since the Matter epoch
+    pub fn get_not_before(&self) -> u32 {
+        self.not_before
+    }
+
     pub fn get_subject_key_id(&self) -> Result<&[u8], Error> {
         self.extensions.subj_key_id.as_deref().ok_or(Error::Invalid)
     }
@@ -603,8 +664,115 @@ impl Cert {
         Ok(w.as_slice().len())
     }
 
-    pub fn verify_chain_start(&self) -> CertVerifier {
-        CertVerifier::new(self)
+    /// The X.509 DER encoding of the certificate
+    ///
+    /// Unlike `as_asn1()`, which is only the TBSCertificate that the signature is on, this
+    /// includes the signature.
+    pub fn as_x509(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut sig = [0u8; MAX_DER_SIG_LEN];
+        let mut sig_w = ASN1Writer::new(&mut sig);
+        encode_ecdsa_sig(self.get_signature(), &mut sig_w)?;
+
+        let mut w = ASN1Writer::new(buf);
+        w.start_seq("")?;
+        self.encode(&mut w)?;
+        w.start_seq("")?;
+        let oid = match get_sign_algo(self.sign_algo).ok_or(Error::Invalid)? {
+            SignAlgoValue::ECDSAWithSHA256 => OID_ECDSA_WITH_SHA256,
+        };
+        w.oid("", &oid)?;
+        w.end_seq()?;
+        w.bitstr("", false, sig_w.as_slice())?;
+        w.end_seq()?;
+        Ok(w.as_slice().len())
+    }
+
+    /// Start the verification of the chain from this certificate
+    ///
+    /// The validity periods are checked against the system time, or against the Last Known
+    /// Good UTC Time, in seconds since the Matter epoch, if the system time is behind it.
+    pub fn verify_chain_start(&self, last_known_good: u32) -> CertVerifier {
+        CertVerifier::new(self, last_known_good)
+    }
+
+    fn get_role(&self) -> Result<CertRole, Error> {
+        if self.subject.u64(DnTags::NodeId).is_some() {
+            Ok(CertRole::Noc)
+        } else if self.subject.u64(DnTags::IcaId).is_some() {
+            Ok(CertRole::Icac)
+        } else if self.subject.u64(DnTags::RootCaId).is_some() {
+            Ok(CertRole::Rcac)
+        } else {
+            error!("Not an operational certificate");
+            Err(Error::Invalid)
+        }
+    }
+
+    fn is_ca(&self) -> bool {
+        matches!(
+            self.extensions.basic_const,
+            Some(BasicConstraints { is_ca: true, .. })
+        )
+    }
+
+    fn verify_validity(&self, time: ValidityTime) -> Result<(), Error> {
+        let (time, check_not_before) = match time {
+            ValidityTime::Current(t) => (t, true),
+            ValidityTime::LastKnownGood(t) => (t, false),
+        };
+        if check_not_before && time < self.not_before {
+            return Err(Error::CertNotYetValid);
+        }
+        // A Not After of 0 means that there is no well-defined expiry
+        if self.not_after != 0 && time > self.not_after {
+            return Err(Error::CertExpired);
+        }
+        Ok(())
+    }
+
+    fn verify_extensions(&self) -> Result<(), Error> {
+        let ext = &self.extensions;
+        let basic_const = ext
+            .basic_const
+            .as_ref()
+            .ok_or(Error::InvalidBasicConstraints)?;
+        let key_usage = ext.key_usage.ok_or(Error::InvalidKeyUsage)?;
+        match self.get_role()? {
+            CertRole::Noc => {
+                if basic_const.is_ca || basic_const.path.is_some() {
+                    return Err(Error::InvalidBasicConstraints);
+                }
+                if key_usage & KEY_USAGE_DIGITAL_SIGN == 0
+                    || key_usage & (KEY_USAGE_KEY_CERT_SIGN | KEY_USAGE_CRL_SIGN) != 0
+                {
+                    return Err(Error::InvalidKeyUsage);
+                }
+                let ext_key_usage = ext
+                    .ext_key_usage
+                    .as_ref()
+                    .ok_or(Error::InvalidExtKeyUsage)?;
+                let has = |usage| ext_key_usage.iter().any(|u| *u == usage);
+                if !has(EXT_KEY_USAGE_SERVER_AUTH) || !has(EXT_KEY_USAGE_CLIENT_AUTH) {
+                    return Err(Error::InvalidExtKeyUsage);
+                }
+            }
+            CertRole::Icac | CertRole::Rcac => {
+                if !basic_const.is_ca {
+                    return Err(Error::InvalidBasicConstraints);
+                }
+                let ca_usage = KEY_USAGE_KEY_CERT_SIGN | KEY_USAGE_CRL_SIGN;
+                if key_usage & ca_usage != ca_usage {
+                    return Err(Error::InvalidKeyUsage);
+                }
+                if ext.ext_key_usage.is_some() {
+                    return Err(Error::InvalidExtKeyUsage);
+                }
+            }
+        }
+        match &ext.subj_key_id {
+            Some(id) if id.len() == KEY_ID_LEN => Ok(()),
+            _ => Err(Error::InvalidSubjKeyId),
+        }
     }
 
     fn encode(&self, w: &mut dyn CertConsumer) -> Result<(), Error> {
@@ -627,7 +795,11 @@ impl Cert {
 
         w.start_seq("Validity:")?;
         w.utctime("Not Before:", self.not_before)?;
-        w.utctime("Not After:", self.not_after)?;
+        if self.not_after == 0 {
+            w.no_expiry("Not After:")?;
+        } else {
+            w.utctime("Not After:", self.not_after)?;
+        }
         w.end_seq()?;
 
         self.subject.encode("Subject:", w)?;
@@ -655,6 +827,31 @@ impl Cert {
     }
 }
 
+// The Matter Certificate has the raw r || s of the signature, while X.509 has the
+// ECDSA-Sig-Value, a sequence of the two as (minimally encoded, positive) integers
+fn encode_ecdsa_sig(signature: &[u8], w: &mut dyn CertConsumer) -> Result<(), Error> {
+    if signature.len() != EC_SIGNATURE_LEN_BYTES {
+        return Err(Error::InvalidSignature);
+    }
+    w.start_seq("")?;
+    for int in signature.chunks(BIGNUM_LEN_BYTES) {
+        let start = int
+            .iter()
+            .position(|b| *b != 0)
+            .unwrap_or(BIGNUM_LEN_BYTES - 1);
+        let int = &int[start..];
+        if int[0] & 0x80 != 0 {
+            // Prefix a zero, so this isn't read as negative
+            let mut padded = [0u8; BIGNUM_LEN_BYTES + 1];
+            padded[1..=int.len()].copy_from_slice(int);
+            w.integer("", &padded[..(int.len() + 1)])?;
+        } else {
+            w.integer("", int)?;
+        }
+    }
+    w.end_seq()
+}
+
 impl fmt::Display for Cert {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut printer = CertPrinter::new(f);
@@ -666,16 +863,46 @@ impl fmt::Display for Cert {
     }
 }
 
+/// Verify a chain of certificates, starting from the NOC, up to the root
+///
+/// Besides the signatures, the validity periods, the Basic Constraints, the Key Usages and
+/// the Key IDs are checked, as the role of each certificate in the chain requires.
 pub struct CertVerifier<'a> {
     cert: &'a Cert,
+    // The number of CA certificates in the chain below this one
+    ca_below: u8,
+    time: ValidityTime,
 }
 
 impl<'a> CertVerifier<'a> {
-    pub fn new(cert: &'a Cert) -> Self {
-        Self { cert }
+    pub fn new(cert: &'a Cert, last_known_good: u32) -> Self {
+        Self {
+            cert,
+            ca_below: 0,
+            time: ValidityTime::new(last_known_good),
+        }
     }
 
     pub fn add_cert(self, parent: &'a Cert) -> Result<CertVerifier<'a>, Error> {
+        self.cert.verify_validity(self.time)?;
+        self.cert.verify_extensions()?;
+
+        // The parent should be a CA, that allows as many CA certificates below it
+        if !parent.is_ca() {
+            return Err(Error::InvalidBasicConstraints);
+        }
+        let self_signed = std::ptr::eq(self.cert, parent);
+        let ca_below = if self.cert.is_ca() && !self_signed {
+            self.ca_below.saturating_add(1)
+        } else {
+            self.ca_below
+        };
+        if let Some(path) = parent.extensions.basic_const.as_ref().and_then(|b| b.path) {
+            if ca_below > path {
+                return Err(Error::CertPathLenExceeded);
+            }
+        }
+
         if !self.cert.is_authority(parent)? {
             return Err(Error::InvalidAuthKey);
         }
@@ -692,8 +919,11 @@ impl<'a> CertVerifier<'a> {
             e
         })?;
 
-        // TODO: other validation checks
-        Ok(CertVerifier::new(parent))
+        Ok(CertVerifier {
+            cert: parent,
+            ca_below,
+            time: self.time,
+        })
     }
 
     pub fn finalise(self) -> Result<(), Error> {
@@ -721,17 +951,27 @@ pub trait CertConsumer {
     fn end_ctx(&mut self) -> Result<(), Error>;
     fn oid(&mut self, tag: &str, oid: &[u8]) -> Result<(), Error>;
     fn utctime(&mut self, tag: &str, epoch: u32) -> Result<(), Error>;
+    // The Not After of a certificate that has no well-defined expiry
+    fn no_expiry(&mut self, tag: &str) -> Result<(), Error>;
 }
 
 const MAX_DEPTH: usize = 10;
 const MAX_ASN1_CERT_SIZE: usize = 1000;
+// The sequence (the writer reserves 3 bytes for its length), and the two integers, each
+// with a possible zero prefix
+const MAX_DER_SIG_LEN: usize = 4 + 2 * (2 + BIGNUM_LEN_BYTES + 1);
 
 mod asn1_writer;
+mod der;
+mod issuer;
 mod printer;
 
 #[cfg(test)]
 mod tests {
-    use crate::cert::Cert;
+    use super::{
+        encode_ecdsa_sig, ValidityTime, BUILD_TIME, KEY_USAGE_DIGITAL_SIGN, KEY_USAGE_KEY_CERT_SIGN,
+    };
+    use crate::cert::{ASN1Writer, Cert};
     use crate::error::Error;
     use crate::tlv::{self, FromTLV, TLVWriter, TagType, ToTLV};
     use crate::utils::writebuf::WriteBuf;
@@ -760,12 +1000,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ecdsa_sig_encoding() {
+        // Leading zeroes are stripped, and a zero is prefixed if the high bit is set
+        let mut sig = [0u8; 64];
+        sig[2] = 0x01;
+        sig[31] = 0x02;
+        sig[32] = 0x80;
+        let mut buf = [0u8; 100];
+        let mut w = ASN1Writer::new(&mut buf);
+        encode_ecdsa_sig(&sig, &mut w).unwrap();
+        let der = w.as_slice();
+        assert_eq!(&der[..4], &[0x30, 67, 0x02, 30]);
+        assert_eq!(&der[4..6], &[0x01, 0x00]);
+        assert_eq!(der[33], 0x02);
+        assert_eq!(&der[34..38], &[0x02, 33, 0x00, 0x80]);
+
+        assert_eq!(
+            Err(Error::InvalidSignature),
+            encode_ecdsa_sig(&sig[..32], &mut ASN1Writer::new(&mut buf))
+        );
+    }
+
     #[test]
     fn test_verify_chain_success() {
         let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
         let rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(BUILD_TIME);
         a.add_cert(&icac)
             .unwrap()
             .add_cert(&rca)
@@ -779,7 +1041,7 @@ mod tests {
         // The chain doesn't lead up to a self-signed certificate
         let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(BUILD_TIME);
         assert_eq!(
             Err(Error::InvalidAuthKey),
             a.add_cert(&icac).unwrap().finalise()
@@ -790,7 +1052,7 @@ mod tests {
     fn test_auth_key_chain_incorrect() {
         let noc = Cert::new(&test_vectors::NOC1_AUTH_KEY_FAIL).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(BUILD_TIME);
         assert_eq!(Err(Error::InvalidAuthKey), a.add_cert(&icac).map(|_| ()));
     }
 
@@ -798,10 +1060,100 @@ mod tests {
     fn test_cert_corrupted() {
         let noc = Cert::new(&test_vectors::NOC1_CORRUPT_CERT).unwrap();
         let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
-        let a = noc.verify_chain_start();
+        let a = noc.verify_chain_start(BUILD_TIME);
         assert_eq!(Err(Error::InvalidSignature), a.add_cert(&icac).map(|_| ()));
     }
 
+    #[test]
+    fn test_verify_chain_validity() {
+        let mut noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+        let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        let verify = |noc: &Cert, time| {
+            let mut a = noc.verify_chain_start(BUILD_TIME);
+            a.time = time;
+            a.add_cert(&icac).map(|_| ())
+        };
+
+        let not_before = noc.not_before;
+        let not_after = noc.not_after;
+        assert_eq!(
+            Err(Error::CertNotYetValid),
+            verify(&noc, ValidityTime::Current(not_before - 1))
+        );
+        // The actual time may be later than the Last Known Good UTC Time
+        assert_eq!(
+            Ok(()),
+            verify(&noc, ValidityTime::LastKnownGood(not_before - 1))
+        );
+        assert_eq!(
+            Err(Error::CertExpired),
+            verify(&noc, ValidityTime::Current(not_after + 1))
+        );
+        assert_eq!(
+            Err(Error::CertExpired),
+            verify(&noc, ValidityTime::LastKnownGood(not_after + 1))
+        );
+
+        // No well-defined expiry, the signature fails after the validity checks pass
+        noc.not_after = 0;
+        assert_eq!(
+            Err(Error::InvalidSignature),
+            verify(&noc, ValidityTime::Current(not_after + 1))
+        );
+    }
+
+    #[test]
+    fn test_verify_chain_extensions() {
+        let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        let verify = |noc: &Cert| {
+            noc.verify_chain_start(BUILD_TIME)
+                .add_cert(&icac)
+                .map(|_| ())
+        };
+        let noc = || Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+
+        let mut c = noc();
+        c.extensions.basic_const.as_mut().unwrap().is_ca = true;
+        assert_eq!(Err(Error::InvalidBasicConstraints), verify(&c));
+
+        let mut c = noc();
+        c.extensions.key_usage = Some(KEY_USAGE_DIGITAL_SIGN | KEY_USAGE_KEY_CERT_SIGN);
+        assert_eq!(Err(Error::InvalidKeyUsage), verify(&c));
+
+        let mut c = noc();
+        c.extensions.ext_key_usage = None;
+        assert_eq!(Err(Error::InvalidExtKeyUsage), verify(&c));
+
+        let mut c = noc();
+        c.extensions.subj_key_id = None;
+        assert_eq!(Err(Error::InvalidSubjKeyId), verify(&c));
+    }
+
+    #[test]
+    fn test_verify_chain_ca_constraints() {
+        let noc = Cert::new(&test_vectors::NOC1_SUCCESS).unwrap();
+
+        // The issuer isn't a CA
+        let mut icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        icac.extensions.basic_const.as_mut().unwrap().is_ca = false;
+        assert_eq!(
+            Err(Error::InvalidBasicConstraints),
+            noc.verify_chain_start(BUILD_TIME)
+                .add_cert(&icac)
+                .map(|_| ())
+        );
+
+        // The root doesn't allow an ICAC below it
+        let icac = Cert::new(&test_vectors::ICAC1_SUCCESS).unwrap();
+        let mut rca = Cert::new(&test_vectors::RCA1_SUCCESS).unwrap();
+        rca.extensions.basic_const.as_mut().unwrap().path = Some(0);
+        let a = noc.verify_chain_start(BUILD_TIME).add_cert(&icac).unwrap();
+        assert_eq!(
+            Err(Error::CertPathLenExceeded),
+            a.add_cert(&rca).map(|_| ())
+        );
+    }
+
     #[test]
     fn test_tlv_conversions() {
         let test_input: [&[u8]; 3] = [
@@ -0,0 +1,712 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+//! The commissioner: bring a commissionable device on to our fabric
+//!
+//! This is the client side of the commissioning clusters in [crate::data_model::sdm]. The
+//! flow runs over a PASE session to the device till the device has its NOC, and is then
+//! completed over a CASE session.
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_channel::Receiver;
+use log::{error, info, warn};
+use rand::prelude::*;
+use smol::{future::FutureExt, Timer};
+
+use crate::{
+    cert::{verify_csr, DerCert, NocIssuer},
+    crypto::{self, CryptoKeyPair, KeyPair},
+    data_model::{
+        cluster_basic_information,
+        objects::EncodeValue,
+        sdm::{
+            general_commissioning::{self, RegLocationType},
+            noc,
+        },
+    },
+    error::Error,
+    fabric::FabricMgr,
+    interaction_model::{
+        client,
+        core::IMStatusCode,
+        messages::{
+            ib::{self, AttrPath, CmdPath},
+            msg::ReadReq,
+            GenericPath,
+        },
+    },
+    secure_channel::{
+        case,
+        pake::{self, PaseSession},
+    },
+    tlv::{get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr},
+    transport::{
+        network::Address,
+        queue::{Msg, WorkQ},
+    },
+};
+
+// The time for which the fail-safe is armed, in seconds
+const DEFAULT_FAILSAFE_EXPIRY: u16 = 60;
+// The time we wait for a session establishment or a response
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const NONCE_LEN: usize = 32;
+// As defined in the Matter Spec
+const MAX_DER_CERT_LEN: usize = 600;
+const MAX_CERT_TLV_LEN: usize = 400;
+
+const CERT_TYPE_DAC: u8 = 1;
+const CERT_TYPE_PAI: u8 = 2;
+
+/// The Commissioner
+///
+/// The commissioning is a blocking call, it must not be made from the thread that runs
+/// the Matter daemon.
+///
+/// A device is only commissioned if its DAC is signed by its PAI, and its PAI by one of
+/// the trusted PAAs, see [Commissioner::add_paa]. The DAC and the PAI have to be valid
+/// now, and for the Vendor ID and Product ID that the device reports in its Basic
+/// Information. The Certification Declaration of the device isn't checked.
+///
+/// If the commissioning fails, the fail-safe on the device is disarmed, so that it rolls
+/// back what was done so far, and the PASE session is closed.
+pub struct Commissioner {
+    fabric_mgr: Arc<FabricMgr>,
+    wq: WorkQ,
+    fab_idx: u8,
+    issuer: Box<dyn NocIssuer>,
+    paas: Vec<Vec<u8>>,
+    allow_untrusted_pai: bool,
+    failsafe_expiry: u16,
+    reg_config: u8,
+    country_code: String,
+    bread_crumb: u64,
+}
+
+// The details from our fabric, that go to the device
+struct FabricCreds {
+    root_ca: Vec<u8>,
+    icac: Option<Vec<u8>>,
+    ipk: Vec<u8>,
+    admin_subject: u64,
+    vendor_id: u16,
+}
+
+impl Commissioner {
+    /// Creates a Commissioner for our fabric with this fabric index
+    ///
+    /// The requests to the device go through the transport of wq, see [Matter::get_workq].
+    ///
+    /// [Matter::get_workq]: crate::core::Matter::get_workq
+    pub fn new(
+        fabric_mgr: Arc<FabricMgr>,
+        wq: WorkQ,
+        fab_idx: u8,
+        issuer: Box<dyn NocIssuer>,
+    ) -> Self {
+        Self {
+            fabric_mgr,
+            wq,
+            fab_idx,
+            issuer,
+            paas: Vec::new(),
+            allow_untrusted_pai: false,
+            failsafe_expiry: DEFAULT_FAILSAFE_EXPIRY,
+            reg_config: RegLocationType::IndoorOutdoor as u8,
+            country_code: "XX".to_owned(),
+            bread_crumb: 0,
+        }
+    }
+
+    /// Trust the devices whose PAI is signed by this PAA, a DER encoded certificate
+    pub fn add_paa(mut self, paa: &[u8]) -> Self {
+        self.paas.push(paa.to_vec());
+        self
+    }
+
+    /// Commission the devices whose PAI isn't signed by any of the trusted PAAs as well
+    ///
+    /// This is only meant for development and testing, with devices that carry test
+    /// attestation certificates.
+    pub fn allow_untrusted_pai(mut self) -> Self {
+        self.allow_untrusted_pai = true;
+        self
+    }
+
+    /// The time, in seconds, for which the fail-safe is armed on the device
+    pub fn set_failsafe_expiry(mut self, secs: u16) -> Self {
+        self.failsafe_expiry = secs;
+        self
+    }
+
+    /// The regulatory config that is set on the device
+    pub fn set_regulatory_config(mut self, location: RegLocationType, country_code: &str) -> Self {
+        self.reg_config = location as u8;
+        self.country_code = country_code.to_owned();
+        self
+    }
+
+    /// Commission the device at this address, with its setup passcode, as the node with
+    /// this node id on our fabric
+    ///
+    /// On success, this returns the local session ID of the CASE session with the device.
+    pub fn commission(
+        &mut self,
+        passcode: u32,
+        peer_addr: Address,
+        node_id: u64,
+    ) -> Result<u16, Error> {
+        info!("Commissioning node {:x} at {}", node_id, peer_addr);
+        let pase = wait(pake::establish_session(&self.wq, passcode, peer_addr)?)?;
+        let sess_id = pase.local_sessid;
+        info!("PASE session established: {}", sess_id);
+
+        let result = self.commission_over(&pase, peer_addr, node_id);
+        if let Err(e) = result {
+            error!("Commissioning of node {:x} failed: {:?}", node_id, e);
+            self.abort(sess_id);
+        }
+        result
+    }
+
+    fn commission_over(
+        &mut self,
+        pase: &PaseSession,
+        peer_addr: Address,
+        node_id: u64,
+    ) -> Result<u16, Error> {
+        let sess_id = pase.local_sessid;
+        self.arm_failsafe(sess_id, self.failsafe_expiry)?;
+        self.set_reg_config(sess_id)?;
+
+        let dac = self.get_cert(sess_id, CERT_TYPE_DAC)?;
+        let pai = self.get_cert(sess_id, CERT_TYPE_PAI)?;
+        let (vendor_id, product_id) = self.get_vendor_product(sess_id)?;
+        let dac_key = self.verify_dac(&dac, &pai, vendor_id, product_id)?;
+        self.attest(sess_id, &dac_key, &pase.att_challenge)?;
+        let noc_pub_key = self.request_csr(sess_id, &dac_key, &pase.att_challenge)?;
+
+        let creds = self.get_fabric_creds()?;
+        self.add_trusted_root(sess_id, &creds)?;
+        self.add_noc(sess_id, &creds, &noc_pub_key, node_id)?;
+
+        let case_sess_id = wait(case::establish_session(
+            &self.wq,
+            self.fab_idx,
+            node_id,
+            peer_addr,
+        )?)?;
+        info!("CASE session established: {}", case_sess_id);
+        self.commissioning_complete(case_sess_id)?;
+        info!("Commissioning of node {:x} complete", node_id);
+        Ok(case_sess_id)
+    }
+
+    // Have the device roll back what it did so far, and close the PASE session. The
+    // device rolls back anyway once the fail-safe expires, this is only sooner.
+    fn abort(&mut self, sess_id: u16) {
+        if let Err(e) = self.arm_failsafe(sess_id, 0) {
+            error!("Error in disarming the fail-safe: {:?}", e);
+        }
+        if let Err(e) = self.wq.sync_send(Msg::CloseSession(sess_id)) {
+            error!("Error in closing the PASE session: {:?}", e);
+        }
+    }
+
+    fn next_bread_crumb(&mut self) -> u64 {
+        self.bread_crumb += 1;
+        self.bread_crumb
+    }
+
+    fn arm_failsafe(&mut self, sess_id: u16, expiry_len: u16) -> Result<(), Error> {
+        let req = ArmFailSafeReq {
+            expiry_len,
+            bread_crumb: self.next_bread_crumb(),
+        };
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            general_commissioning::ID,
+            general_commissioning::Commands::ArmFailsafe as u16,
+            general_commissioning::Commands::ArmFailsafeResp as u16,
+            &req,
+            |d| CommissioningResp::from_tlv(d)?.check("ArmFailSafe"),
+        )
+    }
+
+    fn set_reg_config(&mut self, sess_id: u16) -> Result<(), Error> {
+        let bread_crumb = self.next_bread_crumb();
+        let req = SetRegConfigReq {
+            new_reg_config: self.reg_config,
+            country_code: UtfStr::new(self.country_code.as_bytes()),
+            bread_crumb,
+        };
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            general_commissioning::ID,
+            general_commissioning::Commands::SetRegulatoryConfig as u16,
+            general_commissioning::Commands::SetRegulatoryConfigResp as u16,
+            &req,
+            |d| CommissioningResp::from_tlv(d)?.check("SetRegulatoryConfig"),
+        )
+    }
+
+    fn get_cert(&mut self, sess_id: u16, cert_type: u8) -> Result<Vec<u8>, Error> {
+        let req = CertChainReq { cert_type };
+        let cert = invoke_cmd(
+            &self.wq,
+            sess_id,
+            noc::ID,
+            noc::Commands::CertChainReq as u16,
+            noc::Commands::CertChainResp as u16,
+            &req,
+            |d| Ok(CertChainResp::from_tlv(d)?.cert.0.to_vec()),
+        )?;
+        if cert.is_empty() || cert.len() > MAX_DER_CERT_LEN {
+            error!("Invalid certificate of type {}", cert_type);
+            return Err(Error::Invalid);
+        }
+        Ok(cert)
+    }
+
+    // The Vendor ID and the Product ID that the device reports in its Basic Information
+    fn get_vendor_product(&mut self, sess_id: u16) -> Result<(u16, u16), Error> {
+        let attrs = [
+            cluster_basic_information::Attributes::VendorId,
+            cluster_basic_information::Attributes::ProductId,
+        ];
+        let paths = attrs.map(|attr| {
+            AttrPath::new(&GenericPath::new(
+                Some(0),
+                Some(cluster_basic_information::ID),
+                Some(attr as u32),
+            ))
+        });
+        let req = ReadReq::new(false).set_attr_requests(&paths);
+        let resp = wait(client::read(&self.wq, sess_id, &req)?)?;
+
+        let (mut vendor_id, mut product_id) = (None, None);
+        for report in resp.attr_reports()? {
+            if let ib::AttrResp::Data(d) = report {
+                let value = d.data.unwrap_tlv().ok_or(Error::Invalid)?.u16()?;
+                match d.path.attr {
+                    Some(a) if a == paths[0].attr.unwrap_or_default() => vendor_id = Some(value),
+                    Some(a) if a == paths[1].attr.unwrap_or_default() => product_id = Some(value),
+                    _ => (),
+                }
+            }
+        }
+        match (vendor_id, product_id) {
+            (Some(vendor_id), Some(product_id)) => Ok((vendor_id, product_id)),
+            _ => {
+                error!("The device didn't report its Vendor ID and Product ID");
+                Err(Error::Invalid)
+            }
+        }
+    }
+
+    // Verify the chain of the DAC, and that it is for the device's vendor and product.
+    // This returns the DAC's public key.
+    fn verify_dac(
+        &self,
+        dac: &[u8],
+        pai: &[u8],
+        vendor_id: u16,
+        product_id: u16,
+    ) -> Result<KeyPair, Error> {
+        let dac = DerCert::parse(dac)?;
+        let pai = DerCert::parse(pai)?;
+        if let Err(e) = dac.verify_issuer(&pai) {
+            error!("The DAC isn't signed by the PAI");
+            return Err(e);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| Error::InvalidTime)?
+            .as_secs() as i64;
+        if !dac.is_valid_at(now) || !pai.is_valid_at(now) {
+            error!("The DAC or the PAI isn't valid at this time");
+            return Err(Error::Invalid);
+        }
+        if dac.get_vendor_id() != Some(vendor_id) || dac.get_product_id() != Some(product_id) {
+            error!(
+                "The DAC isn't for vendor {:x}, product {:x}",
+                vendor_id, product_id
+            );
+            return Err(Error::Invalid);
+        }
+        // The PAI may be for all the products of the vendor
+        if pai.get_vendor_id() != Some(vendor_id)
+            || pai.get_product_id().is_some_and(|p| p != product_id)
+        {
+            error!("The PAI isn't for vendor {:x}", vendor_id);
+            return Err(Error::Invalid);
+        }
+        let trusted = self.paas.iter().any(|paa| {
+            DerCert::parse(paa)
+                .and_then(|paa| pai.verify_issuer(&paa))
+                .is_ok()
+        });
+        if !trusted {
+            if !self.allow_untrusted_pai {
+                error!("The PAI isn't signed by any of the trusted PAAs");
+                return Err(Error::Invalid);
+            }
+            warn!("The PAI isn't signed by any of the trusted PAAs, proceeding anyway");
+        }
+        KeyPair::new_from_public(dac.get_pubkey())
+    }
+
+    fn attest(
+        &mut self,
+        sess_id: u16,
+        dac_key: &KeyPair,
+        att_challenge: &[u8],
+    ) -> Result<(), Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let req = NonceReq {
+            nonce: OctetStr::new(&nonce),
+        };
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            noc::ID,
+            noc::Commands::AttReq as u16,
+            noc::Commands::AttReqResp as u16,
+            &req,
+            |d| {
+                let resp = SignedResp::from_tlv(d)?;
+                let elements = get_root_node_struct(resp.elements.0)?;
+                let elements = AttestationElements::from_tlv(&elements)?;
+                if elements.nonce.0 != nonce {
+                    error!("Attestation nonce mismatch");
+                    return Err(Error::Invalid);
+                }
+                verify_signed(dac_key, resp.elements.0, att_challenge, resp.signature.0)
+            },
+        )
+    }
+
+    // Returns the public key of the node's operational key pair
+    fn request_csr(
+        &mut self,
+        sess_id: u16,
+        dac_key: &KeyPair,
+        att_challenge: &[u8],
+    ) -> Result<[u8; crypto::EC_POINT_LEN_BYTES], Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let req = NonceReq {
+            nonce: OctetStr::new(&nonce),
+        };
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            noc::ID,
+            noc::Commands::CSRReq as u16,
+            noc::Commands::CSRResp as u16,
+            &req,
+            |d| {
+                let resp = SignedResp::from_tlv(d)?;
+                let elements = get_root_node_struct(resp.elements.0)?;
+                let elements = NocsrElements::from_tlv(&elements)?;
+                if elements.nonce.0 != nonce {
+                    error!("CSR nonce mismatch");
+                    return Err(Error::Invalid);
+                }
+                verify_signed(dac_key, resp.elements.0, att_challenge, resp.signature.0)?;
+                verify_csr(elements.csr.0)
+            },
+        )
+    }
+
+    fn get_fabric_creds(&self) -> Result<FabricCreds, Error> {
+        let fabric = self.fabric_mgr.get_fabric(self.fab_idx as usize)?;
+        let fabric = (*fabric).as_ref().ok_or(Error::NotFound)?;
+
+        let mut buf = [0u8; MAX_CERT_TLV_LEN];
+        let len = fabric.root_ca.as_tlv(&mut buf)?;
+        let root_ca = buf[..len].to_vec();
+        let icac = match &fabric.icac {
+            Some(icac) => {
+                let len = icac.as_tlv(&mut buf)?;
+                Some(buf[..len].to_vec())
+            }
+            None => None,
+        };
+        Ok(FabricCreds {
+            root_ca,
+            icac,
+            ipk: fabric.ipk.epoch_key().to_vec(),
+            admin_subject: fabric.get_node_id(),
+            vendor_id: fabric.get_vendor_id(),
+        })
+    }
+
+    fn add_trusted_root(&mut self, sess_id: u16, creds: &FabricCreds) -> Result<(), Error> {
+        let req = AddTrustedRootReq {
+            root_cert: OctetStr::new(&creds.root_ca),
+        };
+        invoke_status(
+            &self.wq,
+            sess_id,
+            noc::ID,
+            noc::Commands::AddTrustedRootCert as u16,
+            &req,
+        )
+    }
+
+    fn add_noc(
+        &mut self,
+        sess_id: u16,
+        creds: &FabricCreds,
+        noc_pub_key: &[u8],
+        node_id: u64,
+    ) -> Result<(), Error> {
+        let mut noc = [0u8; MAX_CERT_TLV_LEN];
+        let len = self.issuer.issue_noc(noc_pub_key, node_id, &mut noc)?;
+
+        let req = AddNocReq {
+            noc_value: OctetStr::new(&noc[..len]),
+            icac_value: creds.icac.as_ref().map(|icac| OctetStr::new(icac)),
+            ipk_value: OctetStr::new(&creds.ipk),
+            case_admin_subject: creds.admin_subject,
+            vendor_id: creds.vendor_id,
+        };
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            noc::ID,
+            noc::Commands::AddNOC as u16,
+            noc::Commands::NOCResp as u16,
+            &req,
+            |d| {
+                let resp = NocResp::from_tlv(d)?;
+                if resp.status_code != 0 {
+                    error!("AddNOC failed with status {}", resp.status_code);
+                    return Err(Error::Invalid);
+                }
+                info!("Device added the NOC at fabric index {:?}", resp.fab_idx);
+                Ok(())
+            },
+        )
+    }
+
+    fn commissioning_complete(&mut self, sess_id: u16) -> Result<(), Error> {
+        let req = EncodeValue::Closure(&|tag, tw| {
+            let _ = tw.start_struct(tag);
+            let _ = tw.end_container();
+        });
+        invoke_cmd(
+            &self.wq,
+            sess_id,
+            general_commissioning::ID,
+            general_commissioning::Commands::CommissioningComplete as u16,
+            general_commissioning::Commands::CommissioningCompleteResp as u16,
+            &req,
+            |d| CommissioningResp::from_tlv(d)?.check("CommissioningComplete"),
+        )
+    }
+}
+
+// Wait for the outcome of a request that we queued to the transport
+fn wait<T>(rx: Receiver<Result<T, Error>>) -> Result<T, Error> {
+    let outcome = async { rx.recv().await.map_err(|_| Error::NoExchange)? };
+    let timeout = async {
+        Timer::after(RESPONSE_TIMEOUT).await;
+        Err(Error::Timeout)
+    };
+    smol::block_on(outcome.or(timeout))
+}
+
+// Invoke a command on the root endpoint of the device, and parse the response command's
+// fields
+fn invoke_cmd<T, F>(
+    wq: &WorkQ,
+    sess_id: u16,
+    cluster: u32,
+    cmd: u16,
+    resp_cmd: u16,
+    data: &dyn ToTLV,
+    f: F,
+) -> Result<T, Error>
+where
+    F: FnOnce(&TLVElement) -> Result<T, Error>,
+{
+    let resp = wait(client::invoke(
+        wq,
+        sess_id,
+        CmdPath::new(Some(0), Some(cluster), Some(cmd)),
+        data,
+    )?)?;
+    match resp.cmd_resp()? {
+        ib::InvResp::Cmd(c) if c.path == CmdPath::new(Some(0), Some(cluster), Some(resp_cmd)) => {
+            f(&c.data.unwrap_tlv().ok_or(Error::Invalid)?)
+        }
+        ib::InvResp::Cmd(c) => {
+            error!("Unexpected response to command {}: {:?}", cmd, c.path);
+            Err(Error::Invalid)
+        }
+        ib::InvResp::Status(s) => {
+            error!("Command {} failed with status {:?}", cmd, s.status);
+            Err(Error::Invalid)
+        }
+    }
+}
+
+// Invoke a command on the root endpoint of the device, that only has a status response
+fn invoke_status(
+    wq: &WorkQ,
+    sess_id: u16,
+    cluster: u32,
+    cmd: u16,
+    data: &dyn ToTLV,
+) -> Result<(), Error> {
+    let resp = wait(client::invoke(
+        wq,
+        sess_id,
+        CmdPath::new(Some(0), Some(cluster), Some(cmd)),
+        data,
+    )?)?;
+    match resp.cmd_resp()? {
+        ib::InvResp::Status(s) if s.status.status == IMStatusCode::Success => Ok(()),
+        r => {
+            error!("Command {} failed: {:?}", cmd, r);
+            Err(Error::Invalid)
+        }
+    }
+}
+
+// The device signs the response elements, followed by the attestation challenge, with its
+// DAC
+fn verify_signed(
+    dac_key: &KeyPair,
+    elements: &[u8],
+    att_challenge: &[u8],
+    signature: &[u8],
+) -> Result<(), Error> {
+    let mut msg = elements.to_vec();
+    msg.extend_from_slice(att_challenge);
+    let result = dac_key.verify_msg(&msg, signature);
+    if result.is_err() {
+        error!("Invalid attestation signature");
+    }
+    result
+}
+
+#[derive(ToTLV)]
+struct ArmFailSafeReq {
+    expiry_len: u16,
+    bread_crumb: u64,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct SetRegConfigReq<'a> {
+    new_reg_config: u8,
+    country_code: UtfStr<'a>,
+    bread_crumb: u64,
+}
+
+#[derive(FromTLV)]
+struct CommissioningResp {
+    error_code: u8,
+    debug_txt: String,
+}
+
+impl CommissioningResp {
+    fn check(&self, cmd: &str) -> Result<(), Error> {
+        if self.error_code != 0 {
+            error!(
+                "{} failed with {}: {}",
+                cmd, self.error_code, self.debug_txt
+            );
+            return Err(Error::Invalid);
+        }
+        Ok(())
+    }
+}
+
+#[derive(ToTLV)]
+struct CertChainReq {
+    cert_type: u8,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct CertChainResp<'a> {
+    cert: OctetStr<'a>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct NonceReq<'a> {
+    nonce: OctetStr<'a>,
+}
+
+// The AttestationResponse and the CSRResponse
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct SignedResp<'a> {
+    elements: OctetStr<'a>,
+    signature: OctetStr<'a>,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct AttestationElements<'a> {
+    _cert_dec: OctetStr<'a>,
+    nonce: OctetStr<'a>,
+    _timestamp: u32,
+}
+
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
+struct NocsrElements<'a> {
+    csr: OctetStr<'a>,
+    nonce: OctetStr<'a>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddTrustedRootReq<'a> {
+    root_cert: OctetStr<'a>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+    ipk_value: OctetStr<'a>,
+    case_admin_subject: u64,
+    vendor_id: u16,
+}
+
+#[derive(FromTLV)]
+struct NocResp {
+    status_code: u8,
+    fab_idx: Option<u8>,
+    _debug_txt: Option<String>,
+}
@@ -18,18 +18,28 @@
 use crate::{
     acl::AclMgr,
     data_model::{
-        cluster_basic_information::BasicInfoConfig, core::DataModel,
+        cluster_basic_information::{self, BasicInfoConfig},
+        core::{DataModel, DataModelConfig},
+        objects,
         sdm::dev_att::DevAttDataFetcher,
     },
     error::*,
     fabric::FabricMgr,
-    interaction_model::InteractionModel,
+    interaction_model::{command, InteractionModel},
     mdns::Mdns,
     pairing::{print_pairing_code_and_qr, DiscoveryCapabilities},
     secure_channel::{core::SecureChannel, pake::PaseMgr, spake2p::VerifierData},
-    transport,
+    sys::{Psm, MAX_PACKET_POOL_SIZE, PSM_DIR},
+    transport::{
+        self, exchange::MAX_EXCHANGES, mgr::StopHandle, packet::PacketPool, queue::WorkQ,
+        session::MAX_SESSIONS, udp::MATTER_PORT,
+    },
+};
+use log::info;
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
 };
-use std::sync::Arc;
 
 /// Device Commissioning Data
 pub struct CommissioningData {
@@ -39,11 +49,117 @@ pub struct CommissioningData {
     pub discriminator: u16,
 }
 
+/// The runtime configuration of the Matter stack
+///
+/// The defaults suit a typical device. Gateways that talk to many peers can raise the
+/// limits, while constrained devices can lower them.
+///
+/// Several Matter objects can run in one process, like test instances, as long as each
+/// has a port and a storage directory of its own.
+///
+/// # Limitations
+///
+/// The mDNS responder is shared by the Matter objects in a process, and advertises the
+/// port and the device details of the one that was created last.
+pub struct MatterConfig {
+    dev_comm: CommissioningData,
+    discovery_capabilities: DiscoveryCapabilities,
+    max_sessions: usize,
+    max_exchanges: usize,
+    packet_pool_size: usize,
+    attrs_per_cluster: usize,
+    max_paths_per_invoke: u16,
+    port: u16,
+    psm_dir: String,
+}
+
+impl MatterConfig {
+    pub fn new(dev_comm: CommissioningData) -> Self {
+        Self {
+            dev_comm,
+            discovery_capabilities: DiscoveryCapabilities::default(),
+            max_sessions: MAX_SESSIONS,
+            max_exchanges: MAX_EXCHANGES,
+            packet_pool_size: MAX_PACKET_POOL_SIZE,
+            attrs_per_cluster: objects::ATTRS_PER_CLUSTER,
+            max_paths_per_invoke: command::MAX_PATHS_PER_INVOKE,
+            port: MATTER_PORT,
+            psm_dir: PSM_DIR.to_owned(),
+        }
+    }
+
+    /// The ways in which the device can be discovered for commissioning
+    pub fn set_discovery_capabilities(mut self, capabilities: DiscoveryCapabilities) -> Self {
+        self.discovery_capabilities = capabilities;
+        self
+    }
+
+    /// The maximum number of sessions, beyond which the least recently used one is evicted
+    pub fn set_max_sessions(mut self, count: usize) -> Self {
+        self.max_sessions = count;
+        self
+    }
+
+    /// The maximum number of exchanges on a session
+    pub fn set_max_exchanges(mut self, count: usize) -> Self {
+        self.max_exchanges = count;
+        self
+    }
+
+    /// The number of packet buffers, at least 1
+    ///
+    /// Every message that is being received, sent or retransmitted holds one of these.
+    pub fn set_packet_pool_size(mut self, count: usize) -> Self {
+        self.packet_pool_size = count;
+        self
+    }
+
+    /// The maximum number of attributes in a cluster
+    pub fn set_attrs_per_cluster(mut self, count: usize) -> Self {
+        self.attrs_per_cluster = count;
+        self
+    }
+
+    /// The maximum number of commands in an InvokeRequest, at least 1
+    pub fn set_max_paths_per_invoke(mut self, count: u16) -> Self {
+        self.max_paths_per_invoke = count;
+        self
+    }
+
+    /// The port that Matter listens on, for both UDP and TCP
+    pub fn set_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// The directory where the persistent data is stored
+    pub fn set_psm_dir(mut self, dir: &str) -> Self {
+        self.psm_dir = dir.to_owned();
+        self
+    }
+}
+
 /// The primary Matter Object
 pub struct Matter {
     transport_mgr: transport::mgr::Mgr,
     data_model: DataModel,
     fabric_mgr: Arc<FabricMgr>,
+    pase: PaseMgr,
+    psm: Arc<Mutex<Psm>>,
+}
+
+/// A handle to a Matter daemon that runs on a background thread
+pub struct DaemonHandle {
+    stop: StopHandle,
+    thread: JoinHandle<Result<(), Error>>,
+}
+
+impl DaemonHandle {
+    /// Stops the daemon, and waits till it has shut down
+    pub fn stop(self) -> Result<(), Error> {
+        self.stop.stop();
+        self.thread.join().map_err(|_| Error::Invalid)?
+    }
 }
 
 impl Matter {
@@ -53,39 +169,83 @@ impl Matter {
     /// * dev_att: An object that implements the trait [DevAttDataFetcher]. Any Matter device
     /// requires a set of device attestation certificates and keys. It is the responsibility of
     /// this object to return the device attestation details when queried upon.
+    /// * config: The commissioning data, along with the resource limits, the port and the
+    /// storage location, see [MatterConfig]
     pub fn new(
         dev_det: BasicInfoConfig,
-        dev_att: Box<dyn DevAttDataFetcher>,
-        dev_comm: CommissioningData,
+        dev_att: Box<dyn DevAttDataFetcher + Send + Sync>,
+        config: MatterConfig,
     ) -> Result<Box<Matter>, Error> {
+        if config.max_sessions == 0
+            || config.max_exchanges == 0
+            || config.packet_pool_size == 0
+            || config.max_paths_per_invoke == 0
+        {
+            return Err(Error::Invalid);
+        }
+        let packet_pool = PacketPool::new(config.packet_pool_size);
+        let psm = Arc::new(Mutex::new(Psm::new(&config.psm_dir)?));
+
         let mdns = Mdns::get()?;
         mdns.set_values(dev_det.vid, dev_det.pid, &dev_det.device_name);
+        mdns.set_port(config.port);
+
+        let transport_mgr = transport::mgr::Mgr::new(
+            config.port,
+            config.max_sessions,
+            config.max_exchanges,
+            packet_pool.clone(),
+        )?;
+        let wq = transport_mgr.get_workq();
 
-        let fabric_mgr = Arc::new(FabricMgr::new()?);
+        let sw_ver = dev_det.sw_ver;
+        let dev_comm = config.dev_comm;
+        let fabric_mgr = Arc::new(FabricMgr::new_with_psm(psm.clone())?);
         let open_comm_window = fabric_mgr.is_empty();
         if open_comm_window {
-            print_pairing_code_and_qr(&dev_det, &dev_comm, DiscoveryCapabilities::default());
+            print_pairing_code_and_qr(&dev_det, &dev_comm, config.discovery_capabilities);
         }
 
-        let acl_mgr = Arc::new(AclMgr::new()?);
-        let mut pase = PaseMgr::new();
-        let data_model =
-            DataModel::new(dev_det, dev_att, fabric_mgr.clone(), acl_mgr, pase.clone())?;
+        let acl_mgr = Arc::new(AclMgr::new_with_psm(psm.clone()));
+        let mut pase = PaseMgr::new(wq.clone());
+        let dm_config = DataModelConfig {
+            attrs_per_cluster: config.attrs_per_cluster,
+            max_paths_per_invoke: config.max_paths_per_invoke,
+            packet_pool,
+        };
+        let data_model = DataModel::new(
+            dev_det,
+            dev_att,
+            fabric_mgr.clone(),
+            acl_mgr,
+            pase.clone(),
+            dm_config,
+            wq.clone(),
+        )?;
+        let changes = data_model.get_change_log();
+        changes.load_events(psm.clone());
         let mut matter = Box::new(Matter {
-            transport_mgr: transport::mgr::Mgr::new()?,
+            transport_mgr,
             data_model,
             fabric_mgr,
+            pase: pase.clone(),
+            psm,
         });
-        let interaction_model =
-            Box::new(InteractionModel::new(Box::new(matter.data_model.clone())));
+        let interaction_model = Box::new(InteractionModel::new(
+            Box::new(matter.data_model.clone()),
+            config.max_paths_per_invoke,
+        ));
         matter.transport_mgr.register_protocol(interaction_model)?;
 
+        pase.set_default_comm_data(dev_comm.verifier, dev_comm.discriminator);
         if open_comm_window {
-            pase.enable_pase_session(dev_comm.verifier, dev_comm.discriminator)?;
+            pase.open_basic_comm_window(None, None)?;
         }
 
-        let secure_channel = Box::new(SecureChannel::new(pase, matter.fabric_mgr.clone()));
+        let secure_channel = Box::new(SecureChannel::new(pase, matter.fabric_mgr.clone(), wq)?);
         matter.transport_mgr.register_protocol(secure_channel)?;
+
+        cluster_basic_information::emit_startup(&changes, sw_ver)?;
         Ok(matter)
     }
 
@@ -98,13 +258,54 @@ impl Matter {
         self.data_model.clone()
     }
 
-    /// Starts the Matter daemon
+    /// Returns an Arc to the [FabricMgr]
+    ///
+    /// A commissioner adds its own fabric here, before it commissions other nodes onto it
+    pub fn get_fabric_mgr(&self) -> Arc<FabricMgr> {
+        self.fabric_mgr.clone()
+    }
+
+    /// Returns the queue of the work for the transport of this Matter object
     ///
-    /// This call does NOT return
+    /// The requests that we initiate to other nodes, like those of a
+    /// [Commissioner](crate::commissioner::Commissioner), go through this
+    pub fn get_workq(&self) -> WorkQ {
+        self.transport_mgr.get_workq()
+    }
+
+    /// Starts the Matter daemon
     ///
     /// This call starts the Matter daemon that starts communication with other Matter
-    /// devices on the network.
+    /// devices on the network. It returns only once the daemon is stopped through the
+    /// [StopHandle] from [Matter::get_stop_handle]
     pub fn start_daemon(&mut self) -> Result<(), Error> {
-        self.transport_mgr.start()
+        // The subscriptions are resumed over the transport, which is about to start
+        self.data_model.resume_subscriptions(self.psm.clone())?;
+        self.transport_mgr.start()?;
+        self.shutdown()
+    }
+
+    /// Starts the Matter daemon on a background thread
+    ///
+    /// The returned handle is used to stop the daemon
+    pub fn start_daemon_thread(mut self: Box<Self>) -> Result<DaemonHandle, Error> {
+        let stop = self.get_stop_handle();
+        let thread = thread::Builder::new()
+            .name("matter".to_owned())
+            .spawn(move || self.start_daemon())?;
+        Ok(DaemonHandle { stop, thread })
+    }
+
+    /// Returns a handle that stops the Matter daemon
+    pub fn get_stop_handle(&self) -> StopHandle {
+        self.transport_mgr.get_stop_handle()
+    }
+
+    fn shutdown(&mut self) -> Result<(), Error> {
+        info!("Shutting down the Matter daemon");
+        // Dropping the services unpublishes them
+        self.pase.close_comm_window();
+        self.fabric_mgr.unpublish_services();
+        self.psm.lock().unwrap().flush()
     }
 }
@@ -220,48 +220,13 @@ fn convert_asn1_sign_to_r_s(signature: &mut [u8]) -> Result<usize, Error> {
     if signature[0] == 0x30 {
         // Type 0x30 ASN1 Sequence
         // Length: Skip
-        let mut offset: usize = 2;
+        let offset: usize = 2;
 
-        // Type 0x2 is Integer (first integer is r)
-        if signature[offset] != 2 {
-            return Err(Error::Invalid);
-        }
-        offset += 1;
-
-        // Length
-        let len = signature[offset];
-        offset += 1;
-        // XXX Once, I have seen a crash in this conversion, need to dig
-        if len < 32 {
-            error!(
-                "Cannot deal with this: this will crash: the slice is: {:x?}",
-                signature
-            );
-        }
-
-        // Sometimes length is more than 32 with a 0 prefix-padded, skip over that
-        offset += (len - 32) as usize;
-
-        // Extract the 32 bytes of 'r'
+        // The first integer is r, followed by s
         let mut r = [0_u8; super::BIGNUM_LEN_BYTES];
-        r.copy_from_slice(&signature[offset..(offset + 32)]);
-        offset += 32;
-
-        // Type 0x2 is Integer (this integer is s)
-        if signature[offset] != 2 {
-            return Err(Error::Invalid);
-        }
-        offset += 1;
-
-        // Length
-        let len = signature[offset];
-        offset += 1;
-        // Sometimes length is more than 32 with a 0 prefix-padded, skip over that
-        offset += (len - 32) as usize;
-
-        // Extract the 32 bytes of 's'
+        let offset = extract_asn1_int(signature, offset, &mut r)?;
         let mut s = [0_u8; super::BIGNUM_LEN_BYTES];
-        s.copy_from_slice(&signature[offset..(offset + 32)]);
+        extract_asn1_int(signature, offset, &mut s)?;
 
         signature[0..32].copy_from_slice(&r);
         signature[32..64].copy_from_slice(&s);
@@ -272,6 +237,38 @@ fn convert_asn1_sign_to_r_s(signature: &mut [u8]) -> Result<usize, Error> {
     }
 }
 
+// Extract the 32 bytes of the ASN1 Integer at this offset, returning the offset after it
+fn extract_asn1_int(
+    signature: &[u8],
+    mut offset: usize,
+    out: &mut [u8; super::BIGNUM_LEN_BYTES],
+) -> Result<usize, Error> {
+    // Type 0x2 is Integer
+    if signature[offset] != 2 {
+        return Err(Error::Invalid);
+    }
+    offset += 1;
+
+    // Length
+    let len = signature[offset] as usize;
+    offset += 1;
+    if offset + len > signature.len() {
+        return Err(Error::Invalid);
+    }
+    let int = &signature[offset..(offset + len)];
+
+    // Sometimes length is more than 32 with a 0 prefix-padded, skip over that. Or it is
+    // less than 32, with the leading zeroes stripped, pad those back
+    let int = if len > out.len() {
+        &int[(len - out.len())..]
+    } else {
+        int
+    };
+    let start = out.len() - int.len();
+    out[start..].copy_from_slice(int);
+    Ok(offset + len)
+}
+
 pub fn pbkdf2_hmac(pass: &[u8], iter: usize, salt: &[u8], key: &mut [u8]) -> Result<(), Error> {
     mbedtls::hash::pbkdf2_hmac(Type::Sha256, pass, salt, iter as u32, key)
         .map_err(|_e| Error::TLSStack)
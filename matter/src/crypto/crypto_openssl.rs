@@ -186,10 +186,11 @@ impl CryptoKeyPair for KeyPair {
         safemem::write_bytes(signature, 0);
 
         let sig = EcdsaSig::sign(&msg, self.private_key()?)?;
-        let r = sig.r().to_vec();
-        signature[0..r.len()].copy_from_slice(r.as_slice());
-        let s = sig.s().to_vec();
-        signature[32..(32 + s.len())].copy_from_slice(s.as_slice());
+        // r and s may well be shorter than 32 bytes, these are zero-padded on the left
+        let r = sig.r().to_vec_padded(super::BIGNUM_LEN_BYTES as i32)?;
+        signature[0..32].copy_from_slice(r.as_slice());
+        let s = sig.s().to_vec_padded(super::BIGNUM_LEN_BYTES as i32)?;
+        signature[32..64].copy_from_slice(s.as_slice());
         Ok(64)
     }
 
@@ -16,7 +16,10 @@
  */
 
 use super::objects::*;
-use crate::error::*;
+use crate::{
+    error::*,
+    tlv::{TLVWriter, TagType, ToTLV},
+};
 use num_derive::FromPrimitive;
 
 pub const ID: u32 = 0x0028;
@@ -30,6 +33,47 @@ pub enum Attributes {
     SwVer = 9,
     SwVerString = 0xa,
     SerialNo = 0x0f,
+    MaxPathsPerInvoke = 0x16,
+}
+
+#[derive(FromPrimitive)]
+pub enum Events {
+    StartUp = 0,
+    Leave = 2,
+}
+
+#[derive(ToTLV)]
+struct StartUpEvent {
+    sw_ver: u32,
+}
+
+#[derive(ToTLV)]
+struct LeaveEvent {
+    fab_idx: u8,
+}
+
+/// Emit the StartUp event, for when the node has started up
+pub fn emit_startup(changes: &ChangeLog, sw_ver: u32) -> Result<u64, Error> {
+    changes.emit_event(
+        0,
+        ID,
+        Events::StartUp as u32,
+        EventPriority::Critical,
+        0,
+        &StartUpEvent { sw_ver },
+    )
+}
+
+/// Emit the Leave event, for when the node is removed from the fabric
+pub fn emit_leave(changes: &ChangeLog, fab_idx: u8) -> Result<u64, Error> {
+    changes.emit_event(
+        0,
+        ID,
+        Events::Leave as u32,
+        EventPriority::Info,
+        0,
+        &LeaveEvent { fab_idx },
+    )
 }
 
 #[derive(Default)]
@@ -49,7 +93,7 @@ pub struct BasicInfoCluster {
 }
 
 impl BasicInfoCluster {
-    pub fn new(cfg: BasicInfoConfig) -> Result<Box<Self>, Error> {
+    pub fn new(cfg: BasicInfoConfig, max_paths_per_invoke: u16) -> Result<Box<Self>, Error> {
         let mut cluster = Box::new(BasicInfoCluster {
             base: Cluster::new(ID)?,
         });
@@ -97,8 +141,20 @@ impl BasicInfoCluster {
                 Access::RV,
                 Quality::FIXED,
             ),
+            Attribute::new(
+                Attributes::MaxPathsPerInvoke as u16,
+                AttrValue::Uint16(max_paths_per_invoke),
+                Access::RV,
+                Quality::FIXED,
+            ),
         ];
         cluster.base.add_attributes(&attrs[..])?;
+        cluster
+            .base
+            .add_event(Event::new(Events::StartUp as u32, Access::RV))?;
+        cluster
+            .base
+            .add_event(Event::new(Events::Leave as u32, Access::RV))?;
 
         Ok(cluster)
     }
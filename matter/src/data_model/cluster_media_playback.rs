@@ -42,7 +42,7 @@ pub enum Attributes {
 
 struct ClusterCallback {
     name: Commands,
-    callback: Box<dyn FnMut()>,
+    callback: Box<dyn FnMut() + Send + Sync>,
 }
 
 enum _FeatureMap {
@@ -185,7 +185,7 @@ impl MediaPlaybackCluster {
         Ok(cluster)
     }
 
-    pub fn add_callback(&mut self, name: Commands, callback: Box<dyn FnMut()>) {
+    pub fn add_callback(&mut self, name: Commands, callback: Box<dyn FnMut() + Send + Sync>) {
         self.callbacks.push(ClusterCallback { name, callback });
     }
 
@@ -352,10 +352,10 @@ impl MediaPlaybackCluster {
             let _ = t.end_container();
         };
 
-        let invoke_resp = ib::InvResp::Cmd(ib::CmdData::new(
-            playback_response,
-            EncodeValue::Closure(&cmd_data),
-        ));
+        let invoke_resp = ib::InvResp::Cmd(ib::CmdData {
+            command_ref: cmd_req.command_ref,
+            ..ib::CmdData::new(playback_response, EncodeValue::Closure(&cmd_data))
+        });
         let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
     }
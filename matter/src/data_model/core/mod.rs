@@ -15,13 +15,15 @@
  *    limitations under the License.
  */
 
-use self::subscribe::SubsCtx;
+use self::subscribe::{SubsCtx, SubsMgr, MAX_SUBS_REQ_SIZE};
 
 use super::{
     cluster_basic_information::BasicInfoConfig,
     device_types::device_type_add_root_node,
     objects::{self, *},
-    sdm::dev_att::DevAttDataFetcher,
+    sdm::{
+        admin_commissioning, dev_att::DevAttDataFetcher, failsafe::FailSafe, general_commissioning,
+    },
     system_model::descriptor::DescriptorCluster,
 };
 use crate::{
@@ -29,7 +31,7 @@ use crate::{
     error::*,
     fabric::FabricMgr,
     interaction_model::{
-        command::CommandReq,
+        command::{self, CommandReq},
         core::{IMStatusCode, OpCode},
         messages::{
             ib::{self, AttrData, DataVersionFilter},
@@ -39,54 +41,158 @@ use crate::{
         InteractionConsumer, Transaction,
     },
     secure_channel::pake::PaseMgr,
-    tlv::{self, FromTLV, TLVArray, TLVWriter, TagType, ToTLV},
+    sys::MAX_PACKET_POOL_SIZE,
+    tlv::{self, FromTLV, Nullable, TLVArray, TLVWriter, TagType, ToTLV},
     transport::{
+        packet::{PacketPool, MAX_RX_BUF_SIZE},
         proto_demux::ResponseRequired,
+        queue::WorkQ,
         session::{Session, SessionMode},
     },
+    utils::writebuf::WriteBuf,
+    wb_shrink, wb_unshrink,
 };
 use log::{error, info};
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+/// The resource limits of a [DataModel]
+pub struct DataModelConfig {
+    /// The maximum number of attributes in a cluster
+    pub attrs_per_cluster: usize,
+    /// The maximum number of commands in an InvokeRequest, as advertised in the Basic
+    /// Information cluster
+    pub max_paths_per_invoke: u16,
+    /// The pool of the packets that the multi-hop reads hold on to
+    pub packet_pool: PacketPool,
+}
+
+impl Default for DataModelConfig {
+    fn default() -> Self {
+        Self {
+            attrs_per_cluster: objects::ATTRS_PER_CLUSTER,
+            max_paths_per_invoke: command::MAX_PATHS_PER_INVOKE,
+            packet_pool: PacketPool::new(MAX_PACKET_POOL_SIZE),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct DataModel {
     pub node: Arc<RwLock<Box<Node>>>,
     acl_mgr: Arc<AclMgr>,
+    subs_mgr: Arc<Mutex<SubsMgr>>,
+    failsafe: Arc<FailSafe>,
+    pase_mgr: PaseMgr,
+    packet_pool: PacketPool,
+    changes: ChangeLog,
+    wq: WorkQ,
 }
 
 impl DataModel {
+    /// The transport of the data model is woken up, and handed its work, through `wq`
     pub fn new(
         dev_details: BasicInfoConfig,
-        dev_att: Box<dyn DevAttDataFetcher>,
+        dev_att: Box<dyn DevAttDataFetcher + Send + Sync>,
         fabric_mgr: Arc<FabricMgr>,
         acl_mgr: Arc<AclMgr>,
         pase_mgr: PaseMgr,
+        config: DataModelConfig,
+        wq: WorkQ,
     ) -> Result<Self, Error> {
+        let subs_mgr = Arc::new(Mutex::new(SubsMgr::new()));
+        let failsafe = Arc::new(FailSafe::new(
+            fabric_mgr.clone(),
+            acl_mgr.clone(),
+            subs_mgr.clone(),
+            pase_mgr.clone(),
+            wq.clone(),
+        ));
         let dm = DataModel {
             node: Arc::new(RwLock::new(Node::new()?)),
             acl_mgr: acl_mgr.clone(),
+            subs_mgr,
+            failsafe: failsafe.clone(),
+            pase_mgr: pase_mgr.clone(),
+            packet_pool: config.packet_pool,
+            changes: ChangeLog::new(wq.clone()),
+            wq,
         };
         {
             let mut node = dm.node.write()?;
             node.set_changes_cb(Box::new(dm.clone()));
+            node.set_attrs_per_cluster(config.attrs_per_cluster);
+            node.set_change_log(dm.changes.clone());
             device_type_add_root_node(
                 &mut node,
                 dev_details,
+                config.max_paths_per_invoke,
                 dev_att,
                 fabric_mgr,
                 acl_mgr,
+                dm.subs_mgr.clone(),
                 pase_mgr,
+                failsafe,
+                dm.wq.clone(),
             )?;
         }
         Ok(dm)
     }
 
+    /// Returns the log of the changes to the data model, this is where the events are
+    /// emitted
+    pub fn get_change_log(&self) -> ChangeLog {
+        self.changes.clone()
+    }
+
+    // The commissioning window also opens and closes outside of the AdminCommissioning
+    // cluster, like on its expiry, or on CommissioningComplete. Its subscribers have to
+    // find out about that. Likewise, the BreadCrumb goes back to 0 when the fail-safe
+    // expires.
+    fn commissioning_changed(&self, node: &mut Node) {
+        if self.pase_mgr.take_window_changed() {
+            if let Ok(c) = node.get_cluster_mut(0, admin_commissioning::ID) {
+                c.base_mut().cluster_changed();
+            }
+        }
+        if self.failsafe.take_expired() {
+            if let Ok(c) = node.get_cluster_mut(0, general_commissioning::ID) {
+                let bread_crumb = general_commissioning::Attributes::BreadCrumb as u16;
+                if let Err(e) = c
+                    .base_mut()
+                    .write_attribute_raw(bread_crumb, AttrValue::Uint64(0))
+                {
+                    error!("Error in resetting the BreadCrumb {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn handle_comm_window_timeout(&self) -> Result<(), Error> {
+        let mut pase_mgr = self.pase_mgr.clone();
+        let expiry = pase_mgr.get_comm_window().and_then(|w| w.expiry);
+        if matches!(expiry, Some(t) if t <= SystemTime::now()) {
+            info!("The commissioning window timed out");
+            pase_mgr.close_comm_window();
+        }
+        let mut node = self.node.write()?;
+        self.commissioning_changed(&mut node);
+        Ok(())
+    }
+
     // Encode a write attribute from a path that may or may not be wildcard
+    //
+    // The cur_list is the list attribute that was last replaced in this write request. The
+    // appends to it that follow, are a continuation of the same write.
     fn handle_write_attr_path(
         node: &mut Node,
         accessor: &Accessor,
         attr_data: &AttrData,
         tw: &mut TLVWriter,
+        cur_list: &mut Option<GenericPath>,
     ) {
         let gen_path = attr_data.path.to_gp();
         let mut encoder = AttrWriteEncoder::new(tw, TagType::Anonymous);
@@ -123,10 +229,25 @@ impl DataModel {
             list_index: attr_data.path.list_index,
             fab_filter: false,
             fab_idx: accessor.fab_idx,
+            node_id: accessor.node_id(),
         };
 
+        let replace = attr_data.path.list_index.is_none() && write_data.confirm_array().is_ok();
+        let append = attr_data.path.list_index == Some(Nullable::Null);
+
         let result = node.for_each_cluster_mut(&gen_path, |path, c| {
-            if attr_data.data_ver.is_some() && Some(c.base().get_dataver()) != attr_data.data_ver {
+            // The replace itself moves the data version ahead, so the appends that continue
+            // it aren't checked against the data version again
+            let continued = append && *cur_list == Some(*path);
+            *cur_list = if replace || continued {
+                Some(*path)
+            } else {
+                None
+            };
+            if !continued
+                && attr_data.data_ver.is_some()
+                && Some(c.base().get_dataver()) != attr_data.data_ver
+            {
                 encoder.encode_status(IMStatusCode::DataVersionMismatch, 0);
                 return Ok(());
             }
@@ -160,7 +281,8 @@ impl DataModel {
                 // if there are other conditions in the wildcard scenario that shouldn't be
                 // encoded as CmdStatus
                 if !(wildcard && e == IMStatusCode::UnsupportedCommand) {
-                    let invoke_resp = ib::InvResp::status_new(cmd_req.cmd, e, 0);
+                    let invoke_resp =
+                        ib::InvResp::status_new(cmd_req.cmd, e, 0, cmd_req.command_ref);
                     let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
                 }
             }
@@ -169,7 +291,7 @@ impl DataModel {
         if !wildcard {
             if let Err(e) = result {
                 // We hit this only if this is a non-wildcard path
-                let invoke_resp = ib::InvResp::status_new(cmd_req.cmd, e, 0);
+                let invoke_resp = ib::InvResp::status_new(cmd_req.cmd, e, 0, cmd_req.command_ref);
                 let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
             }
         }
@@ -222,6 +344,70 @@ impl DataModel {
         }
         false
     }
+
+    /// Encode the InvokeResponses array, with as many of the responses as fit
+    ///
+    /// Returns true if some responses are left over for the next chunk
+    fn encode_invoke_responses(
+        responses: &mut VecDeque<Vec<u8>>,
+        old_tw: &mut TLVWriter,
+    ) -> Result<bool, Error> {
+        let old_wb = old_tw.get_buf();
+        // The space for the end of the array, the MoreChunkedMsgs and the end of the
+        // InvokeResponseMessage
+        const RESERVE_SIZE: usize = 8;
+        let mut new_wb = wb_shrink!(old_wb, RESERVE_SIZE);
+        let mut tw = TLVWriter::new(&mut new_wb);
+        tw.start_array(TagType::Context(msg::InvRespTag::InvokeResponses as u8))?;
+        let mut encoded = 0;
+        while let Some(resp) = responses.front() {
+            if tw.get_buf().append(resp).is_err() {
+                if encoded == 0 {
+                    // This won't fit in any chunk
+                    error!("Invoke response too large for a message");
+                    return Err(Error::NoSpace);
+                }
+                break;
+            }
+            responses.pop_front();
+            encoded += 1;
+        }
+        wb_unshrink!(old_wb, new_wb);
+        old_tw.end_container()?; // Finish the InvokeResponses
+
+        let chunked = !responses.is_empty();
+        if chunked {
+            old_tw.bool(
+                TagType::Context(msg::InvRespTag::MoreChunkedMsgs as u8),
+                true,
+            )?;
+        }
+        Ok(chunked)
+    }
+
+    /// Send the next chunk of the InvokeResponses, once the peer acknowledges the previous
+    fn handle_resume_invoke(
+        responses: &mut VecDeque<Vec<u8>>,
+        status: IMStatusCode,
+        trans: &mut Transaction,
+        tw: &mut TLVWriter,
+    ) -> Result<(OpCode, ResponseRequired), Error> {
+        if status != IMStatusCode::Success {
+            error!("Chunked invoke aborted with status {:?}", status);
+            trans.complete();
+            return Ok((OpCode::Reserved, ResponseRequired::No));
+        }
+        tw.start_struct(TagType::Anonymous)?;
+        tw.bool(
+            TagType::Context(msg::InvRespTag::SupressResponse as u8),
+            false,
+        )?;
+        if !Self::encode_invoke_responses(responses, tw)? {
+            trans.complete();
+        }
+        tw.end_container()?;
+        Ok((OpCode::InvokeResponse, ResponseRequired::Yes))
+    }
 }
 
 pub mod read;
@@ -231,6 +417,10 @@ pub mod subscribe;
 enum ResumeReq {
     Subscribe(subscribe::SubsCtx),
     Read(read::ResumeReadReq),
+    // A chunked write request, with the list that was last replaced in it
+    Write(Option<GenericPath>),
+    // The encoded InvokeResponse IBs that didn't fit in the previous chunks
+    Invoke(VecDeque<Vec<u8>>),
 }
 
 impl objects::ChangeConsumer for DataModel {
@@ -248,14 +438,28 @@ impl InteractionConsumer for DataModel {
         tw: &mut TLVWriter,
     ) -> Result<(), Error> {
         let accessor = self.sess_to_accessor(trans.session);
+        // The appends in this chunk may continue a list replaced in the previous chunk
+        let mut cur_list = match trans.exch.take_data_boxed::<ResumeReq>().map(|r| *r) {
+            Some(ResumeReq::Write(cur_list)) => cur_list,
+            Some(_) => {
+                error!("Exchange data already set, and write request");
+                return Err(Error::InvalidState);
+            }
+            None => None,
+        };
 
         tw.start_array(TagType::Context(msg::WriteRespTag::WriteResponses as u8))?;
         let mut node = self.node.write().unwrap();
         for attr_data in write_req.write_requests.iter() {
-            DataModel::handle_write_attr_path(&mut node, &accessor, &attr_data, tw);
+            DataModel::handle_write_attr_path(&mut node, &accessor, &attr_data, tw, &mut cur_list);
         }
         tw.end_container()?;
 
+        if write_req.more_chunked == Some(true) {
+            trans
+                .exch
+                .set_data_boxed(Box::new(ResumeReq::Write(cur_list)));
+        }
         Ok(())
     }
 
@@ -268,10 +472,12 @@ impl InteractionConsumer for DataModel {
         let mut resume_from = None;
         let root = tlv::get_root_node(rx_buf)?;
         let req = ReadReq::from_tlv(&root)?;
-        self.handle_read_req(&req, trans, tw, &mut resume_from)?;
-        if resume_from.is_some() {
+        let mut events = read::EventRange::new(&req, &self.changes);
+        self.handle_read_req(&req, trans, tw, &mut resume_from, &mut events)?;
+        if resume_from.is_some() || !events.is_done() {
             // This is a multi-hop read transaction, remember this read request
-            let resume = read::ResumeReadReq::new(rx_buf, &resume_from)?;
+            let resume =
+                read::ResumeReadReq::new(&self.packet_pool, rx_buf, &resume_from, &events)?;
             if !trans.exch.is_data_none() {
                 error!("Exchange data already set, and multi-hop read");
                 return Err(Error::InvalidState);
@@ -289,8 +495,9 @@ impl InteractionConsumer for DataModel {
     ) -> Result<(), Error> {
         let mut node = self.node.write().unwrap();
         if let Some(inv_requests) = &inv_req_msg.inv_requests {
-            // Array of InvokeResponse IBs
-            tw.start_array(TagType::Context(msg::InvRespTag::InvokeResponses as u8))?;
+            // The responses of each command are encoded separately, so that they can be
+            // spread over multiple chunks, if they don't fit in one
+            let mut responses = VecDeque::new();
             for i in inv_requests.iter() {
                 let data = if let Some(data) = i.data.unwrap_tlv() {
                     data
@@ -298,15 +505,34 @@ impl InteractionConsumer for DataModel {
                     continue;
                 };
                 info!("Invoke Commmand Handler executing: {:?}", i.path);
+                let mut buf = [0u8; MAX_RX_BUF_SIZE];
+                let mut wb = WriteBuf::new(&mut buf, MAX_RX_BUF_SIZE);
+                let mut resp = TLVWriter::new(&mut wb);
                 let mut cmd_req = CommandReq {
                     cmd: i.path,
+                    command_ref: i.command_ref,
                     data,
                     trans,
-                    resp: tw,
+                    resp: &mut resp,
                 };
                 DataModel::handle_command_path(&mut node, &mut cmd_req);
+                if !wb.as_borrow_slice().is_empty() {
+                    responses.push_back(wb.as_borrow_slice().to_vec());
+                }
+            }
+            self.commissioning_changed(&mut node);
+            drop(node);
+
+            if Self::encode_invoke_responses(&mut responses, tw)? {
+                // This is a multi-hop invoke transaction, remember the remaining responses
+                if !trans.exch.is_data_none() {
+                    error!("Exchange data already set, and multi-hop invoke");
+                    return Err(Error::InvalidState);
+                }
+                trans
+                    .exch
+                    .set_data_boxed(Box::new(ResumeReq::Invoke(responses)));
             }
-            tw.end_container()?;
         }
 
         Ok(())
@@ -322,7 +548,18 @@ impl InteractionConsumer for DataModel {
             let result = match *resume {
                 ResumeReq::Read(ref mut read) => self.handle_resume_read(read, trans, tw)?,
 
-                ResumeReq::Subscribe(ref mut ctx) => ctx.handle_status_report(trans, tw, self)?,
+                ResumeReq::Subscribe(ref mut ctx) => {
+                    ctx.handle_status_report(req.status, trans, tw, self)?
+                }
+                ResumeReq::Invoke(ref mut responses) => {
+                    Self::handle_resume_invoke(responses, req.status, trans, tw)?
+                }
+                ResumeReq::Write(_) => {
+                    // The peer gave up on the rest of the write request
+                    trans.complete();
+                    info!("Received status report with status {:?}", req.status);
+                    (OpCode::Reserved, ResponseRequired::No)
+                }
             };
             trans.exch.set_data_boxed(resume);
             Ok(result)
@@ -344,12 +581,56 @@ impl InteractionConsumer for DataModel {
             error!("Exchange data already set!");
             return Err(Error::InvalidState);
         }
+        if rx_buf.len() > MAX_SUBS_REQ_SIZE {
+            // The subscription couldn't be persisted
+            error!("Subscribe request too large, rejecting it");
+            let status = msg::StatusResp {
+                status: IMStatusCode::ResourceExhausted,
+            };
+            status.to_tlv(tw, TagType::Anonymous)?;
+            trans.complete();
+            return Ok((OpCode::StatusResponse, ResponseRequired::Yes));
+        }
         let ctx = SubsCtx::new(rx_buf, trans, tw, self)?;
         trans
             .exch
             .set_data_boxed(Box::new(ResumeReq::Subscribe(ctx)));
         Ok((OpCode::ReportData, ResponseRequired::Yes))
     }
+
+    fn consume_subs_report(
+        &self,
+        subs_id: u32,
+        trans: &mut Transaction,
+        tw: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        self.handle_subs_report(subs_id, trans, tw)
+    }
+
+    fn get_next_timeout(&self) -> Option<SystemTime> {
+        let subs_timeout = self.subs_mgr.lock().ok().and_then(|subs_mgr| {
+            subs_mgr.get_next_timeout(self.changes.get_gen(), self.changes.get_next_event_number())
+        });
+        let window_timeout = self.pase_mgr.get_comm_window().and_then(|w| w.expiry);
+        subs_timeout
+            .into_iter()
+            .chain(self.failsafe.get_expiry())
+            .chain(window_timeout)
+            .min()
+    }
+
+    fn handle_timeout(&self) -> Result<(), Error> {
+        self.failsafe.handle_timeout();
+        self.handle_comm_window_timeout()?;
+        self.handle_subs_timeout()
+    }
+
+    fn handle_session_closed(&self, local_sess_id: u16) {
+        self.failsafe.session_closed(local_sess_id);
+        if let Ok(mut node) = self.node.write() {
+            self.commissioning_changed(&mut node);
+        }
+    }
 }
 
 /// Encoder for generating a response to a write request
@@ -392,3 +673,45 @@ impl<'a, 'b, 'c> Encoder for AttrWriteEncoder<'a, 'b, 'c> {
         let _ = resp.to_tlv(self.tw, self.tag);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{interaction_model::messages::msg::InvResp, tlv::OctetStr};
+
+    #[test]
+    fn test_invoke_resp_chunks() {
+        // Each response takes more than a third of the buffer
+        let mut responses: VecDeque<Vec<u8>> = (0..3u8)
+            .map(|i| {
+                let bytes = [i; 150];
+                let data = OctetStr::new(&bytes);
+                let mut buf = [0u8; 200];
+                let mut wb = WriteBuf::new(&mut buf, 200);
+                let mut tw = TLVWriter::new(&mut wb);
+                let resp = ib::InvResp::cmd_new(0, 0x30, 1, EncodeValue::Value(&data), None);
+                resp.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+                wb.as_borrow_slice().to_vec()
+            })
+            .collect();
+
+        let chunk = |responses: &mut VecDeque<Vec<u8>>| {
+            let mut buf = [0u8; 400];
+            let mut wb = WriteBuf::new(&mut buf, 400);
+            let mut tw = TLVWriter::new(&mut wb);
+            tw.start_struct(TagType::Anonymous).unwrap();
+            let chunked = DataModel::encode_invoke_responses(responses, &mut tw).unwrap();
+            tw.end_container().unwrap();
+
+            let root = tlv::get_root_node_struct(wb.as_borrow_slice()).unwrap();
+            let resp = InvResp::from_tlv(&root).unwrap();
+            assert_eq!(resp.more_chunks, chunked.then_some(true));
+            resp.inv_responses.unwrap().iter().count()
+        };
+
+        assert_eq!(chunk(&mut responses), 2);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(chunk(&mut responses), 1);
+        assert!(responses.is_empty());
+    }
+}
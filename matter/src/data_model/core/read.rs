@@ -22,14 +22,17 @@ use crate::{
     interaction_model::{
         core::{IMStatusCode, OpCode},
         messages::{
-            ib::{self, DataVersionFilter},
+            ib::{self, DataVersionFilter, EventDataTag, EventPath},
             msg::{self, ReadReq, ReportDataTag::MoreChunkedMsgs, ReportDataTag::SupressResponse},
             GenericPath,
         },
         Transaction,
     },
     tlv::{self, FromTLV, TLVArray, TLVWriter, TagType, ToTLV},
-    transport::{packet::Packet, proto_demux::ResponseRequired},
+    transport::{
+        packet::{Packet, PacketPool},
+        proto_demux::ResponseRequired,
+    },
     utils::writebuf::WriteBuf,
     wb_shrink, wb_unshrink,
 };
@@ -42,6 +45,7 @@ pub struct AttrReadEncoder<'a, 'b, 'c> {
     path: GenericPath,
     skip_error: bool,
     data_ver_filters: Option<&'a TLVArray<'a, DataVersionFilter>>,
+    changed_since: Option<u64>,
     is_buffer_full: bool,
 }
 
@@ -53,6 +57,7 @@ impl<'a, 'b, 'c> AttrReadEncoder<'a, 'b, 'c> {
             skip_error: false,
             path: Default::default(),
             data_ver_filters: None,
+            changed_since: None,
             is_buffer_full: false,
         }
     }
@@ -69,6 +74,11 @@ impl<'a, 'b, 'c> AttrReadEncoder<'a, 'b, 'c> {
         self.data_ver_filters = Some(filters);
     }
 
+    /// Only encode the attributes that changed after this generation of the data model
+    pub fn set_changed_since(&mut self, gen: u64) {
+        self.changed_since = Some(gen);
+    }
+
     pub fn set_path(&mut self, path: GenericPath) {
         self.path = path;
     }
@@ -102,6 +112,32 @@ impl<'a, 'b, 'c> Encoder for AttrReadEncoder<'a, 'b, 'c> {
     }
 }
 
+/// The events that a read, or a report on a subscription, covers
+#[derive(Default, Clone, Copy)]
+pub struct EventRange {
+    /// The event number from which the events are yet to be encoded
+    pub from: u64,
+    /// The events from this event number onwards, are left for a later report
+    pub till: u64,
+    /// Whether the errors on the concrete event paths are yet to be encoded
+    pub with_errors: bool,
+}
+
+impl EventRange {
+    /// The events of interest to the read request, that are logged so far
+    pub fn new(read_req: &ReadReq, changes: &ChangeLog) -> Self {
+        Self {
+            from: read_req.event_min(),
+            till: changes.get_next_event_number(),
+            with_errors: true,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.from >= self.till
+    }
+}
+
 /// State to maintain when a Read Request needs to be resumed
 /// resumed - the next chunk of the read needs to be returned
 #[derive(Default)]
@@ -115,10 +151,18 @@ pub struct ResumeReadReq {
     /// Note that given wildcard reads, one PendingPath in the member above can generated
     /// multiple encode paths. Hence this has to be maintained separately.
     pub resume_from: Option<GenericPath>,
+
+    /// The events that are yet to be encoded, these follow the attributes
+    pub events: EventRange,
 }
 impl ResumeReadReq {
-    pub fn new(rx_buf: &[u8], resume_from: &Option<GenericPath>) -> Result<Self, Error> {
-        let mut packet = Packet::new_rx()?;
+    pub fn new(
+        pool: &PacketPool,
+        rx_buf: &[u8],
+        resume_from: &Option<GenericPath>,
+        events: &EventRange,
+    ) -> Result<Self, Error> {
+        let mut packet = Packet::new_rx_with(pool, rx_buf.len())?;
         let dst = packet.as_borrow_slice();
 
         let src_len = rx_buf.len();
@@ -127,6 +171,7 @@ impl ResumeReadReq {
         Ok(ResumeReadReq {
             pending_req: Some(packet),
             resume_from: *resume_from,
+            events: *events,
         })
     }
 }
@@ -170,6 +215,14 @@ impl DataModel {
                 return Ok(());
             }
 
+            attr_details.attr_id = path.leaf.unwrap_or_default() as u16;
+            // For a report on a subscription, only what changed is of interest
+            if let Some(gen) = attr_encoder.changed_since {
+                if !c.base().is_changed_since(attr_details.attr_id, gen) {
+                    return Ok(());
+                }
+            }
+
             // The resume_from indicates that this is the next chunk of a previous Read Request. In such cases, we
             // need to skip until we hit this path.
             if let Some(r) = resume_from {
@@ -182,7 +235,6 @@ impl DataModel {
                 }
             }
 
-            attr_details.attr_id = path.leaf.unwrap_or_default() as u16;
             // Overwrite the previous path with the concrete path
             attr_encoder.set_path(*path);
             // Set the cluster's data version
@@ -197,8 +249,11 @@ impl DataModel {
             Ok(())
         });
         if let Err(e) = result {
-            // We hit this only if this is a non-wildcard path
-            attr_encoder.encode_status(e, 0);
+            // We hit this only if this is a non-wildcard path. The subscriber already knows
+            // about such errors from the priming report.
+            if attr_encoder.changed_since.is_none() {
+                attr_encoder.encode_status(e, 0);
+            }
         }
         status
     }
@@ -206,13 +261,15 @@ impl DataModel {
     /// Process an array of Attribute Read Requests
     ///
     /// When the API returns the chunked read is on, if *resume_from is Some(x) otherwise
-    /// the read is complete
+    /// the read is complete. If changed_since is set, only the attributes that changed
+    /// after that generation of the data model are encoded.
     pub(super) fn handle_read_attr_array(
         &self,
         read_req: &ReadReq,
         trans: &mut Transaction,
         old_tw: &mut TLVWriter,
         resume_from: &mut Option<GenericPath>,
+        changed_since: Option<u64>,
     ) -> Result<(), Error> {
         let old_wb = old_tw.get_buf();
         // Note, this function may be called from multiple places: a) an actual read
@@ -229,6 +286,9 @@ impl DataModel {
         if let Some(filters) = &read_req.dataver_filters {
             attr_encoder.set_data_ver_filters(filters);
         }
+        if let Some(gen) = changed_since {
+            attr_encoder.set_changed_since(gen);
+        }
 
         if let Some(attr_requests) = &read_req.attr_requests {
             let accessor = self.sess_to_accessor(trans.session);
@@ -271,24 +331,187 @@ impl DataModel {
         Ok(())
     }
 
+    // Whether the event path covers the event
+    pub(super) fn event_path_matches(path: &EventPath, event: &EventRecord) -> bool {
+        (path.endpoint.is_none() || path.endpoint == Some(event.endpoint))
+            && (path.cluster.is_none() || path.cluster == Some(event.cluster))
+            && (path.event.is_none() || path.event == Some(event.event))
+    }
+
+    fn event_access_allowed(accessor: &Accessor, path: &GenericPath, event: &Event) -> bool {
+        let mut access_req = AccessReq::new(accessor, path, Access::READ);
+        access_req.set_target_perms(event.access);
+        access_req.allow()
+    }
+
+    // Whether the accessor may read the logged event
+    fn event_allowed(node: &Node, accessor: &Accessor, record: &EventRecord) -> bool {
+        let event = match node
+            .get_cluster(record.endpoint, record.cluster)
+            .ok()
+            .and_then(|c| c.base().get_event(record.event))
+        {
+            Some(event) => event,
+            None => return false,
+        };
+        // Fabric-sensitive events are only for their own fabric
+        if event.access.contains(Access::FAB_SENSITIVE) && record.fab_idx != accessor.fab_idx {
+            return false;
+        }
+        let path = GenericPath::new(
+            Some(record.endpoint),
+            Some(record.cluster),
+            Some(record.event),
+        );
+        Self::event_access_allowed(accessor, &path, event)
+    }
+
+    // The error, if any, on a concrete event path. The wildcard paths don't have errors.
+    fn event_path_error(
+        node: &Node,
+        accessor: &Accessor,
+        path: &EventPath,
+    ) -> Option<IMStatusCode> {
+        let path = path.to_gp();
+        let (endpoint, cluster, event) = path.not_wildcard().ok()?;
+        let cluster = match node.get_endpoint(endpoint) {
+            Ok(e) => match e.get_cluster(cluster) {
+                Ok(c) => c,
+                Err(_) => return Some(IMStatusCode::UnsupportedCluster),
+            },
+            Err(_) => return Some(IMStatusCode::UnsupportedEndpoint),
+        };
+        match cluster.base().get_event(event) {
+            Some(event) if Self::event_access_allowed(accessor, &path, event) => None,
+            Some(_) => Some(IMStatusCode::UnsupportedAccess),
+            None => Some(IMStatusCode::UnsupportedEvent),
+        }
+    }
+
+    fn encode_event(tw: &mut TLVWriter, record: &EventRecord) -> Result<(), Error> {
+        let path = EventPath {
+            endpoint: Some(record.endpoint),
+            cluster: Some(record.cluster),
+            event: Some(record.event),
+            ..Default::default()
+        };
+        // The EventReportIB, with its EventDataIB
+        tw.start_struct(TagType::Anonymous)?;
+        tw.start_struct(TagType::Context(1))?;
+        path.to_tlv(tw, TagType::Context(EventDataTag::Path as u8))?;
+        tw.u64(
+            TagType::Context(EventDataTag::EventNumber as u8),
+            record.number,
+        )?;
+        tw.u8(
+            TagType::Context(EventDataTag::Priority as u8),
+            record.priority as u8,
+        )?;
+        tw.u64(
+            TagType::Context(EventDataTag::SystemTimestamp as u8),
+            record.timestamp,
+        )?;
+        // This is already encoded with its tag
+        tw.get_buf().append(&record.data)?;
+        tw.end_container()?;
+        tw.end_container()
+    }
+
+    /// Process an array of Event Read Requests
+    ///
+    /// The events in the range are encoded, and the range is left with the events that
+    /// didn't fit in this chunk. If the range isn't done, the chunked read is on.
+    pub(super) fn handle_read_event_array(
+        &self,
+        read_req: &ReadReq,
+        trans: &mut Transaction,
+        old_tw: &mut TLVWriter,
+        events: &mut EventRange,
+    ) -> Result<(), Error> {
+        let event_requests = if let Some(event_requests) = &read_req.event_requests {
+            event_requests
+        } else {
+            events.from = events.till;
+            return Ok(());
+        };
+
+        let old_wb = old_tw.get_buf();
+        // This is the amount of space we reserve for other things to be attached towards
+        // the end, as in handle_read_attr_array()
+        const RESERVE_SIZE: usize = 24;
+        let mut new_wb = wb_shrink!(old_wb, RESERVE_SIZE);
+        let mut tw = TLVWriter::new(&mut new_wb);
+        tw.start_array(TagType::Context(msg::ReportDataTag::EventReports as u8))?;
+
+        let accessor = self.sess_to_accessor(trans.session);
+        let node = self.node.read().unwrap();
+        if events.with_errors {
+            for path in event_requests.iter() {
+                if let Some(status) = Self::event_path_error(&node, &accessor, &path) {
+                    let resp = ib::EventResp::Status(ib::EventStatus::new(&path.to_gp(), status));
+                    resp.to_tlv(&mut tw, TagType::Anonymous)?;
+                }
+            }
+            events.with_errors = false;
+        }
+
+        let mut chunked = false;
+        let result = self
+            .changes
+            .for_each_event(events.from, events.till, |record| {
+                if !event_requests
+                    .iter()
+                    .any(|p| Self::event_path_matches(&p, record))
+                    || !Self::event_allowed(&node, &accessor, record)
+                {
+                    return Ok(());
+                }
+                let anchor = tw.get_tail();
+                if Self::encode_event(&mut tw, record).is_err() {
+                    // Buffer is full, the next chunk starts from this event
+                    tw.rewind_to(anchor);
+                    chunked = true;
+                    return Err(Error::NoSpace);
+                }
+                events.from = record.number + 1;
+                Ok(())
+            });
+        if !chunked {
+            result?;
+            events.from = events.till;
+        }
+
+        wb_unshrink!(old_wb, new_wb);
+        old_tw.end_container()?; // Finish the EventReports
+        if chunked {
+            old_tw.bool(TagType::Context(MoreChunkedMsgs as u8), true)?;
+        }
+        Ok(())
+    }
+
     /// Handle a read request
     ///
     /// This could be called from an actual read request or a resumed read request. Subscription
     /// requests do not come to this function.
-    /// When the API returns the chunked read is on, if *resume_from is Some(x) otherwise
-    /// the read is complete
+    /// When the API returns the chunked read is on, if *resume_from is Some(x) or the events
+    /// aren't done, otherwise the read is complete
     pub fn handle_read_req(
         &self,
         read_req: &ReadReq,
         trans: &mut Transaction,
         tw: &mut TLVWriter,
         resume_from: &mut Option<GenericPath>,
+        events: &mut EventRange,
     ) -> Result<(OpCode, ResponseRequired), Error> {
         tw.start_struct(TagType::Anonymous)?;
 
-        self.handle_read_attr_array(read_req, trans, tw, resume_from)?;
-
+        self.handle_read_attr_array(read_req, trans, tw, resume_from, None)?;
+        // The events follow, once all the attributes are done
         if resume_from.is_none() {
+            self.handle_read_event_array(read_req, trans, tw, events)?;
+        }
+
+        if resume_from.is_none() && events.is_done() {
             tw.bool(TagType::Context(SupressResponse as u8), true)?;
             // Mark transaction complete, if not chunked
             trans.complete();
@@ -309,7 +532,13 @@ impl DataModel {
             let root = tlv::get_root_node(rx_buf)?;
             let req = ReadReq::from_tlv(&root)?;
 
-            self.handle_read_req(&req, trans, tw, &mut resume_read_req.resume_from)
+            self.handle_read_req(
+                &req,
+                trans,
+                tw,
+                &mut resume_read_req.resume_from,
+                &mut resume_read_req.events,
+            )
         } else {
             // No pending req, is that even possible?
             error!("This shouldn't have happened");
@@ -15,35 +15,354 @@
  *    limitations under the License.
  */
 
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    convert::TryFrom,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use log::{error, info};
+use smol::{future::FutureExt, Timer};
 
 use crate::{
+    data_model::objects::Node,
     error::Error,
     interaction_model::{
-        core::OpCode,
+        core::{IMStatusCode, OpCode, PROTO_ID_INTERACTION_MODEL},
         messages::{
             msg::{self, SubscribeReq, SubscribeResp},
             GenericPath,
         },
+        SubsReportReq,
+    },
+    secure_channel::case,
+    sys::Psm,
+    tlv::{
+        get_root_node, get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType,
+        ToTLV,
     },
-    tlv::{self, get_root_node_struct, FromTLV, TLVWriter, TagType, ToTLV},
-    transport::proto_demux::ResponseRequired,
+    transport::{
+        exchange::SessionTarget,
+        network::Address,
+        proto_demux::{InitiateReq, ResponseRequired},
+        queue::{Msg, WorkQ},
+    },
+    utils::writebuf::WriteBuf,
 };
 
-use super::{read::ResumeReadReq, DataModel, Transaction};
+use super::{read::EventRange, DataModel, ResumeReq, Transaction};
+
+/// The maximum number of subscriptions that we serve, beyond which the oldest one is
+/// dropped
+pub const MAX_SUBSCRIPTIONS: usize = 6;
+
+// A report that the subscriber doesn't acknowledge in this time, ends the subscription
+const REPORT_ACK_TIMEOUT: Duration = Duration::from_secs(30);
 
-static SUBS_ID: AtomicU32 = AtomicU32::new(1);
+// A subscription that can't be resumed in this time, is dropped
+const RESUME_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Each subscription is persisted in an entry of its own, with this prefix
+const SUBS_KV_ENTRY: &str = "subs_";
+const SUBS_KV_NEXT_ID: &str = "subs_next_id";
+const SUBS_KV_MAX_SIZE: usize = 2048;
+
+/// The largest SubscribeRequest that we accept, so that the subscription fits in its
+/// storage entry, along with the rest of what is persisted about it
+pub const MAX_SUBS_REQ_SIZE: usize = SUBS_KV_MAX_SIZE - 128;
+
+fn subs_kv_entry(slot: usize) -> String {
+    format!("{}{}", SUBS_KV_ENTRY, slot)
+}
+
+// A subscription that we serve
+struct Subscription {
+    id: u32,
+    // The session on which the reports are sent, this is None while the session to
+    // the subscriber is being re-opened
+    sess_id: Option<u16>,
+    peer_node_id: Option<u64>,
+    fab_idx: u8,
+    // Where the subscriber was, when it subscribed
+    peer_addr: Address,
+    // The SubscribeRequest, the paths in it are what we report on
+    req: Vec<u8>,
+    min_int: Duration,
+    max_int: Duration,
+    // The data model generation that the last report covered, this is None if the next
+    // report has to be a full one
+    reported_gen: Option<u64>,
+    // The data model generation at which there was last found nothing to report
+    checked_gen: u64,
+    // The events from this event number onwards are yet to be reported
+    event_min: u64,
+    // The event number at which there was last found nothing to report
+    checked_event: u64,
+    last_report: SystemTime,
+    // When the report in progress was sent, till the subscriber acknowledges it
+    reporting: Option<SystemTime>,
+}
+
+// How a subscription is persisted
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct SubsStore<'a> {
+    id: u32,
+    peer_node_id: u64,
+    fab_idx: u8,
+    peer_ip: OctetStr<'a>,
+    peer_port: u16,
+    tcp: bool,
+    min_int: u16,
+    max_int: u16,
+    req: OctetStr<'a>,
+    // The scope of a link-local IPv6 address
+    peer_scope_id: Option<u32>,
+}
+
+impl Subscription {
+    fn next_timeout(&self, gen: u64, event: u64) -> Option<SystemTime> {
+        // Nothing to do, till the session to the subscriber is in place
+        self.sess_id?;
+        let timeout = if let Some(t) = self.reporting {
+            t + REPORT_ACK_TIMEOUT
+        } else if self.reported_gen.is_none()
+            || gen > self.checked_gen
+            || event > self.checked_event
+        {
+            // Something changed, a report may be due
+            self.last_report + self.min_int
+        } else {
+            // The keep-alive report, if nothing changes till then
+            self.last_report + self.max_int
+        };
+        Some(timeout)
+    }
+
+    fn store(&self, tw: &mut TLVWriter) -> Result<(), Error> {
+        let peer_node_id = self.peer_node_id.ok_or(Error::Invalid)?;
+        let (tcp, addr) = match self.peer_addr {
+            Address::Udp(a) => (false, a),
+            Address::Tcp(a) => (true, a),
+        };
+        let (ip, scope_id) = match addr {
+            SocketAddr::V4(a) => (a.ip().octets().to_vec(), None),
+            SocketAddr::V6(a) => (
+                a.ip().octets().to_vec(),
+                Some(a.scope_id()).filter(|id| *id != 0),
+            ),
+        };
+        SubsStore {
+            id: self.id,
+            peer_node_id,
+            fab_idx: self.fab_idx,
+            peer_ip: OctetStr::new(&ip),
+            peer_port: addr.port(),
+            tcp,
+            min_int: self.min_int.as_secs() as u16,
+            max_int: self.max_int.as_secs() as u16,
+            req: OctetStr::new(&self.req),
+            peer_scope_id: scope_id,
+        }
+        .to_tlv(tw, TagType::Anonymous)
+    }
+
+    fn load(t: &TLVElement) -> Result<Self, Error> {
+        let s = SubsStore::from_tlv(t)?;
+        let addr = if let Ok(ip) = <[u8; 4]>::try_from(s.peer_ip.0) {
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), s.peer_port)
+        } else {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(s.peer_ip.0)?);
+            let scope_id = s.peer_scope_id.unwrap_or_default();
+            SocketAddr::V6(SocketAddrV6::new(ip, s.peer_port, 0, scope_id))
+        };
+        Ok(Self {
+            id: s.id,
+            sess_id: None,
+            peer_node_id: Some(s.peer_node_id),
+            fab_idx: s.fab_idx,
+            peer_addr: if s.tcp {
+                Address::Tcp(addr)
+            } else {
+                Address::Udp(addr)
+            },
+            req: s.req.0.to_vec(),
+            min_int: Duration::from_secs(s.min_int as u64),
+            max_int: Duration::from_secs(s.max_int as u64),
+            reported_gen: None,
+            checked_gen: 0,
+            event_min: 0,
+            checked_event: 0,
+            last_report: SystemTime::UNIX_EPOCH,
+            reporting: None,
+        })
+    }
+}
+
+/// The subscriptions that we serve
+pub struct SubsMgr {
+    subs: Vec<Subscription>,
+    next_id: u32,
+    // The subscriptions are persisted only once this is set, see [DataModel::resume_subscriptions]
+    psm: Option<Arc<Mutex<Psm>>>,
+}
+
+impl Default for SubsMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SubsMgr {
+    pub fn new() -> Self {
+        Self {
+            subs: Vec::new(),
+            next_id: 1,
+            psm: None,
+        }
+    }
+
+    fn store(&self) {
+        if let Some(psm) = &self.psm {
+            let result = psm
+                .lock()
+                .map_err(Error::from)
+                .and_then(|psm| self.store_to(&psm));
+            if let Err(e) = result {
+                error!("Error in storing subscriptions {:?}", e);
+            }
+        }
+    }
+
+    // A subscription that can't be stored, doesn't keep the others from being stored
+    fn store_to(&self, psm: &MutexGuard<Psm>) -> Result<(), Error> {
+        let mut buf = vec![0u8; SUBS_KV_MAX_SIZE];
+        let mut slot = 0;
+        // Without a node ID, the subscriber can't be reached after a reboot
+        for subs in self.subs.iter().filter(|s| s.peer_node_id.is_some()) {
+            let mut wb = WriteBuf::new(&mut buf, SUBS_KV_MAX_SIZE);
+            let mut tw = TLVWriter::new(&mut wb);
+            let result = subs
+                .store(&mut tw)
+                .and_then(|_| psm.set_kv_slice(&subs_kv_entry(slot), wb.as_slice()));
+            match result {
+                Ok(()) => slot += 1,
+                Err(e) => error!("Error in storing subscription {}: {:?}", subs.id, e),
+            }
+        }
+        for slot in slot..MAX_SUBSCRIPTIONS {
+            psm.rm(&subs_kv_entry(slot));
+        }
+        psm.set_kv_u64(SUBS_KV_NEXT_ID, self.next_id as u64)
+    }
+
+    // Load the subscriptions that were persisted before, and persist them from now on
+    fn load(&mut self, psm: Arc<Mutex<Psm>>) -> Result<(), Error> {
+        {
+            let psm = psm.lock()?;
+            for slot in 0..MAX_SUBSCRIPTIONS {
+                let mut buf = Vec::new();
+                if psm.get_kv_slice(&subs_kv_entry(slot), &mut buf).is_err() {
+                    continue;
+                }
+                // The subscriptions that can't be decoded are dropped, the rest are
+                // still resumed
+                match get_root_node(&buf).and_then(|t| Subscription::load(&t)) {
+                    Ok(subs) => self.subs.push(subs),
+                    Err(e) => error!("Dropping a persisted subscription: {:?}", e),
+                }
+            }
+            let mut next_id = 0;
+            if psm.get_kv_u64(SUBS_KV_NEXT_ID, &mut next_id).is_ok() {
+                self.next_id = next_id as u32;
+            }
+        }
+        // Make sure that the IDs that are in use, aren't handed out again
+        if let Some(max_id) = self.subs.iter().map(|s| s.id).max() {
+            self.next_id = self.next_id.max(max_id.wrapping_add(1)).max(1);
+        }
+        self.psm = Some(psm);
+        Ok(())
+    }
+
+    fn new_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        id
+    }
+
+    fn add(&mut self, subs: Subscription) {
+        if self.subs.len() >= MAX_SUBSCRIPTIONS {
+            let oldest = self.subs.remove(0);
+            info!("Too many subscriptions, dropping {}", oldest.id);
+        }
+        self.subs.push(subs);
+        self.store();
+    }
+
+    fn get_mut(&mut self, id: u32) -> Option<&mut Subscription> {
+        self.subs.iter_mut().find(|s| s.id == id)
+    }
+
+    fn remove(&mut self, id: u32) {
+        self.retain(|s| s.id != id);
+    }
+
+    // Drop all the subscriptions of this subscriber
+    fn remove_subscriber(&mut self, peer_node_id: Option<u64>, fab_idx: u8) {
+        self.retain(|s| s.peer_node_id != peer_node_id || s.fab_idx != fab_idx);
+    }
+
+    /// Drop all the subscriptions on this fabric
+    pub fn remove_fabric(&mut self, fab_idx: u8) {
+        self.retain(|s| s.fab_idx != fab_idx);
+    }
+
+    fn retain<F: FnMut(&Subscription) -> bool>(&mut self, f: F) {
+        let count = self.subs.len();
+        self.subs.retain(f);
+        if self.subs.len() != count {
+            self.store();
+        }
+    }
+
+    /// The number of subscriptions that we serve
+    pub fn count(&self) -> usize {
+        self.subs.len()
+    }
+
+    /// The next time at which a report is due, or a report in progress times out
+    ///
+    /// The data model is at the generation gen, and its next event gets the number event.
+    pub fn get_next_timeout(&self, gen: u64, event: u64) -> Option<SystemTime> {
+        self.subs
+            .iter()
+            .filter_map(|s| s.next_timeout(gen, event))
+            .min()
+    }
+}
 
 #[derive(PartialEq)]
 enum SubsState {
     Confirming,
     Confirmed,
+    // A report on a confirmed subscription
+    Reporting,
 }
 
 pub struct SubsCtx {
     state: SubsState,
     id: u32,
-    resume_read_req: Option<ResumeReadReq>,
+    // The SubscribeRequest, and where to resume it from for the next chunk
+    req: Vec<u8>,
+    resume_from: Option<GenericPath>,
+    // For a report, only the attributes that changed after this generation are reported
+    changed_since: Option<u64>,
+    // The events that are yet to be reported, these follow the attributes
+    events: EventRange,
+    // The data model generation at which this started
+    gen: u64,
 }
 
 impl SubsCtx {
@@ -55,88 +374,521 @@ impl SubsCtx {
     ) -> Result<Self, Error> {
         let root = get_root_node_struct(rx_buf)?;
         let req = SubscribeReq::from_tlv(&root)?;
+        let id = {
+            let mut subs_mgr = dm.subs_mgr.lock()?;
+            if !req.keep_subs {
+                subs_mgr.remove_subscriber(
+                    trans.session.get_peer_node_id(),
+                    trans.session.get_local_fabric_idx().unwrap_or_default(),
+                );
+            }
+            subs_mgr.new_id()
+        };
 
         let mut ctx = SubsCtx {
             state: SubsState::Confirming,
-            // TODO
-            id: SUBS_ID.fetch_add(1, Ordering::SeqCst),
-            resume_read_req: None,
+            id,
+            req: rx_buf.to_vec(),
+            resume_from: None,
+            changed_since: None,
+            events: EventRange::new(&req.to_read_req(), &dm.changes),
+            gen: dm.changes.get_gen(),
         };
+        ctx.do_read(trans, tw, dm)?;
+        Ok(ctx)
+    }
 
-        let mut resume_from = None;
-        ctx.do_read(&req, trans, tw, dm, &mut resume_from)?;
-        if resume_from.is_some() {
-            // This is a multi-hop read transaction, remember this read request
-            ctx.resume_read_req = Some(ResumeReadReq::new(rx_buf, &resume_from)?);
-        }
+    // A report of what changed since the last report on the subscription
+    fn new_report(
+        subs: &Subscription,
+        trans: &mut Transaction,
+        tw: &mut TLVWriter,
+        dm: &DataModel,
+    ) -> Result<Self, Error> {
+        let mut ctx = SubsCtx {
+            state: SubsState::Reporting,
+            id: subs.id,
+            req: subs.req.clone(),
+            resume_from: None,
+            changed_since: subs.reported_gen,
+            events: EventRange {
+                from: subs.event_min,
+                till: dm.changes.get_next_event_number(),
+                with_errors: false,
+            },
+            gen: dm.changes.get_gen(),
+        };
+        ctx.do_read(trans, tw, dm)?;
         Ok(ctx)
     }
 
     pub fn handle_status_report(
         &mut self,
+        status: IMStatusCode,
         trans: &mut Transaction,
         tw: &mut TLVWriter,
         dm: &DataModel,
     ) -> Result<(OpCode, ResponseRequired), Error> {
-        if self.state != SubsState::Confirming {
+        if self.state == SubsState::Confirmed {
             // Not relevant for us
             trans.complete();
             return Err(Error::Invalid);
         }
 
-        // Is there a previous resume read pending
-        if self.resume_read_req.is_some() {
-            let mut resume_read_req = self.resume_read_req.take().unwrap();
-            if let Some(packet) = resume_read_req.pending_req.as_mut() {
-                let rx_buf = packet.get_parsebuf()?.as_borrow_slice();
-                let root = tlv::get_root_node(rx_buf)?;
-                let req = SubscribeReq::from_tlv(&root)?;
+        if status != IMStatusCode::Success {
+            error!("Subscription {} ended with status {:?}", self.id, status);
+            dm.subs_mgr.lock()?.remove(self.id);
+            trans.complete();
+            return Ok((OpCode::Reserved, ResponseRequired::No));
+        }
 
-                self.do_read(&req, trans, tw, dm, &mut resume_read_req.resume_from)?;
-                if resume_read_req.resume_from.is_some() {
-                    // More chunks are pending, setup resume_read_req again
-                    self.resume_read_req = Some(resume_read_req);
+        // Are there more chunks pending
+        if self.resume_from.is_some() || !self.events.is_done() {
+            self.do_read(trans, tw, dm)?;
+            if let Some(subs) = dm.subs_mgr.lock()?.get_mut(self.id) {
+                if subs.reporting.is_some() {
+                    subs.reporting = Some(SystemTime::now());
                 }
+            }
+            return Ok((OpCode::ReportData, ResponseRequired::Yes));
+        }
 
-                return Ok((OpCode::ReportData, ResponseRequired::Yes));
+        if self.state == SubsState::Reporting {
+            // The subscriber acknowledged the whole report
+            if let Some(subs) = dm.subs_mgr.lock()?.get_mut(self.id) {
+                subs.reporting = None;
             }
+            trans.complete();
+            return Ok((OpCode::Reserved, ResponseRequired::No));
         }
 
         // We are here implies that the read is now complete
-        self.confirm_subscription(trans, tw)
+        self.confirm_subscription(trans, tw, dm)
     }
 
     fn confirm_subscription(
         &mut self,
         trans: &mut Transaction,
         tw: &mut TLVWriter,
+        dm: &DataModel,
     ) -> Result<(OpCode, ResponseRequired), Error> {
         self.state = SubsState::Confirmed;
 
-        // TODO
-        let resp = SubscribeResp::new(self.id, 40);
+        let (min_int, max_int) = {
+            let root = get_root_node_struct(&self.req)?;
+            let req = SubscribeReq::from_tlv(&root)?;
+            // We go with the subscriber's ceiling, unless that is below its floor
+            (
+                req.min_int_floor,
+                req.max_int_ceil.max(req.min_int_floor).max(1),
+            )
+        };
+        dm.subs_mgr.lock()?.add(Subscription {
+            id: self.id,
+            sess_id: Some(trans.session.get_local_sess_id()),
+            peer_node_id: trans.session.get_peer_node_id(),
+            fab_idx: trans.session.get_local_fabric_idx().unwrap_or_default(),
+            peer_addr: trans.session.get_peer_addr(),
+            req: std::mem::take(&mut self.req),
+            min_int: Duration::from_secs(min_int as u64),
+            max_int: Duration::from_secs(max_int as u64),
+            reported_gen: Some(self.gen),
+            checked_gen: self.gen,
+            event_min: self.events.till,
+            checked_event: self.events.till,
+            last_report: SystemTime::now(),
+            reporting: None,
+        });
+        info!(
+            "Subscription {} confirmed, intervals {}s to {}s",
+            self.id, min_int, max_int
+        );
+
+        let resp = SubscribeResp::new(self.id, max_int);
         resp.to_tlv(tw, TagType::Anonymous)?;
         trans.complete();
         Ok((OpCode::SubscriptResponse, ResponseRequired::Yes))
     }
 
+    // Encode the next chunk of the report
     fn do_read(
         &mut self,
-        req: &SubscribeReq,
         trans: &mut Transaction,
         tw: &mut TLVWriter,
         dm: &DataModel,
-        resume_from: &mut Option<GenericPath>,
     ) -> Result<(), Error> {
-        let read_req = req.to_read_req();
+        let root = get_root_node_struct(&self.req)?;
+        let req = SubscribeReq::from_tlv(&root)?;
+        let mut read_req = req.to_read_req();
+        if self.state == SubsState::Reporting {
+            // The data version filters are only for the priming report
+            read_req.dataver_filters = None;
+        }
+
         tw.start_struct(TagType::Anonymous)?;
         tw.u32(
             TagType::Context(msg::ReportDataTag::SubscriptionId as u8),
             self.id,
         )?;
-        dm.handle_read_attr_array(&read_req, trans, tw, resume_from)?;
+        dm.handle_read_attr_array(
+            &read_req,
+            trans,
+            tw,
+            &mut self.resume_from,
+            self.changed_since,
+        )?;
+        // The events follow, once all the attributes are done
+        if self.resume_from.is_none() {
+            dm.handle_read_event_array(&read_req, trans, tw, &mut self.events)?;
+        }
         tw.end_container()?;
 
         Ok(())
     }
 }
+
+impl DataModel {
+    /// Persist the subscriptions from now on, and resume the ones that were persisted
+    /// before
+    ///
+    /// The subscriptions are resumed with their old IDs, once a CASE session to each of
+    /// the subscribers is re-opened. Their first report is a full one.
+    pub fn resume_subscriptions(&self, psm: Arc<Mutex<Psm>>) -> Result<(), Error> {
+        let mut subs_mgr = self.subs_mgr.lock()?;
+        subs_mgr.load(psm)?;
+        for subs in subs_mgr.subs.iter() {
+            let (id, fab_idx, peer_addr) = (subs.id, subs.fab_idx, subs.peer_addr);
+            let peer_node_id = subs.peer_node_id.ok_or(Error::Invalid)?;
+            let subs_mgr = self.subs_mgr.clone();
+            let wq = self.wq.clone();
+            smol::spawn(async move {
+                let result =
+                    Self::resume_subscription(subs_mgr, wq, id, fab_idx, peer_node_id, peer_addr)
+                        .await;
+                if let Err(e) = result {
+                    error!("Error in resuming subscription {}: {:?}", id, e);
+                }
+            })
+            .detach();
+        }
+        Ok(())
+    }
+
+    async fn resume_subscription(
+        subs_mgr: Arc<Mutex<SubsMgr>>,
+        wq: WorkQ,
+        id: u32,
+        fab_idx: u8,
+        peer_node_id: u64,
+        peer_addr: Address,
+    ) -> Result<(), Error> {
+        info!("Resuming subscription {} from node {:x}", id, peer_node_id);
+        let session = async {
+            let rx = case::initiate_session(&wq, fab_idx, peer_node_id, peer_addr).await?;
+            rx.recv().await.map_err(|_| Error::NoSession)?
+        };
+        let timeout = async {
+            Timer::after(RESUME_TIMEOUT).await;
+            Err(Error::Timeout)
+        };
+        let result = session.or(timeout).await;
+        let mut subs_mgr = subs_mgr.lock()?;
+        match result {
+            Ok(sess_id) => {
+                if let Some(subs) = subs_mgr.get_mut(id) {
+                    subs.sess_id = Some(sess_id);
+                }
+                drop(subs_mgr);
+                // Wake up the transport, so that the first report goes out
+                wq.data_changed();
+            }
+            Err(e) => {
+                error!("Couldn't resume subscription {}: {:?}", id, e);
+                subs_mgr.remove(id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode a report on a subscription, as the first message of a new exchange
+    pub(super) fn handle_subs_report(
+        &self,
+        subs_id: u32,
+        trans: &mut Transaction,
+        tw: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        let mut subs_mgr = self.subs_mgr.lock()?;
+        let subs = subs_mgr.get_mut(subs_id).ok_or(Error::NotFound)?;
+        // The session may have been replaced by one with another peer
+        if trans.session.get_peer_node_id() != subs.peer_node_id
+            || trans.session.get_local_fabric_idx().unwrap_or_default() != subs.fab_idx
+        {
+            error!("Subscription {} has lost its session", subs_id);
+            return Err(Error::NoSession);
+        }
+
+        let ctx = SubsCtx::new_report(subs, trans, tw, self)?;
+        let now = SystemTime::now();
+        subs.reported_gen = Some(ctx.gen);
+        subs.checked_gen = ctx.gen;
+        subs.event_min = ctx.events.till;
+        subs.checked_event = ctx.events.till;
+        subs.last_report = now;
+        subs.reporting = Some(now);
+        trans
+            .exch
+            .set_data_boxed(Box::new(ResumeReq::Subscribe(ctx)));
+        Ok(())
+    }
+
+    /// Start the reports that are due, and drop the subscriptions whose reports are
+    /// not acknowledged
+    pub(super) fn handle_subs_timeout(&self) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let gen = self.changes.get_gen();
+        let event = self.changes.get_next_event_number();
+        let mut subs_mgr = self.subs_mgr.lock()?;
+        subs_mgr.retain(|s| match s.reporting {
+            Some(t) if t + REPORT_ACK_TIMEOUT <= now => {
+                error!("Report not acknowledged, dropping subscription {}", s.id);
+                false
+            }
+            _ => true,
+        });
+
+        let node = self.node.read()?;
+        for subs in subs_mgr.subs.iter_mut() {
+            let sess_id = match subs.sess_id {
+                Some(sess_id) => sess_id,
+                // Still waiting for the session to the subscriber
+                None => continue,
+            };
+            if subs.reporting.is_some() || subs.next_timeout(gen, event) > Some(now) {
+                continue;
+            }
+            let keep_alive = subs.last_report + subs.max_int <= now;
+            if !keep_alive && !self.is_subs_dirty(&node, subs).unwrap_or(true) {
+                // Nothing that this subscriber is interested in, has changed
+                subs.checked_gen = gen;
+                subs.checked_event = event;
+                continue;
+            }
+
+            let req = SubsReportReq { subs_id: subs.id };
+            let msg = Msg::Initiate(InitiateReq::new(
+                PROTO_ID_INTERACTION_MODEL,
+                SessionTarget::Secured(sess_id),
+                Box::new(req),
+            ));
+            if self.wq.try_send(msg).is_err() {
+                // The queue is full, this is retried once the transport drains it
+                break;
+            }
+            subs.reporting = Some(now);
+        }
+        Ok(())
+    }
+
+    // Whether any of the attributes on the subscription changed, or any of its urgent
+    // events were logged, after its last report
+    fn is_subs_dirty(&self, node: &Node, subs: &Subscription) -> Result<bool, Error> {
+        let reported_gen = match subs.reported_gen {
+            Some(gen) => gen,
+            None => return Ok(true),
+        };
+        let root = get_root_node_struct(&subs.req)?;
+        let req = SubscribeReq::from_tlv(&root)?;
+        let mut dirty = false;
+        if let Some(attr_requests) = &req.attr_requests {
+            for attr_path in attr_requests.iter() {
+                let _ = node.for_each_attribute(&attr_path.to_gp(), |path, c| {
+                    let attr_id = path.leaf.unwrap_or_default() as u16;
+                    dirty |= c.base().is_changed_since(attr_id, reported_gen);
+                    Ok(())
+                });
+            }
+        }
+        if let Some(event_requests) = &req.event_requests {
+            // The other events wait for the next report that is due anyway
+            let urgent: Vec<_> = event_requests
+                .iter()
+                .filter(|p| p.is_urgent == Some(true))
+                .collect();
+            if !urgent.is_empty() {
+                self.changes
+                    .for_each_event(subs.event_min, u64::MAX, |record| {
+                        dirty |= urgent.iter().any(|p| Self::event_path_matches(p, record));
+                        Ok(())
+                    })?;
+            }
+        }
+        Ok(dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_utils::temp_psm;
+
+    fn subscription(min_int: u64, max_int: u64) -> Subscription {
+        Subscription {
+            id: 1,
+            sess_id: Some(1),
+            peer_node_id: Some(10),
+            fab_idx: 1,
+            peer_addr: Address::Udp("[fe80::1]:5540".parse().unwrap()),
+            req: Vec::new(),
+            min_int: Duration::from_secs(min_int),
+            max_int: Duration::from_secs(max_int),
+            reported_gen: Some(5),
+            checked_gen: 5,
+            event_min: 3,
+            checked_event: 3,
+            last_report: SystemTime::UNIX_EPOCH,
+            reporting: None,
+        }
+    }
+
+    #[test]
+    fn test_next_timeout() {
+        let epoch = SystemTime::UNIX_EPOCH;
+        let mut subs = subscription(2, 60);
+        // Nothing changed, only the keep-alive is due
+        assert_eq!(
+            subs.next_timeout(5, 3),
+            Some(epoch + Duration::from_secs(60))
+        );
+        // A change can be reported once the min interval is over
+        assert_eq!(
+            subs.next_timeout(6, 3),
+            Some(epoch + Duration::from_secs(2))
+        );
+        // So can an event, if it is an urgent one
+        assert_eq!(
+            subs.next_timeout(5, 4),
+            Some(epoch + Duration::from_secs(2))
+        );
+        // A full report is due, even if nothing changed
+        subs.reported_gen = None;
+        assert_eq!(
+            subs.next_timeout(5, 3),
+            Some(epoch + Duration::from_secs(2))
+        );
+        // A report in progress must be acknowledged in time
+        subs.reporting = Some(epoch + Duration::from_secs(2));
+        assert_eq!(
+            subs.next_timeout(6, 3),
+            Some(epoch + Duration::from_secs(2) + REPORT_ACK_TIMEOUT)
+        );
+        // Nothing to do without a session
+        subs.sess_id = None;
+        assert_eq!(subs.next_timeout(6, 3), None);
+    }
+
+    #[test]
+    fn test_store_load() {
+        let (psm, _dir) = temp_psm();
+        let mut subs_mgr = SubsMgr::new();
+        subs_mgr.load(psm.clone()).unwrap();
+        let mut subs = subscription(2, 60);
+        subs.req = vec![0x15, 0x18];
+        subs_mgr.add(subs);
+        let mut subs = subscription(1, 10);
+        subs.id = 2;
+        subs.peer_addr = Address::Tcp("192.168.1.2:5540".parse().unwrap());
+        subs_mgr.add(subs);
+        // A link-local address, along with its scope
+        let mut subs = subscription(1, 10);
+        subs.id = 4;
+        let addr = SocketAddrV6::new("fe80::2".parse().unwrap(), 5540, 0, 3);
+        subs.peer_addr = Address::Udp(SocketAddr::V6(addr));
+        subs_mgr.add(subs);
+        // Without a node ID, this can't be resumed
+        let mut subs = subscription(1, 10);
+        subs.id = 3;
+        subs.peer_node_id = None;
+        subs_mgr.add(subs);
+
+        let mut loaded = SubsMgr::new();
+        loaded.load(psm.clone()).unwrap();
+        assert_eq!(loaded.count(), 3);
+        assert_eq!(loaded.next_id, 5);
+        let stored = subs_mgr.subs.iter().filter(|s| s.peer_node_id.is_some());
+        for (l, s) in loaded.subs.iter().zip(stored) {
+            assert_eq!(l.id, s.id);
+            assert_eq!(l.peer_node_id, s.peer_node_id);
+            assert_eq!(l.fab_idx, s.fab_idx);
+            assert_eq!(l.peer_addr, s.peer_addr);
+            assert_eq!(l.req, s.req);
+            assert_eq!(l.min_int, s.min_int);
+            assert_eq!(l.max_int, s.max_int);
+            // Reporting resumes with a full report, once there is a session
+            assert_eq!(l.sess_id, None);
+            assert_eq!(l.reported_gen, None);
+        }
+
+        // The removed subscriptions don't come back
+        subs_mgr.remove(2);
+        let mut loaded = SubsMgr::new();
+        loaded.load(psm.clone()).unwrap();
+        let ids: Vec<u32> = loaded.subs.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [1, 4]);
+
+        // A subscription that can't be decoded doesn't keep the others from loading
+        psm.lock()
+            .unwrap()
+            .set_kv_slice(&subs_kv_entry(0), &[0x15, 0x18])
+            .unwrap();
+        let mut loaded = SubsMgr::new();
+        loaded.load(psm).unwrap();
+        let ids: Vec<u32> = loaded.subs.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [4]);
+    }
+
+    #[test]
+    fn test_store_max_req() {
+        let (psm, _dir) = temp_psm();
+        let mut subs_mgr = SubsMgr::new();
+        subs_mgr.load(psm.clone()).unwrap();
+        // The largest request that we accept, with the largest address
+        for id in 0..MAX_SUBSCRIPTIONS as u32 {
+            let mut subs = subscription(1, u16::MAX as u64);
+            subs.id = u32::MAX - id;
+            subs.peer_node_id = Some(u64::MAX);
+            subs.fab_idx = u8::MAX;
+            let addr = SocketAddrV6::new("fe80::2".parse().unwrap(), 5540, 0, u32::MAX);
+            subs.peer_addr = Address::Tcp(SocketAddr::V6(addr));
+            subs.req = vec![0xAA; MAX_SUBS_REQ_SIZE];
+            subs_mgr.add(subs);
+        }
+
+        let mut loaded = SubsMgr::new();
+        loaded.load(psm).unwrap();
+        assert_eq!(loaded.count(), MAX_SUBSCRIPTIONS);
+        assert!(loaded.subs.iter().all(|s| s.req.len() == MAX_SUBS_REQ_SIZE));
+    }
+
+    #[test]
+    fn test_subs_mgr() {
+        let mut subs_mgr = SubsMgr::new();
+        assert_eq!(subs_mgr.get_next_timeout(0, 0), None);
+        for id in 0..MAX_SUBSCRIPTIONS as u32 + 1 {
+            let mut subs = subscription(1, 10);
+            subs.id = id;
+            subs.fab_idx = (id % 2) as u8;
+            subs_mgr.add(subs);
+        }
+        // The oldest one made way
+        assert_eq!(subs_mgr.count(), MAX_SUBSCRIPTIONS);
+        assert!(subs_mgr.get_mut(0).is_none());
+
+        subs_mgr.remove(1);
+        assert!(subs_mgr.get_mut(1).is_none());
+        subs_mgr.remove_subscriber(Some(10), 0);
+        let ids: Vec<u32> = subs_mgr.subs.iter().map(|s| s.id).collect();
+        assert_eq!(ids, [3, 5]);
+        subs_mgr.remove_fabric(1);
+        assert_eq!(subs_mgr.count(), 0);
+    }
+}
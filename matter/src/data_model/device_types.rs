@@ -18,9 +18,11 @@
 use super::cluster_basic_information::BasicInfoCluster;
 use super::cluster_basic_information::BasicInfoConfig;
 use super::cluster_on_off::OnOffCluster;
+use super::core::subscribe::SubsMgr;
 use super::objects::*;
 use super::sdm::admin_commissioning::AdminCommCluster;
 use super::sdm::dev_att::DevAttDataFetcher;
+use super::sdm::failsafe::FailSafe;
 use super::sdm::general_commissioning::GenCommCluster;
 use super::sdm::noc::NocCluster;
 use super::sdm::nw_commissioning::NwCommCluster;
@@ -29,7 +31,9 @@ use crate::acl::AclMgr;
 use crate::error::*;
 use crate::fabric::FabricMgr;
 use crate::secure_channel::pake::PaseMgr;
+use crate::transport::queue::WorkQ;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLockWriteGuard;
 
 pub const DEV_TYPE_ROOT_NODE: DeviceType = DeviceType {
@@ -39,13 +43,18 @@ pub const DEV_TYPE_ROOT_NODE: DeviceType = DeviceType {
 
 type WriteNode<'a> = RwLockWriteGuard<'a, Box<Node>>;
 
+#[allow(clippy::too_many_arguments)]
 pub fn device_type_add_root_node(
     node: &mut WriteNode,
     dev_info: BasicInfoConfig,
-    dev_att: Box<dyn DevAttDataFetcher>,
+    max_paths_per_invoke: u16,
+    dev_att: Box<dyn DevAttDataFetcher + Send + Sync>,
     fabric_mgr: Arc<FabricMgr>,
     acl_mgr: Arc<AclMgr>,
+    subs_mgr: Arc<Mutex<SubsMgr>>,
     pase_mgr: PaseMgr,
+    failsafe: Arc<FailSafe>,
+    wq: WorkQ,
 ) -> Result<u32, Error> {
     // Add the root endpoint
     let endpoint = node.add_endpoint(DEV_TYPE_ROOT_NODE)?;
@@ -54,15 +63,16 @@ pub fn device_type_add_root_node(
         return Err(Error::Invalid);
     };
     // Add the mandatory clusters
-    node.add_cluster(0, BasicInfoCluster::new(dev_info)?)?;
-    let general_commissioning = GenCommCluster::new()?;
-    let failsafe = general_commissioning.failsafe();
-    node.add_cluster(0, general_commissioning)?;
+    node.add_cluster(0, BasicInfoCluster::new(dev_info, max_paths_per_invoke)?)?;
+    node.add_cluster(0, GenCommCluster::new(failsafe.clone())?)?;
     node.add_cluster(0, NwCommCluster::new()?)?;
-    node.add_cluster(0, AdminCommCluster::new(pase_mgr)?)?;
     node.add_cluster(
         0,
-        NocCluster::new(dev_att, fabric_mgr, acl_mgr.clone(), failsafe)?,
+        AdminCommCluster::new(pase_mgr, fabric_mgr.clone(), failsafe.clone())?,
+    )?;
+    node.add_cluster(
+        0,
+        NocCluster::new(dev_att, fabric_mgr, acl_mgr.clone(), subs_mgr, failsafe, wq)?,
     )?;
     node.add_cluster(0, AccessControlCluster::new(acl_mgr)?)?;
     Ok(endpoint)
@@ -80,6 +80,8 @@ bitflags! {
         const PERSISTENT = 0x02;
         const FIXED = 0x03;
         const NULLABLE = 0x04;
+        // The attribute is a list, writes to it are list operations
+        const LIST = 0x08;
     }
 }
 
@@ -157,6 +159,8 @@ pub struct Attribute {
     pub(super) value: AttrValue,
     pub(super) quality: Quality,
     pub(super) access: Access,
+    // The data model generation at which this attribute last changed
+    pub(super) changed_gen: u64,
 }
 
 impl Default for Attribute {
@@ -166,6 +170,7 @@ impl Default for Attribute {
             value: AttrValue::Bool(true),
             quality: Default::default(),
             access: Default::default(),
+            changed_gen: 0,
         }
     }
 }
@@ -177,6 +182,7 @@ impl Attribute {
             value,
             access,
             quality,
+            changed_gen: 0,
         }
     }
 
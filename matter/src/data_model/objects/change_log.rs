@@ -0,0 +1,149 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::{
+    error::Error,
+    interaction_model::messages::ib::EventDataTag,
+    sys::Psm,
+    tlv::{TLVWriter, TagType, ToTLV},
+    transport::queue::WorkQ,
+    utils::writebuf::WriteBuf,
+};
+
+use super::{EventLog, EventPriority, EventRecord, MAX_EVENT_DATA_SIZE};
+
+/// What changed in a data model, for its subscriptions
+///
+/// Every change to an attribute is stamped with the next generation, and the events go to
+/// the event log. This is how the subscriptions find out what changed since their last
+/// report. The clones of a ChangeLog share the generation and the event log.
+#[derive(Clone)]
+pub struct ChangeLog {
+    gen: Arc<AtomicU64>,
+    events: Arc<Mutex<EventLog>>,
+    // The transport may be waiting for its next timer, while a report is now due
+    wq: WorkQ,
+}
+
+impl ChangeLog {
+    /// The transport is woken up through `wq` on every change
+    pub fn new(wq: WorkQ) -> Self {
+        Self {
+            gen: Arc::new(AtomicU64::new(0)),
+            events: Arc::new(Mutex::new(EventLog::new())),
+            wq,
+        }
+    }
+
+    /// Persist the event numbers in psm from now on
+    ///
+    /// The event numbers continue from where they were, before the reboot.
+    pub fn load_events(&self, psm: Arc<Mutex<Psm>>) {
+        self.events.lock().unwrap().load(psm)
+    }
+
+    /// The generation of the latest change to the data model
+    pub fn get_gen(&self) -> u64 {
+        self.gen.load(Ordering::SeqCst)
+    }
+
+    pub(super) fn next_gen(&self) -> u64 {
+        self.wq.data_changed();
+        self.gen.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// The event number that the next event gets
+    pub fn get_next_event_number(&self) -> u64 {
+        self.events.lock().unwrap().next_number()
+    }
+
+    /// Log an event, and return its event number
+    ///
+    /// The data is the fields of the event, as the cluster defines them. Only the fabric
+    /// with the fab_idx can read a fabric-sensitive event, pass 0 for the others.
+    pub fn emit_event(
+        &self,
+        endpoint: u16,
+        cluster: u32,
+        event: u32,
+        priority: EventPriority,
+        fab_idx: u8,
+        data: &dyn ToTLV,
+    ) -> Result<u64, Error> {
+        let mut buf = [0u8; MAX_EVENT_DATA_SIZE];
+        let mut wb = WriteBuf::new(&mut buf, MAX_EVENT_DATA_SIZE);
+        let mut tw = TLVWriter::new(&mut wb);
+        data.to_tlv(&mut tw, TagType::Context(EventDataTag::Data as u8))?;
+
+        let number = {
+            let mut log = self.events.lock().unwrap();
+            let number = log.new_number();
+            let timestamp = log.elapsed_ms();
+            log.add(EventRecord {
+                number,
+                priority,
+                endpoint,
+                cluster,
+                event,
+                fab_idx,
+                timestamp,
+                data: wb.as_slice().to_vec(),
+            });
+            number
+        };
+        // The subscriptions to this event may have a report due
+        self.wq.data_changed();
+        Ok(number)
+    }
+
+    /// Run a closure for the logged events from the event number from, up to but not
+    /// including the event number till, in the order of their event numbers
+    pub fn for_each_event<F>(&self, from: u64, till: u64, f: F) -> Result<(), Error>
+    where
+        F: FnMut(&EventRecord) -> Result<(), Error>,
+    {
+        self.events.lock().unwrap().for_each(from, till, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::queue::{Msg, WorkQ};
+
+    use super::ChangeLog;
+
+    #[test]
+    fn test_separate_logs() {
+        let (wq, rx_q) = WorkQ::new();
+        let changes = ChangeLog::new(wq);
+        let other = ChangeLog::new(WorkQ::new().0);
+
+        assert_eq!(changes.next_gen(), 1);
+        assert_eq!(changes.next_gen(), 2);
+        assert_eq!(changes.clone().get_gen(), 2);
+        assert_eq!(other.get_gen(), 0);
+
+        // A burst of changes wakes the transport up once
+        assert!(matches!(rx_q.try_recv(), Ok(Msg::DataChanged)));
+        assert!(rx_q.try_recv().is_err());
+    }
+}
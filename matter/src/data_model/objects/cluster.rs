@@ -17,9 +17,13 @@
 
 use crate::{
     acl::AccessReq,
-    data_model::objects::{Access, AttrValue, Attribute, EncodeValue, Quality},
+    data_model::objects::{Access, AttrValue, Attribute, EncodeValue, Event, Quality},
     error::*,
-    interaction_model::{command::CommandReq, core::IMStatusCode},
+    interaction_model::{
+        command::CommandReq,
+        core::IMStatusCode,
+        messages::ib::{attr_list_write, ListOperation},
+    },
     // TODO: This layer shouldn't really depend on the TLV layer, should create an abstraction layer
     tlv::{Nullable, TLVElement, TLVWriter, TagType},
 };
@@ -28,17 +32,17 @@ use num_derive::FromPrimitive;
 use rand::Rng;
 use std::fmt::{self, Debug};
 
-use super::Encoder;
+use super::{ChangeLog, Encoder};
 
-pub const ATTRS_PER_CLUSTER: usize = 10;
-pub const CMDS_PER_CLUSTER: usize = 8;
+// The default maximum number of attributes in a cluster
+pub const ATTRS_PER_CLUSTER: usize = 12;
 
 #[derive(FromPrimitive, Debug)]
 pub enum GlobalElements {
     _ClusterRevision = 0xFFFD,
     FeatureMap = 0xFFFC,
     AttributeList = 0xFFFB,
-    _EventList = 0xFFFA,
+    EventList = 0xFFFA,
     _ClientGenCmd = 0xFFF9,
     ServerGenCmd = 0xFFF8,
     FabricIndex = 0xFE,
@@ -57,6 +61,8 @@ pub struct AttrDetails {
     pub list_index: Option<Nullable<u16>>,
     /// The actual attribute ID
     pub attr_id: u16,
+    /// The node ID of the accessor, if it is on a CASE session
+    pub node_id: Option<u64>,
 }
 
 impl AttrDetails {
@@ -66,6 +72,7 @@ impl AttrDetails {
             fab_idx,
             list_index: None,
             attr_id: 0,
+            node_id: None,
         }
     }
 }
@@ -99,12 +106,34 @@ pub trait ClusterType {
     ) -> Result<(), IMStatusCode> {
         self.base_mut().write_attribute_from_tlv(attr.attr_id, data)
     }
+
+    /// Perform an operation on a list attribute
+    ///
+    /// The writes to the attributes with the LIST quality come here instead of
+    /// write_attribute(), one operation at a time. A whole list replace is a DeleteList
+    /// followed by an AddItem for every entry. For a fabric-scoped list, attr.fab_filter is
+    /// set, and the operations must only touch the entries of attr.fab_idx.
+    fn write_list_attribute(
+        &mut self,
+        _op: &ListOperation,
+        _attr: &AttrDetails,
+        _data: &TLVElement,
+    ) -> Result<(), IMStatusCode> {
+        Err(IMStatusCode::UnsupportedWrite)
+    }
 }
 
 pub struct Cluster {
     pub(super) id: u32,
     attributes: Vec<Attribute>,
+    // The maximum number of attributes, which is that of the node the cluster is added to
+    max_attrs: usize,
+    events: Vec<Event>,
     data_ver: u32,
+    // The data model generation at which the cluster, as a whole, last changed
+    changed_gen: u64,
+    // The change log of the node the cluster is added to
+    changes: Option<ChangeLog>,
 }
 
 impl Cluster {
@@ -112,7 +141,11 @@ impl Cluster {
         let mut c = Cluster {
             id,
             attributes: Vec::with_capacity(ATTRS_PER_CLUSTER),
+            max_attrs: usize::MAX,
+            events: Vec::new(),
             data_ver: rand::thread_rng().gen_range(0..0xFFFFFFFF),
+            changed_gen: 0,
+            changes: None,
         };
         c.add_default_attributes()?;
         Ok(c)
@@ -126,6 +159,14 @@ impl Cluster {
         self.data_ver
     }
 
+    /// Whether the attribute changed after the given generation of the data model
+    pub fn is_changed_since(&self, attr_id: u16, gen: u64) -> bool {
+        self.changed_gen > gen
+            || self
+                .get_attribute(attr_id)
+                .is_ok_and(|a| a.changed_gen > gen)
+    }
+
     pub fn set_feature_map(&mut self, map: u32) -> Result<(), Error> {
         self.write_attribute_raw(GlobalElements::FeatureMap as u16, AttrValue::Uint32(map))
             .map_err(|_| Error::Invalid)?;
@@ -150,7 +191,7 @@ impl Cluster {
     }
 
     pub fn add_attributes(&mut self, attrs: &[Attribute]) -> Result<(), Error> {
-        if self.attributes.len() + attrs.len() <= self.attributes.capacity() {
+        if self.attributes.len() + attrs.len() <= self.max_attrs {
             self.attributes.extend_from_slice(attrs);
             Ok(())
         } else {
@@ -158,8 +199,28 @@ impl Cluster {
         }
     }
 
+    /// Limit the number of attributes, this fails if the cluster already has more
+    pub fn set_max_attrs(&mut self, count: usize) -> Result<(), Error> {
+        if self.attributes.len() > count {
+            return Err(Error::NoSpace);
+        }
+        self.max_attrs = count;
+        Ok(())
+    }
+
+    /// Record the changes to the cluster in this log, from now on
+    pub fn set_change_log(&mut self, changes: ChangeLog) {
+        self.changes = Some(changes);
+    }
+
+    /// The change log of the data model that the cluster is in, this is where its
+    /// events are emitted
+    pub fn change_log(&self) -> Option<&ChangeLog> {
+        self.changes.as_ref()
+    }
+
     pub fn add_attribute(&mut self, attr: Attribute) -> Result<(), Error> {
-        if self.attributes.len() < self.attributes.capacity() {
+        if self.attributes.len() < self.max_attrs {
             self.attributes.push(attr);
             Ok(())
         } else {
@@ -167,6 +228,26 @@ impl Cluster {
         }
     }
 
+    /// Add an event that the cluster may emit
+    ///
+    /// The EventList attribute is added along with the first event
+    pub fn add_event(&mut self, event: Event) -> Result<(), Error> {
+        if self.events.is_empty() {
+            self.add_attribute(Attribute::new(
+                GlobalElements::EventList as u16,
+                AttrValue::Custom,
+                Access::RV,
+                Quality::NONE,
+            ))?;
+        }
+        self.events.push(event);
+        Ok(())
+    }
+
+    pub fn get_event(&self, event_id: u32) -> Option<&Event> {
+        self.events.iter().find(|e| e.id == event_id)
+    }
+
     fn get_attribute_index(&self, attr_id: u16) -> Option<usize> {
         self.attributes.iter().position(|c| c.id == attr_id)
     }
@@ -244,6 +325,14 @@ impl Cluster {
         let _ = tw.end_container();
     }
 
+    fn encode_event_ids(&self, tag: TagType, tw: &mut TLVWriter) {
+        let _ = tw.start_array(tag);
+        for e in &self.events {
+            let _ = tw.u32(TagType::Anonymous, e.id);
+        }
+        let _ = tw.end_container();
+    }
+
     fn read_system_attribute(&self, encoder: &mut dyn Encoder, attr: &Attribute) {
         let global_attr: Option<GlobalElements> = num::FromPrimitive::from_u16(attr.id);
         if let Some(global_attr) = global_attr {
@@ -254,6 +343,12 @@ impl Cluster {
                     }));
                     return;
                 }
+                GlobalElements::EventList => {
+                    encoder.encode(EncodeValue::Closure(&|tag, tw| {
+                        self.encode_event_ids(tag, tw)
+                    }));
+                    return;
+                }
                 GlobalElements::FeatureMap => {
                     encoder.encode(EncodeValue::Value(&attr.value));
                     return;
@@ -295,7 +390,21 @@ impl Cluster {
             return Err(IMStatusCode::UnsupportedAccess);
         }
 
-        c.write_attribute(attr, data)
+        if !a.quality.contains(Quality::LIST) {
+            return c.write_attribute(attr, data);
+        }
+        // A fabric-scoped list can only be written from within a fabric
+        let fab_scoped = a.access.contains(Access::FAB_SCOPED);
+        if fab_scoped && attr.fab_idx == 0 {
+            return Err(IMStatusCode::UnsupportedAccess);
+        }
+        let attr = AttrDetails {
+            fab_filter: fab_scoped,
+            ..*attr
+        };
+        attr_list_write(&attr, data, |op, data| {
+            c.write_list_attribute(&op, &attr, data)
+        })
     }
 
     pub fn write_attribute_from_tlv(
@@ -311,7 +420,7 @@ impl Cluster {
                 .map_err(|_| IMStatusCode::Failure)?;
             a.set_value(value)
                 .map(|_| {
-                    self.cluster_changed();
+                    self.attr_changed(attr_id);
                 })
                 .map_err(|_| IMStatusCode::UnsupportedWrite)
         } else {
@@ -322,14 +431,31 @@ impl Cluster {
     pub fn write_attribute_raw(&mut self, attr_id: u16, value: AttrValue) -> Result<(), Error> {
         let a = self.get_attribute_mut(attr_id)?;
         a.set_value(value).map(|_| {
-            self.cluster_changed();
+            self.attr_changed(attr_id);
         })
     }
 
+    // A cluster that isn't in a data model yet, has no subscribers to tell
+    fn next_change_gen(&self) -> Option<u64> {
+        self.changes.as_ref().map(|c| c.next_gen())
+    }
+
+    fn attr_changed(&mut self, attr_id: u16) {
+        if let Some(gen) = self.next_change_gen() {
+            if let Ok(a) = self.get_attribute_mut(attr_id) {
+                a.changed_gen = gen;
+            }
+        }
+        self.data_ver = self.data_ver.wrapping_add(1);
+    }
+
     /// This method must be called for any changes to the data model
-    ///     Currently this only increments the data version, but we can reuse the same
-    ///     for raising events too
+    ///     This increments the data version, and marks all the attributes of the cluster
+    ///     as changed, for the subscriptions
     pub fn cluster_changed(&mut self) {
+        if let Some(gen) = self.next_change_gen() {
+            self.changed_gen = gen;
+        }
         self.data_ver = self.data_ver.wrapping_add(1);
     }
 }
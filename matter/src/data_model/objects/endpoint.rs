@@ -25,10 +25,10 @@ pub const CLUSTERS_PER_ENDPT: usize = 9;
 
 pub struct Endpoint {
     dev_type: DeviceType,
-    clusters: Vec<Box<dyn ClusterType>>,
+    clusters: Vec<Box<dyn ClusterType + Send + Sync>>,
 }
 
-pub type BoxedClusters = [Box<dyn ClusterType>];
+pub type BoxedClusters = [Box<dyn ClusterType + Send + Sync>];
 
 impl Endpoint {
     pub fn new(dev_type: DeviceType) -> Result<Box<Endpoint>, Error> {
@@ -38,7 +38,10 @@ impl Endpoint {
         }))
     }
 
-    pub fn add_cluster(&mut self, cluster: Box<dyn ClusterType>) -> Result<(), Error> {
+    pub fn add_cluster(
+        &mut self,
+        cluster: Box<dyn ClusterType + Send + Sync>,
+    ) -> Result<(), Error> {
         if self.clusters.len() < self.clusters.capacity() {
             self.clusters.push(cluster);
             Ok(())
@@ -0,0 +1,230 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use log::error;
+
+use crate::{error::Error, sys::Psm};
+
+use super::Access;
+
+/// The priority of an event, the higher priority events are retained for longer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventPriority {
+    Debug = 0,
+    Info = 1,
+    Critical = 2,
+}
+
+/// An event that a cluster may emit
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub id: u32,
+    pub access: Access,
+}
+
+impl Event {
+    pub fn new(id: u32, access: Access) -> Self {
+        Self { id, access }
+    }
+}
+
+// The number of events of each priority that are retained, the oldest ones make way for
+// the new ones
+const EVENTS_PER_PRIORITY: [usize; 3] = [16, 32, 16];
+pub(super) const MAX_EVENT_DATA_SIZE: usize = 256;
+
+const EVENT_NUM_KV_ENTRY: &str = "event_num";
+// The event numbers are persisted in steps of this, instead of for every event. After a
+// reboot, the numbering continues from the next step.
+const EVENT_NUM_STEP: u64 = 0x1000;
+
+/// An event in the event log
+pub struct EventRecord {
+    pub number: u64,
+    pub priority: EventPriority,
+    pub endpoint: u16,
+    pub cluster: u32,
+    pub event: u32,
+    /// The fabric of a fabric-sensitive event, 0 otherwise
+    pub fab_idx: u8,
+    /// Milliseconds since the event log was started
+    pub timestamp: u64,
+    /// The fields of the event, encoded with the tag of the Data in an EventDataIB
+    pub data: Vec<u8>,
+}
+
+pub(super) struct EventLog {
+    bufs: [VecDeque<EventRecord>; 3],
+    next_number: u64,
+    // The event numbers before this, are accounted for in the persistent storage
+    reserved_till: u64,
+    start: Instant,
+    psm: Option<Arc<Mutex<Psm>>>,
+}
+
+impl EventLog {
+    pub(super) fn new() -> Self {
+        Self {
+            bufs: Default::default(),
+            next_number: 0,
+            reserved_till: 0,
+            start: Instant::now(),
+            psm: None,
+        }
+    }
+
+    pub(super) fn add(&mut self, record: EventRecord) {
+        let priority = record.priority as usize;
+        let buf = &mut self.bufs[priority];
+        if buf.len() >= EVENTS_PER_PRIORITY[priority] {
+            buf.pop_front();
+        }
+        buf.push_back(record);
+    }
+
+    pub(super) fn next_number(&self) -> u64 {
+        self.next_number
+    }
+
+    // Milliseconds since the event log was started
+    pub(super) fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    pub(super) fn new_number(&mut self) -> u64 {
+        let number = self.next_number;
+        self.next_number += 1;
+        if self.next_number > self.reserved_till {
+            self.reserve();
+        }
+        number
+    }
+
+    fn reserve(&mut self) {
+        self.reserved_till = self.next_number + EVENT_NUM_STEP;
+        if let Some(psm) = &self.psm {
+            let psm = psm.lock().unwrap();
+            if let Err(e) = psm.set_kv_u64(EVENT_NUM_KV_ENTRY, self.reserved_till) {
+                error!("Error in storing the event number {:?}", e);
+            }
+        }
+    }
+
+    pub(super) fn load(&mut self, psm: Arc<Mutex<Psm>>) {
+        let mut number = 0;
+        if psm
+            .lock()
+            .unwrap()
+            .get_kv_u64(EVENT_NUM_KV_ENTRY, &mut number)
+            .is_ok()
+        {
+            self.next_number = self.next_number.max(number);
+        }
+        self.psm = Some(psm);
+        self.reserve();
+    }
+
+    pub(super) fn for_each<F>(&self, from: u64, till: u64, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&EventRecord) -> Result<(), Error>,
+    {
+        let mut records: Vec<&EventRecord> = self
+            .bufs
+            .iter()
+            .flatten()
+            .filter(|r| r.number >= from && r.number < till)
+            .collect();
+        records.sort_by_key(|r| r.number);
+        for r in records {
+            f(r)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(log: &mut EventLog, priority: EventPriority) -> u64 {
+        let number = log.new_number();
+        log.add(EventRecord {
+            number,
+            priority,
+            endpoint: 0,
+            cluster: 0x28,
+            event: 0,
+            fab_idx: 0,
+            timestamp: 0,
+            data: Vec::new(),
+        });
+        number
+    }
+
+    fn numbers(log: &EventLog, from: u64, till: u64) -> Vec<u64> {
+        let mut numbers = Vec::new();
+        log.for_each(from, till, |r| {
+            numbers.push(r.number);
+            Ok(())
+        })
+        .unwrap();
+        numbers
+    }
+
+    #[test]
+    fn test_event_order() {
+        let mut log = EventLog::new();
+        record(&mut log, EventPriority::Info);
+        record(&mut log, EventPriority::Critical);
+        record(&mut log, EventPriority::Debug);
+        record(&mut log, EventPriority::Info);
+        assert_eq!(numbers(&log, 0, 4), [0, 1, 2, 3]);
+        assert_eq!(numbers(&log, 1, 3), [1, 2]);
+        assert_eq!(log.next_number, 4);
+    }
+
+    #[test]
+    fn test_event_eviction() {
+        let mut log = EventLog::new();
+        let critical = record(&mut log, EventPriority::Critical);
+        let debug_events = EVENTS_PER_PRIORITY[EventPriority::Debug as usize] as u64;
+        for _ in 0..debug_events + 2 {
+            record(&mut log, EventPriority::Debug);
+        }
+        // The oldest debug events made way, the critical one is retained
+        let numbers = numbers(&log, 0, u64::MAX);
+        assert_eq!(numbers.len() as u64, debug_events + 1);
+        assert_eq!(numbers[0], critical);
+        assert_eq!(numbers[1], 3);
+    }
+
+    #[test]
+    fn test_event_number_reserve() {
+        let mut log = EventLog::new();
+        record(&mut log, EventPriority::Info);
+        assert_eq!(log.reserved_till, 1 + EVENT_NUM_STEP);
+        log.next_number = log.reserved_till;
+        record(&mut log, EventPriority::Info);
+        assert_eq!(log.reserved_till, 2 + 2 * EVENT_NUM_STEP);
+    }
+}
@@ -32,3 +32,9 @@ pub use privilege::*;
 
 mod encoder;
 pub use encoder::*;
+
+mod event;
+pub use event::*;
+
+mod change_log;
+pub use change_log::*;
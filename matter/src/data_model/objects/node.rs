@@ -23,9 +23,9 @@ use crate::{
 };
 use std::fmt;
 
-use super::DeviceType;
+use super::{ChangeLog, Cluster, DeviceType, ATTRS_PER_CLUSTER};
 
-pub trait ChangeConsumer {
+pub trait ChangeConsumer: Send + Sync {
     fn endpoint_added(&self, id: u16, endpoint: &mut Endpoint) -> Result<(), Error>;
 }
 
@@ -33,10 +33,22 @@ pub const ENDPTS_PER_ACC: usize = 3;
 
 pub type BoxedEndpoints = [Option<Box<Endpoint>>];
 
-#[derive(Default)]
 pub struct Node {
     endpoints: [Option<Box<Endpoint>>; ENDPTS_PER_ACC],
     changes_cb: Option<Box<dyn ChangeConsumer>>,
+    attrs_per_cluster: usize,
+    changes: Option<ChangeLog>,
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self {
+            endpoints: Default::default(),
+            changes_cb: None,
+            attrs_per_cluster: ATTRS_PER_CLUSTER,
+            changes: None,
+        }
+    }
 }
 
 impl std::fmt::Display for Node {
@@ -61,6 +73,29 @@ impl Node {
         self.changes_cb = Some(consumer);
     }
 
+    /// Set the maximum number of attributes in a cluster
+    ///
+    /// This applies to the clusters that are added after this call
+    pub fn set_attrs_per_cluster(&mut self, count: usize) {
+        self.attrs_per_cluster = count;
+    }
+
+    /// Record the changes to the clusters in this log
+    ///
+    /// This applies to the clusters that are added after this call
+    pub fn set_change_log(&mut self, changes: ChangeLog) {
+        self.changes = Some(changes);
+    }
+
+    // Apply the settings of the node to a cluster that is added to it
+    fn adopt_cluster(&self, cluster: &mut Cluster) -> Result<(), Error> {
+        cluster.set_max_attrs(self.attrs_per_cluster)?;
+        if let Some(changes) = &self.changes {
+            cluster.set_change_log(changes.clone());
+        }
+        Ok(())
+    }
+
     pub fn add_endpoint(&mut self, dev_type: DeviceType) -> Result<u32, Error> {
         let index = self
             .endpoints
@@ -71,6 +106,12 @@ impl Node {
         if let Some(cb) = &self.changes_cb {
             cb.endpoint_added(index as u16, &mut endpoint)?;
         }
+        // The callback may have added clusters of its own
+        if let Ok((clusters, _)) = endpoint.get_wildcard_clusters_mut(None) {
+            for c in clusters.iter_mut() {
+                self.adopt_cluster(c.base_mut())?;
+            }
+        }
         self.endpoints[index] = Some(endpoint);
         Ok(index as u32)
     }
@@ -108,8 +149,9 @@ impl Node {
     pub fn add_cluster(
         &mut self,
         endpoint_id: u32,
-        cluster: Box<dyn ClusterType>,
+        mut cluster: Box<dyn ClusterType + Send + Sync>,
     ) -> Result<(), Error> {
+        self.adopt_cluster(cluster.base_mut())?;
         let endpoint_id = endpoint_id as usize;
         if endpoint_id < ENDPTS_PER_ACC {
             self.endpoints[endpoint_id]
@@ -15,18 +15,35 @@
  *    limitations under the License.
  */
 
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
 use crate::cmd_enter;
 use crate::data_model::objects::*;
+use crate::fabric::{FabricMgr, MAX_SUPPORTED_FABRICS};
 use crate::interaction_model::core::IMStatusCode;
-use crate::secure_channel::pake::PaseMgr;
-use crate::secure_channel::spake2p::VerifierData;
-use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement};
+use crate::interaction_model::messages::ib;
+use crate::mdns::CommissioningMode;
+use crate::secure_channel::pake::{CommWindowInfo, PaseMgr};
+use crate::secure_channel::spake2p::{
+    VerifierData, MAX_ITERATION_COUNT, MAX_SALT_SIZE_BYTES, MIN_ITERATION_COUNT,
+    MIN_SALT_SIZE_BYTES, VERIFIER_SIZE_BYTES,
+};
+use crate::tlv::{FromTLV, Nullable, OctetStr, TLVElement, TLVWriter, TagType, ToTLV};
 use crate::{error::*, interaction_model::command::CommandReq};
 use log::{error, info};
 use num_derive::FromPrimitive;
 
+use super::failsafe::FailSafe;
+
 pub const ID: u32 = 0x003C;
 
+// The range of the commissioning timeout, in seconds
+const MIN_COMM_TIMEOUT: u16 = 180;
+const MAX_COMM_TIMEOUT: u16 = 900;
+
+const MAX_DISCRIMINATOR: u16 = 0xFFF;
+
 #[derive(FromPrimitive, Debug, Copy, Clone, PartialEq)]
 pub enum WindowStatus {
     WindowNotOpen = 0,
@@ -34,6 +51,14 @@ pub enum WindowStatus {
     BasicWindowOpen = 2,
 }
 
+// The cluster specific status codes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum StatusCode {
+    Busy = 2,
+    PAKEParameterError = 3,
+    WindowNotOpen = 4,
+}
+
 #[derive(FromPrimitive)]
 pub enum Attributes {
     WindowStatus = 0,
@@ -77,6 +102,8 @@ fn attr_admin_vid_new() -> Attribute {
 
 pub struct AdminCommCluster {
     pase_mgr: PaseMgr,
+    fabric_mgr: Arc<FabricMgr>,
+    failsafe: Arc<FailSafe>,
     base: Cluster,
 }
 
@@ -89,19 +116,25 @@ impl ClusterType for AdminCommCluster {
     }
 
     fn read_custom_attribute(&self, encoder: &mut dyn Encoder, attr: &AttrDetails) {
+        let window = self.pase_mgr.get_comm_window();
+        let admin = window.and_then(|w| w.admin);
         match num::FromPrimitive::from_u16(attr.attr_id) {
             Some(Attributes::WindowStatus) => {
-                let status = 1_u8;
-                encoder.encode(EncodeValue::Value(&status))
+                let status = match window.map(|w| w.mode) {
+                    None => WindowStatus::WindowNotOpen,
+                    Some(CommissioningMode::Enhanced) => WindowStatus::EnhancedWindowOpen,
+                    Some(CommissioningMode::Basic) => WindowStatus::BasicWindowOpen,
+                };
+                encoder.encode(EncodeValue::Value(&(status as u8)))
             }
             Some(Attributes::AdminVendorId) => {
-                let vid = Nullable::NotNull(1_u8);
-
+                let vid = admin.map_or(Nullable::Null, |(_, vid)| Nullable::NotNull(vid));
                 encoder.encode(EncodeValue::Value(&vid))
             }
             Some(Attributes::AdminFabricIndex) => {
-                let vid = Nullable::NotNull(1_u8);
-                encoder.encode(EncodeValue::Value(&vid))
+                let fab_idx =
+                    admin.map_or(Nullable::Null, |(fab_idx, _)| Nullable::NotNull(fab_idx));
+                encoder.encode(EncodeValue::Value(&fab_idx))
             }
             _ => {
                 error!("Unsupported Attribute: this shouldn't happen");
@@ -116,17 +149,66 @@ impl ClusterType for AdminCommCluster {
             .map(num::FromPrimitive::from_u32)
             .ok_or(IMStatusCode::UnsupportedCommand)?
             .ok_or(IMStatusCode::UnsupportedCommand)?;
-        match cmd {
+        // Opening a window is only allowed through a timed invoke
+        let open_cmd = matches!(
+            cmd,
+            Commands::OpenCommWindow | Commands::OpenBasicCommWindow
+        );
+        if open_cmd && cmd_req.trans.get_timeout().is_none() {
+            return Err(IMStatusCode::NeedsTimedInteraction);
+        }
+        let result = match cmd {
             Commands::OpenCommWindow => self.handle_command_opencomm_win(cmd_req),
-            _ => Err(IMStatusCode::UnsupportedCommand),
+            Commands::OpenBasicCommWindow => self.handle_command_openbasiccomm_win(cmd_req),
+            Commands::RevokeComm => self.handle_command_revokecomm(cmd_req),
+        };
+        match result {
+            Err(CommandError::Cluster(status)) => {
+                error!("Admin Commissioning command failed: {:?}", status);
+                let invoke_resp = ib::InvResp::status_new(
+                    cmd_req.cmd,
+                    IMStatusCode::Failure,
+                    status as u16,
+                    cmd_req.command_ref,
+                );
+                let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
+                cmd_req.trans.complete();
+                Ok(())
+            }
+            Err(CommandError::Im(status)) => Err(status),
+            Ok(()) => {
+                // The window status, and the admin, changed
+                self.pase_mgr.take_window_changed();
+                self.base.cluster_changed();
+                cmd_req.trans.complete();
+                Err(IMStatusCode::Success)
+            }
         }
     }
 }
 
+// The commands fail with either a cluster specific status, or a generic one
+enum CommandError {
+    Cluster(StatusCode),
+    Im(IMStatusCode),
+}
+
+impl From<IMStatusCode> for CommandError {
+    fn from(e: IMStatusCode) -> Self {
+        CommandError::Im(e)
+    }
+}
+
 impl AdminCommCluster {
-    pub fn new(pase_mgr: PaseMgr) -> Result<Box<Self>, Error> {
+    pub fn new(
+        pase_mgr: PaseMgr,
+        fabric_mgr: Arc<FabricMgr>,
+        failsafe: Arc<FailSafe>,
+    ) -> Result<Box<Self>, Error> {
         let mut c = Box::new(AdminCommCluster {
             pase_mgr,
+            fabric_mgr,
+            failsafe,
             base: Cluster::new(ID)?,
         });
         c.base.add_attribute(attr_window_status_new())?;
@@ -135,26 +217,98 @@ impl AdminCommCluster {
         Ok(c)
     }
 
+    // A window can't be opened while another one is open, or during a commissioning
+    fn check_window(&self, timeout: u16) -> Result<SystemTime, CommandError> {
+        if self.pase_mgr.get_comm_window().is_some() || self.failsafe.is_armed() {
+            return Err(CommandError::Cluster(StatusCode::Busy));
+        }
+        if !(MIN_COMM_TIMEOUT..=MAX_COMM_TIMEOUT).contains(&timeout) {
+            return Err(CommandError::Im(IMStatusCode::InvalidCommand));
+        }
+        Ok(SystemTime::now() + Duration::from_secs(timeout as u64))
+    }
+
+    // The fabric index and vendor id of the administrator that invoked the command
+    fn get_admin(&self, cmd_req: &CommandReq) -> Option<(u8, u16)> {
+        let fab_idx = cmd_req.trans.session.get_local_fabric_idx()?;
+        if fab_idx as usize >= MAX_SUPPORTED_FABRICS {
+            return None;
+        }
+        let fabric = self.fabric_mgr.get_fabric(fab_idx as usize).ok()?;
+        let vendor_id = (*fabric).as_ref()?.get_vendor_id();
+        Some((fab_idx, vendor_id))
+    }
+
     fn handle_command_opencomm_win(
         &mut self,
         cmd_req: &mut CommandReq,
-    ) -> Result<(), IMStatusCode> {
+    ) -> Result<(), CommandError> {
         cmd_enter!("Open Commissioning Window");
         let req =
             OpenCommWindowReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        let expiry = self.check_window(req.timeout)?;
+        if req.discriminator > MAX_DISCRIMINATOR {
+            return Err(CommandError::Im(IMStatusCode::InvalidCommand));
+        }
+        if req.verifier.0.len() != VERIFIER_SIZE_BYTES
+            || !(MIN_ITERATION_COUNT..=MAX_ITERATION_COUNT).contains(&req.iterations)
+            || !(MIN_SALT_SIZE_BYTES..=MAX_SALT_SIZE_BYTES).contains(&req.salt.0.len())
+        {
+            return Err(CommandError::Cluster(StatusCode::PAKEParameterError));
+        }
+
+        let info = CommWindowInfo {
+            mode: CommissioningMode::Enhanced,
+            admin: self.get_admin(cmd_req),
+            expiry: Some(expiry),
+        };
         let verifier = VerifierData::new(req.verifier.0, req.iterations, req.salt.0);
         self.pase_mgr
-            .enable_pase_session(verifier, req.discriminator)?;
-        Err(IMStatusCode::Success)
+            .open_comm_window(info, verifier, req.discriminator)
+            .map_err(|_| IMStatusCode::Failure)?;
+        Ok(())
+    }
+
+    fn handle_command_openbasiccomm_win(
+        &mut self,
+        cmd_req: &mut CommandReq,
+    ) -> Result<(), CommandError> {
+        cmd_enter!("Open Basic Commissioning Window");
+        let req = OpenBasicCommWindowReq::from_tlv(&cmd_req.data)
+            .map_err(|_| IMStatusCode::InvalidCommand)?;
+        let expiry = self.check_window(req.timeout)?;
+
+        self.pase_mgr
+            .open_basic_comm_window(self.get_admin(cmd_req), Some(expiry))
+            .map_err(|_| IMStatusCode::Failure)?;
+        Ok(())
+    }
+
+    fn handle_command_revokecomm(&mut self, _cmd_req: &mut CommandReq) -> Result<(), CommandError> {
+        cmd_enter!("Revoke Commissioning");
+        if self.pase_mgr.get_comm_window().is_none() {
+            return Err(CommandError::Cluster(StatusCode::WindowNotOpen));
+        }
+        info!("Revoking the commissioning window");
+        // The window is closed first, so that undoing the commissioning in progress doesn't
+        // advertise it again
+        self.pase_mgr.close_comm_window();
+        self.failsafe.force_expiry();
+        Ok(())
     }
 }
 
-#[derive(FromTLV)]
+#[derive(FromTLV, ToTLV)]
 #[tlvargs(lifetime = "'a")]
 pub struct OpenCommWindowReq<'a> {
-    _timeout: u16,
-    verifier: OctetStr<'a>,
-    discriminator: u16,
-    iterations: u32,
-    salt: OctetStr<'a>,
+    pub timeout: u16,
+    pub verifier: OctetStr<'a>,
+    pub discriminator: u16,
+    pub iterations: u32,
+    pub salt: OctetStr<'a>,
+}
+
+#[derive(FromTLV, ToTLV)]
+pub struct OpenBasicCommWindowReq {
+    pub timeout: u16,
 }
@@ -15,12 +15,25 @@
  *    limitations under the License.
  */
 
-use crate::{error::Error, transport::session::SessionMode};
-use log::error;
-use std::sync::RwLock;
+use crate::{
+    acl::AclMgr,
+    cert::Cert,
+    data_model::core::subscribe::SubsMgr,
+    error::Error,
+    fabric::{Fabric, FabricMgr},
+    secure_channel::pake::PaseMgr,
+    transport::{
+        queue::{Msg, WorkQ},
+        session::SessionMode,
+    },
+};
+use log::{error, info};
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
 
 #[derive(PartialEq)]
-#[allow(dead_code)]
 #[allow(clippy::enum_variant_names)]
 enum NocState {
     NocNotRecvd,
@@ -29,50 +42,111 @@ enum NocState {
     UpdateNocRecvd(u8),
 }
 
-#[derive(PartialEq)]
 pub struct ArmedCtx {
     session_mode: SessionMode,
-    timeout: u8,
+    // The local session id of the session that armed the fail-safe
+    sess_id: u16,
+    expiry: SystemTime,
     noc_state: NocState,
+    // The root certificate from AddTrustedRootCert, for the AddNOC that follows
+    trusted_root: Option<Box<Cert>>,
+    // The fabric as it was before the UpdateNOC, to put back if the fail-safe expires
+    prev_fabric: Option<Box<Fabric>>,
+    // The Last Known Good UTC Time, as per the certificates of the AddNOC, to take on once
+    // the commissioning is complete
+    last_known_good: Option<u32>,
 }
 
-#[derive(PartialEq)]
 pub enum State {
     Idle,
     Armed(ArmedCtx),
 }
 
+impl ArmedCtx {
+    // Whether the fail-safe was armed through this session. Over PASE, it has to be the
+    // same session, over CASE, any session on the same fabric.
+    fn armed_by(&self, session_mode: SessionMode, sess_id: u16) -> bool {
+        match (self.session_mode, session_mode) {
+            (SessionMode::Pase, SessionMode::Pase) => self.sess_id == sess_id,
+            (SessionMode::Case(armed), SessionMode::Case(c)) => armed.fab_idx == c.fab_idx,
+            _ => false,
+        }
+    }
+}
+
 pub struct FailSafeInner {
     state: State,
+    // Whether the fail-safe expired, since the data model last looked
+    expired: bool,
 }
 
+/// The fail-safe context of the commissioning
+///
+/// Everything that is done under an armed fail-safe is rolled back, if it expires before
+/// the commissioning is complete.
 pub struct FailSafe {
     state: RwLock<FailSafeInner>,
+    fabric_mgr: Arc<FabricMgr>,
+    acl_mgr: Arc<AclMgr>,
+    subs_mgr: Arc<Mutex<SubsMgr>>,
+    pase_mgr: PaseMgr,
+    wq: WorkQ,
 }
 
 impl FailSafe {
-    pub fn new() -> Self {
+    pub fn new(
+        fabric_mgr: Arc<FabricMgr>,
+        acl_mgr: Arc<AclMgr>,
+        subs_mgr: Arc<Mutex<SubsMgr>>,
+        pase_mgr: PaseMgr,
+        wq: WorkQ,
+    ) -> Self {
         Self {
-            state: RwLock::new(FailSafeInner { state: State::Idle }),
+            state: RwLock::new(FailSafeInner {
+                state: State::Idle,
+                expired: false,
+            }),
+            fabric_mgr,
+            acl_mgr,
+            subs_mgr,
+            pase_mgr,
+            wq,
         }
     }
 
-    pub fn arm(&self, timeout: u8, session_mode: SessionMode) -> Result<(), Error> {
+    /// Arm the fail-safe for timeout seconds, or re-arm it if it is already armed
+    ///
+    /// Only the PASE session, or the fabric of the CASE session, that armed the fail-safe
+    /// can re-arm it. A timeout of 0 expires an armed fail-safe right away.
+    pub fn arm(&self, timeout: u16, session_mode: SessionMode, sess_id: u16) -> Result<(), Error> {
+        let expiry = SystemTime::now() + Duration::from_secs(timeout as u64);
         let mut inner = self.state.write()?;
         match &mut inner.state {
             State::Idle => {
+                if timeout == 0 {
+                    return Ok(());
+                }
                 inner.state = State::Armed(ArmedCtx {
                     session_mode,
-                    timeout,
+                    sess_id,
+                    expiry,
                     noc_state: NocState::NocNotRecvd,
+                    trusted_root: None,
+                    prev_fabric: None,
+                    last_known_good: None,
                 })
             }
             State::Armed(c) => {
-                if c.session_mode != session_mode {
+                if !c.armed_by(session_mode, sess_id) {
                     return Err(Error::Invalid);
                 }
+                if timeout == 0 {
+                    drop(inner);
+                    self.expire();
+                    return Ok(());
+                }
                 // re-arm
-                c.timeout = timeout;
+                c.expiry = expiry;
             }
         }
         Ok(())
@@ -102,23 +176,61 @@ impl FailSafe {
                         }
                     }
                 }
+                if let Some(time) = c.last_known_good {
+                    if let Err(e) = self.fabric_mgr.set_last_known_good_time(time) {
+                        error!("Error in storing the Last Known Good UTC Time {:?}", e);
+                    }
+                }
                 inner.state = State::Idle;
             }
         }
+        // The commissioning is complete
+        self.pase_mgr.clone().close_comm_window();
         Ok(())
     }
 
     pub fn is_armed(&self) -> bool {
-        self.state.read().unwrap().state != State::Idle
+        matches!(self.state.read().unwrap().state, State::Armed(_))
     }
 
-    pub fn record_add_noc(&self, fabric_index: u8) -> Result<(), Error> {
+    /// Record the AddNOC of this fabric, along with the Last Known Good UTC Time that its
+    /// certificates vouch for
+    ///
+    /// The time is only taken on once the commissioning is complete, so that it doesn't
+    /// move forward for a fabric that is rolled back.
+    pub fn record_add_noc(&self, fabric_index: u8, last_known_good: u32) -> Result<(), Error> {
         let mut inner = self.state.write()?;
         match &mut inner.state {
             State::Idle => Err(Error::Invalid),
             State::Armed(c) => {
                 if c.noc_state == NocState::NocNotRecvd {
                     c.noc_state = NocState::AddNocRecvd(fabric_index);
+                    c.last_known_good = Some(last_known_good);
+                    Ok(())
+                } else {
+                    Err(Error::Invalid)
+                }
+            }
+        }
+    }
+
+    /// Record the UpdateNOC on this fabric, along with the fabric that it replaced
+    ///
+    /// The update itself is done by f, which returns the previous fabric. It is only run
+    /// once the fail-safe is known to accept the UpdateNOC, so that there is no update
+    /// that can't be rolled back.
+    pub fn record_update_noc<F>(&self, fabric_index: u8, f: F) -> Result<(), Error>
+    where
+        F: FnOnce() -> Result<Fabric, Error>,
+    {
+        let mut inner = self.state.write()?;
+        match &mut inner.state {
+            State::Idle => Err(Error::Invalid),
+            State::Armed(c) => {
+                if c.noc_state == NocState::NocNotRecvd {
+                    let prev_fabric = f()?;
+                    c.noc_state = NocState::UpdateNocRecvd(fabric_index);
+                    c.prev_fabric = Some(Box::new(prev_fabric));
                     Ok(())
                 } else {
                     Err(Error::Invalid)
@@ -127,6 +239,23 @@ impl FailSafe {
         }
     }
 
+    /// Whether an UpdateNOC is allowed on a session with this mode
+    ///
+    /// The fail-safe should have been armed on a CASE session of the same fabric, and no
+    /// other NOC or trusted root should have been added under it.
+    pub fn allow_update_noc(&self, session_mode: SessionMode) -> Result<bool, Error> {
+        let allow = match &self.state.read()?.state {
+            State::Idle => false,
+            State::Armed(c) => {
+                matches!(session_mode, SessionMode::Case(_))
+                    && c.session_mode == session_mode
+                    && c.noc_state == NocState::NocNotRecvd
+                    && c.trusted_root.is_none()
+            }
+        };
+        Ok(allow)
+    }
+
     pub fn allow_noc_change(&self) -> Result<bool, Error> {
         let mut inner = self.state.write()?;
         let allow = match &mut inner.state {
@@ -135,10 +264,281 @@ impl FailSafe {
         };
         Ok(allow)
     }
+
+    /// Hold on to the root certificate from AddTrustedRootCert, till the AddNOC
+    pub fn add_trusted_root(&self, root_ca: Cert) -> Result<(), Error> {
+        let mut inner = self.state.write()?;
+        match &mut inner.state {
+            State::Idle => Err(Error::Invalid),
+            State::Armed(c) => {
+                c.trusted_root = Some(Box::new(root_ca));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn take_trusted_root(&self) -> Option<Cert> {
+        let mut inner = self.state.write().unwrap();
+        match &mut inner.state {
+            State::Idle => None,
+            State::Armed(c) => c.trusted_root.take().map(|root_ca| *root_ca),
+        }
+    }
+
+    /// The time at which the fail-safe expires, if it is armed
+    pub fn get_expiry(&self) -> Option<SystemTime> {
+        match &self.state.read().unwrap().state {
+            State::Idle => None,
+            State::Armed(c) => Some(c.expiry),
+        }
+    }
+
+    /// Whether the fail-safe expired, since the last time this was called
+    ///
+    /// The BreadCrumb goes back to 0 with the expiry, which is up to the data model.
+    pub fn take_expired(&self) -> bool {
+        match self.state.write() {
+            Ok(mut inner) => std::mem::take(&mut inner.expired),
+            Err(_) => false,
+        }
+    }
+
+    /// Expire the fail-safe, if its time is up
+    pub fn handle_timeout(&self) {
+        if matches!(self.get_expiry(), Some(t) if t <= SystemTime::now()) {
+            info!("Fail-Safe timer expired");
+            self.expire();
+        }
+    }
+
+    /// Expire the fail-safe right away, if it is armed
+    pub fn force_expiry(&self) {
+        self.expire();
+    }
+
+    /// The session with this local session id was closed
+    ///
+    /// If it is the PASE session that armed the fail-safe, there is no one left to
+    /// complete the commissioning, so the fail-safe expires.
+    pub fn session_closed(&self, sess_id: u16) {
+        let armed_on_session = match &self.state.read().unwrap().state {
+            State::Armed(c) => c.session_mode == SessionMode::Pase && c.sess_id == sess_id,
+            State::Idle => false,
+        };
+        if armed_on_session {
+            info!("PASE session closed with an armed Fail-Safe");
+            self.expire();
+        }
+    }
+
+    fn remove_subscriptions(&self, fab_idx: u8) {
+        match self.subs_mgr.lock() {
+            Ok(mut subs_mgr) => subs_mgr.remove_fabric(fab_idx),
+            Err(e) => error!("Error in removing the subscriptions {:?}", e),
+        }
+    }
+
+    // Roll back whatever was done under the fail-safe. The trusted root that wasn't
+    // used yet, goes away along with the context.
+    //
+    // The Network Commissioning cluster is Ethernet only, there is no network config
+    // to revert.
+    fn expire(&self) {
+        let (noc_state, prev_fabric) = {
+            let mut inner = self.state.write().unwrap();
+            match std::mem::replace(&mut inner.state, State::Idle) {
+                State::Armed(c) => {
+                    inner.expired = true;
+                    (c.noc_state, c.prev_fabric)
+                }
+                State::Idle => return,
+            }
+        };
+
+        match noc_state {
+            NocState::NocNotRecvd => (),
+            NocState::AddNocRecvd(fab_idx) => {
+                info!("Fail-Safe: Removing the fabric {} from AddNOC", fab_idx);
+                if let Err(e) = self.fabric_mgr.remove(fab_idx) {
+                    error!("Error in removing the fabric {:?}", e);
+                }
+                let _ = self.acl_mgr.delete_for_fabric(fab_idx);
+                self.remove_subscriptions(fab_idx);
+                // This runs in the transport's own context, so don't wait for room in the
+                // queue
+                if self.wq.try_send(Msg::FabricRemoved(fab_idx)).is_err() {
+                    error!("Couldn't close the sessions on the removed fabric");
+                }
+            }
+            NocState::UpdateNocRecvd(fab_idx) => {
+                info!(
+                    "Fail-Safe: Reverting the UpdateNOC on the fabric {}",
+                    fab_idx
+                );
+                if let Some(prev_fabric) = prev_fabric {
+                    if let Err(e) = self.fabric_mgr.update(fab_idx, *prev_fabric) {
+                        error!("Error in reverting the fabric {:?}", e);
+                    }
+                }
+                // The sessions, and the subscriptions, that were established with the
+                // updated NOC have to go
+                self.remove_subscriptions(fab_idx);
+                if self.wq.try_send(Msg::FabricUpdated(fab_idx, None)).is_err() {
+                    error!("Couldn't close the sessions on the reverted fabric");
+                }
+            }
+        }
+
+        // The window through which this commissioning came, is available again
+        let mut pase_mgr = self.pase_mgr.clone();
+        let result = if pase_mgr.get_comm_window().is_some() {
+            pase_mgr.resume_comm_window()
+        } else if self.fabric_mgr.is_empty() {
+            info!("Fail-Safe: No fabrics left, opening the commissioning window");
+            pase_mgr.open_basic_comm_window(None, None)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            error!("Error in opening the commissioning window {:?}", e);
+        }
+    }
 }
 
-impl Default for FailSafe {
-    fn default() -> Self {
-        Self::new()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{transport::session::CaseDetails, utils::test_utils::temp_fabric_mgr};
+    use tempfile::TempDir;
+
+    // Each test has storage of its own, so they don't see each other's fabrics
+    fn failsafe() -> (FailSafe, TempDir) {
+        let (fabric_mgr, dir) = temp_fabric_mgr();
+        let fs = FailSafe::new(
+            fabric_mgr,
+            Arc::new(AclMgr::new()),
+            Arc::new(Mutex::new(SubsMgr::new())),
+            PaseMgr::new(WorkQ::new().0),
+            WorkQ::new().0,
+        );
+        (fs, dir)
+    }
+
+    #[test]
+    fn test_failsafe_expiry() {
+        let (fs, _dir) = failsafe();
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        assert!(fs.get_expiry().unwrap() > SystemTime::now());
+
+        // Not due yet
+        fs.handle_timeout();
+        assert!(fs.is_armed());
+
+        if let State::Armed(c) = &mut fs.state.write().unwrap().state {
+            c.expiry = SystemTime::now();
+        }
+        fs.handle_timeout();
+        assert!(!fs.is_armed());
+        assert!(fs.get_expiry().is_none());
+        // The data model finds out about it once
+        assert!(fs.take_expired());
+        assert!(!fs.take_expired());
+    }
+
+    #[test]
+    fn test_failsafe_arm_zero() {
+        let (fs, _dir) = failsafe();
+        // Nothing to expire
+        fs.arm(0, SessionMode::Pase, 1).unwrap();
+        assert!(!fs.is_armed());
+
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        fs.add_trusted_root(Cert::default()).unwrap();
+        fs.arm(0, SessionMode::Pase, 1).unwrap();
+        assert!(!fs.is_armed());
+        // The trusted root went away with the expiry
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        assert!(fs.take_trusted_root().is_none());
+    }
+
+    #[test]
+    fn test_failsafe_other_session_mode() {
+        let (fs, _dir) = failsafe();
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        let case = SessionMode::Case(CaseDetails::new(1, &[0; 3]));
+        assert_eq!(fs.arm(60, case, 2), Err(Error::Invalid));
+        assert!(fs.is_armed());
+    }
+
+    #[test]
+    fn test_failsafe_rearm() {
+        // Only the PASE session that armed it, can re-arm it
+        let (fs, _dir) = failsafe();
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        assert_eq!(fs.arm(60, SessionMode::Pase, 2), Err(Error::Invalid));
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        fs.arm(0, SessionMode::Pase, 1).unwrap();
+        assert!(fs.take_expired());
+
+        // Over CASE, any session on the fabric that armed it
+        let case = |fab_idx| SessionMode::Case(CaseDetails::new(fab_idx, &[0; 3]));
+        fs.arm(60, case(1), 3).unwrap();
+        fs.arm(60, case(1), 4).unwrap();
+        assert_eq!(fs.arm(60, case(2), 5), Err(Error::Invalid));
+        assert!(fs.is_armed());
+    }
+
+    #[test]
+    fn test_failsafe_allow_update_noc() {
+        let (fs, _dir) = failsafe();
+        let case = SessionMode::Case(CaseDetails::new(1, &[0; 3]));
+        assert!(!fs.allow_update_noc(case).unwrap());
+
+        // Only on the CASE session of the fabric that armed it
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        assert!(!fs.allow_update_noc(SessionMode::Pase).unwrap());
+        fs.arm(0, SessionMode::Pase, 1).unwrap();
+        fs.arm(60, case, 2).unwrap();
+        assert!(fs.allow_update_noc(case).unwrap());
+        assert!(!fs
+            .allow_update_noc(SessionMode::Case(CaseDetails::new(2, &[0; 3])))
+            .unwrap());
+
+        // Not after an AddTrustedRootCert
+        fs.add_trusted_root(Cert::default()).unwrap();
+        assert!(!fs.allow_update_noc(case).unwrap());
+    }
+
+    #[test]
+    fn test_failsafe_last_known_good_time() {
+        // The time from the AddNOC is only taken on, once the commissioning is complete
+        let (fs, _dir) = failsafe();
+        let time = crate::cert::BUILD_TIME + 100;
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        fs.record_add_noc(1, time).unwrap();
+        fs.arm(0, SessionMode::Pase, 1).unwrap();
+        assert!(fs.fabric_mgr.get_last_known_good_time() < time);
+
+        fs.arm(60, SessionMode::Pase, 1).unwrap();
+        fs.record_add_noc(1, time).unwrap();
+        fs.disarm(SessionMode::Case(CaseDetails::new(1, &[0; 3])))
+            .unwrap();
+        assert_eq!(fs.fabric_mgr.get_last_known_good_time(), time);
+    }
+
+    #[test]
+    fn test_failsafe_pase_session_closed() {
+        let (fs, _dir) = failsafe();
+        fs.arm(60, SessionMode::Pase, 5).unwrap();
+        fs.session_closed(6);
+        assert!(fs.is_armed());
+        fs.session_closed(5);
+        assert!(!fs.is_armed());
+
+        // Only the PASE session that armed it, expires it
+        let case = SessionMode::Case(CaseDetails::new(1, &[0; 3]));
+        fs.arm(60, case, 7).unwrap();
+        fs.session_closed(7);
+        assert!(fs.is_armed());
     }
 }
@@ -100,8 +100,8 @@ fn attr_comm_info_new() -> Attribute {
 
 #[derive(FromTLV, ToTLV)]
 struct FailSafeParams {
-    expiry_len: u8,
-    bread_crumb: u8,
+    expiry_len: u16,
+    bread_crumb: u64,
 }
 
 pub struct GenCommCluster {
@@ -151,9 +151,7 @@ impl ClusterType for GenCommCluster {
 }
 
 impl GenCommCluster {
-    pub fn new() -> Result<Box<Self>, Error> {
-        let failsafe = Arc::new(FailSafe::new());
-
+    pub fn new(failsafe: Arc<FailSafe>) -> Result<Box<Self>, Error> {
         let mut c = Box::new(GenCommCluster {
             // TODO: Arch-Specific
             expiry_len: 120,
@@ -172,8 +170,13 @@ impl GenCommCluster {
         Ok(c)
     }
 
-    pub fn failsafe(&self) -> Arc<FailSafe> {
-        self.failsafe.clone()
+    fn set_bread_crumb(&mut self, bread_crumb: u64) {
+        if let Err(e) = self.base.write_attribute_raw(
+            Attributes::BreadCrumb as u16,
+            AttrValue::Uint64(bread_crumb),
+        ) {
+            error!("Error in setting the BreadCrumb {:?}", e);
+        }
     }
 
     fn handle_command_armfailsafe(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
@@ -184,10 +187,16 @@ impl GenCommCluster {
 
         if self
             .failsafe
-            .arm(p.expiry_len, cmd_req.trans.session.get_session_mode())
+            .arm(
+                p.expiry_len,
+                cmd_req.trans.session.get_session_mode(),
+                cmd_req.trans.session.get_local_sess_id(),
+            )
             .is_err()
         {
             status = CommissioningError::ErrBusyWithOtherAdmin as u8;
+        } else if p.expiry_len > 0 {
+            self.set_bread_crumb(p.bread_crumb);
         }
 
         let cmd_data = CommonResponse {
@@ -199,6 +208,7 @@ impl GenCommCluster {
             ID,
             Commands::ArmFailsafeResp as u16,
             EncodeValue::Value(&cmd_data),
+            cmd_req.command_ref,
         );
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
@@ -227,6 +237,7 @@ impl GenCommCluster {
             ID,
             Commands::SetRegulatoryConfigResp as u16,
             EncodeValue::Value(&cmd_data),
+            cmd_req.command_ref,
         );
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
@@ -253,6 +264,8 @@ impl GenCommCluster {
             .is_err()
         {
             status = CommissioningError::ErrInvalidAuth as u8;
+        } else {
+            self.set_bread_crumb(0);
         }
 
         let cmd_data = CommonResponse {
@@ -264,6 +277,7 @@ impl GenCommCluster {
             ID,
             Commands::CommissioningCompleteResp as u16,
             EncodeValue::Value(&cmd_data),
+            cmd_req.command_ref,
         );
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
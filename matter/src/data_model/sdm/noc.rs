@@ -15,12 +15,14 @@
  *    limitations under the License.
  */
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::acl::{AclEntry, AclMgr, AuthMode};
 use crate::cert::Cert;
 use crate::crypto::{self, CryptoKeyPair, KeyPair};
+use crate::data_model::cluster_basic_information;
+use crate::data_model::core::subscribe::SubsMgr;
 use crate::data_model::objects::*;
 use crate::data_model::sdm::dev_att;
 use crate::fabric::{Fabric, FabricMgr, MAX_SUPPORTED_FABRICS};
@@ -28,6 +30,7 @@ use crate::interaction_model::command::CommandReq;
 use crate::interaction_model::core::IMStatusCode;
 use crate::interaction_model::messages::ib;
 use crate::tlv::{FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV, UtfStr};
+use crate::transport::queue::{Msg, WorkQ};
 use crate::transport::session::SessionMode;
 use crate::utils::writebuf::WriteBuf;
 use crate::{cmd_enter, error::*};
@@ -62,6 +65,7 @@ const MAX_CERT_DECLARATION_LEN: usize = 600;
 const MAX_CSR_LEN: usize = 300;
 // As defined in the Matter Spec
 const RESP_MAX: usize = 900;
+const MAX_FABRIC_LABEL_LEN: usize = 32;
 
 pub const ID: u32 = 0x003E;
 
@@ -74,6 +78,7 @@ pub enum Commands {
     CSRReq = 0x04,
     CSRResp = 0x05,
     AddNOC = 0x06,
+    UpdateNOC = 0x07,
     NOCResp = 0x08,
     UpdateFabricLabel = 0x09,
     RemoveFabric = 0x0a,
@@ -92,37 +97,44 @@ pub enum Attributes {
 
 pub struct NocCluster {
     base: Cluster,
-    dev_att: Box<dyn DevAttDataFetcher>,
+    dev_att: Box<dyn DevAttDataFetcher + Send + Sync>,
     fabric_mgr: Arc<FabricMgr>,
     acl_mgr: Arc<AclMgr>,
+    subs_mgr: Arc<Mutex<SubsMgr>>,
     failsafe: Arc<FailSafe>,
+    wq: WorkQ,
 }
 struct NocData {
     pub key_pair: KeyPair,
-    pub root_ca: Cert,
+    // The CSR was requested for an UpdateNOC, rather than an AddNOC
+    pub for_update_noc: bool,
 }
 
 impl NocData {
-    pub fn new(key_pair: KeyPair) -> Self {
+    pub fn new(key_pair: KeyPair, for_update_noc: bool) -> Self {
         Self {
             key_pair,
-            root_ca: Cert::default(),
+            for_update_noc,
         }
     }
 }
 
 impl NocCluster {
     pub fn new(
-        dev_att: Box<dyn DevAttDataFetcher>,
+        dev_att: Box<dyn DevAttDataFetcher + Send + Sync>,
         fabric_mgr: Arc<FabricMgr>,
         acl_mgr: Arc<AclMgr>,
+        subs_mgr: Arc<Mutex<SubsMgr>>,
         failsafe: Arc<FailSafe>,
+        wq: WorkQ,
     ) -> Result<Box<Self>, Error> {
         let mut c = Box::new(Self {
             dev_att,
             fabric_mgr,
             acl_mgr,
+            subs_mgr,
             failsafe,
+            wq,
             base: Cluster::new(ID)?,
         });
         let attrs = [
@@ -178,20 +190,26 @@ impl NocCluster {
         }
 
         let r = AddNocReq::from_tlv(&cmd_req.data).map_err(|_| NocStatus::InvalidNOC)?;
+        let (noc_value, icac_value) = get_noc_icac(r.noc_value, r.icac_value)?;
 
-        let noc_value = Cert::new(r.noc_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-        info!("Received NOC as: {}", noc_value);
-        let icac_value = if !r.icac_value.0.is_empty() {
-            let cert = Cert::new(r.icac_value.0).map_err(|_| NocStatus::InvalidNOC)?;
-            info!("Received ICAC as: {}", cert);
-            Some(cert)
-        } else {
-            None
-        };
-
+        // The trusted root goes along with the fail-safe, if it expires
+        let root_ca = self
+            .failsafe
+            .take_trusted_root()
+            .ok_or(NocStatus::InvalidNOC)?;
+        let time = self.fabric_mgr.get_last_known_good_time();
+        verify_noc_chain(&noc_value, icac_value.as_ref(), &root_ca, time)?;
+        // The time is at least the start of the validity of the certificates that the
+        // commissioner gave us, once the commissioning is complete
+        let not_before = [Some(&noc_value), icac_value.as_ref(), Some(&root_ca)]
+            .iter()
+            .flatten()
+            .map(|c| c.get_not_before())
+            .max()
+            .unwrap_or(0);
         let fabric = Fabric::new(
             noc_data.key_pair,
-            noc_data.root_ca,
+            root_ca,
             icac_value,
             noc_value,
             r.ipk_value.0,
@@ -207,16 +225,82 @@ impl NocCluster {
             error!("Failed to add ACL, what to do?");
         }
 
-        if self.failsafe.record_add_noc(fab_idx).is_err() {
+        if self.failsafe.record_add_noc(fab_idx, not_before).is_err() {
             error!("Failed to record NoC in the FailSafe, what to do?");
         }
-        NocCluster::create_nocresponse(cmd_req.resp, NocStatus::Ok, fab_idx, "".to_owned());
+        NocCluster::create_nocresponse(cmd_req, NocStatus::Ok, fab_idx, "".to_owned());
         cmd_req.trans.complete();
         Ok(())
     }
 
+    fn _handle_command_updatenoc(
+        &mut self,
+        cmd_req: &mut CommandReq,
+        fab_idx: u8,
+    ) -> Result<(), NocStatus> {
+        let noc_data = cmd_req
+            .trans
+            .session
+            .take_data::<NocData>()
+            .ok_or(NocStatus::MissingCsr)?;
+
+        let r = UpdateNocReq::from_tlv(&cmd_req.data).map_err(|_| NocStatus::InvalidNOC)?;
+        let (noc_value, icac_value) = get_noc_icac(r.noc_value, r.icac_value)?;
+
+        // The NOC should be for the key pair of the CSRRequest
+        let mut pubkey = [0u8; crypto::EC_POINT_LEN_BYTES];
+        let len = noc_data
+            .key_pair
+            .get_public_key(&mut pubkey)
+            .map_err(|_| NocStatus::InvalidPublicKey)?;
+        if noc_value.get_pubkey() != &pubkey[..len] {
+            error!("UpdateNOC: NOC doesn't match the key pair of the CSR");
+            return Err(NocStatus::InvalidPublicKey);
+        }
+
+        let fabric = {
+            let fabric = self
+                .fabric_mgr
+                .get_fabric(fab_idx as usize)
+                .map_err(|_| NocStatus::InvalidFabricIndex)?;
+            let fabric = (*fabric).as_ref().ok_or(NocStatus::InvalidFabricIndex)?;
+
+            // The new chain should lead to the root of the fabric, and stay on the fabric
+            let time = self.fabric_mgr.get_last_known_good_time();
+            verify_noc_chain(&noc_value, icac_value.as_ref(), &fabric.root_ca, time)?;
+            if noc_value.get_fabric_id() != Ok(fabric.get_fabric_id()) {
+                error!("UpdateNOC: NOC is for a different fabric");
+                return Err(NocStatus::InvalidNOC);
+            }
+
+            fabric
+                .with_noc(noc_data.key_pair, icac_value, noc_value)
+                .map_err(|_| NocStatus::InvalidNOC)?
+        };
+        // The previous NOC is put back, if the fail-safe expires
+        self.failsafe
+            .record_update_noc(fab_idx, || self.fabric_mgr.update(fab_idx, fabric))
+            .map_err(|e| {
+                error!("UpdateNOC: Error in updating the fabric {:?}", e);
+                NocStatus::InvalidNOC
+            })?;
+
+        // The other sessions on this fabric were established with the previous NOC, this
+        // one stays for the rest of the commissioning. This runs in the transport's own
+        // context, so don't wait for room in the queue
+        let sess_id = cmd_req.trans.session.get_local_sess_id();
+        if self
+            .wq
+            .try_send(Msg::FabricUpdated(fab_idx, Some(sess_id)))
+            .is_err()
+        {
+            error!("Couldn't close the sessions established with the previous NOC");
+        }
+        Ok(())
+    }
+
     fn create_nocresponse(
-        tw: &mut TLVWriter,
+        cmd_req: &mut CommandReq,
         status_code: NocStatus,
         fab_idx: u8,
         debug_txt: String,
@@ -231,8 +315,9 @@ impl NocCluster {
             ID,
             Commands::NOCResp as u16,
             EncodeValue::Value(&cmd_data),
+            cmd_req.command_ref,
         );
-        let _ = invoke_resp.to_tlv(tw, TagType::Anonymous);
+        let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
     }
 
     fn handle_command_updatefablabel(
@@ -246,6 +331,9 @@ impl NocCluster {
             .label
             .to_string()
             .map_err(|_| IMStatusCode::InvalidDataType)?;
+        if label.len() > MAX_FABRIC_LABEL_LEN {
+            return Err(IMStatusCode::ConstraintError);
+        }
 
         let (result, fab_idx) =
             if let SessionMode::Case(c) = cmd_req.trans.session.get_session_mode() {
@@ -258,7 +346,7 @@ impl NocCluster {
                 // Update Fabric Label not allowed
                 (NocStatus::InvalidFabricIndex, 0)
             };
-        NocCluster::create_nocresponse(cmd_req.resp, result, fab_idx, "".to_string());
+        NocCluster::create_nocresponse(cmd_req, result, fab_idx, "".to_string());
         cmd_req.trans.complete();
         Ok(())
     }
@@ -269,10 +357,24 @@ impl NocCluster {
             RemoveFabricReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
         if self.fabric_mgr.remove(req.fab_idx).is_ok() {
             let _ = self.acl_mgr.delete_for_fabric(req.fab_idx);
+            if let Ok(mut subs_mgr) = self.subs_mgr.lock() {
+                subs_mgr.remove_fabric(req.fab_idx);
+            }
+            if let Some(changes) = self.base.change_log() {
+                if let Err(e) = cluster_basic_information::emit_leave(changes, req.fab_idx) {
+                    error!("Error in emitting the Leave event {:?}", e);
+                }
+            }
+            // The sessions on this fabric, including the current one, are closed once
+            // this transaction is done. This runs in the transport's own context, so don't
+            // wait for room in the queue
+            if self.wq.try_send(Msg::FabricRemoved(req.fab_idx)).is_err() {
+                error!("Couldn't close the sessions on the removed fabric");
+            }
             cmd_req.trans.terminate();
         } else {
             NocCluster::create_nocresponse(
-                cmd_req.resp,
+                cmd_req,
                 NocStatus::InvalidFabricIndex,
                 req.fab_idx,
                 "".to_string(),
@@ -283,14 +385,53 @@ impl NocCluster {
 
     fn handle_command_addnoc(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("AddNOC");
+        let noc_data = cmd_req.trans.session.get_data::<NocData>();
+        if matches!(noc_data, Some(d) if d.for_update_noc) {
+            error!("AddNOC: The CSR was requested for an UpdateNOC");
+            return Err(IMStatusCode::ConstraintError);
+        }
         if let Err(e) = self._handle_command_addnoc(cmd_req) {
             //TODO: Fab-idx 0?
-            NocCluster::create_nocresponse(cmd_req.resp, e, 0, "".to_owned());
+            NocCluster::create_nocresponse(cmd_req, e, 0, "".to_owned());
             cmd_req.trans.complete();
         }
         Ok(())
     }
 
+    fn handle_command_updatenoc(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
+        cmd_enter!("UpdateNOC");
+        // This is only allowed on CASE, for the fabric of the session
+        let fab_idx = cmd_req
+            .trans
+            .session
+            .get_local_fabric_idx()
+            .ok_or(IMStatusCode::UnsupportedAccess)?;
+        if !self.failsafe.is_armed() {
+            return Err(IMStatusCode::FailSafeRequired);
+        }
+        if !self
+            .failsafe
+            .allow_update_noc(cmd_req.trans.session.get_session_mode())
+            .map_err(|_| IMStatusCode::Failure)?
+        {
+            error!("UpdateNOC not allowed by Fail Safe");
+            return Err(IMStatusCode::ConstraintError);
+        }
+        let noc_data = cmd_req.trans.session.get_data::<NocData>();
+        if matches!(noc_data, Some(d) if !d.for_update_noc) {
+            error!("UpdateNOC: The CSR was requested for an AddNOC");
+            return Err(IMStatusCode::ConstraintError);
+        }
+
+        let status = match self._handle_command_updatenoc(cmd_req, fab_idx) {
+            Ok(()) => NocStatus::Ok,
+            Err(e) => e,
+        };
+        NocCluster::create_nocresponse(cmd_req, status, fab_idx, "".to_owned());
+        cmd_req.trans.complete();
+        Ok(())
+    }
+
     fn handle_command_attrequest(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("AttestationRequest");
 
@@ -319,6 +460,7 @@ impl NocCluster {
             ID,
             Commands::AttReqResp as u16,
             EncodeValue::Closure(&cmd_data),
+            cmd_req.command_ref,
         );
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
@@ -350,6 +492,7 @@ impl NocCluster {
             ID,
             Commands::CertChainResp as u16,
             EncodeValue::Value(&cmd_data),
+            cmd_req.command_ref,
         );
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
         cmd_req.trans.complete();
@@ -359,12 +502,18 @@ impl NocCluster {
     fn handle_command_csrrequest(&mut self, cmd_req: &mut CommandReq) -> Result<(), IMStatusCode> {
         cmd_enter!("CSRRequest");
 
-        let req = CommonReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
-        info!("Received CSR Nonce:{:?}", req.str);
+        let req = CsrReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        info!("Received CSR Nonce:{:?}", req.nonce);
 
         if !self.failsafe.is_armed() {
             return Err(IMStatusCode::UnsupportedAccess);
         }
+        // An UpdateNOC is only for a fabric that the session is already on
+        let for_update_noc = req.for_update_noc.unwrap_or(false);
+        if for_update_noc && cmd_req.trans.session.get_session_mode() == SessionMode::Pase {
+            error!("CSRRequest: IsForUpdateNOC is not allowed over PASE");
+            return Err(IMStatusCode::InvalidCommand);
+        }
 
         let noc_keypair = KeyPair::new().map_err(|_| IMStatusCode::Failure)?;
         let mut attest_challenge = [0u8; crypto::SYMM_KEY_LEN_BYTES];
@@ -374,7 +523,7 @@ impl NocCluster {
             let mut buf: [u8; RESP_MAX] = [0; RESP_MAX];
             let mut nocsr_element = WriteBuf::new(&mut buf, RESP_MAX);
             let _ = t.start_struct(tag);
-            let _ = add_nocsrelement(&noc_keypair, req.str.0, &mut nocsr_element, t);
+            let _ = add_nocsrelement(&noc_keypair, req.nonce.0, &mut nocsr_element, t);
             let _ = add_attestation_signature(
                 self.dev_att.as_ref(),
                 &mut nocsr_element,
@@ -388,10 +537,11 @@ impl NocCluster {
             ID,
             Commands::CSRResp as u16,
             EncodeValue::Closure(&cmd_data),
+            cmd_req.command_ref,
         );
 
         let _ = resp.to_tlv(cmd_req.resp, TagType::Anonymous);
-        let noc_data = Box::new(NocData::new(noc_keypair));
+        let noc_data = Box::new(NocData::new(noc_keypair, for_update_noc));
         // Store this in the session data instead of cluster data, so it gets cleared
         // if the session goes away for some reason
         cmd_req.trans.session.set_data(noc_data);
@@ -408,24 +558,15 @@ impl NocCluster {
             return Err(IMStatusCode::UnsupportedAccess);
         }
 
-        // This may happen on CASE or PASE. For PASE, the existence of NOC Data is necessary
-        match cmd_req.trans.session.get_session_mode() {
-            SessionMode::Case(_) => error!("CASE: AddTrustedRootCert handling pending"), // For a CASE Session, we just return success for now,
-            SessionMode::Pase => {
-                let noc_data = cmd_req
-                    .trans
-                    .session
-                    .get_data::<NocData>()
-                    .ok_or(IMStatusCode::Failure)?;
-
-                let req =
-                    CommonReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
-                info!("Received Trusted Cert:{:x?}", req.str);
-
-                noc_data.root_ca = Cert::new(req.str.0).map_err(|_| IMStatusCode::Failure)?;
-            }
-            _ => (),
-        }
+        // This may happen on CASE or PASE. The root is held in the fail-safe, so it is
+        // dropped if the fail-safe expires before an AddNOC uses it.
+        let req = CommonReq::from_tlv(&cmd_req.data).map_err(|_| IMStatusCode::InvalidCommand)?;
+        info!("Received Trusted Cert:{:x?}", req.str);
+
+        let root_ca = Cert::new(req.str.0).map_err(|_| IMStatusCode::Failure)?;
+        self.failsafe
+            .add_trusted_root(root_ca)
+            .map_err(|_| IMStatusCode::UnsupportedAccess)?;
         cmd_req.trans.complete();
 
         Err(IMStatusCode::Success)
@@ -450,6 +591,7 @@ impl ClusterType for NocCluster {
             .ok_or(IMStatusCode::UnsupportedCommand)?;
         match cmd {
             Commands::AddNOC => self.handle_command_addnoc(cmd_req),
+            Commands::UpdateNOC => self.handle_command_updatenoc(cmd_req),
             Commands::CSRReq => self.handle_command_csrrequest(cmd_req),
             Commands::AddTrustedRootCert => self.handle_command_addtrustedrootcert(cmd_req),
             Commands::AttReq => self.handle_command_attrequest(cmd_req),
@@ -562,18 +704,32 @@ struct NocResp {
 #[tlvargs(lifetime = "'a")]
 struct AddNocReq<'a> {
     noc_value: OctetStr<'a>,
-    icac_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
     ipk_value: OctetStr<'a>,
     case_admin_subject: u64,
     vendor_id: u16,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct UpdateNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct CommonReq<'a> {
     str: OctetStr<'a>,
 }
 
+#[derive(FromTLV)]
+#[tlvargs(lifetime = "'a")]
+struct CsrReq<'a> {
+    nonce: OctetStr<'a>,
+    for_update_noc: Option<bool>,
+}
+
 #[derive(FromTLV)]
 #[tlvargs(lifetime = "'a")]
 struct UpdateFabricLabelReq<'a> {
@@ -590,6 +746,46 @@ struct RemoveFabricReq {
     fab_idx: u8,
 }
 
+fn get_noc_icac(
+    noc_value: OctetStr,
+    icac_value: Option<OctetStr>,
+) -> Result<(Cert, Option<Cert>), NocStatus> {
+    let noc_value = Cert::new(noc_value.0).map_err(|_| NocStatus::InvalidNOC)?;
+    info!("Received NOC as: {}", noc_value);
+    let icac_value = if let Some(icac) = icac_value.filter(|icac| !icac.0.is_empty()) {
+        let cert = Cert::new(icac.0).map_err(|_| NocStatus::InvalidNOC)?;
+        info!("Received ICAC as: {}", cert);
+        Some(cert)
+    } else {
+        None
+    };
+    Ok((noc_value, icac_value))
+}
+
+// The NOC, and the ICAC if any, should chain up to this root, at the Last Known Good UTC
+// Time, or later
+fn verify_noc_chain(
+    noc: &Cert,
+    icac: Option<&Cert>,
+    root_ca: &Cert,
+    last_known_good: u32,
+) -> Result<(), NocStatus> {
+    let mut verifier = noc.verify_chain_start(last_known_good);
+    if let Some(icac) = icac {
+        verifier = verifier.add_cert(icac).map_err(|e| {
+            error!("Error in verifying the NOC against the ICAC {:?}", e);
+            NocStatus::InvalidNOC
+        })?;
+    }
+    verifier
+        .add_cert(root_ca)
+        .and_then(|v| v.finalise())
+        .map_err(|e| {
+            error!("Error in verifying the NOC chain {:?}", e);
+            NocStatus::InvalidNOC
+        })
+}
+
 fn get_certchainrequest_params(data: &TLVElement) -> Result<DataType, Error> {
     let cert_type = CertChainReq::from_tlv(data)?.cert_type;
 
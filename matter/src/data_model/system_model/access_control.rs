@@ -23,8 +23,8 @@ use crate::acl::{self, AclEntry, AclMgr};
 use crate::data_model::objects::*;
 use crate::error::*;
 use crate::interaction_model::core::IMStatusCode;
-use crate::interaction_model::messages::ib::{attr_list_write, ListOperation};
-use crate::tlv::{FromTLV, TLVElement, TagType, ToTLV};
+use crate::interaction_model::messages::ib::ListOperation;
+use crate::tlv::{FromTLV, Nullable, TLVElement, TLVWriter, TagType, ToTLV};
 use log::{error, info};
 
 pub const ID: u32 = 0x001F;
@@ -38,6 +38,29 @@ pub enum Attributes {
     EntriesPerFabric = 4,
 }
 
+#[derive(FromPrimitive)]
+pub enum Events {
+    AccessControlEntryChanged = 0,
+}
+
+#[derive(Clone, Copy)]
+enum ChangeType {
+    Changed = 0,
+    Added = 1,
+    Removed = 2,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(start = 1)]
+struct AclChangedEvent {
+    admin_node_id: Nullable<u64>,
+    admin_passcode_id: Nullable<u16>,
+    change_type: u8,
+    latest_value: Nullable<AclEntry>,
+    #[tagval(0xFE)]
+    fab_idx: u8,
+}
+
 pub struct AccessControlCluster {
     base: Cluster,
     acl_mgr: Arc<AclMgr>,
@@ -54,9 +77,85 @@ impl AccessControlCluster {
         c.base.add_attribute(attr_subjects_per_entry_new())?;
         c.base.add_attribute(attr_targets_per_entry_new())?;
         c.base.add_attribute(attr_entries_per_fabric_new())?;
+        c.base.add_event(Event::new(
+            Events::AccessControlEntryChanged as u32,
+            Access::READ | Access::NEED_ADMIN | Access::FAB_SENSITIVE,
+        ))?;
         Ok(c)
     }
 
+    // The entries of a fabric, in the order of their index within the fabric
+    fn fabric_entries(&self, fab_idx: u8) -> Vec<AclEntry> {
+        let mut entries = Vec::new();
+        let _ = self.acl_mgr.for_each_acl(|e| {
+            if e.fab_idx == Some(fab_idx) {
+                entries.push(*e);
+            }
+        });
+        entries
+    }
+
+    // Emit the events for an ACL operation, given the entries of the fabric before it
+    fn emit_acl_changes(
+        &self,
+        op: &ListOperation,
+        data: &TLVElement,
+        attr: &AttrDetails,
+        old: &[AclEntry],
+    ) {
+        let written = || {
+            AclEntry::from_tlv(data).ok().map(|mut e| {
+                e.fab_idx = Some(attr.fab_idx);
+                e
+            })
+        };
+        let changes = match op {
+            ListOperation::AddItem => vec![(ChangeType::Added, written())],
+            ListOperation::EditItem(_) => vec![(ChangeType::Changed, written())],
+            ListOperation::DeleteItem(index) => {
+                vec![(ChangeType::Removed, old.get(*index as usize).copied())]
+            }
+            ListOperation::DeleteList => old
+                .iter()
+                .map(|e| (ChangeType::Removed, Some(*e)))
+                .collect(),
+        };
+        for (change_type, entry) in changes {
+            if let Some(entry) = entry {
+                self.emit_acl_changed(attr, change_type, entry);
+            }
+        }
+    }
+
+    fn emit_acl_changed(&self, attr: &AttrDetails, change_type: ChangeType, entry: AclEntry) {
+        let changes = match self.base.change_log() {
+            Some(changes) => changes,
+            None => return,
+        };
+        // The admin is either on a CASE session, or on the PASE session with passcode ID 0
+        let (admin_node_id, admin_passcode_id) = match attr.node_id {
+            Some(node_id) => (Nullable::NotNull(node_id), Nullable::Null),
+            None => (Nullable::Null, Nullable::NotNull(0)),
+        };
+        let event = AclChangedEvent {
+            admin_node_id,
+            admin_passcode_id,
+            change_type: change_type as u8,
+            latest_value: Nullable::NotNull(entry),
+            fab_idx: attr.fab_idx,
+        };
+        if let Err(e) = changes.emit_event(
+            0,
+            ID,
+            Events::AccessControlEntryChanged as u32,
+            EventPriority::Info,
+            attr.fab_idx,
+            &event,
+        ) {
+            error!("Error in emitting the ACL change event {:?}", e);
+        }
+    }
+
     /// Write the ACL Attribute
     ///
     /// This takes care of 4 things, add item, edit item, delete item, delete list.
@@ -123,23 +222,22 @@ impl ClusterType for AccessControlCluster {
         }
     }
 
-    fn write_attribute(
+    fn write_list_attribute(
         &mut self,
+        op: &ListOperation,
         attr: &AttrDetails,
         data: &TLVElement,
     ) -> Result<(), IMStatusCode> {
-        let result = if let Some(Attributes::Acl) = num::FromPrimitive::from_u16(attr.attr_id) {
-            attr_list_write(attr, data, |op, data| {
-                self.write_acl_attr(&op, data, attr.fab_idx)
-            })
+        if let Some(Attributes::Acl) = num::FromPrimitive::from_u16(attr.attr_id) {
+            let old = self.fabric_entries(attr.fab_idx);
+            self.write_acl_attr(op, data, attr.fab_idx)?;
+            self.emit_acl_changes(op, data, attr, &old);
+            self.base.cluster_changed();
+            Ok(())
         } else {
             error!("Attribute not yet supported: this shouldn't happen");
             Err(IMStatusCode::NotFound)
-        };
-        if result.is_ok() {
-            self.base.cluster_changed();
         }
-        result
     }
 }
 
@@ -148,7 +246,7 @@ fn attr_acl_new() -> Attribute {
         Attributes::Acl as u16,
         AttrValue::Custom,
         Access::RWFA,
-        Quality::NONE,
+        Quality::LIST,
     )
 }
 
@@ -157,7 +255,7 @@ fn attr_extension_new() -> Attribute {
         Attributes::Extension as u16,
         AttrValue::Custom,
         Access::RWFA,
-        Quality::NONE,
+        Quality::LIST,
     )
 }
 
@@ -213,7 +311,7 @@ mod tests {
         let mut writebuf = WriteBuf::new(&mut buf, buf_len);
         let mut tw = TLVWriter::new(&mut writebuf);
 
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new());
         let mut acl = AccessControlCluster::new(acl_mgr.clone()).unwrap();
 
         let new = AclEntry::new(2, Privilege::VIEW, AuthMode::Case);
@@ -242,7 +340,7 @@ mod tests {
         let mut tw = TLVWriter::new(&mut writebuf);
 
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new());
         let mut verifier = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -277,7 +375,7 @@ mod tests {
     /// - The listindex used for delete should be relative to the current fabric
     fn acl_cluster_delete() {
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new());
         let input = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -313,7 +411,7 @@ mod tests {
         let mut writebuf = WriteBuf::new(&mut buf, buf_len);
 
         // Add 3 ACLs, belonging to fabric index 2, 1 and 2, in that order
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
+        let acl_mgr = Arc::new(AclMgr::new());
         let input = [
             AclEntry::new(2, Privilege::VIEW, AuthMode::Case),
             AclEntry::new(1, Privilege::VIEW, AuthMode::Case),
@@ -332,6 +430,7 @@ mod tests {
                 list_index: None,
                 fab_idx: 1,
                 fab_filter: false,
+                node_id: None,
             };
             acl.read_custom_attribute(&mut encoder, &attr_details);
             assert_eq!(
@@ -356,6 +455,7 @@ mod tests {
                 list_index: None,
                 fab_idx: 1,
                 fab_filter: true,
+                node_id: None,
             };
             acl.read_custom_attribute(&mut encoder, &attr_details);
             assert_eq!(
@@ -378,6 +478,7 @@ mod tests {
                 list_index: None,
                 fab_idx: 2,
                 fab_filter: true,
+                node_id: None,
             };
             acl.read_custom_attribute(&mut encoder, &attr_details);
             assert_eq!(
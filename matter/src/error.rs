@@ -49,8 +49,11 @@ pub enum Error {
     NoTagFound,
     NotFound,
     PacketPoolExhaust,
+    RetransExhausted,
+    Duplicate,
     StdIoError,
     SysTimeFail,
+    Timeout,
     Invalid,
     InvalidAAD,
     InvalidData,
@@ -59,6 +62,18 @@ pub enum Error {
     InvalidPeerAddr,
     // Invalid Auth Key in the Matter Certificate
     InvalidAuthKey,
+    // The Basic Constraints of the Matter Certificate don't match its role in the chain
+    InvalidBasicConstraints,
+    // More CA certificates in the chain than the Path Length Constraint allows
+    CertPathLenExceeded,
+    // The Key Usage, or the Extended Key Usage, of the Matter Certificate doesn't match its role
+    InvalidKeyUsage,
+    InvalidExtKeyUsage,
+    // Missing, or invalid, Subject Key ID in the Matter Certificate
+    InvalidSubjKeyId,
+    // The Matter Certificate is outside its validity period
+    CertNotYetValid,
+    CertExpired,
     InvalidSignature,
     InvalidState,
     InvalidTime,
@@ -15,18 +15,27 @@
  *    limitations under the License.
  */
 
-use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::{
+    convert::TryFrom,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard, RwLock,
+    },
+};
 
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use log::{error, info};
 use owning_ref::RwLockReadGuardRef;
 
 use crate::{
-    cert::Cert,
-    crypto::{self, crypto_dummy::KeyPairDummy, hkdf_sha256, CryptoKeyPair, HmacSha256, KeyPair},
+    cert::{self, Cert},
+    crypto::{
+        self, crypto_dummy::KeyPairDummy, hkdf_sha256, CryptoKeyPair, HmacSha256, KeyPair, Sha256,
+    },
     error::Error,
     group_keys::KeySet,
     mdns::{self, Mdns},
+    secure_channel::resumption::{NocHash, ResumptionMgr},
     sys::{Psm, SysMdnsService},
     tlv::{OctetStr, TLVWriter, TagType, ToTLV, UtfStr},
 };
@@ -49,12 +58,14 @@ const ST_LBL: &str = "label";
 const ST_PBKEY: &str = "pubkey";
 const ST_PRKEY: &str = "privkey";
 
+const LAST_KNOWN_GOOD_KV_ENTRY: &str = "last_known_good";
+
 #[allow(dead_code)]
 pub struct Fabric {
     node_id: u64,
     fabric_id: u64,
     vendor_id: u16,
-    key_pair: Box<dyn CryptoKeyPair>,
+    key_pair: Box<dyn CryptoKeyPair + Send + Sync>,
     pub root_ca: Cert,
     pub icac: Option<Cert>,
     pub noc: Cert,
@@ -104,22 +115,48 @@ impl Fabric {
         };
         Fabric::get_compressed_id(f.root_ca.get_pubkey(), fabric_id, &mut f.compressed_id)?;
         f.ipk = KeySet::new(ipk, &f.compressed_id)?;
+        f.publish()?;
+        Ok(f)
+    }
+
+    /// The fabric with new operational credentials, as from an UpdateNOC
+    ///
+    /// The root certificate, the IPK and the vendor id remain the same.
+    pub fn with_noc(
+        &self,
+        key_pair: KeyPair,
+        icac: Option<Cert>,
+        noc: Cert,
+    ) -> Result<Self, Error> {
+        let mut buf = [0u8; MAX_CERT_TLV_LEN];
+        let len = self.root_ca.as_tlv(&mut buf)?;
+        let root_ca = Cert::new(&buf[..len])?;
+        Fabric::new(
+            key_pair,
+            root_ca,
+            icac,
+            noc,
+            self.ipk.epoch_key(),
+            self.vendor_id,
+        )
+    }
 
+    fn publish(&mut self) -> Result<(), Error> {
         let mut mdns_service_name = String::with_capacity(33);
-        for c in f.compressed_id {
+        for c in self.compressed_id {
             mdns_service_name.push_str(&format!("{:02X}", c));
         }
         mdns_service_name.push('-');
         let mut node_id_be: [u8; 8] = [0; 8];
-        BigEndian::write_u64(&mut node_id_be, node_id);
+        BigEndian::write_u64(&mut node_id_be, self.node_id);
         for c in node_id_be {
             mdns_service_name.push_str(&format!("{:02X}", c));
         }
         info!("MDNS Service Name: {}", mdns_service_name);
-        f.mdns_service = Some(
+        self.mdns_service = Some(
             Mdns::get()?.publish_service(&mdns_service_name, mdns::ServiceMode::Commissioned)?,
         );
-        Ok(f)
+        Ok(())
     }
 
     pub fn dummy() -> Result<Self, Error> {
@@ -150,7 +187,9 @@ impl Fabric {
             .map_err(|_| Error::NoSpace)
     }
 
-    pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<(), Error> {
+    /// The destination ID, that an initiator uses in Sigma1, to address the node with
+    /// this node id on this fabric
+    pub fn get_dest_id(&self, random: &[u8], node_id: u64, out: &mut [u8]) -> Result<(), Error> {
         let mut mac = HmacSha256::new(self.ipk.op_key())?;
 
         mac.update(random)?;
@@ -160,11 +199,15 @@ impl Fabric {
         LittleEndian::write_u64(&mut buf, self.fabric_id);
         mac.update(&buf)?;
 
-        LittleEndian::write_u64(&mut buf, self.node_id);
+        LittleEndian::write_u64(&mut buf, node_id);
         mac.update(&buf)?;
 
+        mac.finish(out)
+    }
+
+    pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<(), Error> {
         let mut id = [0_u8; crypto::SHA256_HASH_LEN_BYTES];
-        mac.finish(&mut id)?;
+        self.get_dest_id(random, self.node_id, &mut id)?;
         if id.as_slice() == target {
             Ok(())
         } else {
@@ -184,6 +227,21 @@ impl Fabric {
         self.fabric_id
     }
 
+    /// The SHA-256 hash of our NOC on this fabric
+    pub fn get_noc_hash(&self) -> Result<NocHash, Error> {
+        let mut buf = [0u8; MAX_CERT_TLV_LEN];
+        let len = self.noc.as_tlv(&mut buf)?;
+        let mut hasher = Sha256::new()?;
+        hasher.update(&buf[..len])?;
+        let mut hash: NocHash = Default::default();
+        hasher.finish(&mut hash)?;
+        Ok(hash)
+    }
+
+    pub fn get_vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
     pub fn get_fabric_desc(&self, fab_idx: u8) -> FabricDescriptor {
         FabricDescriptor {
             root_public_key: OctetStr::new(self.root_ca.get_pubkey()),
@@ -299,16 +357,24 @@ pub struct FabricMgrInner {
 pub struct FabricMgr {
     inner: RwLock<FabricMgrInner>,
     psm: Arc<Mutex<Psm>>,
+    // The CASE resumption entries, these go away with the credentials of their fabric
+    resumption: Mutex<ResumptionMgr>,
+    // The Last Known Good UTC Time, in seconds since the Matter epoch
+    last_known_good: AtomicU32,
 }
 
 impl FabricMgr {
-    pub fn new() -> Result<Self, Error> {
+    /// The fabrics, the CASE resumption entries on them, and the Last Known Good UTC Time
+    /// are loaded from, and stored to, this storage
+    pub fn new_with_psm(psm: Arc<Mutex<Psm>>) -> Result<Self, Error> {
         let dummy_fabric = Fabric::dummy()?;
         let mut mgr = FabricMgrInner::default();
         mgr.fabrics[0] = Some(dummy_fabric);
         let mut fm = Self {
             inner: RwLock::new(mgr),
-            psm: Psm::get()?,
+            resumption: Mutex::new(ResumptionMgr::new_with_psm(psm.clone())),
+            psm,
+            last_known_good: AtomicU32::new(cert::BUILD_TIME),
         };
         fm.load()?;
         Ok(fm)
@@ -322,6 +388,12 @@ impl FabricMgr {
     fn load(&mut self) -> Result<(), Error> {
         let mut mgr = self.inner.write()?;
         let psm = self.psm.lock().unwrap();
+        // It never goes behind the build time
+        let mut time = 0;
+        if psm.get_kv_u64(LAST_KNOWN_GOOD_KV_ENTRY, &mut time).is_ok() {
+            let time = u32::try_from(time).unwrap_or(0);
+            self.last_known_good.fetch_max(time, Ordering::Relaxed);
+        }
         for i in 0..MAX_SUPPORTED_FABRICS {
             let result = Fabric::load(i, &psm);
             if let Ok(fabric) = result {
@@ -346,19 +418,75 @@ impl FabricMgr {
         Ok(index as u8)
     }
 
+    /// Replace the fabric at this index, as an UpdateNOC does, and return the one that was
+    /// replaced
+    ///
+    /// The label stays with the fabric index. The replaced fabric isn't advertised any more,
+    /// it is advertised again if it is put back with another update.
+    pub fn update(&self, fab_idx: u8, mut f: Fabric) -> Result<Fabric, Error> {
+        let index = fab_idx as usize;
+        let mut mgr = self.inner.write()?;
+        let old = mgr.fabrics[index].as_ref().ok_or(Error::NotFound)?;
+        f.label = old.label.clone();
+        if f.mdns_service.is_none() {
+            f.publish()?;
+        }
+
+        self.store(index, &f)?;
+
+        let mut old = mgr.fabrics[index].replace(f).ok_or(Error::NotFound)?;
+        old.mdns_service = None;
+        self.resumption.lock()?.remove_fabric(fab_idx);
+        Ok(old)
+    }
+
     pub fn remove(&self, fab_idx: u8) -> Result<(), Error> {
         let fab_idx = fab_idx as usize;
         let mut mgr = self.inner.write().unwrap();
-        let psm = self.psm.lock().unwrap();
         if let Some(f) = &mgr.fabrics[fab_idx] {
-            f.rm_store(fab_idx, &psm);
+            f.rm_store(fab_idx, &self.psm.lock().unwrap());
             mgr.fabrics[fab_idx] = None;
+            self.resumption.lock()?.remove_fabric(fab_idx as u8);
             Ok(())
         } else {
             Err(Error::NotFound)
         }
     }
 
+    /// The CASE resumption entries of the peers on our fabrics
+    pub fn get_resumption_mgr(&self) -> Result<MutexGuard<ResumptionMgr>, Error> {
+        Ok(self.resumption.lock()?)
+    }
+
+    /// The Last Known Good UTC Time, in seconds since the Matter epoch
+    ///
+    /// The validity periods of the certificates are checked against this, if the system
+    /// time isn't set, or is behind it. It is at least [cert::BUILD_TIME].
+    pub fn get_last_known_good_time(&self) -> u32 {
+        self.last_known_good.load(Ordering::Relaxed)
+    }
+
+    /// Move the Last Known Good UTC Time forward to this time, and persist it
+    ///
+    /// This only ever moves forward.
+    pub fn set_last_known_good_time(&self, time: u32) -> Result<(), Error> {
+        if self.last_known_good.fetch_max(time, Ordering::Relaxed) >= time {
+            return Ok(());
+        }
+        let time = self.get_last_known_good_time();
+        self.psm
+            .lock()?
+            .set_kv_u64(LAST_KNOWN_GOOD_KV_ENTRY, time.into())
+    }
+
+    /// Stop advertising the operational mDNS services of all the fabrics
+    pub fn unpublish_services(&self) {
+        let mut mgr = self.inner.write().unwrap();
+        for fabric in mgr.fabrics.iter_mut().flatten() {
+            fabric.mdns_service = None;
+        }
+    }
+
     pub fn match_dest_id(&self, random: &[u8], target: &[u8]) -> Result<usize, Error> {
         let mgr = self.inner.read()?;
         for i in 0..MAX_SUPPORTED_FABRICS {
@@ -418,6 +546,9 @@ impl FabricMgr {
         let mut mgr = self.inner.write()?;
         if !label.is_empty() {
             for i in 1..MAX_SUPPORTED_FABRICS {
+                if i == index {
+                    continue;
+                }
                 if let Some(fabric) = &mgr.fabrics[i] {
                     if fabric.label == label {
                         return Err(Error::Invalid);
@@ -437,3 +568,29 @@ impl FabricMgr {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{cert::BUILD_TIME, fabric::FabricMgr, utils::test_utils::temp_psm};
+
+    #[test]
+    fn test_last_known_good_time_persisted() {
+        let (psm, _dir) = temp_psm();
+        let fabric_mgr = FabricMgr::new_with_psm(psm.clone()).unwrap();
+        assert_eq!(fabric_mgr.get_last_known_good_time(), BUILD_TIME);
+
+        let time = BUILD_TIME + 100;
+        fabric_mgr.set_last_known_good_time(time).unwrap();
+        // It only moves forward
+        fabric_mgr.set_last_known_good_time(time - 50).unwrap();
+        assert_eq!(fabric_mgr.get_last_known_good_time(), time);
+
+        // Another instance on the same storage picks it up, one on storage of its own
+        // doesn't
+        let fabric_mgr = FabricMgr::new_with_psm(psm).unwrap();
+        assert_eq!(fabric_mgr.get_last_known_good_time(), time);
+        let (other_psm, _other_dir) = temp_psm();
+        let fabric_mgr = FabricMgr::new_with_psm(other_psm).unwrap();
+        assert_eq!(fabric_mgr.get_last_known_good_time(), BUILD_TIME);
+    }
+}
@@ -0,0 +1,621 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+// The client side of the Interaction Model: requests that we initiate to a peer, on an
+// already established session
+
+use async_channel::{bounded, Receiver, Sender, TrySendError};
+use log::{error, info};
+
+use crate::{
+    data_model::objects::EncodeValue,
+    error::Error,
+    tlv::{self, get_root_node_struct, FromTLV, TLVElement, TLVWriter, TagType, ToTLV},
+    transport::{
+        exchange::{ExchangeCtx, SessionTarget},
+        packet::{Packet, MAX_RX_BUF_SIZE},
+        proto_demux::{InitiateReq, ProtoCtx, ResponseRequired},
+        queue::{Msg, WorkQ},
+    },
+    utils::writebuf::WriteBuf,
+};
+
+use super::{
+    core::{IMStatusCode, OpCode, PROTO_ID_INTERACTION_MODEL},
+    messages::{
+        ib::{self, AttrStatus, CmdData, CmdPath},
+        msg::{
+            InvReq, InvResp, ReadReq, ReportDataMsg, StatusResp, SubscribeReq, SubscribeResp,
+            WriteReq, WriteResp,
+        },
+    },
+    InteractionModel,
+};
+
+// The reports of a subscription that are queued, till the application picks them up
+const MAX_PENDING_REPORTS: usize = 8;
+
+/// The response from the peer, to a request that we initiated
+///
+/// For a Read or a Subscribe, the ReportData chunks are gathered here, as they arrive. So
+/// are the InvokeResponse chunks, for an Invoke.
+#[derive(Debug)]
+pub struct ImResponse {
+    opcode: OpCode,
+    payload: Vec<u8>,
+    reports: Vec<Vec<u8>>,
+}
+
+impl ImResponse {
+    /// The opcode of the last message of the response
+    pub fn opcode(&self) -> OpCode {
+        self.opcode
+    }
+
+    /// The TLV payload of the last message of the response
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// The response to the (only) command of an InvokeRequest
+    pub fn cmd_resp(&self) -> Result<ib::InvResp<'_>, Error> {
+        self.cmd_resps()?.into_iter().next().ok_or(Error::Invalid)
+    }
+
+    /// The responses to the commands of an InvokeRequest
+    pub fn cmd_resps(&self) -> Result<Vec<ib::InvResp<'_>>, Error> {
+        let root = self.get_root(OpCode::InvokeResponse)?;
+        if self.reports.is_empty() {
+            return Ok(InvResp::from_tlv(&root)?
+                .inv_responses
+                .map(|r| r.iter().collect())
+                .unwrap_or_default());
+        }
+        let mut cmd_resps = Vec::new();
+        for report in &self.reports {
+            let root = get_root_node_struct(report)?;
+            if let Some(r) = InvResp::from_tlv(&root)?.inv_responses {
+                cmd_resps.extend(r.iter());
+            }
+        }
+        Ok(cmd_resps)
+    }
+
+    /// The attribute data and statuses of all the ReportData chunks, in the order in
+    /// which they came
+    pub fn attr_reports(&self) -> Result<Vec<ib::AttrResp<'_>>, Error> {
+        if self.reports.is_empty() {
+            // Nothing was reported, possibly because the request was rejected
+            self.get_root(OpCode::ReportData)?;
+        }
+        let mut attr_reports = Vec::new();
+        for report in &self.reports {
+            let root = get_root_node_struct(report)?;
+            if let Some(r) = ReportDataMsg::from_tlv(&root)?.attr_reports {
+                attr_reports.extend(r.iter());
+            }
+        }
+        Ok(attr_reports)
+    }
+
+    /// The statuses of the attributes of a WriteRequest
+    pub fn write_resp(&self) -> Result<Vec<AttrStatus>, Error> {
+        let root = self.get_root(OpCode::WriteResponse)?;
+        Ok(WriteResp::from_tlv(&root)?.write_responses.iter().collect())
+    }
+
+    /// The subscription, as the peer confirmed it
+    pub fn subscription(&self) -> Result<SubscribeResp, Error> {
+        let root = self.get_root(OpCode::SubscriptResponse)?;
+        SubscribeResp::from_tlv(&root)
+    }
+
+    // If the peer rejected the whole request with a StatusResponse, this is an error
+    fn get_root(&self, expected: OpCode) -> Result<TLVElement<'_>, Error> {
+        let root = get_root_node_struct(&self.payload)?;
+        if self.opcode == OpCode::StatusResponse {
+            let status = StatusResp::from_tlv(&root)?;
+            error!("Request rejected with status {:?}", status.status);
+            Err(Error::Invalid)
+        } else if self.opcode != expected {
+            Err(Error::InvalidOpcode)
+        } else {
+            Ok(root)
+        }
+    }
+}
+
+/// The outcome of a request that we initiated
+pub type ImResult = Result<ImResponse, Error>;
+
+/// A request that we initiate, this goes to the Interaction Model in an InitiateReq
+pub struct ImInitiateReq {
+    opcode: OpCode,
+    payload: Vec<u8>,
+    notify: Sender<ImResult>,
+}
+
+// What to do, once a message of the response is received
+#[derive(Debug, PartialEq)]
+enum Step {
+    // More is to come on this exchange, acknowledge with a StatusResponse
+    Ack,
+    // The response is complete, acknowledge it with a StatusResponse if ack is set
+    Done { ack: bool },
+}
+
+// This lives in the exchange data, till the response is complete
+struct ImClient {
+    // The request that we sent, or ReportData for a report on one of our subscriptions
+    req: OpCode,
+    reports: Vec<Vec<u8>>,
+    notify: Sender<ImResult>,
+}
+
+impl ImClient {
+    fn new(req: OpCode, notify: Sender<ImResult>) -> Self {
+        Self {
+            req,
+            reports: Vec::new(),
+            notify,
+        }
+    }
+
+    fn on_resp(&mut self, opcode: OpCode, payload: &[u8]) -> Result<Step, Error> {
+        match (self.req, opcode) {
+            (
+                OpCode::ReadRequest | OpCode::SubscribeRequest | OpCode::ReportData,
+                OpCode::ReportData,
+            ) => {
+                let root = get_root_node_struct(payload)?;
+                let report = ReportDataMsg::from_tlv(&root)?;
+                self.reports.push(payload.to_vec());
+                // The priming reports of a subscription are followed by the
+                // SubscribeResponse, so each of them is acknowledged
+                if report.more_chunks == Some(true) || self.req == OpCode::SubscribeRequest {
+                    Ok(Step::Ack)
+                } else {
+                    Ok(Step::Done {
+                        ack: report.suppress_response != Some(true),
+                    })
+                }
+            }
+            (OpCode::InvokeRequest, OpCode::InvokeResponse) => {
+                let root = get_root_node_struct(payload)?;
+                let resp = InvResp::from_tlv(&root)?;
+                self.reports.push(payload.to_vec());
+                if resp.more_chunks == Some(true) {
+                    Ok(Step::Ack)
+                } else {
+                    Ok(Step::Done { ack: false })
+                }
+            }
+            (OpCode::SubscribeRequest, OpCode::SubscriptResponse)
+            | (OpCode::WriteRequest, OpCode::WriteResponse)
+            | (_, OpCode::StatusResponse) => Ok(Step::Done { ack: false }),
+            _ => {
+                error!("Unexpected response {:?} to {:?}", opcode, self.req);
+                Err(Error::InvalidOpcode)
+            }
+        }
+    }
+
+    fn into_response(self, opcode: OpCode, payload: &[u8]) -> ImResponse {
+        ImResponse {
+            opcode,
+            payload: payload.to_vec(),
+            reports: self.reports,
+        }
+    }
+}
+
+/// Read attributes from the peer of a session
+///
+/// This only queues the request to the transport of wq. The response is delivered on
+/// the returned channel, which is closed without a response if the peer stops responding.
+pub fn read(wq: &WorkQ, sess_id: u16, req: &ReadReq) -> Result<Receiver<ImResult>, Error> {
+    send_request(wq, sess_id, OpCode::ReadRequest, req, bounded(1))
+}
+
+/// Write attributes on the peer of a session
+///
+/// The response is delivered as in [read].
+pub fn write(wq: &WorkQ, sess_id: u16, req: &WriteReq) -> Result<Receiver<ImResult>, Error> {
+    send_request(wq, sess_id, OpCode::WriteRequest, req, bounded(1))
+}
+
+/// Invoke the commands of an InvokeRequest on the peer of a session
+///
+/// The response is delivered as in [read].
+pub fn invoke_req(wq: &WorkQ, sess_id: u16, req: &InvReq) -> Result<Receiver<ImResult>, Error> {
+    send_request(wq, sess_id, OpCode::InvokeRequest, req, bounded(1))
+}
+
+/// Invoke a command on the peer of a session
+///
+/// The command fields are encoded from data. The response is delivered as in [read].
+pub fn invoke(
+    wq: &WorkQ,
+    sess_id: u16,
+    path: CmdPath,
+    data: &dyn ToTLV,
+) -> Result<Receiver<ImResult>, Error> {
+    let cmd = [CmdData::new(path, EncodeValue::Value(data))];
+    invoke_req(wq, sess_id, &InvReq::new(&cmd))
+}
+
+/// Subscribe to attributes on the peer of a session
+///
+/// The first response on the returned channel carries the priming reports, along with
+/// the SubscribeResponse. Each report that the peer sends later on, follows as a
+/// response of its own. Once the channel is dropped, the next report from the peer is
+/// rejected, which ends the subscription.
+///
+/// The reports have to be picked up as they come. If a report arrives while 8 of them
+/// are still pending, the subscription ends: the peer is told so, and the channel is
+/// closed once the pending reports are picked up.
+pub fn subscribe(
+    wq: &WorkQ,
+    sess_id: u16,
+    req: &SubscribeReq,
+) -> Result<Receiver<ImResult>, Error> {
+    send_request(
+        wq,
+        sess_id,
+        OpCode::SubscribeRequest,
+        req,
+        bounded(MAX_PENDING_REPORTS),
+    )
+}
+
+fn send_request(
+    wq: &WorkQ,
+    sess_id: u16,
+    opcode: OpCode,
+    req: &dyn ToTLV,
+    (notify, rx): (Sender<ImResult>, Receiver<ImResult>),
+) -> Result<Receiver<ImResult>, Error> {
+    let mut buf = [0u8; MAX_RX_BUF_SIZE];
+    let mut wb = WriteBuf::new(&mut buf, MAX_RX_BUF_SIZE);
+    let mut tw = TLVWriter::new(&mut wb);
+    req.to_tlv(&mut tw, TagType::Anonymous)?;
+
+    let req = ImInitiateReq {
+        opcode,
+        payload: wb.as_borrow_slice().to_vec(),
+        notify,
+    };
+    wq.sync_send(Msg::Initiate(InitiateReq::new(
+        PROTO_ID_INTERACTION_MODEL,
+        SessionTarget::Secured(sess_id),
+        Box::new(req),
+    )))?;
+    Ok(rx)
+}
+
+impl InteractionModel {
+    pub(super) fn initiate_req(
+        &mut self,
+        req: ImInitiateReq,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        tx.set_proto_opcode(req.opcode as u8);
+        if let Err(e) = tx.get_writebuf()?.copy_from_slice(&req.payload) {
+            let _ = req.notify.try_send(Err(e));
+            return Err(e);
+        }
+        info!("Sending request {:?}", req.opcode);
+        tlv::print_tlv_list(tx.as_borrow_slice());
+        exch_ctx
+            .exch
+            .set_data_boxed(Box::new(ImClient::new(req.opcode, req.notify)));
+        Ok(())
+    }
+
+    /// Whether this exchange is a request that we initiated
+    pub(super) fn is_client(ctx: &mut ProtoCtx) -> bool {
+        ctx.exch_ctx.exch.get_data_boxed::<ImClient>().is_some()
+    }
+
+    pub(super) fn handle_client_resp(
+        &mut self,
+        opcode: OpCode,
+        ctx: &mut ProtoCtx,
+    ) -> Result<ResponseRequired, Error> {
+        let mut client = ctx
+            .exch_ctx
+            .exch
+            .take_data_boxed::<ImClient>()
+            .ok_or(Error::InvalidState)?;
+
+        let rx_buf = ctx.rx.as_borrow_slice();
+        let status = match client.on_resp(opcode, rx_buf) {
+            Ok(Step::Ack) => {
+                ctx.exch_ctx.exch.set_data_boxed(client);
+                Some(IMStatusCode::Success)
+            }
+            Ok(Step::Done { ack }) => {
+                ctx.exch_ctx.exch.close();
+                let notify = client.notify.clone();
+                let resp = client.into_response(opcode, rx_buf);
+                if let Ok(subs) = resp.subscription() {
+                    info!("Subscription {} confirmed", subs.subs_id);
+                    self.remove_subscriptions(None);
+                    let peer = ctx.exch_ctx.sess.get_peer_node_id();
+                    let sess_id = ctx.exch_ctx.sess.get_local_sess_id();
+                    self.subscriptions
+                        .insert((peer, subs.subs_id), (sess_id, notify.clone()));
+                }
+                let status = match notify.try_send(Ok(resp)) {
+                    Ok(()) => IMStatusCode::Success,
+                    Err(TrySendError::Full(_)) => {
+                        // The application doesn't keep up with the reports. Rather than
+                        // lose this one silently, the subscription ends: the channel
+                        // closes after the pending reports, and the peer is told.
+                        error!("Too many reports pending, ending the subscription");
+                        notify.close();
+                        self.remove_subscriptions(None);
+                        IMStatusCode::ResourceExhausted
+                    }
+                    Err(TrySendError::Closed(_)) => {
+                        error!("Dropping response, nobody is waiting for it");
+                        IMStatusCode::Success
+                    }
+                };
+                ack.then_some(status)
+            }
+            Err(e) => {
+                ctx.exch_ctx.exch.close();
+                let _ = client.notify.try_send(Err(e));
+                None
+            }
+        };
+
+        if let Some(status) = status {
+            InteractionModel::create_status_response(&mut ctx.tx, status)?;
+            Ok(ResponseRequired::Yes)
+        } else {
+            Ok(ResponseRequired::No)
+        }
+    }
+
+    /// Handle a report that the peer sends on one of our subscriptions
+    pub(super) fn handle_subs_report(
+        &mut self,
+        ctx: &mut ProtoCtx,
+    ) -> Result<ResponseRequired, Error> {
+        let root = get_root_node_struct(ctx.rx.as_borrow_slice())?;
+        let subs_id = ReportDataMsg::from_tlv(&root)?.subscription_id;
+        let key = (
+            ctx.exch_ctx.sess.get_peer_node_id(),
+            subs_id.unwrap_or_default(),
+        );
+
+        match self.subscriptions.get(&key) {
+            Some((_, notify)) if subs_id.is_some() && !notify.is_closed() => {
+                let client = ImClient::new(OpCode::ReportData, notify.clone());
+                ctx.exch_ctx.exch.set_data_boxed(Box::new(client));
+                self.handle_client_resp(OpCode::ReportData, ctx)
+            }
+            _ => {
+                info!("Rejecting report on subscription {:?}", subs_id);
+                self.subscriptions.remove(&key);
+                ctx.exch_ctx.exch.close();
+                InteractionModel::create_status_response(
+                    &mut ctx.tx,
+                    IMStatusCode::InvalidSubscription,
+                )?;
+                Ok(ResponseRequired::Yes)
+            }
+        }
+    }
+
+    /// Forget our subscriptions whose reports nobody waits for anymore, along with those
+    /// on the session with this local session ID, if any
+    pub(super) fn remove_subscriptions(&mut self, closed_sess_id: Option<u16>) {
+        self.subscriptions
+            .retain(|(_, subs_id), (sess_id, notify)| {
+                let keep = !notify.is_closed() && Some(*sess_id) != closed_sess_id;
+                if !keep {
+                    info!("Removing subscription {}", subs_id);
+                }
+                keep
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        interaction_model::messages::{ib::AttrPath, GenericPath},
+        tlv::TLVArray,
+    };
+
+    fn encode(data: &dyn ToTLV) -> Vec<u8> {
+        let mut buf = [0u8; 100];
+        let mut wb = WriteBuf::new(&mut buf, 100);
+        let mut tw = TLVWriter::new(&mut wb);
+        data.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+        wb.as_borrow_slice().to_vec()
+    }
+
+    fn report(value: u8, more_chunks: bool, suppress_response: bool) -> Vec<u8> {
+        let path = AttrPath::new(&GenericPath::new(Some(0), Some(6), Some(0)));
+        let attr = [ib::AttrResp::new(1, &path, EncodeValue::Value(&value))];
+        encode(&ReportDataMsg {
+            subscription_id: None,
+            attr_reports: Some(TLVArray::new(&attr)),
+            event_reports: None,
+            more_chunks: more_chunks.then_some(true),
+            suppress_response: suppress_response.then_some(true),
+        })
+    }
+
+    #[test]
+    fn test_cmd_resp() {
+        let data = 5u8;
+        let resp = [ib::InvResp::cmd_new(
+            0,
+            0x30,
+            1,
+            EncodeValue::Value(&data),
+            None,
+        )];
+        let resp = ImResponse {
+            opcode: OpCode::InvokeResponse,
+            payload: encode(&InvResp {
+                suppress_response: Some(false),
+                inv_responses: Some(TLVArray::new(&resp)),
+                more_chunks: None,
+            }),
+            reports: Vec::new(),
+        };
+        match resp.cmd_resp().unwrap() {
+            ib::InvResp::Cmd(c) => {
+                assert_eq!(c.path, CmdPath::new(Some(0), Some(0x30), Some(1)));
+                assert_eq!(c.data.unwrap_tlv().unwrap().u8(), Ok(5));
+            }
+            _ => panic!("Expected command data"),
+        }
+
+        let resp = ImResponse {
+            opcode: OpCode::StatusResponse,
+            payload: encode(&StatusResp {
+                status: IMStatusCode::UnsupportedAccess,
+            }),
+            reports: Vec::new(),
+        };
+        assert_eq!(resp.cmd_resp().err(), Some(Error::Invalid));
+        assert_eq!(resp.attr_reports().err(), Some(Error::Invalid));
+    }
+
+    #[test]
+    fn test_chunked_read() {
+        let (notify, _rx) = bounded(1);
+        let mut client = ImClient::new(OpCode::ReadRequest, notify);
+
+        let chunk = report(1, true, false);
+        assert_eq!(client.on_resp(OpCode::ReportData, &chunk), Ok(Step::Ack));
+        let last = report(2, false, true);
+        assert_eq!(
+            client.on_resp(OpCode::ReportData, &last),
+            Ok(Step::Done { ack: false })
+        );
+        assert_eq!(
+            client.on_resp(OpCode::InvokeResponse, &last),
+            Err(Error::InvalidOpcode)
+        );
+
+        let resp = client.into_response(OpCode::ReportData, &last);
+        let values: Vec<u8> = resp
+            .attr_reports()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap_data().data.unwrap_tlv().unwrap().u8().unwrap())
+            .collect();
+        assert_eq!(values, [1, 2]);
+    }
+
+    #[test]
+    fn test_chunked_invoke() {
+        let (notify, _rx) = bounded(1);
+        let mut client = ImClient::new(OpCode::InvokeRequest, notify);
+
+        let resp = |value: u8, more_chunks: bool| {
+            let resp = [ib::InvResp::cmd_new(
+                0,
+                0x30,
+                1,
+                EncodeValue::Value(&value),
+                None,
+            )];
+            encode(&InvResp {
+                suppress_response: Some(false),
+                inv_responses: Some(TLVArray::new(&resp)),
+                more_chunks: more_chunks.then_some(true),
+            })
+        };
+        let chunk = resp(1, true);
+        assert_eq!(
+            client.on_resp(OpCode::InvokeResponse, &chunk),
+            Ok(Step::Ack)
+        );
+        let last = resp(2, false);
+        assert_eq!(
+            client.on_resp(OpCode::InvokeResponse, &last),
+            Ok(Step::Done { ack: false })
+        );
+
+        let resp = client.into_response(OpCode::InvokeResponse, &last);
+        let values: Vec<u8> = resp
+            .cmd_resps()
+            .unwrap()
+            .into_iter()
+            .map(|r| match r {
+                ib::InvResp::Cmd(c) => c.data.unwrap_tlv().unwrap().u8().unwrap(),
+                _ => panic!("Expected command data"),
+            })
+            .collect();
+        assert_eq!(values, [1, 2]);
+    }
+
+    #[test]
+    fn test_subscribe_resp() {
+        let (notify, _rx) = bounded(1);
+        let mut client = ImClient::new(OpCode::SubscribeRequest, notify);
+
+        // Every priming report is acknowledged, even the last one
+        let chunk = report(1, false, false);
+        assert_eq!(client.on_resp(OpCode::ReportData, &chunk), Ok(Step::Ack));
+        let subs_resp = encode(&SubscribeResp::new(10, 60));
+        assert_eq!(
+            client.on_resp(OpCode::SubscriptResponse, &subs_resp),
+            Ok(Step::Done { ack: false })
+        );
+        let resp = client.into_response(OpCode::SubscriptResponse, &subs_resp);
+        let subs = resp.subscription().unwrap();
+        assert_eq!((subs.subs_id, subs.max_int), (10, 60));
+        assert_eq!(resp.attr_reports().unwrap().len(), 1);
+
+        // A later report on the subscription is acknowledged once it is complete
+        let (notify, _rx) = bounded(1);
+        let mut client = ImClient::new(OpCode::ReportData, notify);
+        assert_eq!(
+            client.on_resp(OpCode::ReportData, &chunk),
+            Ok(Step::Done { ack: true })
+        );
+    }
+
+    #[test]
+    fn test_write_resp() {
+        let path = GenericPath::new(Some(0), Some(6), Some(0));
+        let statuses = [AttrStatus::new(&path, IMStatusCode::UnsupportedWrite, 0)];
+        let resp = ImResponse {
+            opcode: OpCode::WriteResponse,
+            payload: encode(&WriteResp {
+                write_responses: TLVArray::new(&statuses),
+            }),
+            reports: Vec::new(),
+        };
+        let statuses = resp.write_resp().unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].status.status, IMStatusCode::UnsupportedWrite);
+        assert_eq!(statuses[0].path.to_gp(), path);
+    }
+}
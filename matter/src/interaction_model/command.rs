@@ -24,11 +24,16 @@ use super::InteractionModel;
 use super::Transaction;
 use crate::{
     error::*,
-    tlv::{get_root_node_struct, print_tlv_list, FromTLV, TLVElement, TLVWriter, TagType},
+    tlv::{
+        get_root_node_struct, print_tlv_list, FromTLV, TLVArray, TLVElement, TLVWriter, TagType,
+    },
     transport::{packet::Packet, proto_demux::ResponseRequired},
 };
 use log::error;
 
+// The default maximum number of commands in an InvokeRequest
+pub const MAX_PATHS_PER_INVOKE: u16 = 8;
+
 #[macro_export]
 macro_rules! cmd_enter {
     ($e:expr) => {{
@@ -39,6 +44,8 @@ macro_rules! cmd_enter {
 
 pub struct CommandReq<'a, 'b, 'c, 'd> {
     pub cmd: ib::CmdPath,
+    /// The CommandRef of the command, the responses to it carry this too
+    pub command_ref: Option<u16>,
     pub data: TLVElement<'a>,
     pub resp: &'a mut TLVWriter<'b, 'c>,
     pub trans: &'a mut Transaction<'d>,
@@ -67,6 +74,13 @@ impl InteractionModel {
             InteractionModel::create_status_response(proto_tx, IMStatusCode::TimedRequestMisMatch)?;
             return Ok(ResponseRequired::Yes);
         }
+        if let Some(inv_requests) = &inv_req.inv_requests {
+            if let Err(e) = self.validate_inv_requests(inv_requests) {
+                error!("Invalid InvokeRequests: {:?}", e);
+                InteractionModel::create_status_response(proto_tx, e)?;
+                return Ok(ResponseRequired::Yes);
+            }
+        }
 
         tw.start_struct(TagType::Anonymous)?;
         // Suppress Response -> TODO: Need to revisit this for cases where we send a command back
@@ -85,4 +99,31 @@ impl InteractionModel {
         tw.end_container()?;
         Ok(ResponseRequired::Yes)
     }
+
+    // A batch of commands should have unique, concrete paths, and each command should have
+    // a unique CommandRef, so its responses can be correlated with it
+    fn validate_inv_requests(
+        &self,
+        inv_requests: &TLVArray<ib::CmdData>,
+    ) -> Result<(), IMStatusCode> {
+        let count = inv_requests.iter().count();
+        if count > self.max_paths_per_invoke as usize {
+            return Err(IMStatusCode::InvalidAction);
+        }
+        if count > 1 {
+            let mut seen: Vec<(ib::CmdPath, u16)> = Vec::with_capacity(count);
+            for i in inv_requests.iter() {
+                let command_ref = i.command_ref.ok_or(IMStatusCode::InvalidAction)?;
+                if i.path.path.is_wildcard()
+                    || seen
+                        .iter()
+                        .any(|(path, r)| *path == i.path || *r == command_ref)
+                {
+                    return Err(IMStatusCode::InvalidAction);
+                }
+                seen.push((i.path, command_ref));
+            }
+        }
+        Ok(())
+    }
 }
@@ -15,14 +15,18 @@
  *    limitations under the License.
  */
 
-use std::time::{Duration, SystemTime};
+use std::{
+    any::Any,
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
 use crate::{
     error::*,
     interaction_model::messages::msg::StatusResp,
     tlv::{self, get_root_node_struct, FromTLV, TLVElement, TLVWriter, TagType, ToTLV},
     transport::{
-        exchange::Exchange,
+        exchange::{Exchange, ExchangeCtx},
         packet::Packet,
         proto_demux::{self, ProtoCtx, ResponseRequired},
         session::Session,
@@ -33,7 +37,9 @@ use log::{error, info};
 use num;
 use num_derive::FromPrimitive;
 
+use super::client::ImInitiateReq;
 use super::InteractionModel;
+use super::SubsReportReq;
 use super::Transaction;
 use super::TransactionState;
 use super::{messages::msg::TimedReq, InteractionConsumer};
@@ -42,7 +48,7 @@ use super::{messages::msg::TimedReq, InteractionConsumer};
  */
 
 /* Interaction Model ID as per the Matter Spec */
-const PROTO_ID_INTERACTION_MODEL: usize = 0x01;
+pub const PROTO_ID_INTERACTION_MODEL: usize = 0x01;
 
 #[derive(FromPrimitive, Debug, Copy, Clone, PartialEq)]
 pub enum OpCode {
@@ -106,8 +112,16 @@ impl<'a> Transaction<'a> {
 }
 
 impl InteractionModel {
-    pub fn new(consumer: Box<dyn InteractionConsumer>) -> InteractionModel {
-        InteractionModel { consumer }
+    /// An InvokeRequest with more than `max_paths_per_invoke` commands is rejected
+    pub fn new(
+        consumer: Box<dyn InteractionConsumer + Send>,
+        max_paths_per_invoke: u16,
+    ) -> InteractionModel {
+        InteractionModel {
+            consumer,
+            max_paths_per_invoke,
+            subscriptions: HashMap::new(),
+        }
     }
 
     pub fn handle_subscribe_req(
@@ -172,6 +186,22 @@ impl InteractionModel {
         }
     }
 
+    fn initiate_report(
+        &mut self,
+        req: SubsReportReq,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        tx.set_proto_opcode(OpCode::ReportData as u8);
+        let mut trans = Transaction::new(&mut exch_ctx.sess, exch_ctx.exch);
+        let mut tw = TLVWriter::new(tx.get_writebuf()?);
+        self.consumer
+            .consume_subs_report(req.subs_id, &mut trans, &mut tw)?;
+        info!("Sending report on subscription {}", req.subs_id);
+        tlv::print_tlv_list(tx.as_borrow_slice());
+        Ok(())
+    }
+
     pub(super) fn create_status_response(
         proto_tx: &mut Packet,
         status: IMStatusCode,
@@ -185,10 +215,20 @@ impl InteractionModel {
 
 impl proto_demux::HandleProto for InteractionModel {
     fn handle_proto_id(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
-        let mut trans = Transaction::new(&mut ctx.exch_ctx.sess, ctx.exch_ctx.exch);
         let proto_opcode: OpCode =
             num::FromPrimitive::from_u8(ctx.rx.get_proto_opcode()).ok_or(Error::Invalid)?;
         ctx.tx.set_proto_id(PROTO_ID_INTERACTION_MODEL as u16);
+        if InteractionModel::is_client(ctx) {
+            info!("{} {:?}", "Received response".cyan(), proto_opcode);
+            tlv::print_tlv_list(ctx.rx.as_borrow_slice());
+            return self.handle_client_resp(proto_opcode, ctx);
+        } else if proto_opcode == OpCode::ReportData {
+            info!("{} {:?}", "Received report".cyan(), proto_opcode);
+            tlv::print_tlv_list(ctx.rx.as_borrow_slice());
+            return self.handle_subs_report(ctx);
+        }
+
+        let mut trans = Transaction::new(&mut ctx.exch_ctx.sess, ctx.exch_ctx.exch);
 
         let buf = ctx.rx.as_borrow_slice();
         info!("{} {:?}", "Received command".cyan(), proto_opcode);
@@ -221,6 +261,37 @@ impl proto_demux::HandleProto for InteractionModel {
     fn get_proto_id(&self) -> usize {
         PROTO_ID_INTERACTION_MODEL
     }
+
+    fn initiate(
+        &mut self,
+        data: Box<dyn Any + Send>,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        match data.downcast::<ImInitiateReq>() {
+            Ok(req) => self.initiate_req(*req, exch_ctx, tx),
+            Err(data) => {
+                let req = data
+                    .downcast::<SubsReportReq>()
+                    .map_err(|_| Error::Invalid)?;
+                self.initiate_report(*req, exch_ctx, tx)
+            }
+        }
+    }
+
+    fn get_next_timeout(&self) -> Option<SystemTime> {
+        self.consumer.get_next_timeout()
+    }
+
+    fn handle_timeout(&mut self) -> Result<(), Error> {
+        self.remove_subscriptions(None);
+        self.consumer.handle_timeout()
+    }
+
+    fn handle_session_closed(&mut self, local_sess_id: u16) {
+        self.remove_subscriptions(Some(local_sess_id));
+        self.consumer.handle_session_closed(local_sess_id)
+    }
 }
 
 #[derive(FromPrimitive, Debug, Clone, Copy, PartialEq)]
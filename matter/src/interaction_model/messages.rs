@@ -73,7 +73,7 @@ pub mod msg {
 
     use super::ib::{
         self, AttrData, AttrPath, AttrResp, AttrStatus, CmdData, DataVersionFilter, EventFilter,
-        EventPath,
+        EventPath, EventResp,
     };
 
     #[derive(Default, FromTLV, ToTLV)]
@@ -83,8 +83,8 @@ pub mod msg {
         pub min_int_floor: u16,
         pub max_int_ceil: u16,
         pub attr_requests: Option<TLVArray<'a, AttrPath>>,
-        event_requests: Option<TLVArray<'a, EventPath>>,
-        event_filters: Option<TLVArray<'a, EventFilter>>,
+        pub event_requests: Option<TLVArray<'a, EventPath>>,
+        pub event_filters: Option<TLVArray<'a, EventFilter>>,
         // The Context Tags are discontiguous for some reason
         _dummy: Option<bool>,
         pub fabric_filtered: bool,
@@ -106,6 +106,16 @@ pub mod msg {
             self
         }
 
+        pub fn set_event_requests(mut self, requests: &'a [EventPath]) -> Self {
+            self.event_requests = Some(TLVArray::new(requests));
+            self
+        }
+
+        pub fn set_event_filters(mut self, filters: &'a [EventFilter]) -> Self {
+            self.event_filters = Some(TLVArray::new(filters));
+            self
+        }
+
         pub fn to_read_req(&self) -> ReadReq<'a> {
             ReadReq {
                 attr_requests: self.attr_requests,
@@ -159,11 +169,22 @@ pub mod msg {
         pub inv_requests: Option<TLVArray<'a, CmdData<'a>>>,
     }
 
+    impl<'a> InvReq<'a> {
+        pub fn new(inv_requests: &'a [CmdData<'a>]) -> Self {
+            Self {
+                suppress_response: Some(false),
+                timed_request: Some(false),
+                inv_requests: Some(TLVArray::new(inv_requests)),
+            }
+        }
+    }
+
     // This enum is helpful when we are constructing the response
     // step by step in incremental manner
     pub enum InvRespTag {
         SupressResponse = 0,
         InvokeResponses = 1,
+        MoreChunkedMsgs = 2,
     }
 
     #[derive(FromTLV, ToTLV, Debug)]
@@ -171,14 +192,15 @@ pub mod msg {
     pub struct InvResp<'a> {
         pub suppress_response: Option<bool>,
         pub inv_responses: Option<TLVArray<'a, ib::InvResp<'a>>>,
+        pub more_chunks: Option<bool>,
     }
 
     #[derive(Default, ToTLV, FromTLV)]
     #[tlvargs(lifetime = "'a")]
     pub struct ReadReq<'a> {
         pub attr_requests: Option<TLVArray<'a, AttrPath>>,
-        event_requests: Option<TLVArray<'a, EventPath>>,
-        event_filters: Option<TLVArray<'a, EventFilter>>,
+        pub event_requests: Option<TLVArray<'a, EventPath>>,
+        pub event_filters: Option<TLVArray<'a, EventFilter>>,
         pub fabric_filtered: bool,
         pub dataver_filters: Option<TLVArray<'a, DataVersionFilter>>,
     }
@@ -195,6 +217,24 @@ pub mod msg {
             self.attr_requests = Some(TLVArray::new(requests));
             self
         }
+
+        pub fn set_event_requests(mut self, requests: &'a [EventPath]) -> Self {
+            self.event_requests = Some(TLVArray::new(requests));
+            self
+        }
+
+        pub fn set_event_filters(mut self, filters: &'a [EventFilter]) -> Self {
+            self.event_filters = Some(TLVArray::new(filters));
+            self
+        }
+
+        /// The first event number that is of interest, as per the event filters
+        pub fn event_min(&self) -> u64 {
+            self.event_filters
+                .as_ref()
+                .and_then(|f| f.iter().filter_map(|f| f.event_min).max())
+                .unwrap_or(0)
+        }
     }
 
     #[derive(ToTLV, FromTLV)]
@@ -203,7 +243,7 @@ pub mod msg {
         pub supress_response: Option<bool>,
         timed_request: Option<bool>,
         pub write_requests: TLVArray<'a, AttrData<'b>>,
-        more_chunked: Option<bool>,
+        pub more_chunked: Option<bool>,
     }
 
     impl<'a, 'b> WriteReq<'a, 'b> {
@@ -219,6 +259,12 @@ pub mod msg {
             }
             w
         }
+
+        /// This is a chunk of the write request, more chunks follow
+        pub fn set_more_chunked(mut self) -> Self {
+            self.more_chunked = Some(true);
+            self
+        }
     }
 
     // Report Data
@@ -227,8 +273,7 @@ pub mod msg {
     pub struct ReportDataMsg<'a> {
         pub subscription_id: Option<u32>,
         pub attr_reports: Option<TLVArray<'a, AttrResp<'a>>>,
-        // TODO
-        pub event_reports: Option<bool>,
+        pub event_reports: Option<TLVArray<'a, EventResp<'a>>>,
         pub more_chunks: Option<bool>,
         pub suppress_response: Option<bool>,
     }
@@ -236,7 +281,7 @@ pub mod msg {
     pub enum ReportDataTag {
         SubscriptionId = 0,
         AttributeReports = 1,
-        _EventReport = 2,
+        EventReports = 2,
         MoreChunkedMsgs = 3,
         SupressResponse = 4,
     }
@@ -275,25 +320,39 @@ pub mod ib {
     }
 
     impl<'a> InvResp<'a> {
-        pub fn cmd_new(endpoint: u16, cluster: u32, cmd: u16, data: EncodeValue<'a>) -> Self {
-            Self::Cmd(CmdData::new(
-                CmdPath::new(Some(endpoint), Some(cluster), Some(cmd)),
-                data,
-            ))
+        /// The command_ref is that of the command that this is a response to
+        pub fn cmd_new(
+            endpoint: u16,
+            cluster: u32,
+            cmd: u16,
+            data: EncodeValue<'a>,
+            command_ref: Option<u16>,
+        ) -> Self {
+            Self::Cmd(CmdData {
+                command_ref,
+                ..CmdData::new(CmdPath::new(Some(endpoint), Some(cluster), Some(cmd)), data)
+            })
         }
 
-        pub fn status_new(cmd_path: CmdPath, status: IMStatusCode, cluster_status: u16) -> Self {
+        /// The command_ref is that of the command that this is a response to
+        pub fn status_new(
+            cmd_path: CmdPath,
+            status: IMStatusCode,
+            cluster_status: u16,
+            command_ref: Option<u16>,
+        ) -> Self {
             Self::Status(CmdStatus {
-                path: cmd_path,
-                status: Status::new(status, cluster_status),
+                command_ref,
+                ..CmdStatus::new(cmd_path, status, cluster_status)
             })
         }
     }
 
     #[derive(FromTLV, ToTLV, Copy, Clone, PartialEq, Debug)]
     pub struct CmdStatus {
-        path: CmdPath,
-        status: Status,
+        pub path: CmdPath,
+        pub status: Status,
+        pub command_ref: Option<u16>,
     }
 
     impl CmdStatus {
@@ -304,8 +363,14 @@ pub mod ib {
                     status,
                     cluster_status,
                 },
+                command_ref: None,
             }
         }
+
+        pub fn set_command_ref(mut self, command_ref: u16) -> Self {
+            self.command_ref = Some(command_ref);
+            self
+        }
     }
 
     #[derive(Debug, Clone, Copy, FromTLV, ToTLV)]
@@ -313,11 +378,23 @@ pub mod ib {
     pub struct CmdData<'a> {
         pub path: CmdPath,
         pub data: EncodeValue<'a>,
+        /// Correlates the responses with the request, in an InvokeRequest with more than
+        /// one command
+        pub command_ref: Option<u16>,
     }
 
     impl<'a> CmdData<'a> {
         pub fn new(path: CmdPath, data: EncodeValue<'a>) -> Self {
-            Self { path, data }
+            Self {
+                path,
+                data,
+                command_ref: None,
+            }
+        }
+
+        pub fn set_command_ref(mut self, command_ref: u16) -> Self {
+            self.command_ref = Some(command_ref);
+            self
         }
     }
 
@@ -429,8 +506,8 @@ pub mod ib {
 
     #[derive(Debug, Clone, Copy, PartialEq, FromTLV, ToTLV)]
     pub struct AttrStatus {
-        path: AttrPath,
-        status: Status,
+        pub path: AttrPath,
+        pub status: Status,
     }
 
     impl AttrStatus {
@@ -535,7 +612,8 @@ pub mod ib {
         pub data_ver: u32,
     }
 
-    #[derive(FromTLV, ToTLV, Copy, Clone)]
+    // Event Path
+    #[derive(Default, FromTLV, ToTLV, Copy, Clone, Debug, PartialEq)]
     #[tlvargs(datatype = "list")]
     pub struct EventPath {
         pub node: Option<u64>,
@@ -545,9 +623,82 @@ pub mod ib {
         pub is_urgent: Option<bool>,
     }
 
+    impl EventPath {
+        pub fn new(path: &GenericPath) -> Self {
+            Self {
+                endpoint: path.endpoint,
+                cluster: path.cluster,
+                event: path.leaf,
+                ..Default::default()
+            }
+        }
+
+        pub fn to_gp(&self) -> GenericPath {
+            GenericPath::new(self.endpoint, self.cluster, self.event)
+        }
+    }
+
     #[derive(FromTLV, ToTLV, Copy, Clone)]
     pub struct EventFilter {
         pub node: Option<u64>,
         pub event_min: Option<u64>,
     }
+
+    impl EventFilter {
+        pub fn new(event_min: u64) -> Self {
+            Self {
+                node: None,
+                event_min: Some(event_min),
+            }
+        }
+    }
+
+    // Event Response
+    #[derive(Clone, Copy, FromTLV, ToTLV, PartialEq, Debug)]
+    #[tlvargs(lifetime = "'a")]
+    pub enum EventResp<'a> {
+        Status(EventStatus),
+        Data(EventData<'a>),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, FromTLV, ToTLV)]
+    pub struct EventStatus {
+        pub path: EventPath,
+        pub status: Status,
+    }
+
+    impl EventStatus {
+        pub fn new(path: &GenericPath, status: IMStatusCode) -> Self {
+            Self {
+                path: EventPath::new(path),
+                status: Status::new(status, 0),
+            }
+        }
+    }
+
+    // This enum is helpful when we are constructing the Event Data step by step
+    pub enum EventDataTag {
+        Path = 0,
+        EventNumber = 1,
+        Priority = 2,
+        EpochTimestamp = 3,
+        SystemTimestamp = 4,
+        DeltaEpochTimestamp = 5,
+        DeltaSystemTimestamp = 6,
+        Data = 7,
+    }
+
+    // Event Data
+    #[derive(Clone, Copy, PartialEq, FromTLV, ToTLV, Debug)]
+    #[tlvargs(lifetime = "'a")]
+    pub struct EventData<'a> {
+        pub path: EventPath,
+        pub event_number: u64,
+        pub priority: u8,
+        pub epoch_ts: Option<u64>,
+        pub system_ts: Option<u64>,
+        pub delta_epoch_ts: Option<u64>,
+        pub delta_system_ts: Option<u64>,
+        pub data: EncodeValue<'a>,
+    }
 }
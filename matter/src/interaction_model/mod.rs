@@ -15,6 +15,10 @@
  *    limitations under the License.
  */
 
+use std::{collections::HashMap, time::SystemTime};
+
+use async_channel::Sender;
+
 use crate::{
     error::Error,
     tlv::TLVWriter,
@@ -22,6 +26,7 @@ use crate::{
 };
 
 use self::{
+    client::ImResult,
     core::OpCode,
     messages::msg::{InvReq, StatusResp, WriteReq},
 };
@@ -75,11 +80,47 @@ pub trait InteractionConsumer {
         _trans: &mut Transaction,
         _tw: &mut TLVWriter,
     ) -> Result<(OpCode, ResponseRequired), Error>;
+
+    /// Encode a report on a subscription, as the first message of a new exchange
+    fn consume_subs_report(
+        &self,
+        _subs_id: u32,
+        _trans: &mut Transaction,
+        _tw: &mut TLVWriter,
+    ) -> Result<(), Error> {
+        Err(Error::Invalid)
+    }
+
+    /// The next time at which the consumer has some timed work to do, like a report on
+    /// a subscription
+    fn get_next_timeout(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Perform the timed work that is due
+    fn handle_timeout(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The session with this local session id was closed
+    fn handle_session_closed(&self, _local_sess_id: u16) {}
+}
+
+/// A report on a subscription that we serve, this goes to the Interaction Model in an
+/// InitiateReq
+pub struct SubsReportReq {
+    pub subs_id: u32,
 }
 
 pub struct InteractionModel {
-    consumer: Box<dyn InteractionConsumer>,
+    consumer: Box<dyn InteractionConsumer + Send>,
+    // The maximum number of commands in an InvokeRequest
+    max_paths_per_invoke: u16,
+    // Our subscriptions on peers, by the peer's node ID and the subscription ID, along with
+    // the local session ID of the session that they are on
+    subscriptions: HashMap<(Option<u64>, u32), (u16, Sender<ImResult>)>,
 }
+pub mod client;
 pub mod command;
 pub mod core;
 pub mod messages;
@@ -47,6 +47,10 @@ impl InteractionModel {
             .consume_write_attr(&write_req, trans, &mut tw)?;
         tw.end_container()?;
 
+        if write_req.more_chunked == Some(true) {
+            // The exchange stays on for the next chunk of the write request
+            return Ok(ResponseRequired::Yes);
+        }
         trans.complete();
         if supress_response {
             error!("Supress response is set, is this the expected handling?");
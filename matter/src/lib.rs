@@ -24,7 +24,7 @@
 //!
 //! # Examples
 //! ```
-//! use matter::{Matter, CommissioningData};
+//! use matter::{Matter, MatterConfig, CommissioningData};
 //! use matter::data_model::device_types::device_type_add_on_off_light;
 //! use matter::data_model::cluster_basic_information::BasicInfoConfig;
 //! use matter::secure_channel::spake2p::VerifierData;
@@ -56,7 +56,7 @@
 //!
 //! /// Get the Matter Object
 //! /// The dev_att is an object that implements the DevAttDataFetcher trait.
-//! let mut matter = Matter::new(dev_info, dev_att, comm_data).unwrap();
+//! let mut matter = Matter::new(dev_info, dev_att, MatterConfig::new(comm_data)).unwrap();
 //! let dm = matter.get_data_model();
 //! {
 //!     let mut node = dm.node.write().unwrap();
@@ -71,6 +71,7 @@
 pub mod acl;
 pub mod cert;
 pub mod codec;
+pub mod commissioner;
 pub mod core;
 pub mod crypto;
 pub mod data_model;
@@ -20,7 +20,10 @@ use std::sync::{Arc, Mutex, Once};
 use crate::{
     error::Error,
     sys::{sys_publish_service, SysMdnsService},
-    transport::udp::MATTER_PORT,
+    transport::{
+        mrp::{MRP_LOCAL_ACTIVE_INTERVAL, MRP_LOCAL_IDLE_INTERVAL},
+        udp::MATTER_PORT,
+    },
 };
 
 #[derive(Default)]
@@ -32,6 +35,8 @@ pub struct MdnsInner {
     pid: u16,
     /// Device name
     device_name: String,
+    /// The port that the services are published on
+    port: u16,
 }
 
 pub struct Mdns {
@@ -44,17 +49,27 @@ const SHORT_DISCRIMINATOR_SHIFT: u16 = 8;
 static mut G_MDNS: Option<Arc<Mdns>> = None;
 static INIT: Once = Once::new();
 
+/// How the commissioning window was opened, this is advertised as the CM key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommissioningMode {
+    /// With the passcode that the device was provisioned with
+    Basic = 1,
+    /// With a new verifier, from an administrator
+    Enhanced = 2,
+}
+
 pub enum ServiceMode {
     /// The commissioned state
     Commissioned,
     /// The commissionable state with the discriminator that should be used
-    Commissionable(u16),
+    Commissionable(u16, CommissioningMode),
 }
 
 impl Mdns {
     fn new() -> Self {
         Self {
             inner: Mutex::new(MdnsInner {
+                port: MATTER_PORT,
                 ..Default::default()
             }),
         }
@@ -80,32 +95,38 @@ impl Mdns {
         inner.device_name = device_name.chars().take(32).collect();
     }
 
+    /// Set the port that the services are published on
+    pub fn set_port(&self, port: u16) {
+        self.inner.lock().unwrap().port = port;
+    }
+
     /// Publish a mDNS service
     /// name - is the service name (comma separated subtypes may follow)
     /// mode - the current service mode
     #[allow(clippy::needless_pass_by_value)]
     pub fn publish_service(&self, name: &str, mode: ServiceMode) -> Result<SysMdnsService, Error> {
+        let inner = self.inner.lock().unwrap();
         match mode {
-            ServiceMode::Commissioned => {
-                sys_publish_service(name, "_matter._tcp", MATTER_PORT, &[])
-            }
-            ServiceMode::Commissionable(discriminator) => {
-                let inner = self.inner.lock().unwrap();
+            ServiceMode::Commissioned => sys_publish_service(name, "_matter._tcp", inner.port, &[]),
+            ServiceMode::Commissionable(discriminator, mode) => {
                 let short = compute_short_discriminator(discriminator);
                 let serv_type = format!("_matterc._udp,_S{},_L{}", short, discriminator);
 
                 let str_discriminator = format!("{}", discriminator);
+                let str_mode = format!("{}", mode as u8);
+                let str_sii = format!("{}", MRP_LOCAL_IDLE_INTERVAL);
+                let str_sai = format!("{}", MRP_LOCAL_ACTIVE_INTERVAL);
                 let txt_kvs = [
                     ["D", &str_discriminator],
-                    ["CM", "1"],
+                    ["CM", &str_mode],
                     ["DN", &inner.device_name],
                     ["VP", &format!("{}+{}", inner.vid, inner.pid)],
-                    ["SII", "5000"], /* Sleepy Idle Interval */
-                    ["SAI", "300"],  /* Sleepy Active Interval */
-                    ["PH", "33"],    /* Pairing Hint */
-                    ["PI", ""],      /* Pairing Instruction */
+                    ["SII", &str_sii], /* Sleepy Idle Interval */
+                    ["SAI", &str_sai], /* Sleepy Active Interval */
+                    ["PH", "33"],      /* Pairing Hint */
+                    ["PI", ""],        /* Pairing Instruction */
                 ];
-                sys_publish_service(name, &serv_type, MATTER_PORT, &txt_kvs)
+                sys_publish_service(name, &serv_type, inner.port, &txt_kvs)
             }
         }
     }
@@ -17,7 +17,14 @@
 
 use num_derive::FromPrimitive;
 
-use crate::{error::Error, transport::packet::Packet};
+use crate::{
+    error::Error,
+    tlv::{FromTLV, TLVElement, TLVWriter, TagType, ToTLV},
+    transport::{
+        mrp::{MrpParams, MRP_LOCAL_ACTIVE_INTERVAL, MRP_LOCAL_IDLE_INTERVAL},
+        packet::Packet,
+    },
+};
 
 use super::status_report::{create_status_report, GeneralCode};
 
@@ -78,8 +85,73 @@ pub fn create_sc_status_report(
     )
 }
 
+/// The MRP session parameters exchanged during PASE and CASE session establishment
+#[derive(FromTLV, ToTLV, Default, Debug, Copy, Clone, PartialEq)]
+#[tlvargs(start = 1)]
+pub struct SessionParams {
+    pub idle_interval: Option<u32>,
+    pub active_interval: Option<u32>,
+    pub active_threshold: Option<u16>,
+}
+
+impl SessionParams {
+    /// Our own session parameters, these match with what we advertise over mDNS
+    pub fn local() -> Self {
+        Self {
+            idle_interval: Some(MRP_LOCAL_IDLE_INTERVAL),
+            active_interval: Some(MRP_LOCAL_ACTIVE_INTERVAL),
+            active_threshold: None,
+        }
+    }
+
+    /// Convert the peer's parameters into MRP parameters, using the defaults for
+    /// anything that the peer didn't specify
+    pub fn to_mrp_params(&self) -> MrpParams {
+        let default = MrpParams::default();
+        MrpParams {
+            idle_interval: self.idle_interval.unwrap_or(default.idle_interval),
+            active_interval: self.active_interval.unwrap_or(default.active_interval),
+            active_threshold: self.active_threshold.unwrap_or(default.active_threshold),
+        }
+    }
+}
+
 pub fn create_mrp_standalone_ack(proto_tx: &mut Packet) {
     proto_tx.set_proto_id(PROTO_ID_SECURE_CHANNEL as u16);
     proto_tx.set_proto_opcode(OpCode::MRPStandAloneAck as u8);
     proto_tx.unset_reliable();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SessionParams;
+    use crate::{
+        tlv::{get_root_node_struct, FromTLV, TLVWriter, TagType, ToTLV},
+        transport::mrp::MrpParams,
+        utils::writebuf::WriteBuf,
+    };
+
+    #[test]
+    fn test_session_params_defaults() {
+        let mut buf = [0u8; 32];
+        let buf_len = buf.len();
+        let mut wb = WriteBuf::new(&mut buf, buf_len);
+        let mut tw = TLVWriter::new(&mut wb);
+        let params = SessionParams {
+            idle_interval: Some(2000),
+            ..Default::default()
+        };
+        params.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+        let root = get_root_node_struct(wb.as_slice()).unwrap();
+        let params = SessionParams::from_tlv(&root).unwrap();
+        let mrp_params = params.to_mrp_params();
+        assert_eq!(
+            mrp_params,
+            MrpParams {
+                idle_interval: 2000,
+                ..Default::default()
+            }
+        );
+    }
+}
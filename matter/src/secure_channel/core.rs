@@ -15,19 +15,28 @@
  *    limitations under the License.
  */
 
-use std::sync::Arc;
+use std::{any::Any, sync::Arc};
 
 use crate::{
     error::*,
     fabric::FabricMgr,
     secure_channel::common::*,
     tlv,
-    transport::proto_demux::{self, ProtoCtx, ResponseRequired},
+    transport::{
+        exchange::ExchangeCtx,
+        packet::Packet,
+        proto_demux::{self, ProtoCtx, ResponseRequired},
+        queue::{Msg, WorkQ},
+    },
 };
 use log::{error, info};
 use num;
 
-use super::{case::Case, pake::PaseMgr};
+use super::{
+    case::{Case, CaseInitiateReq},
+    pake::{PaseInitiateReq, PaseMgr},
+    status_report::StatusReport,
+};
 
 /* Handle messages related to the Secure Channel
  */
@@ -35,14 +44,44 @@ use super::{case::Case, pake::PaseMgr};
 pub struct SecureChannel {
     case: Case,
     pase: PaseMgr,
+    wq: WorkQ,
 }
 
 impl SecureChannel {
-    pub fn new(pase: PaseMgr, fabric_mgr: Arc<FabricMgr>) -> SecureChannel {
-        SecureChannel {
+    pub fn new(
+        pase: PaseMgr,
+        fabric_mgr: Arc<FabricMgr>,
+        wq: WorkQ,
+    ) -> Result<SecureChannel, Error> {
+        Ok(SecureChannel {
             pase,
-            case: Case::new(fabric_mgr),
+            case: Case::new(fabric_mgr, wq.clone())?,
+            wq,
+        })
+    }
+
+    fn statusreport_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        let status = StatusReport::parse(ctx.rx.as_borrow_slice())?;
+        if status.proto_id != PROTO_ID_SECURE_CHANNEL as u32
+            || status.proto_code != SCStatusCodes::CloseSession as u16
+        {
+            if PaseMgr::is_initiator(&mut ctx.exch_ctx) {
+                return self.pase.pasestatus_handler(ctx);
+            }
+            return self.case.casestatus_handler(ctx);
         }
+
+        ctx.exch_ctx.exch.close();
+        if ctx.exch_ctx.sess.is_encrypted() {
+            // The session can't be removed from under the exchange that is being
+            // processed, so let the transport mgr take care of it. This runs in the
+            // transport's own context, so don't wait for room in the queue
+            let local_sess_id = ctx.exch_ctx.sess.get_local_sess_id();
+            if self.wq.try_send(Msg::SessionClosed(local_sess_id)).is_err() {
+                error!("Couldn't close the session {}", local_sess_id);
+            }
+        }
+        Ok(ResponseRequired::No)
     }
 }
 
@@ -57,10 +96,14 @@ impl proto_demux::HandleProto for SecureChannel {
         let result = match proto_opcode {
             OpCode::MRPStandAloneAck => Ok(ResponseRequired::No),
             OpCode::PBKDFParamRequest => self.pase.pbkdfparamreq_handler(ctx),
+            OpCode::PBKDFParamResponse => self.pase.pbkdfparamresp_handler(ctx),
             OpCode::PASEPake1 => self.pase.pasepake1_handler(ctx),
+            OpCode::PASEPake2 => self.pase.pasepake2_handler(ctx),
             OpCode::PASEPake3 => self.pase.pasepake3_handler(ctx),
             OpCode::CASESigma1 => self.case.casesigma1_handler(ctx),
+            OpCode::CASESigma2 => self.case.casesigma2_handler(ctx),
             OpCode::CASESigma3 => self.case.casesigma3_handler(ctx),
+            OpCode::StatusReport => self.statusreport_handler(ctx),
             _ => {
                 error!("OpCode Not Handled: {:?}", proto_opcode);
                 Err(Error::InvalidOpcode)
@@ -76,4 +119,20 @@ impl proto_demux::HandleProto for SecureChannel {
     fn get_proto_id(&self) -> usize {
         PROTO_ID_SECURE_CHANNEL
     }
+
+    fn initiate(
+        &mut self,
+        data: Box<dyn Any + Send>,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        let data = match data.downcast::<CaseInitiateReq>() {
+            Ok(req) => return self.case.initiate(*req, exch_ctx, tx),
+            Err(data) => data,
+        };
+        match data.downcast::<PaseInitiateReq>() {
+            Ok(req) => self.pase.initiate(*req, exch_ctx, tx),
+            Err(_) => Err(Error::Invalid),
+        }
+    }
 }
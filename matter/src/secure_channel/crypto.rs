@@ -18,7 +18,6 @@
 use crate::error::Error;
 
 // This trait allows us to switch between crypto providers like OpenSSL and mbedTLS for Spake2
-// Currently this is only validate for a verifier(responder)
 
 // A verifier will typically do:
 // Step 1: w0 and L
@@ -27,7 +26,15 @@ use crate::error::Error;
 // Step 2: get_pB
 // Step 3: get_TT_as_verifier(pA)
 // Step 4: Computation of cA and cB happens outside since it doesn't use either BigNum or EcPoint
-pub trait CryptoSpake2 {
+
+// A prover will typically do:
+// Step 1: w0 and w1
+//      set_w0_from_w0s
+//      set_w1_from_w1s
+// Step 2: get_pA
+// Step 3: get_TT_as_prover(pB)
+// Step 4: Computation of cA and cB happens outside, as for the verifier
+pub trait CryptoSpake2: Send {
     fn new() -> Result<Self, Error>
     where
         Self: Sized;
@@ -44,6 +51,8 @@ pub trait CryptoSpake2 {
     #[allow(non_snake_case)]
     fn get_pB(&mut self, pB: &mut [u8]) -> Result<(), Error>;
     #[allow(non_snake_case)]
+    fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error>;
+    #[allow(non_snake_case)]
     fn get_TT_as_verifier(
         &mut self,
         context: &[u8],
@@ -51,4 +60,12 @@ pub trait CryptoSpake2 {
         pB: &[u8],
         out: &mut [u8],
     ) -> Result<(), Error>;
+    #[allow(non_snake_case)]
+    fn get_TT_as_prover(
+        &mut self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error>;
 }
@@ -89,6 +89,17 @@ impl CryptoSpake2 for CryptoEspMbedTls {
         Ok(())
     }
 
+    #[allow(non_snake_case)]
+    fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error> {
+        // From the SPAKE2+ spec (https://datatracker.ietf.org/doc/draft-bar-cfrg-spake2plus/)
+        //   for x
+        //   - select random x between 0 to p
+        //   - X = x*P + w0*M
+        //   - pA = X
+
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     fn get_TT_as_verifier(
         &mut self,
@@ -99,6 +110,17 @@ impl CryptoSpake2 for CryptoEspMbedTls {
     ) -> Result<(), Error> {
         Ok(())
     }
+
+    #[allow(non_snake_case)]
+    fn get_TT_as_prover(
+        &mut self,
+        context: &[u8],
+        pA: &[u8],
+        pB: &[u8],
+        out: &mut [u8],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 impl CryptoEspMbedTls {}
@@ -27,6 +27,7 @@ pub mod crypto_openssl;
 pub mod core;
 pub mod crypto;
 pub mod pake;
+pub mod resumption;
 pub mod spake2p;
 pub mod spake2p_test_vectors;
 pub mod status_report;
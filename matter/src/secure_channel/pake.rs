@@ -21,24 +21,30 @@ use std::{
 };
 
 use super::{
-    common::{create_sc_status_report, SCStatusCodes},
-    spake2p::{Spake2P, VerifierData},
+    common::{create_sc_status_report, SCStatusCodes, SessionParams, PROTO_ID_SECURE_CHANNEL},
+    spake2p::{
+        Spake2P, VerifierData, MAX_ITERATION_COUNT, MAX_SALT_SIZE_BYTES, MIN_ITERATION_COUNT,
+        MIN_SALT_SIZE_BYTES,
+    },
+    status_report::StatusReport,
 };
 use crate::{
     crypto,
     error::Error,
-    mdns::{self, Mdns},
+    mdns::{self, CommissioningMode, Mdns},
     secure_channel::common::OpCode,
     sys::SysMdnsService,
     tlv::{self, get_root_node_struct, FromTLV, OctetStr, TLVElement, TLVWriter, TagType, ToTLV},
     transport::{
-        exchange::ExchangeCtx,
+        exchange::{ExchangeCtx, SessionTarget},
         network::Address,
-        proto_demux::{ProtoCtx, ResponseRequired},
+        packet::Packet,
+        proto_demux::{InitiateReq, ProtoCtx, ResponseRequired},
         queue::{Msg, WorkQ},
         session::{CloneData, SessionMode},
     },
 };
+use async_channel::{bounded, Receiver, Sender};
 use log::{error, info};
 use rand::prelude::*;
 
@@ -47,8 +53,31 @@ enum PaseMgrState {
     Disabled,
 }
 
+/// The commissioning window that is open, and who opened it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommWindowInfo {
+    pub mode: CommissioningMode,
+    /// The fabric index and the vendor id of the administrator that opened the window
+    pub admin: Option<(u8, u16)>,
+    /// The time at which the window closes on its own
+    pub expiry: Option<SystemTime>,
+}
+
+struct CommWindow {
+    info: CommWindowInfo,
+    verifier: VerifierData,
+    discriminator: u16,
+}
+
 pub struct PaseMgrInternal {
     state: PaseMgrState,
+    // The verifier and discriminator of the device itself, for when it has no fabrics
+    default_comm: Option<(VerifierData, u16)>,
+    window: Option<CommWindow>,
+    // The window opened or closed, since this was last looked at
+    window_changed: bool,
+    // The new sessions are handed over to the transport through this
+    wq: WorkQ,
 }
 
 #[derive(Clone)]
@@ -56,26 +85,121 @@ pub struct PaseMgrInternal {
 pub struct PaseMgr(Arc<Mutex<PaseMgrInternal>>);
 
 impl PaseMgr {
-    pub fn new() -> Self {
+    pub fn new(wq: WorkQ) -> Self {
         Self(Arc::new(Mutex::new(PaseMgrInternal {
             state: PaseMgrState::Disabled,
+            default_comm: None,
+            window: None,
+            window_changed: false,
+            wq,
         })))
     }
 
-    pub fn enable_pase_session(
+    fn get_workq(&self) -> WorkQ {
+        self.0.lock().unwrap().wq.clone()
+    }
+
+    /// Set the verifier and discriminator that the device was provisioned with
+    pub fn set_default_comm_data(&mut self, verifier: VerifierData, discriminator: u16) {
+        self.0.lock().unwrap().default_comm = Some((verifier, discriminator));
+    }
+
+    /// Open the commissioning window, for a PASE session with this verifier
+    ///
+    /// Only one window can be open at a time.
+    pub fn open_comm_window(
         &mut self,
+        info: CommWindowInfo,
         verifier: VerifierData,
         discriminator: u16,
     ) -> Result<(), Error> {
         let mut s = self.0.lock().unwrap();
+        if s.window.is_some() {
+            return Err(Error::InvalidState);
+        }
+        s.state = PaseMgr::enable(verifier.clone(), discriminator, info.mode)?;
+        s.window = Some(CommWindow {
+            info,
+            verifier,
+            discriminator,
+        });
+        s.window_changed = true;
+        info!("Opened the commissioning window {:?}", info);
+        Ok(())
+    }
+
+    /// Open the commissioning window, with the verifier and discriminator that the device
+    /// was provisioned with
+    pub fn open_basic_comm_window(
+        &mut self,
+        admin: Option<(u8, u16)>,
+        expiry: Option<SystemTime>,
+    ) -> Result<(), Error> {
+        let (verifier, discriminator) = self
+            .0
+            .lock()
+            .unwrap()
+            .default_comm
+            .clone()
+            .ok_or(Error::Invalid)?;
+        let info = CommWindowInfo {
+            mode: CommissioningMode::Basic,
+            admin,
+            expiry,
+        };
+        self.open_comm_window(info, verifier, discriminator)
+    }
+
+    /// Close the commissioning window, and withdraw its advertisement
+    pub fn close_comm_window(&mut self) {
+        let mut s = self.0.lock().unwrap();
+        if s.window.take().is_some() {
+            s.window_changed = true;
+            info!("Closed the commissioning window");
+        }
+        s.state = PaseMgrState::Disabled;
+    }
+
+    /// The commissioning window that is open, if any
+    pub fn get_comm_window(&self) -> Option<CommWindowInfo> {
+        self.0.lock().unwrap().window.as_ref().map(|w| w.info)
+    }
+
+    /// Whether the commissioning window opened or closed, since the last call to this
+    pub fn take_window_changed(&self) -> bool {
+        std::mem::take(&mut self.0.lock().unwrap().window_changed)
+    }
+
+    /// Advertise the open commissioning window again
+    ///
+    /// The advertisement stops once a PASE session is established through the window. If
+    /// that session doesn't complete the commissioning, the window is available again.
+    pub fn resume_comm_window(&mut self) -> Result<(), Error> {
+        let mut guard = self.0.lock().unwrap();
+        let s = &mut *guard;
+        if let (PaseMgrState::Disabled, Some(w)) = (&s.state, &s.window) {
+            let state = PaseMgr::enable(w.verifier.clone(), w.discriminator, w.info.mode)?;
+            s.state = state;
+        }
+        Ok(())
+    }
+
+    fn enable(
+        verifier: VerifierData,
+        discriminator: u16,
+        mode: CommissioningMode,
+    ) -> Result<PaseMgrState, Error> {
         let name: u64 = rand::thread_rng().gen_range(0..0xFFFFFFFFFFFFFFFF);
         let name = format!("{:016X}", name);
-        let mdns = Mdns::get()?
-            .publish_service(&name, mdns::ServiceMode::Commissionable(discriminator))?;
-        s.state = PaseMgrState::Enabled(PAKE::new(verifier), mdns);
-        Ok(())
+        let mdns = Mdns::get()?.publish_service(
+            &name,
+            mdns::ServiceMode::Commissionable(discriminator, mode),
+        )?;
+        Ok(PaseMgrState::Enabled(PAKE::new(verifier), mdns))
     }
 
+    /// Stop accepting PASE sessions, and withdraw the advertisement, the window itself
+    /// remains open
     pub fn disable_pase_session(&mut self) {
         let mut s = self.0.lock().unwrap();
         s.state = PaseMgrState::Disabled;
@@ -109,15 +233,289 @@ impl PaseMgr {
     }
 
     pub fn pasepake3_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
-        self.if_enabled(ctx, |pake, ctx| pake.handle_pasepake3(ctx))?;
+        let wq = self.get_workq();
+        self.if_enabled(ctx, |pake, ctx| pake.handle_pasepake3(ctx, &wq))?;
         self.disable_pase_session();
         Ok(ResponseRequired::Yes)
     }
+
+    /// Start a PASE session establishment, by filling in the PBKDFParamRequest
+    pub fn initiate(
+        &mut self,
+        req: PaseInitiateReq,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        let result = PaseInitiator::send_pbkdfparamreq(&req, exch_ctx, tx);
+        if let Err(e) = result {
+            let _ = req.notify.try_send(Err(e));
+        }
+        result
+    }
+
+    pub fn pbkdfparamresp_handler(
+        &mut self,
+        ctx: &mut ProtoCtx,
+    ) -> Result<ResponseRequired, Error> {
+        ctx.tx.set_proto_opcode(OpCode::PASEPake1 as u8);
+        PaseInitiator::with_exchange(ctx, PaseInitiatorState::PBKDFParamReq, |p, ctx| {
+            p.handle_pbkdfparamresp(ctx)
+        })
+    }
+
+    pub fn pasepake2_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        ctx.tx.set_proto_opcode(OpCode::PASEPake3 as u8);
+        PaseInitiator::with_exchange(ctx, PaseInitiatorState::Pake1, |p, ctx| {
+            p.handle_pasepake2(ctx)
+        })
+    }
+
+    /// Handle the status report that the responder sends on a PASE session establishment
+    /// that we initiated
+    pub fn pasestatus_handler(&mut self, ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error> {
+        let status = StatusReport::parse(ctx.rx.as_borrow_slice())?;
+        let initiator = ctx.exch_ctx.exch.take_data_boxed::<PaseInitiator>();
+        ctx.exch_ctx.exch.close();
+
+        let mut initiator = initiator.ok_or(Error::InvalidState)?;
+        match initiator.session.take() {
+            Some(clone_data) if status.is_success() => {
+                // Queue a transport mgr request to add a new session
+                let result = PaseSession {
+                    local_sessid: initiator.local_sessid,
+                    att_challenge: clone_data.att_challenge,
+                };
+                self.get_workq().sync_send(Msg::NewSession(clone_data))?;
+                initiator.complete(Ok(result));
+            }
+            _ => {
+                error!("PASE session establishment failed: {:?}", status);
+                initiator.complete(Err(Error::Invalid));
+            }
+        }
+        Ok(ResponseRequired::No)
+    }
+
+    /// Whether this exchange is a PASE session establishment that we initiated
+    pub fn is_initiator(exch_ctx: &mut ExchangeCtx) -> bool {
+        exch_ctx.exch.get_data_boxed::<PaseInitiator>().is_some()
+    }
 }
 
-impl Default for PaseMgr {
-    fn default() -> Self {
-        Self::new()
+/// A PASE session that we established
+pub struct PaseSession {
+    /// The local session ID of the new session
+    pub local_sessid: u16,
+    /// The attestation challenge of the new session, the device signs its attestation
+    /// and CSR responses with this
+    pub att_challenge: [u8; crypto::SYMM_KEY_LEN_BYTES],
+}
+
+/// The outcome of a PASE session establishment that we initiated
+pub type PaseResult = Result<PaseSession, Error>;
+
+/// A PASE session establishment that we initiate, this goes to the Secure Channel in an
+/// InitiateReq
+pub struct PaseInitiateReq {
+    passcode: u32,
+    notify: Sender<PaseResult>,
+}
+
+/// Establish a PASE session with a commissionable device, using its setup passcode
+///
+/// This only queues the request to the transport. The outcome is delivered on the
+/// returned channel, which is closed without an outcome if the peer stops responding.
+pub fn establish_session(
+    wq: &WorkQ,
+    passcode: u32,
+    peer_addr: Address,
+) -> Result<Receiver<PaseResult>, Error> {
+    let (notify, rx) = bounded(1);
+    let req = PaseInitiateReq { passcode, notify };
+    wq.sync_send(Msg::Initiate(InitiateReq::new(
+        PROTO_ID_SECURE_CHANNEL,
+        SessionTarget::Unsecured(peer_addr),
+        Box::new(req),
+    )))?;
+    Ok(rx)
+}
+
+// The last message that the initiator sent
+#[derive(PartialEq)]
+enum PaseInitiatorState {
+    PBKDFParamReq,
+    Pake1,
+    Pake3,
+}
+
+// The prover's side of PASE, this lives in the exchange data, till the session is
+// established
+#[allow(non_snake_case)]
+pub struct PaseInitiator {
+    state: PaseInitiatorState,
+    passcode: u32,
+    local_sessid: u16,
+    our_random: [u8; 32],
+    // The PBKDFParamRequest goes into the Spake2+ context, along with the response
+    req: Vec<u8>,
+    spake2p: Box<Spake2P>,
+    pA: [u8; 65],
+    session: Option<CloneData>,
+    notify: Option<Sender<PaseResult>>,
+}
+
+impl PaseInitiator {
+    fn send_pbkdfparamreq(
+        req: &PaseInitiateReq,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        tx.set_proto_opcode(OpCode::PBKDFParamRequest as u8);
+
+        let mut initiator = Box::new(PaseInitiator {
+            state: PaseInitiatorState::PBKDFParamReq,
+            passcode: req.passcode,
+            local_sessid: exch_ctx.sess.reserve_new_sess_id(),
+            our_random: [0; 32],
+            req: Vec::new(),
+            spake2p: Box::new(Spake2P::new()),
+            pA: [0; 65],
+            session: None,
+            notify: Some(req.notify.clone()),
+        });
+        rand::thread_rng().fill_bytes(&mut initiator.our_random);
+
+        let mut tw = TLVWriter::new(tx.get_writebuf()?);
+        let params_req = PBKDFParamReq {
+            initiator_random: OctetStr(&initiator.our_random),
+            initiator_ssid: initiator.local_sessid,
+            passcode_id: 0,
+            has_params: false,
+            initiator_mrp_params: Some(SessionParams::local()),
+        };
+        params_req.to_tlv(&mut tw, TagType::Anonymous)?;
+        initiator.req = tx.as_borrow_slice().to_vec();
+        exch_ctx.exch.set_data_boxed(initiator);
+        Ok(())
+    }
+
+    // Run the handler on the initiator in the exchange, if it is in this state. A failure
+    // is reported to the peer, and to whoever is waiting for the session.
+    fn with_exchange<F>(
+        ctx: &mut ProtoCtx,
+        state: PaseInitiatorState,
+        f: F,
+    ) -> Result<ResponseRequired, Error>
+    where
+        F: FnOnce(&mut PaseInitiator, &mut ProtoCtx) -> Result<(), Error>,
+    {
+        let mut initiator = ctx
+            .exch_ctx
+            .exch
+            .take_data_boxed::<PaseInitiator>()
+            .ok_or(Error::InvalidState)?;
+        if initiator.state != state {
+            return Err(Error::Invalid);
+        }
+        match f(&mut initiator, ctx) {
+            Ok(()) => {
+                ctx.exch_ctx.exch.set_data_boxed(initiator);
+            }
+            Err(e) => {
+                error!("PASE session establishment failed: {:?}", e);
+                create_sc_status_report(&mut ctx.tx, SCStatusCodes::InvalidParameter, None)?;
+                ctx.exch_ctx.exch.close();
+                initiator.complete(Err(e));
+            }
+        }
+        Ok(ResponseRequired::Yes)
+    }
+
+    fn handle_pbkdfparamresp(&mut self, ctx: &mut ProtoCtx) -> Result<(), Error> {
+        let rx_buf = ctx.rx.as_borrow_slice();
+        let root = tlv::get_root_node(rx_buf)?;
+        let resp = PBKDFParamResp::from_tlv(&root)?;
+        if resp.init_random.0 != self.our_random {
+            error!("Initiator random doesn't match");
+            return Err(Error::Invalid);
+        }
+        // We didn't send any PBKDF parameters, so the responder must
+        let params = resp.params.ok_or(Error::Invalid)?;
+        if !(MIN_ITERATION_COUNT..=MAX_ITERATION_COUNT).contains(&params.count)
+            || !(MIN_SALT_SIZE_BYTES..=MAX_SALT_SIZE_BYTES).contains(&params.salt.0.len())
+        {
+            error!(
+                "Invalid PBKDF parameters: {} iterations, {}-byte salt",
+                params.count,
+                params.salt.0.len()
+            );
+            return Err(Error::Invalid);
+        }
+        if let Some(mrp_params) = resp.mrp_params {
+            ctx.exch_ctx.sess.set_mrp_params(mrp_params.to_mrp_params());
+        }
+
+        self.spake2p.set_context(&self.req, rx_buf)?;
+        self.spake2p
+            .start_prover(self.passcode, params.count, params.salt.0)?;
+        self.spake2p.get_pA(&mut self.pA)?;
+        // The responder's session id goes along in the spake2p data, as for the verifier
+        let spake2p_data: u32 = ((self.local_sessid as u32) << 16) | resp.local_sessid as u32;
+        self.spake2p.set_app_data(spake2p_data);
+
+        let mut tw = TLVWriter::new(ctx.tx.get_writebuf()?);
+        tw.start_struct(TagType::Anonymous)?;
+        tw.str8(TagType::Context(1), &self.pA)?;
+        tw.end_container()?;
+        self.state = PaseInitiatorState::Pake1;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn handle_pasepake2(&mut self, ctx: &mut ProtoCtx) -> Result<(), Error> {
+        let root = get_root_node_struct(ctx.rx.as_borrow_slice())?;
+        let resp = Pake1Resp::from_tlv(&root)?;
+
+        let mut cA: [u8; 32] = [0; 32];
+        let Ke = self
+            .spake2p
+            .handle_pB(&self.pA, resp.pb.0, resp.cb.0, &mut cA)?;
+        let mut session_keys: [u8; 48] = [0; 48];
+        crypto::hkdf_sha256(&[], Ke, &SPAKE2_SESSION_KEYS_INFO, &mut session_keys)
+            .map_err(|_x| Error::NoSpace)?;
+
+        let data = self.spake2p.get_app_data();
+        let peer_sessid: u16 = (data & 0xffff) as u16;
+        let mut clone_data = CloneData::new(
+            0,
+            0,
+            peer_sessid,
+            self.local_sessid,
+            ctx.exch_ctx.sess.get_peer_addr(),
+            SessionMode::Pase,
+        );
+        // The I2R key is what the initiator encrypts with
+        clone_data.enc_key.copy_from_slice(&session_keys[0..16]);
+        clone_data.dec_key.copy_from_slice(&session_keys[16..32]);
+        clone_data
+            .att_challenge
+            .copy_from_slice(&session_keys[32..48]);
+        clone_data.mrp_params = *ctx.exch_ctx.sess.get_mrp_params();
+        self.session = Some(clone_data);
+
+        let mut tw = TLVWriter::new(ctx.tx.get_writebuf()?);
+        tw.start_struct(TagType::Anonymous)?;
+        tw.str8(TagType::Context(1), &cA)?;
+        tw.end_container()?;
+        self.state = PaseInitiatorState::Pake3;
+        Ok(())
+    }
+
+    // Let the initiator of the session establishment know how it went
+    fn complete(&mut self, result: PaseResult) {
+        if let Some(notify) = self.notify.take() {
+            let _ = notify.try_send(result);
+        }
     }
 }
 
@@ -210,7 +608,7 @@ impl PAKE {
     }
 
     #[allow(non_snake_case)]
-    pub fn handle_pasepake3(&mut self, ctx: &mut ProtoCtx) -> Result<(), Error> {
+    pub fn handle_pasepake3(&mut self, ctx: &mut ProtoCtx, wq: &WorkQ) -> Result<(), Error> {
         let mut sd = self.state.take_sess_data(&ctx.exch_ctx)?;
 
         let cA = extract_pasepake_1_or_3_params(ctx.rx.as_borrow_slice())?;
@@ -240,9 +638,11 @@ impl PAKE {
             clone_data
                 .att_challenge
                 .copy_from_slice(&session_keys[32..48]);
+            // The peer's MRP parameters were recorded in the PBKDFParamRequest
+            clone_data.mrp_params = *ctx.exch_ctx.sess.get_mrp_params();
 
             // Queue a transport mgr request to add a new session
-            WorkQ::get()?.sync_send(Msg::NewSession(clone_data))?;
+            wq.sync_send(Msg::NewSession(clone_data))?;
         }
 
         create_sc_status_report(&mut ctx.tx, status_code, None)?;
@@ -292,6 +692,10 @@ impl PAKE {
             error!("Can't yet handle passcode_id != 0");
             return Err(Error::Invalid);
         }
+        if let Some(mrp_params) = a.initiator_mrp_params {
+            // Use the peer's MRP parameters for the rest of the PASE exchange too
+            ctx.exch_ctx.sess.set_mrp_params(mrp_params.to_mrp_params());
+        }
 
         let mut our_random: [u8; 32] = [0; 32];
         rand::thread_rng().fill_bytes(&mut our_random);
@@ -308,6 +712,7 @@ impl PAKE {
             our_random: OctetStr(&our_random),
             local_sessid,
             params: None,
+            mrp_params: Some(SessionParams::local()),
         };
         if !a.has_params {
             let params_resp = PBKDFParamRespParams {
@@ -325,27 +730,28 @@ impl PAKE {
     }
 }
 
-#[derive(ToTLV)]
-#[tlvargs(start = 1)]
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
 struct Pake1Resp<'a> {
     pb: OctetStr<'a>,
     cb: OctetStr<'a>,
 }
 
-#[derive(ToTLV)]
-#[tlvargs(start = 1)]
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
 struct PBKDFParamRespParams<'a> {
     count: u32,
     salt: OctetStr<'a>,
 }
 
-#[derive(ToTLV)]
-#[tlvargs(start = 1)]
+#[derive(ToTLV, FromTLV)]
+#[tlvargs(lifetime = "'a", start = 1)]
 struct PBKDFParamResp<'a> {
     init_random: OctetStr<'a>,
     our_random: OctetStr<'a>,
     local_sessid: u16,
     params: Option<PBKDFParamRespParams<'a>>,
+    mrp_params: Option<SessionParams>,
 }
 
 #[allow(non_snake_case)]
@@ -355,11 +761,12 @@ fn extract_pasepake_1_or_3_params(buf: &[u8]) -> Result<&[u8], Error> {
     Ok(pA)
 }
 
-#[derive(FromTLV)]
+#[derive(FromTLV, ToTLV)]
 #[tlvargs(lifetime = "'a", start = 1)]
 struct PBKDFParamReq<'a> {
     initiator_random: OctetStr<'a>,
     initiator_ssid: u16,
     passcode_id: u16,
     has_params: bool,
+    initiator_mrp_params: Option<SessionParams>,
 }
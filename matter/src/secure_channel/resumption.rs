@@ -0,0 +1,245 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::error;
+
+use crate::{
+    crypto,
+    error::Error,
+    sys::Psm,
+    tlv::{FromTLV, TLVElement, TLVList, TLVWriter, TagType, ToTLV},
+    transport::session::NocCatIds,
+    utils::writebuf::WriteBuf,
+};
+
+pub const RESUMPTION_ID_LEN: usize = 16;
+pub const MAX_RESUMPTION_ENTRIES: usize = 16;
+
+const RESUMPTION_KV_ENTRY: &str = "case_resumption";
+const RESUMPTION_KV_MAX_SIZE: usize = 4096;
+
+type ResumptionId = [u8; RESUMPTION_ID_LEN];
+type SharedSecret = [u8; crypto::ECDH_SHARED_SECRET_LEN_BYTES];
+pub type NocHash = [u8; crypto::SHA256_HASH_LEN_BYTES];
+
+/// The state that is kept for a peer after a successful CASE, so the peer can resume
+/// the session later without going through the full handshake
+///
+/// The entry is bound to our NOC on the fabric, that the peer authenticated us with. It
+/// can't be resumed once that changes, as it does with an UpdateNOC, or its rollback.
+#[derive(ToTLV, FromTLV, Copy, Clone, Debug, Default, PartialEq)]
+#[tlvargs(start = 1)]
+pub struct ResumptionEntry {
+    pub resumption_id: ResumptionId,
+    pub shared_secret: SharedSecret,
+    pub fab_idx: u8,
+    pub fabric_id: u64,
+    pub peer_nodeid: u64,
+    pub peer_catids: NocCatIds,
+    pub noc_hash: NocHash,
+}
+
+/// The table of resumption entries
+///
+/// There is at most one entry per peer. The entries are kept in the order of their
+/// last use, so when the table is full, the least recently used entry is dropped.
+pub struct ResumptionMgr {
+    entries: [Option<ResumptionEntry>; MAX_RESUMPTION_ENTRIES],
+    psm: Option<Arc<Mutex<Psm>>>,
+}
+
+impl ResumptionMgr {
+    /// The entries are only kept in memory
+    pub fn new() -> Self {
+        Self {
+            entries: [None; MAX_RESUMPTION_ENTRIES],
+            psm: None,
+        }
+    }
+
+    /// The entries are loaded from, and stored to, this storage
+    pub fn new_with_psm(psm: Arc<Mutex<Psm>>) -> Self {
+        let mut mgr = ResumptionMgr::new();
+        {
+            let psm_lock = psm.lock().unwrap();
+            // A missing or corrupt table only means that peers will have to do a
+            // full CASE
+            let _ = mgr.load(&psm_lock);
+        }
+        mgr.psm = Some(psm);
+        mgr
+    }
+
+    pub fn find(&self, resumption_id: &[u8]) -> Option<ResumptionEntry> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|e| e.resumption_id == resumption_id)
+            .copied()
+    }
+
+    /// Add an entry, replacing any previous entry for the same peer
+    pub fn add(&mut self, entry: ResumptionEntry) {
+        let index = self
+            .entries
+            .iter()
+            .position(|e| match e {
+                Some(e) => e.fab_idx == entry.fab_idx && e.peer_nodeid == entry.peer_nodeid,
+                None => true,
+            })
+            .unwrap_or(MAX_RESUMPTION_ENTRIES - 1);
+        // Move everything before 'index' one step down, the entry at 'index' is dropped
+        self.entries[..=index].rotate_right(1);
+        self.entries[0] = Some(entry);
+        self.store();
+    }
+
+    /// Remove the entries of a fabric, as it is removed or its credentials change
+    pub fn remove_fabric(&mut self, fab_idx: u8) {
+        if !self.entries.iter().flatten().any(|e| e.fab_idx == fab_idx) {
+            return;
+        }
+        let mut entries = [None; MAX_RESUMPTION_ENTRIES];
+        for (slot, e) in entries.iter_mut().zip(
+            self.entries
+                .iter()
+                .flatten()
+                .filter(|e| e.fab_idx != fab_idx),
+        ) {
+            *slot = Some(*e);
+        }
+        self.entries = entries;
+        self.store();
+    }
+
+    fn store(&self) {
+        if let Some(psm) = self.psm.as_ref() {
+            let psm = psm.lock().unwrap();
+            let _ = self.store_entries(&psm).map_err(|e| {
+                error!("Error in storing resumption entries {}", e);
+            });
+        }
+    }
+
+    fn store_entries(&self, psm: &MutexGuard<Psm>) -> Result<(), Error> {
+        let mut tlvs = [0u8; RESUMPTION_KV_MAX_SIZE];
+        let mut wb = WriteBuf::new(&mut tlvs, RESUMPTION_KV_MAX_SIZE);
+        let mut tw = TLVWriter::new(&mut wb);
+        self.entries.to_tlv(&mut tw, TagType::Anonymous)?;
+        psm.set_kv_slice(RESUMPTION_KV_ENTRY, wb.as_slice())
+    }
+
+    fn load(&mut self, psm: &MutexGuard<Psm>) -> Result<(), Error> {
+        let mut tlvs = Vec::new();
+        psm.get_kv_slice(RESUMPTION_KV_ENTRY, &mut tlvs)?;
+        let root = TLVList::new(&tlvs).iter().next().ok_or(Error::Invalid)?;
+        self.entries = FromTLV::from_tlv(&root)?;
+        Ok(())
+    }
+}
+
+impl Default for ResumptionMgr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u8, fab_idx: u8, peer_nodeid: u64) -> ResumptionEntry {
+        ResumptionEntry {
+            resumption_id: [id; RESUMPTION_ID_LEN],
+            shared_secret: [id; crypto::ECDH_SHARED_SECRET_LEN_BYTES],
+            fab_idx,
+            fabric_id: 1,
+            peer_nodeid,
+            peer_catids: [0xabcd0001, 0, 0],
+            noc_hash: [id; crypto::SHA256_HASH_LEN_BYTES],
+        }
+    }
+
+    #[test]
+    fn test_find_and_replace() {
+        let mut mgr = ResumptionMgr::new();
+        mgr.add(entry(1, 1, 100));
+        mgr.add(entry(2, 1, 200));
+        assert_eq!(mgr.find(&[1; RESUMPTION_ID_LEN]), Some(entry(1, 1, 100)));
+        assert_eq!(mgr.find(&[3; RESUMPTION_ID_LEN]), None);
+
+        // Same peer, the older entry goes away
+        mgr.add(entry(3, 1, 100));
+        assert_eq!(mgr.find(&[1; RESUMPTION_ID_LEN]), None);
+        assert_eq!(mgr.find(&[3; RESUMPTION_ID_LEN]), Some(entry(3, 1, 100)));
+        assert_eq!(mgr.find(&[2; RESUMPTION_ID_LEN]), Some(entry(2, 1, 200)));
+
+        // Same node id on a different fabric is a different peer
+        mgr.add(entry(4, 2, 100));
+        assert_eq!(mgr.find(&[3; RESUMPTION_ID_LEN]), Some(entry(3, 1, 100)));
+    }
+
+    #[test]
+    fn test_remove_fabric() {
+        let mut mgr = ResumptionMgr::new();
+        mgr.add(entry(1, 1, 100));
+        mgr.add(entry(2, 2, 100));
+        mgr.add(entry(3, 1, 200));
+        mgr.remove_fabric(1);
+        assert_eq!(mgr.find(&[1; RESUMPTION_ID_LEN]), None);
+        assert_eq!(mgr.find(&[3; RESUMPTION_ID_LEN]), None);
+        assert_eq!(mgr.find(&[2; RESUMPTION_ID_LEN]), Some(entry(2, 2, 100)));
+        assert_eq!(mgr.entries[0], Some(entry(2, 2, 100)));
+        assert!(mgr.entries[1..].iter().all(|e| e.is_none()));
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut mgr = ResumptionMgr::new();
+        for i in 0..MAX_RESUMPTION_ENTRIES as u8 {
+            mgr.add(entry(i + 1, 1, i as u64));
+        }
+        // Refresh the oldest peer
+        mgr.add(entry(100, 1, 0));
+        mgr.add(entry(101, 1, 1000));
+        // The peer with node id 1 was the least recently used
+        assert_eq!(mgr.find(&[2; RESUMPTION_ID_LEN]), None);
+        assert!(mgr.find(&[100; RESUMPTION_ID_LEN]).is_some());
+        assert!(mgr.find(&[101; RESUMPTION_ID_LEN]).is_some());
+        assert!(mgr.find(&[3; RESUMPTION_ID_LEN]).is_some());
+    }
+
+    #[test]
+    fn test_tlv_roundtrip() {
+        let mut mgr = ResumptionMgr::new();
+        for i in 0..MAX_RESUMPTION_ENTRIES as u8 {
+            mgr.add(entry(0xff - i, 0xff, u64::MAX - i as u64));
+        }
+
+        let mut buf = [0u8; RESUMPTION_KV_MAX_SIZE];
+        let mut wb = WriteBuf::new(&mut buf, RESUMPTION_KV_MAX_SIZE);
+        let mut tw = TLVWriter::new(&mut wb);
+        mgr.entries.to_tlv(&mut tw, TagType::Anonymous).unwrap();
+
+        let root = TLVList::new(wb.as_slice()).iter().next().unwrap();
+        let entries: [Option<ResumptionEntry>; MAX_RESUMPTION_ENTRIES] =
+            FromTLV::from_tlv(&root).unwrap();
+        assert_eq!(entries, mgr.entries);
+    }
+}
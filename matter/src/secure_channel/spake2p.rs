@@ -81,8 +81,12 @@ const CRYPTO_GROUP_SIZE_BYTES: usize = 32;
 const CRYPTO_W_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + 8;
 const CRYPTO_PUBLIC_KEY_SIZE_BYTES: usize = (2 * CRYPTO_GROUP_SIZE_BYTES) + 1;
 
-const MAX_SALT_SIZE_BYTES: usize = 32;
-const VERIFIER_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + CRYPTO_PUBLIC_KEY_SIZE_BYTES;
+pub const MIN_SALT_SIZE_BYTES: usize = 16;
+pub const MAX_SALT_SIZE_BYTES: usize = 32;
+/// The range of PBKDF2 iterations that the spec allows for
+pub const MIN_ITERATION_COUNT: u32 = 1000;
+pub const MAX_ITERATION_COUNT: u32 = 100000;
+pub const VERIFIER_SIZE_BYTES: usize = CRYPTO_GROUP_SIZE_BYTES + CRYPTO_PUBLIC_KEY_SIZE_BYTES;
 
 #[cfg(feature = "crypto_openssl")]
 fn crypto_spake2_new() -> Result<Box<dyn CryptoSpake2>, Error> {
@@ -105,6 +109,7 @@ impl Default for Spake2P {
     }
 }
 
+#[derive(Clone)]
 pub struct VerifierData {
     pub data: VerifierOption,
     // For the VerifierOption::Verifier, the following fields only serve
@@ -113,6 +118,7 @@ pub struct VerifierData {
     pub count: u32,
 }
 
+#[derive(Clone)]
 pub enum VerifierOption {
     /// With Password
     Password(u32),
@@ -214,6 +220,63 @@ impl Spake2P {
         Ok(())
     }
 
+    pub fn start_prover(&mut self, pw: u32, count: u32, salt: &[u8]) -> Result<(), Error> {
+        let mut crypto_spake2 = crypto_spake2_new()?;
+        // Derive w0 and w1 from the password
+        let mut w0w1s: [u8; (2 * CRYPTO_W_SIZE_BYTES)] = [0; (2 * CRYPTO_W_SIZE_BYTES)];
+        Spake2P::get_w0w1s(pw, count, salt, &mut w0w1s);
+
+        let w0s_len = w0w1s.len() / 2;
+        crypto_spake2.set_w0_from_w0s(&w0w1s[0..w0s_len])?;
+        crypto_spake2.set_w1_from_w1s(&w0w1s[w0s_len..])?;
+        self.crypto_spake2 = Some(crypto_spake2);
+        self.mode = Spake2Mode::Prover;
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    pub fn get_pA(&mut self, pA: &mut [u8]) -> Result<(), Error> {
+        if self.mode != Spake2Mode::Prover {
+            return Err(Error::InvalidState);
+        }
+        self.crypto_spake2
+            .as_mut()
+            .ok_or(Error::InvalidState)?
+            .get_pA(pA)
+    }
+
+    /// Validate the verifier's cB, and generate our cA
+    ///
+    /// Unlike the verifier, the prover has the Ke once this succeeds.
+    #[allow(non_snake_case)]
+    pub fn handle_pB(
+        &mut self,
+        pA: &[u8],
+        pB: &[u8],
+        cB: &[u8],
+        cA: &mut [u8],
+    ) -> Result<&[u8], Error> {
+        if self.mode != Spake2Mode::Prover {
+            return Err(Error::InvalidState);
+        }
+        let mut crypto_spake2 = self.crypto_spake2.take().ok_or(Error::InvalidState)?;
+        let context = self.context.take().ok_or(Error::InvalidState)?;
+
+        let mut hash = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        context.finish(&mut hash)?;
+        let mut TT = [0u8; crypto::SHA256_HASH_LEN_BYTES];
+        crypto_spake2.get_TT_as_prover(&hash, pA, pB, &mut TT)?;
+
+        let mut our_cB = [0u8; 32];
+        Spake2P::get_Ke_and_cAcB(&TT, pA, pB, &mut self.Ke, cA, &mut our_cB)?;
+        if cB.ct_eq(&our_cB).unwrap_u8() == 1 {
+            Ok(&self.Ke)
+        } else {
+            error!("cB doesn't match");
+            Err(Error::InvalidSignature)
+        }
+    }
+
     #[allow(non_snake_case)]
     pub fn handle_pA(&mut self, pA: &[u8], pB: &mut [u8], cB: &mut [u8]) -> Result<(), Error> {
         if self.mode != Spake2Mode::Verifier(Spake2VerifierState::Init) {
@@ -296,10 +359,14 @@ impl Spake2P {
 #[cfg(test)]
 mod tests {
 
-    use super::Spake2P;
+    use super::{Spake2P, VerifierData};
     use crate::{
         crypto,
-        secure_channel::{spake2p::CRYPTO_W_SIZE_BYTES, spake2p_test_vectors::test_vectors::*},
+        error::Error,
+        secure_channel::{
+            common::SCStatusCodes, spake2p::CRYPTO_W_SIZE_BYTES,
+            spake2p_test_vectors::test_vectors::*,
+        },
     };
 
     #[test]
@@ -324,6 +391,46 @@ mod tests {
         )
     }
 
+    #[test]
+    #[allow(non_snake_case)]
+    fn test_prover_verifier() {
+        let verifier = VerifierData::new_with_pw(123456);
+        let mut v = Spake2P::new();
+        v.set_context(b"request", b"response").unwrap();
+        v.start_verifier(&verifier).unwrap();
+
+        let mut p = Spake2P::new();
+        p.set_context(b"request", b"response").unwrap();
+        p.start_prover(123456, verifier.count, &verifier.salt)
+            .unwrap();
+
+        let mut pA = [0u8; 65];
+        let mut pB = [0u8; 65];
+        let mut cA = [0u8; 32];
+        let mut cB = [0u8; 32];
+        p.get_pA(&mut pA).unwrap();
+        v.handle_pA(&pA, &mut pB, &mut cB).unwrap();
+        let prover_Ke = p.handle_pB(&pA, &pB, &cB, &mut cA).unwrap().to_vec();
+        let (status, verifier_Ke) = v.handle_cA(&cA);
+        assert!(status == SCStatusCodes::SessionEstablishmentSuccess);
+        assert_eq!(verifier_Ke, Some(prover_Ke.as_slice()));
+
+        // A prover with the wrong passcode can't confirm the verifier's cB
+        let mut v = Spake2P::new();
+        v.set_context(b"request", b"response").unwrap();
+        v.start_verifier(&verifier).unwrap();
+        let mut p = Spake2P::new();
+        p.set_context(b"request", b"response").unwrap();
+        p.start_prover(654321, verifier.count, &verifier.salt)
+            .unwrap();
+        p.get_pA(&mut pA).unwrap();
+        v.handle_pA(&pA, &mut pB, &mut cB).unwrap();
+        assert_eq!(
+            p.handle_pB(&pA, &pB, &cB, &mut cA).err(),
+            Some(Error::InvalidSignature)
+        );
+    }
+
     #[test]
     #[allow(non_snake_case)]
     fn test_get_Ke_and_cAcB() {
@@ -17,6 +17,7 @@
 
 use super::common::*;
 use crate::{error::Error, transport::packet::Packet};
+use byteorder::{ByteOrder, LittleEndian};
 
 #[allow(dead_code)]
 #[derive(Debug, Copy, Clone)]
@@ -58,3 +59,28 @@ pub fn create_status_report(
 
     Ok(())
 }
+
+/// A received status report
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StatusReport {
+    pub general_code: u16,
+    pub proto_id: u32,
+    pub proto_code: u16,
+}
+
+impl StatusReport {
+    pub fn parse(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < 8 {
+            return Err(Error::Invalid);
+        }
+        Ok(Self {
+            general_code: LittleEndian::read_u16(&buf[0..2]),
+            proto_id: LittleEndian::read_u32(&buf[2..6]),
+            proto_code: LittleEndian::read_u16(&buf[6..8]),
+        })
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.general_code == GeneralCode::Success as u16
+    }
+}
@@ -17,73 +17,59 @@
 
 use std::{
     convert::TryInto,
-    fs::{remove_file, DirBuilder, File},
+    fs::{read_dir, remove_file, DirBuilder, File},
     io::{Read, Write},
-    sync::{Arc, Mutex, Once},
 };
 
 use crate::error::Error;
 
 pub const SPAKE2_ITERATION_COUNT: u32 = 2000;
 
-// The Packet Pool that is allocated from. POSIX systems can use
+// The default size of the Packet Pool that is allocated from. POSIX systems can use
 // higher values unlike embedded systems
 pub const MAX_PACKET_POOL_SIZE: usize = 25;
 
-pub struct Psm {}
-
-static mut G_PSM: Option<Arc<Mutex<Psm>>> = None;
-static INIT: Once = Once::new();
+pub struct Psm {
+    dir: String,
+}
 
-const PSM_DIR: &str = "/tmp/matter_psm";
+pub const PSM_DIR: &str = "/tmp/matter_psm";
 
 macro_rules! psm_path {
-    ($key:ident) => {
-        format!("{}/{}", PSM_DIR, $key)
+    ($psm:ident, $key:ident) => {
+        format!("{}/{}", $psm.dir, $key)
     };
 }
 
 impl Psm {
-    fn new() -> Result<Self, Error> {
-        let result = DirBuilder::new().create(PSM_DIR);
-        if let Err(e) = result {
-            if e.kind() != std::io::ErrorKind::AlreadyExists {
-                return Err(e.into());
-            }
-        }
-
-        Ok(Self {})
-    }
-
-    pub fn get() -> Result<Arc<Mutex<Self>>, Error> {
-        unsafe {
-            INIT.call_once(|| {
-                G_PSM = Some(Arc::new(Mutex::new(Psm::new().unwrap())));
-            });
-            Ok(G_PSM.as_ref().ok_or(Error::Invalid)?.clone())
-        }
+    /// Storage in the given directory
+    pub fn new(dir: &str) -> Result<Self, Error> {
+        DirBuilder::new().recursive(true).create(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+        })
     }
 
     pub fn set_kv_slice(&self, key: &str, val: &[u8]) -> Result<(), Error> {
-        let mut f = File::create(psm_path!(key))?;
+        let mut f = File::create(psm_path!(self, key))?;
         f.write_all(val)?;
         Ok(())
     }
 
     pub fn get_kv_slice(&self, key: &str, val: &mut Vec<u8>) -> Result<usize, Error> {
-        let mut f = File::open(psm_path!(key))?;
+        let mut f = File::open(psm_path!(self, key))?;
         let len = f.read_to_end(val)?;
         Ok(len)
     }
 
     pub fn set_kv_u64(&self, key: &str, val: u64) -> Result<(), Error> {
-        let mut f = File::create(psm_path!(key))?;
+        let mut f = File::create(psm_path!(self, key))?;
         f.write_all(&val.to_be_bytes())?;
         Ok(())
     }
 
     pub fn get_kv_u64(&self, key: &str, val: &mut u64) -> Result<(), Error> {
-        let mut f = File::open(psm_path!(key))?;
+        let mut f = File::open(psm_path!(self, key))?;
         let mut vec = Vec::new();
         let _ = f.read_to_end(&mut vec)?;
         *val = u64::from_be_bytes(vec.as_slice().try_into()?);
@@ -91,6 +77,15 @@ impl Psm {
     }
 
     pub fn rm(&self, key: &str) {
-        let _ = remove_file(psm_path!(key));
+        let _ = remove_file(psm_path!(self, key));
+    }
+
+    /// Make sure that everything that was stored so far, is on the disk
+    pub fn flush(&self) -> Result<(), Error> {
+        for entry in read_dir(&self.dir)? {
+            File::open(entry?.path())?.sync_all()?;
+        }
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
     }
 }
@@ -0,0 +1,189 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+// Duplicate detection of received messages, as per the message counter rules in the spec
+
+const MSG_COUNTER_WINDOW_SIZE: u32 = 32;
+
+// With rollover, a counter ahead of the max counter by less than this is a new counter
+const MSG_COUNTER_ROLLOVER_HALF: u32 = 1 << 31;
+
+/// The counter processing rules that apply to a session
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CtrPolicy {
+    /// Secure unicast sessions: counters never roll over, anything behind the window is
+    /// a duplicate
+    Encrypted,
+    /// Unsecured sessions: counters may roll over, anything behind the window is
+    /// accepted and resets the state (the peer may have rebooted)
+    Unencrypted,
+    /// Group sessions: counters may roll over, anything behind the window is a duplicate
+    Group,
+}
+
+/// The state of the received message counters from a peer
+#[derive(Debug, Copy, Clone)]
+pub struct RxCtrState {
+    policy: CtrPolicy,
+    // None, till the first message is received
+    max_ctr: Option<u32>,
+    // Bit 'n' is set, if the message with counter (max_ctr - n - 1) has been received
+    bitmap: u32,
+}
+
+impl RxCtrState {
+    pub fn new(policy: CtrPolicy) -> Self {
+        Self {
+            policy,
+            max_ctr: None,
+            bitmap: 0,
+        }
+    }
+
+    /// Process a received message counter
+    ///
+    /// Returns true if the message is a duplicate. The state is updated only for
+    /// messages that are not duplicates.
+    pub fn recv(&mut self, msg_ctr: u32) -> bool {
+        let max_ctr = match self.max_ctr {
+            Some(m) => m,
+            None => {
+                self.reset(msg_ctr);
+                return false;
+            }
+        };
+
+        let ahead = match self.policy {
+            CtrPolicy::Encrypted => msg_ctr > max_ctr,
+            CtrPolicy::Unencrypted | CtrPolicy::Group => {
+                let diff = msg_ctr.wrapping_sub(max_ctr);
+                diff != 0 && diff < MSG_COUNTER_ROLLOVER_HALF
+            }
+        };
+        if ahead {
+            let shift = msg_ctr.wrapping_sub(max_ctr);
+            self.bitmap = self.bitmap.checked_shl(shift).unwrap_or(0);
+            if shift <= MSG_COUNTER_WINDOW_SIZE {
+                // The previous max counter now moves into the window
+                self.bitmap |= 1 << (shift - 1);
+            }
+            self.max_ctr = Some(msg_ctr);
+            return false;
+        }
+
+        let behind = max_ctr.wrapping_sub(msg_ctr);
+        if behind == 0 {
+            return true;
+        }
+        if behind > MSG_COUNTER_WINDOW_SIZE {
+            return match self.policy {
+                CtrPolicy::Unencrypted => {
+                    self.reset(msg_ctr);
+                    false
+                }
+                CtrPolicy::Encrypted | CtrPolicy::Group => true,
+            };
+        }
+
+        let bit = 1 << (behind - 1);
+        if self.bitmap & bit != 0 {
+            true
+        } else {
+            self.bitmap |= bit;
+            false
+        }
+    }
+
+    fn reset(&mut self, msg_ctr: u32) {
+        self.max_ctr = Some(msg_ctr);
+        self.bitmap = 0;
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
+mod tests {
+    use super::{CtrPolicy, RxCtrState};
+
+    #[test]
+    fn test_encrypted_in_order_and_dups() {
+        let mut s = RxCtrState::new(CtrPolicy::Encrypted);
+        assert_eq!(s.recv(100), false);
+        assert_eq!(s.recv(100), true);
+        assert_eq!(s.recv(101), false);
+        assert_eq!(s.recv(101), true);
+        assert_eq!(s.recv(100), true);
+    }
+
+    #[test]
+    fn test_encrypted_out_of_order() {
+        let mut s = RxCtrState::new(CtrPolicy::Encrypted);
+        assert_eq!(s.recv(100), false);
+        assert_eq!(s.recv(105), false);
+        assert_eq!(s.recv(103), false);
+        assert_eq!(s.recv(103), true);
+        assert_eq!(s.recv(104), false);
+        assert_eq!(s.recv(100), true);
+        // The window's edge
+        assert_eq!(s.recv(73), false);
+        assert_eq!(s.recv(73), true);
+        // Behind the window
+        assert_eq!(s.recv(72), true);
+    }
+
+    #[test]
+    fn test_encrypted_large_jump() {
+        let mut s = RxCtrState::new(CtrPolicy::Encrypted);
+        assert_eq!(s.recv(100), false);
+        assert_eq!(s.recv(132), false);
+        assert_eq!(s.recv(100), true);
+        assert_eq!(s.recv(200), false);
+        assert_eq!(s.recv(132), true);
+        assert_eq!(s.recv(199), false);
+    }
+
+    #[test]
+    fn test_encrypted_no_rollover() {
+        let mut s = RxCtrState::new(CtrPolicy::Encrypted);
+        assert_eq!(s.recv(0xffff_fffe), false);
+        assert_eq!(s.recv(0xffff_ffff), false);
+        assert_eq!(s.recv(0), true);
+    }
+
+    #[test]
+    fn test_unencrypted_rollover_and_reset() {
+        let mut s = RxCtrState::new(CtrPolicy::Unencrypted);
+        assert_eq!(s.recv(0xffff_fffe), false);
+        assert_eq!(s.recv(1), false);
+        assert_eq!(s.recv(0xffff_ffff), false);
+        assert_eq!(s.recv(0xffff_fffe), true);
+        // A peer that rebooted
+        assert_eq!(s.recv(0x1000), false);
+        assert_eq!(s.recv(10), false);
+        assert_eq!(s.recv(10), true);
+        assert_eq!(s.recv(11), false);
+    }
+
+    #[test]
+    fn test_group_behind_window() {
+        let mut s = RxCtrState::new(CtrPolicy::Group);
+        assert_eq!(s.recv(0xffff_ffff), false);
+        assert_eq!(s.recv(5), false);
+        assert_eq!(s.recv(0), false);
+        assert_eq!(s.recv(0xffff_ff00), true);
+    }
+}
@@ -15,10 +15,10 @@
  *    limitations under the License.
  */
 
-use boxslab::{BoxSlab, Slab};
 use colored::*;
 use log::{error, info, trace};
 use std::any::Any;
+use std::collections::HashMap;
 use std::fmt;
 use std::time::SystemTime;
 
@@ -27,7 +27,7 @@ use crate::secure_channel;
 
 use heapless::LinearMap;
 
-use super::packet::PacketPool;
+use super::network::{Address, Readable};
 use super::session::CloneData;
 use super::{mrp::ReliableMessage, packet::Packet, session::SessionHandle, session::SessionMgr};
 
@@ -69,7 +69,7 @@ impl Default for State {
 // where the commonly used higher layer data store does't have to do a Box
 #[derive(Debug)]
 pub enum DataOption {
-    Boxed(Box<dyn Any>),
+    Boxed(Box<dyn Any + Send>),
     Time(SystemTime),
     None,
 }
@@ -136,7 +136,7 @@ impl Exchange {
         matches!(self.data, DataOption::None)
     }
 
-    pub fn set_data_boxed(&mut self, data: Box<dyn Any>) {
+    pub fn set_data_boxed(&mut self, data: Box<dyn Any + Send>) {
         self.data = DataOption::Boxed(data);
     }
 
@@ -177,7 +177,7 @@ impl Exchange {
 
     fn send(
         &mut self,
-        mut proto_tx: BoxSlab<PacketPool>,
+        mut proto_tx: Box<Packet<'static>>,
         session: &mut SessionHandle,
     ) -> Result<(), Error> {
         if self.state == State::Terminate {
@@ -197,10 +197,26 @@ impl Exchange {
         if self.role == Role::Initiator {
             proto_tx.proto.set_initiator();
         }
+        if session.get_peer_addr().is_reliable() {
+            // MRP is not used over reliable transports like TCP
+            proto_tx.unset_reliable();
+        }
 
         session.pre_send(&mut proto_tx)?;
         self.mrp.pre_send(&mut proto_tx)?;
-        session.send(proto_tx)
+        session.send(&mut proto_tx)?;
+        self.mrp.post_send(proto_tx, session.get_retrans_interval())
+    }
+
+    fn retrans(&mut self, sess_mgr: &mut SessionMgr) -> Result<(), Error> {
+        let base_interval = sess_mgr
+            .mut_by_index(self.sess_idx)
+            .ok_or(Error::NoSession)?
+            .get_retrans_interval();
+        if let Some(proto_tx) = self.mrp.get_retrans(base_interval)? {
+            sess_mgr.transmit(proto_tx)?;
+        }
+        Ok(())
     }
 }
 
@@ -214,6 +230,15 @@ impl fmt::Display for Exchange {
     }
 }
 
+/// The session on which we initiate a new exchange
+#[derive(Debug, Clone, Copy)]
+pub enum SessionTarget {
+    /// A new unsecured session with this peer, for establishing a secure session
+    Unsecured(Address),
+    /// The secure session with this local session id
+    Secured(u16),
+}
+
 pub fn get_role(is_initiator: bool) -> Role {
     if is_initiator {
         Role::Initiator
@@ -230,22 +255,41 @@ pub fn get_complementary_role(is_initiator: bool) -> Role {
     }
 }
 
-const MAX_EXCHANGES: usize = 8;
+// The default maximum number of exchanges on a session
+pub const MAX_EXCHANGES: usize = 8;
 
-#[derive(Default)]
 pub struct ExchangeMgr {
     // keys: exch-id
-    exchanges: LinearMap<u16, Exchange, MAX_EXCHANGES>,
+    exchanges: HashMap<u16, Exchange>,
+    max_exchanges: usize,
+    // The id of the next exchange that we initiate
+    next_exch_id: u16,
     sess_mgr: SessionMgr,
+    // The local session ids of the secure sessions that were removed, for the protocols
+    // to hear about
+    closed_sessions: Vec<u16>,
+}
+
+impl Default for ExchangeMgr {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
 }
 
 pub const MAX_MRP_ENTRIES: usize = 4;
 
 impl ExchangeMgr {
     pub fn new(sess_mgr: SessionMgr) -> Self {
+        Self::new_with(sess_mgr, MAX_EXCHANGES)
+    }
+
+    pub fn new_with(sess_mgr: SessionMgr, max_exchanges: usize) -> Self {
         Self {
             sess_mgr,
             exchanges: Default::default(),
+            max_exchanges,
+            next_exch_id: rand::random(),
+            closed_sessions: Vec::new(),
         }
     }
 
@@ -254,7 +298,7 @@ impl ExchangeMgr {
     }
 
     pub fn _get_with_id(
-        exchanges: &mut LinearMap<u16, Exchange, MAX_EXCHANGES>,
+        exchanges: &mut HashMap<u16, Exchange>,
         exch_id: u16,
     ) -> Option<&mut Exchange> {
         exchanges.get_mut(&exch_id)
@@ -265,7 +309,8 @@ impl ExchangeMgr {
     }
 
     fn _get(
-        exchanges: &mut LinearMap<u16, Exchange, MAX_EXCHANGES>,
+        exchanges: &mut HashMap<u16, Exchange>,
+        max_exchanges: usize,
         sess_idx: usize,
         id: u16,
         role: Role,
@@ -275,11 +320,16 @@ impl ExchangeMgr {
         if !exchanges.contains_key(&(id)) {
             if create_new {
                 // If an exchange doesn't exist, create a new one
-                info!("Creating new exchange");
-                let e = Exchange::new(id, sess_idx, role);
-                if exchanges.insert(id, e).is_err() {
+                let count = exchanges
+                    .values()
+                    .filter(|e| e.sess_idx == sess_idx)
+                    .count();
+                if count >= max_exchanges {
                     return Err(Error::NoSpace);
                 }
+                info!("Creating new exchange");
+                let e = Exchange::new(id, sess_idx, role);
+                exchanges.insert(id, e);
             } else {
                 return Err(Error::NoSpace);
             }
@@ -299,8 +349,52 @@ impl ExchangeMgr {
         }
     }
 
+    fn get_next_exch_id(&mut self) -> u16 {
+        loop {
+            let exch_id = self.next_exch_id;
+            self.next_exch_id = self.next_exch_id.wrapping_add(1);
+            if !self.exchanges.contains_key(&exch_id) {
+                return exch_id;
+            }
+        }
+    }
+
+    /// Create a new exchange, on which we are the initiator
+    pub fn initiate(&mut self, target: SessionTarget) -> Result<ExchangeCtx, Error> {
+        let sess_idx = match target {
+            SessionTarget::Unsecured(peer_addr) => {
+                match self.sess_mgr.add_unsecured_initiator(peer_addr) {
+                    Err(Error::NoSpace) => {
+                        let evict_index = self.sess_mgr.get_lru();
+                        self.evict_session(evict_index)?;
+                        self.sess_mgr.add_unsecured_initiator(peer_addr)?
+                    }
+                    result => result?,
+                }
+            }
+            SessionTarget::Secured(local_sess_id) => self
+                .sess_mgr
+                .get_index_with_id(local_sess_id)
+                .ok_or(Error::NoSession)?,
+        };
+
+        let exch_id = self.get_next_exch_id();
+        let exch = ExchangeMgr::_get(
+            &mut self.exchanges,
+            self.max_exchanges,
+            sess_idx,
+            exch_id,
+            Role::Initiator,
+            true,
+        )?;
+        Ok(ExchangeCtx {
+            exch,
+            sess: self.sess_mgr.get_session_handle(sess_idx),
+        })
+    }
+
     /// The Exchange Mgr receive is like a big processing function
-    pub fn recv(&mut self) -> Result<Option<(BoxSlab<PacketPool>, ExchangeCtx)>, Error> {
+    pub fn recv(&mut self) -> Result<Option<(Box<Packet<'static>>, ExchangeCtx)>, Error> {
         // Get the session
         let (mut proto_rx, index) = self.sess_mgr.recv()?;
 
@@ -316,11 +410,22 @@ impl ExchangeMgr {
         let mut session = self.sess_mgr.get_session_handle(index);
 
         // Decrypt the message
-        session.recv(&mut proto_rx)?;
+        match session.recv(&mut proto_rx) {
+            Err(Error::Duplicate) => {
+                info!("Dropping duplicate message ctr {}", proto_rx.plain.ctr);
+                if proto_rx.proto.is_reliable() && !session.get_peer_addr().is_reliable() {
+                    // The peer probably missed our ack, acknowledge it again
+                    ExchangeMgr::send_dup_ack(&proto_rx, &mut session)?;
+                }
+                return Ok(None);
+            }
+            result => result?,
+        }
 
         // Get the exchange
         let exch = ExchangeMgr::_get(
             &mut self.exchanges,
+            self.max_exchanges,
             index,
             proto_rx.proto.exch_id,
             get_complementary_role(proto_rx.proto.is_initiator()),
@@ -346,7 +451,21 @@ impl ExchangeMgr {
         }
     }
 
-    pub fn send(&mut self, exch_id: u16, proto_tx: BoxSlab<PacketPool>) -> Result<(), Error> {
+    // A duplicate is acknowledged immediately, without going through its exchange, which
+    // may not even exist anymore
+    fn send_dup_ack(proto_rx: &Packet, session: &mut SessionHandle) -> Result<(), Error> {
+        let mut proto_tx = Box::new(Packet::new_tx(session.packet_pool())?);
+        secure_channel::common::create_mrp_standalone_ack(&mut proto_tx);
+        proto_tx.proto.exch_id = proto_rx.proto.exch_id;
+        if !proto_rx.proto.is_initiator() {
+            proto_tx.proto.set_initiator();
+        }
+        proto_tx.proto.set_ack(proto_rx.plain.ctr);
+        session.pre_send(&mut proto_tx)?;
+        session.send(&mut proto_tx)
+    }
+
+    pub fn send(&mut self, exch_id: u16, proto_tx: Box<Packet<'static>>) -> Result<(), Error> {
         let exchange =
             ExchangeMgr::_get_with_id(&mut self.exchanges, exch_id).ok_or(Error::NoExchange)?;
         let mut session = self.sess_mgr.get_session_handle(exchange.sess_idx);
@@ -354,22 +473,49 @@ impl ExchangeMgr {
     }
 
     pub fn purge(&mut self) {
-        let mut to_purge: LinearMap<u16, (), MAX_EXCHANGES> = LinearMap::new();
+        self.exchanges
+            .retain(|_, exchange| !exchange.is_purgeable());
+    }
 
+    pub fn pending_acks(&mut self, expired_entries: &mut LinearMap<u16, (), MAX_MRP_ENTRIES>) {
         for (exch_id, exchange) in self.exchanges.iter() {
-            if exchange.is_purgeable() {
-                let _ = to_purge.insert(*exch_id, ());
+            if exchange.mrp.is_ack_ready() && expired_entries.insert(*exch_id, ()).is_err() {
+                // The rest are picked up the next time around
+                break;
             }
         }
-        for (exch_id, _) in to_purge.iter() {
-            self.exchanges.remove(exch_id);
-        }
     }
 
-    pub fn pending_acks(&mut self, expired_entries: &mut LinearMap<u16, (), MAX_MRP_ENTRIES>) {
-        for (exch_id, exchange) in self.exchanges.iter() {
-            if exchange.mrp.is_ack_ready() {
-                expired_entries.insert(*exch_id, ()).unwrap();
+    /// The earliest time at which any exchange has an MRP timer expiring
+    pub fn get_next_timeout(&self) -> Option<SystemTime> {
+        self.exchanges
+            .values()
+            .filter_map(|e| e.mrp.get_next_timeout())
+            .min()
+    }
+
+    pub fn readable(&self) -> Result<Readable<'_>, Error> {
+        self.sess_mgr.readable()
+    }
+
+    /// Retransmit the reliable messages whose ACKs haven't arrived in time
+    ///
+    /// Exchanges that have run out of retransmission attempts are terminated.
+    pub fn retrans(&mut self) {
+        for (exch_id, exchange) in self.exchanges.iter_mut() {
+            if !exchange.mrp.is_retrans_ready() {
+                continue;
+            }
+            match exchange.retrans(&mut self.sess_mgr) {
+                Err(Error::RetransExhausted) => {
+                    error!(
+                        "Terminating exchange {} on MRP retransmission failure",
+                        exch_id
+                    );
+                    exchange.terminate();
+                }
+                Err(e) => error!("Error in retransmitting on exchange {}: {:?}", exch_id, e),
+                Ok(()) => (),
             }
         }
     }
@@ -378,23 +524,96 @@ impl ExchangeMgr {
         info!("Sessions full, vacating session with index: {}", index);
         // If we enter here, we have an LRU session that needs to be reclaimed
         // As per the spec, we need to send a CLOSE here
+        self.close_session(index);
+        Ok(())
+    }
 
-        let mut session = self.sess_mgr.get_session_handle(index);
-        let mut tx = Slab::<PacketPool>::try_new(Packet::new_tx()?).ok_or(Error::NoSpace)?;
+    /// Inform the peer that the session is being closed
+    ///
+    /// This goes out on a new exchange, and without MRP, as the session is removed
+    /// right after this
+    fn send_close_session(&mut self, index: usize) -> Result<(), Error> {
+        let mut tx = Box::new(Packet::new_tx(self.sess_mgr.packet_pool())?);
         secure_channel::common::create_sc_status_report(
             &mut tx,
             secure_channel::common::SCStatusCodes::CloseSession,
             None,
         )?;
+        tx.proto.exch_id = rand::random();
+        tx.proto.set_initiator();
+
+        let mut session = self.sess_mgr.get_session_handle(index);
+        session.pre_send(&mut tx)?;
+        session.send(&mut tx)
+    }
 
-        if let Some((_, exchange)) = self.exchanges.iter_mut().find(|(_, e)| e.sess_idx == index) {
-            // Send Close_session on this exchange, and then close the session
-            // Should this be done for all exchanges?
-            error!("Sending Close Session");
-            exchange.send(tx, &mut session)?;
-            // TODO: This wouldn't actually send it out, because 'transport' isn't owned yet.
+    /// Close the session, informing the peer about it, if it is a secure session
+    pub fn close_session(&mut self, index: usize) {
+        let is_encrypted = match self.sess_mgr.mut_by_index(index) {
+            Some(session) => session.is_encrypted(),
+            None => return,
+        };
+        if is_encrypted {
+            info!("Sending Close Session on session with index {}", index);
+            if let Err(e) = self.send_close_session(index) {
+                error!("Error in sending Close Session {:?}", e);
+            }
+        }
+        self.remove_session(index);
+    }
+
+    /// Close all the secure sessions, informing the peers about it
+    pub fn close_sessions(&mut self) {
+        for index in 0..self.sess_mgr.max_sessions() {
+            self.close_session(index);
+        }
+    }
+
+    /// Close all the sessions on this fabric, other than the one with the local session id
+    /// `except`, informing the peers about it
+    pub fn close_fabric_sessions(&mut self, fab_idx: u8, except: Option<u16>) {
+        for index in 0..self.sess_mgr.max_sessions() {
+            let on_fabric = self
+                .sess_mgr
+                .mut_by_index(index)
+                .map(|s| {
+                    s.get_local_fabric_idx() == Some(fab_idx)
+                        && Some(s.get_local_sess_id()) != except
+                })
+                .unwrap_or(false);
+            if on_fabric {
+                self.close_session(index);
+            }
         }
+    }
+
+    /// The peer has closed the session with this local session id, remove our side
+    /// of it too
+    pub fn peer_closed_session(&mut self, local_sess_id: u16) {
+        if let Some(index) = self.get_secure_index(local_sess_id) {
+            info!("Peer closed the session with index {}", index);
+            self.remove_session(index);
+        }
+    }
+
+    /// Close the secure session with this local session id, informing the peer about it
+    pub fn close_session_with_id(&mut self, local_sess_id: u16) {
+        if let Some(index) = self.get_secure_index(local_sess_id) {
+            self.close_session(index);
+        }
+    }
+
+    fn get_secure_index(&mut self, local_sess_id: u16) -> Option<usize> {
+        (0..self.sess_mgr.max_sessions()).find(|i| {
+            self.sess_mgr
+                .mut_by_index(*i)
+                .map(|s| s.is_encrypted() && s.get_local_sess_id() == local_sess_id)
+                .unwrap_or(false)
+        })
+    }
 
+    /// Remove the session, along with all the exchanges on it
+    fn remove_session(&mut self, index: usize) {
         let remove_exchanges: Vec<u16> = self
             .exchanges
             .iter()
@@ -414,8 +633,34 @@ impl ExchangeMgr {
             // Remove from exchange list
             self.exchanges.remove(&exch_id);
         }
+        let mut peer = None;
+        if let Some(session) = self.sess_mgr.mut_by_index(index) {
+            if session.is_encrypted() {
+                self.closed_sessions.push(session.get_local_sess_id());
+            }
+            peer = Some(session.get_peer_addr());
+        }
         self.sess_mgr.remove(index);
-        Ok(())
+        // A connection is of no use without any sessions on it
+        if let Some(peer) = peer.filter(|p| p.is_reliable()) {
+            if self.sess_mgr.get_with_peer_addr(peer).is_none() {
+                self.sess_mgr.close_connection(peer);
+            }
+        }
+    }
+
+    /// The local session ids of the secure sessions that were removed, since the last call
+    pub fn take_closed_sessions(&mut self) -> Vec<u16> {
+        std::mem::take(&mut self.closed_sessions)
+    }
+
+    /// The connection to this peer is gone, the sessions on that connection can't be
+    /// used anymore
+    pub fn connection_closed(&mut self, peer: Address) {
+        while let Some(index) = self.sess_mgr.get_with_peer_addr(peer) {
+            info!("Removing session with index {} on closed connection", index);
+            self.remove_session(index);
+        }
     }
 
     pub fn add_session(&mut self, clone_data: &CloneData) -> Result<SessionHandle, Error> {
@@ -450,43 +695,118 @@ impl fmt::Display for ExchangeMgr {
 #[allow(clippy::bool_assert_comparison)]
 mod tests {
 
+    use std::sync::{Arc, Mutex};
+
     use crate::{
         error::Error,
         transport::{
-            network::{Address, NetworkInterface},
-            session::{CloneData, SessionMgr, SessionMode, MAX_SESSIONS},
+            network::{Address, NetworkInterface, Readable},
+            session::{CaseDetails, CloneData, SessionMgr, SessionMode, MAX_SESSIONS},
         },
     };
 
-    use super::{ExchangeMgr, Role};
+    use super::{ExchangeMgr, Role, SessionTarget, MAX_EXCHANGES};
 
     #[test]
     fn test_purge() {
         let sess_mgr = SessionMgr::new();
         let mut mgr = ExchangeMgr::new(sess_mgr);
-        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 1, 2, Role::Responder, true).unwrap();
-        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 1, 3, Role::Responder, true).unwrap();
+        let _ = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            1,
+            2,
+            Role::Responder,
+            true,
+        )
+        .unwrap();
+        let _ = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            1,
+            3,
+            Role::Responder,
+            true,
+        )
+        .unwrap();
 
         mgr.purge();
         assert_eq!(
-            ExchangeMgr::_get(&mut mgr.exchanges, 1, 2, Role::Responder, false).is_ok(),
+            ExchangeMgr::_get(
+                &mut mgr.exchanges,
+                MAX_EXCHANGES,
+                1,
+                2,
+                Role::Responder,
+                false
+            )
+            .is_ok(),
             true
         );
         assert_eq!(
-            ExchangeMgr::_get(&mut mgr.exchanges, 1, 3, Role::Responder, false).is_ok(),
+            ExchangeMgr::_get(
+                &mut mgr.exchanges,
+                MAX_EXCHANGES,
+                1,
+                3,
+                Role::Responder,
+                false
+            )
+            .is_ok(),
             true
         );
 
         // Close e1
-        let e1 = ExchangeMgr::_get(&mut mgr.exchanges, 1, 2, Role::Responder, false).unwrap();
+        let e1 = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            1,
+            2,
+            Role::Responder,
+            false,
+        )
+        .unwrap();
         e1.close();
         mgr.purge();
         assert_eq!(
-            ExchangeMgr::_get(&mut mgr.exchanges, 1, 2, Role::Responder, false).is_ok(),
+            ExchangeMgr::_get(
+                &mut mgr.exchanges,
+                MAX_EXCHANGES,
+                1,
+                2,
+                Role::Responder,
+                false
+            )
+            .is_ok(),
             false
         );
         assert_eq!(
-            ExchangeMgr::_get(&mut mgr.exchanges, 1, 3, Role::Responder, false).is_ok(),
+            ExchangeMgr::_get(
+                &mut mgr.exchanges,
+                MAX_EXCHANGES,
+                1,
+                3,
+                Role::Responder,
+                false
+            )
+            .is_ok(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_exchange_limit() {
+        let sess_mgr = SessionMgr::new();
+        let mut mgr = ExchangeMgr::new_with(sess_mgr, 2);
+        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 2, 1, 2, Role::Responder, true).unwrap();
+        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 2, 1, 3, Role::Responder, true).unwrap();
+        assert_eq!(
+            ExchangeMgr::_get(&mut mgr.exchanges, 2, 1, 4, Role::Responder, true).err(),
+            Some(Error::NoSpace)
+        );
+        // The limit is per session
+        assert_eq!(
+            ExchangeMgr::_get(&mut mgr.exchanges, 2, 2, 4, Role::Responder, true).is_ok(),
             true
         );
     }
@@ -534,6 +854,10 @@ mod tests {
         fn send(&self, _out_buf: &[u8], _addr: Address) -> Result<usize, Error> {
             Ok(0)
         }
+
+        fn readable(&self) -> Readable<'_> {
+            Box::pin(async { Ok(()) })
+        }
     }
 
     #[test]
@@ -552,8 +876,24 @@ mod tests {
 
         // Create exchanges for sessions 2 (i.e. session index 1) and 3 (session index 2)
         //   Exchange IDs are 20 and 30 respectively
-        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 1, 20, Role::Responder, true).unwrap();
-        let _ = ExchangeMgr::_get(&mut mgr.exchanges, 2, 30, Role::Responder, true).unwrap();
+        let _ = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            1,
+            20,
+            Role::Responder,
+            true,
+        )
+        .unwrap();
+        let _ = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            2,
+            30,
+            Role::Responder,
+            true,
+        )
+        .unwrap();
 
         // Confirm that session ids 1 to MAX_SESSIONS exists
         for i in 1..(MAX_SESSIONS + 1) {
@@ -601,4 +941,133 @@ mod tests {
         }
         //        println!("Session mgr {}", mgr.sess_mgr);
     }
+
+    #[test]
+    fn test_close_sessions() {
+        let mut sess_mgr = SessionMgr::new();
+        let transport = Box::new(DummyNetwork::new());
+        sess_mgr.add_network_interface(transport).unwrap();
+        let mut mgr = ExchangeMgr::new(sess_mgr);
+
+        for (local_sess_id, fab_idx) in [(1, 1), (2, 2), (3, 1)] {
+            let clone_data = CloneData::new(
+                12341234,
+                43211234,
+                100 + local_sess_id,
+                local_sess_id,
+                Address::default(),
+                SessionMode::Case(CaseDetails::new(fab_idx, &[0; 3])),
+            );
+            mgr.add_session(&clone_data).unwrap();
+        }
+        let _ = ExchangeMgr::_get(
+            &mut mgr.exchanges,
+            MAX_EXCHANGES,
+            0,
+            20,
+            Role::Responder,
+            true,
+        )
+        .unwrap();
+
+        // Only the sessions on fabric 1 go away, along with their exchanges
+        mgr.close_fabric_sessions(1, None);
+        assert_eq!(mgr.sess_mgr.get_with_id(1).is_none(), true);
+        assert_eq!(mgr.sess_mgr.get_with_id(2).is_none(), false);
+        assert_eq!(mgr.sess_mgr.get_with_id(3).is_none(), true);
+        assert_eq!(mgr.get_with_id(20).is_none(), true);
+
+        // The session that is excepted, stays
+        mgr.close_fabric_sessions(2, Some(2));
+        assert_eq!(mgr.sess_mgr.get_with_id(2).is_none(), false);
+
+        mgr.peer_closed_session(5);
+        assert_eq!(mgr.sess_mgr.get_with_id(2).is_none(), false);
+        mgr.peer_closed_session(2);
+        assert_eq!(mgr.sess_mgr.get_with_id(2).is_none(), true);
+    }
+
+    // Keeps track of the connections that it was asked to close
+    struct ConnNetwork {
+        closed: Arc<Mutex<Vec<Address>>>,
+    }
+
+    impl NetworkInterface for ConnNetwork {
+        fn recv(&self, _in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+            Ok((0, Address::default()))
+        }
+
+        fn send(&self, _out_buf: &[u8], _addr: Address) -> Result<usize, Error> {
+            Ok(0)
+        }
+
+        fn readable(&self) -> Readable<'_> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn close(&self, addr: Address) {
+            self.closed.lock().unwrap().push(addr);
+        }
+    }
+
+    #[test]
+    fn test_close_connection() {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let mut sess_mgr = SessionMgr::new();
+        let transport = Box::new(ConnNetwork {
+            closed: closed.clone(),
+        });
+        sess_mgr.add_network_interface(transport).unwrap();
+        let mut mgr = ExchangeMgr::new(sess_mgr);
+
+        let tcp = Address::Tcp("192.168.1.2:5540".parse().unwrap());
+        let udp = Address::Udp("192.168.1.2:5540".parse().unwrap());
+        for (local_sess_id, peer_addr) in [(1, tcp), (2, tcp), (3, udp)] {
+            let clone_data = CloneData::new(
+                12341234,
+                43211234,
+                100 + local_sess_id,
+                local_sess_id,
+                peer_addr,
+                SessionMode::Pase,
+            );
+            mgr.add_session(&clone_data).unwrap();
+        }
+
+        // The connection stays, while there is a session on it
+        mgr.peer_closed_session(1);
+        assert!(closed.lock().unwrap().is_empty());
+        mgr.peer_closed_session(2);
+        assert_eq!(*closed.lock().unwrap(), [tcp]);
+        // Only the connection oriented transports have connections to close
+        mgr.peer_closed_session(3);
+        assert_eq!(*closed.lock().unwrap(), [tcp]);
+    }
+
+    #[test]
+    fn test_initiate() {
+        let mut sess_mgr = SessionMgr::new();
+        let transport = Box::new(DummyNetwork::new());
+        sess_mgr.add_network_interface(transport).unwrap();
+        let mut mgr = ExchangeMgr::new(sess_mgr);
+
+        // A new unsecured session, on which we are the initiator
+        let exch_ctx = mgr
+            .initiate(SessionTarget::Unsecured(Address::default()))
+            .unwrap();
+        assert_eq!(exch_ctx.exch.get_role(), Role::Initiator);
+        assert_eq!(exch_ctx.sess.is_encrypted(), false);
+        let exch_id = exch_ctx.exch.get_id();
+
+        // An existing secure session
+        mgr.add_session(&get_clone_data(100, 1)).unwrap();
+        let exch_ctx = mgr.initiate(SessionTarget::Secured(1)).unwrap();
+        assert_eq!(exch_ctx.sess.get_local_sess_id(), 1);
+        assert_ne!(exch_ctx.exch.get_id(), exch_id);
+
+        assert_eq!(
+            mgr.initiate(SessionTarget::Secured(2)).err(),
+            Some(Error::NoSession)
+        );
+    }
 }
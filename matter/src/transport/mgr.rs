@@ -15,42 +15,94 @@
  *    limitations under the License.
  */
 
-use async_channel::Receiver;
-use boxslab::{BoxSlab, Slab};
+use std::time::{Duration, SystemTime};
+
+use async_channel::{bounded, Receiver, Sender};
 use heapless::LinearMap;
-use log::{debug, error, info};
+use log::{debug, error, info, trace};
+use smol::{future::FutureExt, Timer};
 
 use crate::error::*;
 
 use crate::transport::mrp::ReliableMessage;
-use crate::transport::packet::PacketPool;
-use crate::transport::{exchange, packet::Packet, proto_demux, queue, session, udp};
+use crate::transport::packet::{PacketPool, MAX_LARGE_MSG_SIZE, MAX_RX_BUF_SIZE};
+use crate::transport::{exchange, packet::Packet, proto_demux, session, tcp};
+
+use super::network::Address;
+use super::proto_demux::{InitiateReq, ProtoCtx};
+use super::queue::{Msg, WorkQ};
 
-use super::proto_demux::ProtoCtx;
-use super::queue::Msg;
+// The events that the transport loop waits on
+enum Event {
+    Rx,
+    Queue(Msg),
+    Timeout,
+    Stop,
+}
+
+/// Stops the transport loop, possibly from another thread
+#[derive(Clone)]
+pub struct StopHandle {
+    tx: Sender<()>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        // If this fails, a stop is already pending, or the loop is gone
+        let _ = self.tx.try_send(());
+    }
+}
 
 pub struct Mgr {
     exch_mgr: exchange::ExchangeMgr,
     proto_demux: proto_demux::ProtoDemux,
+    packet_pool: PacketPool,
+    wq: WorkQ,
     rx_q: Receiver<Msg>,
+    stop_tx: Sender<()>,
+    stop_rx: Receiver<()>,
 }
 
 impl Mgr {
-    pub fn new() -> Result<Mgr, Error> {
-        let mut sess_mgr = session::SessionMgr::new();
-        let udp_transport = Box::new(udp::UdpListener::new()?);
-        sess_mgr.add_network_interface(udp_transport)?;
+    /// The packets, received or sent, come from the `packet_pool`
+    pub fn new(
+        port: u16,
+        max_sessions: usize,
+        max_exchanges: usize,
+        packet_pool: PacketPool,
+    ) -> Result<Mgr, Error> {
+        let (wq, rx_q) = WorkQ::new();
+        let mut sess_mgr = session::SessionMgr::new_with(max_sessions, packet_pool.clone());
+        // Every connection carries at least one session, so there's no use for more
+        let ip_transport = Box::new(tcp::IpListener::new(port, max_sessions, wq.clone())?);
+        sess_mgr.add_network_interface(ip_transport)?;
+        let (stop_tx, stop_rx) = bounded(1);
         Ok(Mgr {
             proto_demux: proto_demux::ProtoDemux::new(),
-            exch_mgr: exchange::ExchangeMgr::new(sess_mgr),
-            rx_q: queue::WorkQ::init()?,
+            exch_mgr: exchange::ExchangeMgr::new_with(sess_mgr, max_exchanges),
+            packet_pool,
+            wq,
+            rx_q,
+            stop_tx,
+            stop_rx,
         })
     }
 
+    /// The queue through which the rest of the stack hands work over to this transport
+    pub fn get_workq(&self) -> WorkQ {
+        self.wq.clone()
+    }
+
+    pub fn get_stop_handle(&self) -> StopHandle {
+        StopHandle {
+            tx: self.stop_tx.clone(),
+        }
+    }
+
     // Allows registration of different protocols with the Transport/Protocol Demux
     pub fn register_protocol(
         &mut self,
-        proto_id_handle: Box<dyn proto_demux::HandleProto>,
+        proto_id_handle: Box<dyn proto_demux::HandleProto + Send>,
     ) -> Result<(), Error> {
         self.proto_demux.register(proto_id_handle)
     }
@@ -58,7 +110,7 @@ impl Mgr {
     fn send_to_exchange(
         &mut self,
         exch_id: u16,
-        proto_tx: BoxSlab<PacketPool>,
+        proto_tx: Box<Packet<'static>>,
     ) -> Result<(), Error> {
         self.exch_mgr.send(exch_id, proto_tx)
     }
@@ -78,7 +130,7 @@ impl Mgr {
         let (rx, exch_ctx) = result.unwrap();
 
         debug!("Exchange is {:?}", exch_ctx.exch);
-        let tx = Self::new_tx()?;
+        let tx = Self::new_tx_for(&self.packet_pool, rx.peer)?;
 
         let mut proto_ctx = ProtoCtx::new(exch_ctx, rx, tx);
         // Proto Dispatch
@@ -111,65 +163,193 @@ impl Mgr {
         Ok(())
     }
 
-    fn handle_queue_msgs(&mut self) -> Result<(), Error> {
-        if let Ok(msg) = self.rx_q.try_recv() {
-            match msg {
-                Msg::NewSession(clone_data) => {
-                    // If a new session was created, add it
-                    let _ = self
-                        .exch_mgr
-                        .add_session(&clone_data)
-                        .map_err(|e| error!("Error adding new session {:?}", e));
+    // Start a new exchange, with the first message filled in by the protocol
+    fn initiate(&mut self, req: InitiateReq) -> Result<(), Error> {
+        let mut exch_ctx = self.exch_mgr.initiate(req.target)?;
+        let exch_id = exch_ctx.exch.get_id();
+        let mut tx = match Self::new_tx_for(&self.packet_pool, exch_ctx.sess.get_peer_addr()) {
+            Ok(tx) => tx,
+            Err(e) => {
+                exch_ctx.exch.close();
+                return Err(e);
+            }
+        };
+        tx.set_proto_id(req.proto_id as u16);
+
+        if let Err(e) = self
+            .proto_demux
+            .initiate(req.proto_id, req.data, &mut exch_ctx, &mut tx)
+        {
+            exch_ctx.exch.close();
+            return Err(e);
+        }
+        self.send_to_exchange(exch_id, tx)
+    }
+
+    fn handle_queue_msg(&mut self, msg: Msg) {
+        match msg {
+            Msg::NewSession(clone_data) => {
+                // If a new session was created, add it
+                let _ = self
+                    .exch_mgr
+                    .add_session(&clone_data)
+                    .map_err(|e| error!("Error adding new session {:?}", e));
+            }
+            Msg::ConnectionClosed(peer) => {
+                self.exch_mgr.connection_closed(peer);
+            }
+            Msg::SessionClosed(local_sess_id) => {
+                self.exch_mgr.peer_closed_session(local_sess_id);
+            }
+            Msg::CloseSession(local_sess_id) => {
+                self.exch_mgr.close_session_with_id(local_sess_id);
+            }
+            Msg::FabricRemoved(fab_idx) => {
+                self.exch_mgr.close_fabric_sessions(fab_idx, None);
+            }
+            Msg::FabricUpdated(fab_idx, except) => {
+                self.exch_mgr.close_fabric_sessions(fab_idx, except);
+            }
+            Msg::Initiate(req) => {
+                let _ = self
+                    .initiate(req)
+                    .map_err(|e| error!("Error initiating exchange {:?}", e));
+            }
+            Msg::DataChanged => {
+                // The protocol timers are re-evaluated after this
+                self.wq.data_change_handled();
+            }
+            _ => {
+                error!("Queue Message Type not yet handled {:?}", msg);
+            }
+        }
+    }
+
+    // The time till the earliest of the transport and protocol timers expires
+    fn get_next_timeout(&self) -> Option<Duration> {
+        let next = self
+            .exch_mgr
+            .get_next_timeout()
+            .into_iter()
+            .chain(self.proto_demux.get_next_timeout())
+            .min()?;
+        Some(
+            next.duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+
+    // Block till a message is received, the WorkQ has something for us, or the next
+    // timer expires
+    fn wait_event(&self) -> Result<Event, Error> {
+        let timeout = self.get_next_timeout();
+        let readable = self.exch_mgr.readable()?;
+
+        let stop = async {
+            self.stop_rx.recv().await.map_err(|_| Error::Invalid)?;
+            Ok(Event::Stop)
+        };
+        let queue = async {
+            let msg = self.rx_q.recv().await.map_err(|_| Error::Invalid)?;
+            Ok(Event::Queue(msg))
+        };
+        let rx = async {
+            readable.await?;
+            Ok(Event::Rx)
+        };
+        let timer = async {
+            match timeout {
+                Some(timeout) => {
+                    Timer::after(timeout).await;
                 }
-                _ => {
-                    error!("Queue Message Type not yet handled {:?}", msg);
+                None => smol::future::pending::<()>().await,
+            }
+            Ok(Event::Timeout)
+        };
+        // The queue is checked first, so new sessions are in place before any further
+        // messages from the network are processed
+        smol::block_on(stop.or(queue).or(rx).or(timer))
+    }
+
+    fn handle_timers(&mut self) {
+        // Handle any pending acknowledgement send
+        let mut acks_to_send: LinearMap<u16, (), { exchange::MAX_MRP_ENTRIES }> = LinearMap::new();
+        self.exch_mgr.pending_acks(&mut acks_to_send);
+        for exch_id in acks_to_send.keys() {
+            info!("Sending MRP Standalone ACK for  exch {}", exch_id);
+            let mut proto_tx = match Self::new_tx(&self.packet_pool) {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("Error creating proto_tx {:?}", e);
+                    break;
                 }
+            };
+            ReliableMessage::prepare_ack(*exch_id, &mut proto_tx);
+            if let Err(e) = self.send_to_exchange(*exch_id, proto_tx) {
+                error!("Error in sending Ack {:?}", e);
             }
         }
-        Ok(())
+
+        // Handle any pending retransmissions
+        self.exch_mgr.retrans();
+
+        // Protocol level timers
+        self.proto_demux.handle_timeout();
+
+        // Let the protocols know about the sessions that went away
+        for local_sess_id in self.exch_mgr.take_closed_sessions() {
+            self.proto_demux.handle_session_closed(local_sess_id);
+        }
+
+        // Handle exchange purging
+        //    This need not be done in each turn of the loop, maybe once in 5 times or so?
+        self.exch_mgr.purge();
     }
 
+    /// Run the transport loop, till it is stopped through a [StopHandle]
+    ///
+    /// All the secure sessions are closed, before this returns
     pub fn start(&mut self) -> Result<(), Error> {
         loop {
-            // Handle network operations
-            if self.handle_rxtx().is_err() {
-                error!("Error in handle_rxtx");
-                continue;
-            }
-
-            if self.handle_queue_msgs().is_err() {
-                error!("Error in handle_queue_msg");
-                continue;
-            }
-
-            // Handle any pending acknowledgement send
-            let mut acks_to_send: LinearMap<u16, (), { exchange::MAX_MRP_ENTRIES }> =
-                LinearMap::new();
-            self.exch_mgr.pending_acks(&mut acks_to_send);
-            for exch_id in acks_to_send.keys() {
-                info!("Sending MRP Standalone ACK for  exch {}", exch_id);
-                let mut proto_tx = match Self::new_tx() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        error!("Error creating proto_tx {:?}", e);
-                        break;
+            match self.wait_event() {
+                Ok(Event::Stop) => {
+                    info!("Stopping the transport");
+                    self.exch_mgr.close_sessions();
+                    return Ok(());
+                }
+                Ok(Event::Rx) => {
+                    // Handle network operations
+                    if self.handle_rxtx().is_err() {
+                        error!("Error in handle_rxtx");
                     }
-                };
-                ReliableMessage::prepare_ack(*exch_id, &mut proto_tx);
-                if let Err(e) = self.send_to_exchange(*exch_id, proto_tx) {
-                    error!("Error in sending Ack {:?}", e);
                 }
+                Ok(Event::Queue(msg)) => self.handle_queue_msg(msg),
+                Ok(Event::Timeout) => (),
+                Err(e) => error!("Error in waiting for events {:?}", e),
             }
 
-            // Handle exchange purging
-            //    This need not be done in each turn of the loop, maybe once in 5 times or so?
-            self.exch_mgr.purge();
+            // Anything queued while processing the event
+            while let Ok(msg) = self.rx_q.try_recv() {
+                self.handle_queue_msg(msg);
+            }
 
-            info!("Exchange Mgr: {}", self.exch_mgr);
+            self.handle_timers();
+            trace!("Exchange Mgr: {}", self.exch_mgr);
         }
     }
 
-    fn new_tx() -> Result<BoxSlab<PacketPool>, Error> {
-        Slab::<PacketPool>::try_new(Packet::new_tx()?).ok_or(Error::PacketPoolExhaust)
+    fn new_tx(pool: &PacketPool) -> Result<Box<Packet<'static>>, Error> {
+        Ok(Box::new(Packet::new_tx(pool)?))
+    }
+
+    // A packet for a message to this peer, which may be a large one if the transport
+    // carries large messages
+    fn new_tx_for(pool: &PacketPool, peer: Address) -> Result<Box<Packet<'static>>, Error> {
+        let len = if peer.is_reliable() {
+            MAX_LARGE_MSG_SIZE
+        } else {
+            MAX_RX_BUF_SIZE
+        };
+        Ok(Box::new(Packet::new_tx_with(pool, len)?))
     }
 }
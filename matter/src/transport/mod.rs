@@ -15,14 +15,17 @@
  *    limitations under the License.
  */
 
+pub mod dedup;
 pub mod exchange;
 pub mod mgr;
 pub mod mrp;
 pub mod network;
 pub mod packet;
 pub mod plain_hdr;
+pub mod privacy;
 pub mod proto_demux;
 pub mod proto_hdr;
 pub mod queue;
 pub mod session;
+pub mod tcp;
 pub mod udp;
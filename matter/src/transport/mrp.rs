@@ -15,30 +15,136 @@
  *    limitations under the License.
  */
 
+use std::fmt;
 use std::time::Duration;
 use std::time::SystemTime;
 
 use crate::{error::*, secure_channel, transport::packet::Packet};
-use log::error;
+use log::{error, info};
+use rand::Rng;
 
 // 200 ms
 const MRP_STANDALONE_ACK_TIMEOUT: u64 = 200;
 
-#[derive(Debug)]
+// The MRP retransmission parameters as defined in the Matter Spec
+// The maximum number of transmissions (the initial one + retransmissions)
+const MRP_MAX_TRANSMISSIONS: u8 = 5;
+const MRP_BACKOFF_BASE: f64 = 1.6;
+const MRP_BACKOFF_JITTER: f64 = 0.25;
+const MRP_BACKOFF_MARGIN: f64 = 1.1;
+const MRP_BACKOFF_THRESHOLD: u8 = 1;
+
+// The default session parameters, used if the peer hasn't told us otherwise
+const MRP_DEFAULT_IDLE_INTERVAL: u32 = 500;
+const MRP_DEFAULT_ACTIVE_INTERVAL: u32 = 300;
+const MRP_DEFAULT_ACTIVE_THRESHOLD: u16 = 4000;
+
+// Our own session parameters, these are advertised over mDNS (SII/SAI) and during
+// session establishment
+pub const MRP_LOCAL_IDLE_INTERVAL: u32 = 5000;
+pub const MRP_LOCAL_ACTIVE_INTERVAL: u32 = 300;
+
+/// The MRP parameters of a peer, all values are in milliseconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MrpParams {
+    /// The retransmission interval to be used while the peer is idle
+    pub idle_interval: u32,
+    /// The retransmission interval to be used while the peer is active
+    pub active_interval: u32,
+    /// The duration the peer stays active after network activity
+    pub active_threshold: u16,
+}
+
+impl Default for MrpParams {
+    fn default() -> Self {
+        Self {
+            idle_interval: MRP_DEFAULT_IDLE_INTERVAL,
+            active_interval: MRP_DEFAULT_ACTIVE_INTERVAL,
+            active_threshold: MRP_DEFAULT_ACTIVE_THRESHOLD,
+        }
+    }
+}
+
+impl MrpParams {
+    /// Returns the base retransmission interval based on whether the peer is currently active
+    pub fn get_interval(&self, peer_active: bool) -> Duration {
+        if peer_active {
+            Duration::from_millis(self.active_interval as u64)
+        } else {
+            Duration::from_millis(self.idle_interval as u64)
+        }
+    }
+
+    pub fn get_active_threshold(&self) -> Duration {
+        Duration::from_millis(self.active_threshold as u64)
+    }
+}
+
+/// Returns the time to wait for an acknowledgement, before the next transmission
+///
+/// `send_count` is the number of transmissions of this message that have already happened
+fn get_backoff(base_interval: Duration, send_count: u8) -> Duration {
+    let exp = send_count.saturating_sub(MRP_BACKOFF_THRESHOLD + 1) as i32;
+    let jitter = 1.0 + rand::thread_rng().gen_range(0.0..1.0) * MRP_BACKOFF_JITTER;
+    base_interval.mul_f64(MRP_BACKOFF_MARGIN * MRP_BACKOFF_BASE.powi(exp) * jitter)
+}
+
 pub struct RetransEntry {
     // The msg counter that we are waiting to be acknowledged
     msg_ctr: u32,
-    // This will additionally have retransmission count and periods once we implement it
+    // The fully encoded (and encrypted) packet, retransmissions reuse it as is
+    packet: Box<Packet<'static>>,
+    // The number of times this packet has been sent out
+    send_count: u8,
+    // The time at which the next retransmission is due
+    retrans_at: SystemTime,
 }
 
 impl RetransEntry {
-    pub fn new(msg_ctr: u32) -> Self {
-        Self { msg_ctr }
+    pub fn new(packet: Box<Packet<'static>>, base_interval: Duration) -> Result<Self, Error> {
+        let mut entry = Self {
+            msg_ctr: packet.plain.ctr,
+            packet,
+            send_count: 0,
+            retrans_at: SystemTime::now(),
+        };
+        entry.record_send(base_interval)?;
+        Ok(entry)
     }
 
     pub fn get_msg_ctr(&self) -> u32 {
         self.msg_ctr
     }
+
+    pub fn get_send_count(&self) -> u8 {
+        self.send_count
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.send_count >= MRP_MAX_TRANSMISSIONS
+    }
+
+    pub fn has_timed_out(&self) -> bool {
+        SystemTime::now() >= self.retrans_at
+    }
+
+    fn record_send(&mut self, base_interval: Duration) -> Result<(), Error> {
+        self.send_count += 1;
+        self.retrans_at = SystemTime::now()
+            .checked_add(get_backoff(base_interval, self.send_count))
+            .ok_or(Error::Invalid)?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RetransEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetransEntry")
+            .field("msg_ctr", &self.msg_ctr)
+            .field("send_count", &self.send_count)
+            .field("retrans_at", &self.retrans_at)
+            .finish()
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -68,7 +174,7 @@ impl AckEntry {
     }
 
     pub fn has_timed_out(&self) -> bool {
-        self.ack_timeout > SystemTime::now()
+        SystemTime::now() >= self.ack_timeout
     }
 }
 
@@ -99,6 +205,53 @@ impl ReliableMessage {
         }
     }
 
+    pub fn is_retrans_ready(&self) -> bool {
+        if let Some(entry) = &self.retrans {
+            entry.has_timed_out()
+        } else {
+            false
+        }
+    }
+
+    /// The earliest time at which an acknowledgement or a retransmission is due
+    pub fn get_next_timeout(&self) -> Option<SystemTime> {
+        let ack = self.ack.map(|a| a.ack_timeout);
+        let retrans = self.retrans.as_ref().map(|r| r.retrans_at);
+        ack.into_iter().chain(retrans).min()
+    }
+
+    /// Returns the packet to be retransmitted, if the retransmission timer has expired
+    ///
+    /// If the maximum number of transmissions have already happened, the pending
+    /// retransmission is dropped and an error is returned.
+    pub fn get_retrans(
+        &mut self,
+        base_interval: Duration,
+    ) -> Result<Option<&mut Box<Packet<'static>>>, Error> {
+        let exhausted = match &self.retrans {
+            Some(entry) if entry.has_timed_out() => entry.is_exhausted(),
+            _ => return Ok(None),
+        };
+        if exhausted {
+            let entry = self.retrans.take().ok_or(Error::Invalid)?;
+            error!(
+                "No ACK received for msg counter {} after {} transmissions",
+                entry.get_msg_ctr(),
+                entry.get_send_count()
+            );
+            return Err(Error::RetransExhausted);
+        }
+
+        let entry = self.retrans.as_mut().ok_or(Error::Invalid)?;
+        entry.record_send(base_interval)?;
+        info!(
+            "Retransmitting msg counter {}, attempt {}",
+            entry.get_msg_ctr(),
+            entry.get_send_count()
+        );
+        Ok(Some(&mut entry.packet))
+    }
+
     pub fn prepare_ack(_exch_id: u16, proto_tx: &mut Packet) {
         secure_channel::common::create_mrp_standalone_ack(proto_tx);
     }
@@ -122,8 +275,18 @@ impl ReliableMessage {
             error!("Previous retrans entry for this exchange already exists");
             return Err(Error::Invalid);
         }
+        Ok(())
+    }
 
-        self.retrans = Some(RetransEntry::new(proto_tx.plain.ctr));
+    /// Hold on to the sent packet, if it requires an acknowledgement
+    pub fn post_send(
+        &mut self,
+        mut proto_tx: Box<Packet<'static>>,
+        base_interval: Duration,
+    ) -> Result<(), Error> {
+        if proto_tx.is_reliable() {
+            self.retrans = Some(RetransEntry::new(proto_tx, base_interval)?);
+        }
         Ok(())
     }
 
@@ -138,10 +301,15 @@ impl ReliableMessage {
             let ack_msg_ctr = proto_rx.proto.get_ack_msg_ctr().ok_or(Error::Invalid)?;
             if let Some(entry) = &self.retrans {
                 if entry.get_msg_ctr() != ack_msg_ctr {
-                    // TODO: XXX Fix this
-                    error!("Mismatch in retrans-table's msg counter and received msg counter: received {}, expected {}. This is expected for the timebeing", ack_msg_ctr, entry.get_msg_ctr());
+                    // The ACK isn't for our pending message, keep retransmitting it
+                    error!(
+                        "Mismatch in retrans-table's msg counter and received msg counter: received {}, expected {}",
+                        ack_msg_ctr,
+                        entry.get_msg_ctr()
+                    );
+                } else {
+                    self.retrans = None;
                 }
-                self.retrans = None;
             }
         }
 
@@ -158,3 +326,88 @@ impl ReliableMessage {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        error::Error,
+        transport::packet::{Packet, PacketPool},
+    };
+
+    use super::{get_backoff, AckEntry, ReliableMessage, MRP_MAX_TRANSMISSIONS};
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(300);
+        // The first two transmissions only apply the margin and the jitter
+        for send_count in 1..3 {
+            let t = get_backoff(base, send_count);
+            assert!(t >= Duration::from_millis(330));
+            assert!(t <= Duration::from_micros(412500));
+        }
+        // Post that, the backoff increases exponentially
+        let t = get_backoff(base, 3);
+        assert!(t >= Duration::from_millis(528));
+        assert!(t <= Duration::from_millis(660));
+        let t = get_backoff(base, 4);
+        assert!(t >= Duration::from_micros(844800));
+        assert!(t <= Duration::from_millis(1056));
+    }
+
+    #[test]
+    fn test_retrans_exhausted() {
+        let mut mrp = ReliableMessage::new();
+        let pool = PacketPool::new(1);
+        let mut tx = Box::new(Packet::new_tx(&pool).unwrap());
+        tx.plain.ctr = 10;
+        mrp.pre_send(&mut tx).unwrap();
+        mrp.post_send(tx, Duration::ZERO).unwrap();
+        assert!(!mrp.is_empty());
+
+        for _ in 1..MRP_MAX_TRANSMISSIONS {
+            mrp.retrans.as_mut().unwrap().retrans_at = SystemTime::now();
+            let tx = mrp.get_retrans(Duration::ZERO).unwrap().unwrap();
+            assert_eq!(tx.plain.ctr, 10);
+        }
+        mrp.retrans.as_mut().unwrap().retrans_at = SystemTime::now();
+        assert_eq!(
+            mrp.get_retrans(Duration::ZERO).map(|_| ()),
+            Err(Error::RetransExhausted)
+        );
+        assert!(mrp.is_empty());
+    }
+
+    #[test]
+    fn test_unreliable_not_retransmitted() {
+        let mut mrp = ReliableMessage::new();
+        let pool = PacketPool::new(1);
+        let mut tx = Box::new(Packet::new_tx(&pool).unwrap());
+        tx.unset_reliable();
+        mrp.pre_send(&mut tx).unwrap();
+        mrp.post_send(tx, Duration::ZERO).unwrap();
+        assert!(mrp.is_empty());
+        assert!(mrp.get_retrans(Duration::ZERO).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_next_timeout() {
+        let mut mrp = ReliableMessage::new();
+        assert_eq!(mrp.get_next_timeout(), None);
+
+        mrp.ack = Some(AckEntry::new(5).unwrap());
+        let ack_timeout = mrp.get_next_timeout().unwrap();
+        assert!(ack_timeout > SystemTime::now());
+
+        let pool = PacketPool::new(1);
+        let mut tx = Box::new(Packet::new_tx(&pool).unwrap());
+        mrp.pre_send(&mut tx).unwrap();
+        mrp.post_send(tx, Duration::from_secs(10)).unwrap();
+        // The ack was piggybacked, only the retransmission is pending
+        assert!(mrp.get_next_timeout().unwrap() > ack_timeout);
+
+        mrp.ack = Some(AckEntry::new(6).unwrap());
+        assert!(mrp.get_next_timeout().unwrap() < SystemTime::now() + Duration::from_secs(1));
+    }
+}
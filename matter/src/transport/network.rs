@@ -17,7 +17,9 @@
 
 use std::{
     fmt::{Debug, Display},
+    future::Future,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
 };
 
 use crate::error::Error;
@@ -25,6 +27,15 @@ use crate::error::Error;
 #[derive(PartialEq, Copy, Clone)]
 pub enum Address {
     Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+impl Address {
+    /// Returns true if the underlying transport takes care of reliable delivery. MRP
+    /// isn't used on such transports.
+    pub fn is_reliable(&self) -> bool {
+        matches!(self, Address::Tcp(_))
+    }
 }
 
 impl Default for Address {
@@ -37,6 +48,7 @@ impl Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "tcp:{}", addr),
         }
     }
 }
@@ -45,11 +57,25 @@ impl Debug for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Address::Udp(addr) => writeln!(f, "{}", addr),
+            Address::Tcp(addr) => writeln!(f, "tcp:{}", addr),
         }
     }
 }
 
+/// A future that completes when a message can be received
+pub type Readable<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
 pub trait NetworkInterface {
     fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error>;
     fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error>;
+    /// Wait for a message, without receiving it. A recv() after this completes
+    /// doesn't block.
+    fn readable(&self) -> Readable<'_>;
+    /// The length of the next message, if it is known before it is received. The buffer
+    /// for the recv() has to be at least this large.
+    fn pending_len(&self) -> Option<usize> {
+        None
+    }
+    /// Close the connection to this peer, on a connection oriented transport
+    fn close(&self, _addr: Address) {}
 }
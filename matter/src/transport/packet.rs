@@ -16,68 +16,70 @@
  */
 
 use log::{error, trace};
-use std::sync::Mutex;
-
-use boxslab::box_slab;
+use std::sync::{Arc, Mutex};
 
 use crate::{
+    crypto,
     error::Error,
-    sys::MAX_PACKET_POOL_SIZE,
     utils::{parsebuf::ParseBuf, writebuf::WriteBuf},
 };
 
 use super::{
     network::Address,
     plain_hdr::{self, PlainHdr},
+    privacy,
     proto_hdr::{self, ProtoHdr},
 };
 
 pub const MAX_RX_BUF_SIZE: usize = 1583;
-type Buffer = [u8; MAX_RX_BUF_SIZE];
 
-// TODO: I am not very happy with this construction, need to find another way to do this
-pub struct BufferPool {
-    buffers: [Option<Buffer>; MAX_PACKET_POOL_SIZE],
+/// The largest message on a transport that carries large messages, like TCP
+pub const MAX_LARGE_MSG_SIZE: usize = 64000;
+
+type Buffer = [u8];
+
+/// The buffers of the packets that a Matter object sends and receives
+///
+/// Every [Packet] holds one of these buffers, till it is dropped. The clones of a pool share
+/// its buffers.
+#[derive(Clone)]
+pub struct PacketPool {
+    buffers: Arc<Mutex<Vec<Option<Box<Buffer>>>>>,
 }
 
-impl BufferPool {
-    const INIT: Option<Buffer> = None;
-    fn get() -> &'static Mutex<BufferPool> {
-        static mut BUFFER_HOLDER: Option<Mutex<BufferPool>> = None;
-        static ONCE: Once = Once::new();
-        unsafe {
-            ONCE.call_once(|| {
-                BUFFER_HOLDER = Some(Mutex::new(BufferPool {
-                    buffers: [BufferPool::INIT; MAX_PACKET_POOL_SIZE],
-                }));
-            });
-            BUFFER_HOLDER.as_ref().unwrap()
+impl PacketPool {
+    /// A pool of `size` buffers
+    pub fn new(size: usize) -> Self {
+        let mut buffers = Vec::new();
+        buffers.resize_with(size, || None);
+        Self {
+            buffers: Arc::new(Mutex::new(buffers)),
         }
     }
 
-    pub fn alloc() -> Option<(usize, &'static mut Buffer)> {
+    // Allocate a buffer of `len` bytes
+    fn alloc(&self, len: usize) -> Option<(usize, &'static mut Buffer)> {
         trace!("Buffer Alloc called\n");
 
-        let mut pool = BufferPool::get().lock().unwrap();
-        for i in 0..MAX_PACKET_POOL_SIZE {
-            if pool.buffers[i].is_none() {
-                pool.buffers[i] = Some([0; MAX_RX_BUF_SIZE]);
-                // Sigh! to by-pass the borrow-checker telling us we are stealing a mutable reference
-                // from under the lock
-                // In this case the lock only protects against the setting of Some/None,
-                // the objects then are independently accessed in a unique way
-                let buffer = unsafe { &mut *(pool.buffers[i].as_mut().unwrap() as *mut Buffer) };
-                return Some((i, buffer));
-            }
-        }
-        None
+        let mut buffers = self.buffers.lock().ok()?;
+        let i = buffers.iter().position(|b| b.is_none())?;
+        let buffer = buffers[i].insert(vec![0; len].into_boxed_slice());
+        // Sigh! to by-pass the borrow-checker telling us we are stealing a mutable reference
+        // from under the lock
+        // In this case the lock only protects against the setting of Some/None,
+        // the objects then are independently accessed in a unique way. The buffer
+        // is boxed, so it stays in place, and the packet holds on to the pool, so the
+        // buffer outlives the packet
+        let buffer = unsafe { &mut *(buffer.as_mut() as *mut Buffer) };
+        Some((i, buffer))
     }
 
-    pub fn free(index: usize) {
+    fn free(&self, index: usize) {
         trace!("Buffer Free called\n");
-        let mut pool = BufferPool::get().lock().unwrap();
-        if pool.buffers[index].is_some() {
-            pool.buffers[index] = None;
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if let Some(b) = buffers.get_mut(index) {
+                *b = None;
+            }
         }
     }
 }
@@ -99,26 +101,38 @@ pub struct Packet<'a> {
     pub proto: ProtoHdr,
     pub peer: Address,
     data: Direction<'a>,
+    pool: PacketPool,
     buffer_index: usize,
 }
 
 impl<'a> Packet<'a> {
     const HDR_RESERVE: usize = plain_hdr::max_plain_hdr_len() + proto_hdr::max_proto_hdr_len();
 
-    pub fn new_rx() -> Result<Self, Error> {
-        let (buffer_index, buffer) = BufferPool::alloc().ok_or(Error::NoSpace)?;
+    pub fn new_rx(pool: &PacketPool) -> Result<Self, Error> {
+        Self::new_rx_with(pool, MAX_RX_BUF_SIZE)
+    }
+
+    /// A packet that can receive a message of up to `len` bytes
+    pub fn new_rx_with(pool: &PacketPool, len: usize) -> Result<Self, Error> {
+        let (buffer_index, buffer) = pool.alloc(len).ok_or(Error::PacketPoolExhaust)?;
         let buf_len = buffer.len();
         Ok(Self {
             plain: Default::default(),
             proto: Default::default(),
+            pool: pool.clone(),
             buffer_index,
             peer: Address::default(),
             data: Direction::Rx(ParseBuf::new(buffer, buf_len), RxState::Uninit),
         })
     }
 
-    pub fn new_tx() -> Result<Self, Error> {
-        let (buffer_index, buffer) = BufferPool::alloc().ok_or(Error::NoSpace)?;
+    pub fn new_tx(pool: &PacketPool) -> Result<Self, Error> {
+        Self::new_tx_with(pool, MAX_RX_BUF_SIZE)
+    }
+
+    /// A packet that can send a message of up to `len` bytes, headers included
+    pub fn new_tx_with(pool: &PacketPool, len: usize) -> Result<Self, Error> {
+        let (buffer_index, buffer) = pool.alloc(len).ok_or(Error::PacketPoolExhaust)?;
         let buf_len = buffer.len();
 
         let mut wb = WriteBuf::new(buffer, buf_len);
@@ -127,6 +141,7 @@ impl<'a> Packet<'a> {
         let mut p = Self {
             plain: Default::default(),
             proto: Default::default(),
+            pool: pool.clone(),
             buffer_index,
             peer: Address::default(),
             data: Direction::Tx(wb),
@@ -213,6 +228,32 @@ impl<'a> Packet<'a> {
         }
     }
 
+    /// De-obfuscate and decode the rest of the plain header of a message with privacy
+    ///
+    /// The key is the decryption key of the session that the message belongs to
+    pub fn plain_hdr_decode_privacy(&mut self, dec_key: &[u8]) -> Result<(), Error> {
+        match &mut self.data {
+            Direction::Rx(pb, RxState::PlainDecode) if self.plain.is_privacy() => {
+                let mut privacy_key = [0u8; crypto::SYMM_KEY_LEN_BYTES];
+                privacy::get_privacy_key(dec_key, &mut privacy_key)?;
+
+                let len = self.plain.obfuscated_len();
+                let rest = pb.as_borrow_slice();
+                if rest.len() < len + crypto::AEAD_MIC_LEN_BYTES {
+                    return Err(Error::Invalid);
+                }
+                let (hdr, payload) = rest.split_at_mut(len);
+                let mic = &payload[payload.len() - crypto::AEAD_MIC_LEN_BYTES..];
+                privacy::obfuscate_in_place(&privacy_key, self.plain.sess_id, mic, hdr)?;
+                self.plain.decode_obfuscated(pb)
+            }
+            _ => {
+                error!("Invalid state for plain_decode_privacy");
+                Err(Error::InvalidState)
+            }
+        }
+    }
+
     pub fn plain_hdr_decode(&mut self) -> Result<(), Error> {
         match &mut self.data {
             Direction::Rx(pb, state) => {
@@ -231,9 +272,34 @@ impl<'a> Packet<'a> {
 
 impl<'a> Drop for Packet<'a> {
     fn drop(&mut self) {
-        BufferPool::free(self.buffer_index);
+        self.pool.free(self.buffer_index);
         trace!("Dropping Packet......");
     }
 }
 
-box_slab!(PacketPool, Packet<'static>, MAX_PACKET_POOL_SIZE);
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+
+    use super::{Packet, PacketPool};
+
+    #[test]
+    fn test_pool_exhaust() {
+        let pool = PacketPool::new(2);
+        let other = PacketPool::new(1);
+        let rx = Packet::new_rx(&pool).unwrap();
+        let tx = Packet::new_tx_with(&pool, 100).unwrap();
+        assert_eq!(
+            Packet::new_rx(&pool).map(|_| ()),
+            Err(Error::PacketPoolExhaust)
+        );
+        // The pools don't share their buffers
+        let _other_rx = Packet::new_rx(&other).unwrap();
+
+        // A dropped packet returns its buffer to its pool
+        drop(rx);
+        let _rx = Packet::new_rx(&pool).unwrap();
+        drop(tx);
+        assert!(Packet::new_tx(&pool).is_ok());
+    }
+}
@@ -42,25 +42,42 @@ bitflags! {
     }
 }
 
+bitflags! {
+    #[derive(Default)]
+    pub struct SecFlags: u8 {
+        const GROUP_SESSION = 0x01;
+        const MSG_EXTENSIONS = 0x20;
+        const CONTROL_MSG = 0x40;
+        const PRIVACY = 0x80;
+    }
+}
+
 // This is the unencrypted message
 #[derive(Debug, Default)]
 pub struct PlainHdr {
     pub flags: MsgFlags,
+    pub sec_flags: SecFlags,
     pub sess_type: SessionType,
     pub sess_id: u16,
     pub ctr: u32,
-    peer_nodeid: Option<u64>,
+    src_nodeid: Option<u64>,
+    dest_nodeid: Option<u64>,
 }
 
 impl PlainHdr {
     pub fn set_dest_u64(&mut self, id: u64) {
         self.flags |= MsgFlags::DSIZ_UNICAST_NODEID;
-        self.peer_nodeid = Some(id);
+        self.dest_nodeid = Some(id);
+    }
+
+    pub fn set_src_u64(&mut self, id: u64) {
+        self.flags |= MsgFlags::SRC_ADDR_PRESENT;
+        self.src_nodeid = Some(id);
     }
 
     pub fn get_src_u64(&self) -> Option<u64> {
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
-            self.peer_nodeid
+            self.src_nodeid
         } else {
             None
         }
@@ -69,19 +86,47 @@ impl PlainHdr {
 
 impl PlainHdr {
     // it will have an additional 'message length' field first
+    //
+    // With privacy, only the fields up to the security flags can be decoded, the rest
+    // is decoded through decode_obfuscated(), once the session is known
     pub fn decode(&mut self, msg: &mut ParseBuf) -> Result<(), Error> {
         self.flags = MsgFlags::from_bits(msg.le_u8()?).ok_or(Error::Invalid)?;
         self.sess_id = msg.le_u16()?;
-        let _sec_flags = msg.le_u8()?;
+        self.sec_flags = SecFlags::from_bits(msg.le_u8()?).ok_or(Error::Invalid)?;
         self.sess_type = if self.sess_id != 0 {
             SessionType::Encrypted
         } else {
             SessionType::None
         };
+        if self.is_privacy() {
+            // The length of the message extensions is obfuscated as well, so the end of the
+            // obfuscated fields wouldn't be known
+            if !self.is_encrypted() || self.sec_flags.contains(SecFlags::MSG_EXTENSIONS) {
+                return Err(Error::Invalid);
+            }
+            return Ok(());
+        }
+        self.decode_obfuscated(msg)
+    }
+
+    /// Decode the fields that are obfuscated, if privacy is in use
+    pub fn decode_obfuscated(&mut self, msg: &mut ParseBuf) -> Result<(), Error> {
         self.ctr = msg.le_u32()?;
 
         if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
-            self.peer_nodeid = Some(msg.le_u64()?);
+            self.src_nodeid = Some(msg.le_u64()?);
+        }
+        // The destination is us, there is nothing to keep from it
+        if self.flags.contains(MsgFlags::DSIZ_UNICAST_NODEID) {
+            msg.le_u64()?;
+        } else if self.flags.contains(MsgFlags::DSIZ_GROUPCAST_NODEID) {
+            msg.le_u16()?;
+        }
+        // None of the message extensions are known to us, but they are still part of the
+        // header that the message is authenticated with
+        if self.sec_flags.contains(SecFlags::MSG_EXTENSIONS) {
+            let len = msg.le_u16()? as usize;
+            msg.parse_head_with(len, |_| ())?;
         }
 
         info!(
@@ -94,9 +139,12 @@ impl PlainHdr {
     pub fn encode(&mut self, resp_buf: &mut WriteBuf) -> Result<(), Error> {
         resp_buf.le_u8(self.flags.bits())?;
         resp_buf.le_u16(self.sess_id)?;
-        resp_buf.le_u8(0)?;
+        resp_buf.le_u8(self.sec_flags.bits())?;
         resp_buf.le_u32(self.ctr)?;
-        if let Some(d) = self.peer_nodeid {
+        if let Some(s) = self.src_nodeid {
+            resp_buf.le_u64(s)?;
+        }
+        if let Some(d) = self.dest_nodeid {
             resp_buf.le_u64(d)?;
         }
         Ok(())
@@ -105,6 +153,29 @@ impl PlainHdr {
     pub fn is_encrypted(&self) -> bool {
         self.sess_type == SessionType::Encrypted
     }
+
+    pub fn is_privacy(&self) -> bool {
+        self.sec_flags.contains(SecFlags::PRIVACY)
+    }
+
+    pub fn set_privacy(&mut self) {
+        self.sec_flags |= SecFlags::PRIVACY;
+    }
+
+    /// The length of the fields that are obfuscated, if privacy is in use
+    pub fn obfuscated_len(&self) -> usize {
+        // Message counter
+        let mut len = 4;
+        if self.flags.contains(MsgFlags::SRC_ADDR_PRESENT) {
+            len += 8;
+        }
+        if self.flags.contains(MsgFlags::DSIZ_UNICAST_NODEID) {
+            len += 8;
+        } else if self.flags.contains(MsgFlags::DSIZ_GROUPCAST_NODEID) {
+            len += 2;
+        }
+        len
+    }
 }
 
 pub const fn max_plain_hdr_len() -> usize {
@@ -123,3 +194,35 @@ pub const fn max_plain_hdr_len() -> usize {
     // [optional] destination node ID
         8
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_msg_extensions() {
+        let mut buf = [
+            // flags, session ID, security flags with message extensions, message counter
+            0x00, 0x01, 0x00, 0x20, 0x04, 0x03, 0x02, 0x01, // the message extensions
+            0x03, 0x00, 0xaa, 0xbb, 0xcc, // the payload
+            0x11, 0x22,
+        ];
+        let len = buf.len();
+        let mut pb = ParseBuf::new(&mut buf, len);
+        let mut plain = PlainHdr::default();
+        plain.decode(&mut pb).unwrap();
+        assert_eq!(plain.ctr, 0x01020304);
+        assert_eq!(pb.as_slice(), [0x11, 0x22]);
+
+        // Truncated message extensions
+        let mut buf = [
+            0x00, 0x01, 0x00, 0x20, 0x04, 0x03, 0x02, 0x01, 0x03, 0x00, 0xaa,
+        ];
+        let len = buf.len();
+        let mut pb = ParseBuf::new(&mut buf, len);
+        assert_eq!(
+            PlainHdr::default().decode(&mut pb),
+            Err(Error::TruncatedPacket)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+// Message privacy: obfuscation of the message counter and the node IDs in the message
+// header, once the message has been encrypted
+
+use crate::{crypto, error::Error};
+
+const PRIVACY_KEY_INFO: [u8; 10] = *b"PrivacyKey";
+
+// The privacy nonce uses these bytes of the MIC, after the session ID
+const PRIVACY_NONCE_MIC_OFFSET: usize = 5;
+
+// Counter (4) + Source Node ID (8) + Destination Node ID (8)
+const MAX_OBFUSCATED_LEN: usize = 20;
+
+/// Derive the privacy key from the encryption key of a session, or from an operational
+/// group key
+pub fn get_privacy_key(enc_key: &[u8], privacy_key: &mut [u8]) -> Result<(), Error> {
+    crypto::hkdf_sha256(&[], enc_key, &PRIVACY_KEY_INFO, privacy_key).map_err(|_x| Error::NoSpace)
+}
+
+fn get_privacy_nonce(sess_id: u16, mic: &[u8], nonce: &mut [u8]) -> Result<(), Error> {
+    if mic.len() != crypto::AEAD_MIC_LEN_BYTES {
+        return Err(Error::Invalid);
+    }
+    nonce[..2].copy_from_slice(&sess_id.to_be_bytes());
+    nonce[2..].copy_from_slice(&mic[PRIVACY_NONCE_MIC_OFFSET..]);
+    Ok(())
+}
+
+/// Obfuscate, or de-obfuscate, the privacy protected part of the message header
+///
+/// This is AES-CTR, as it is done within AES-CCM, which is why it is the same operation
+/// in both directions. The nonce is made from the session ID and the message's MIC.
+pub fn obfuscate_in_place(
+    privacy_key: &[u8],
+    sess_id: u16,
+    mic: &[u8],
+    hdr: &mut [u8],
+) -> Result<(), Error> {
+    if hdr.len() > MAX_OBFUSCATED_LEN {
+        return Err(Error::NoSpace);
+    }
+    let mut nonce = [0u8; crypto::AEAD_NONCE_LEN_BYTES];
+    get_privacy_nonce(sess_id, mic, &mut nonce)?;
+
+    // The AES-CCM tag that comes along, is not of any use here
+    let mut buf = [0u8; MAX_OBFUSCATED_LEN + crypto::AEAD_MIC_LEN_BYTES];
+    let len = hdr.len();
+    buf[..len].copy_from_slice(hdr);
+    crypto::encrypt_in_place(
+        privacy_key,
+        &nonce,
+        &[],
+        &mut buf[..len + crypto::AEAD_MIC_LEN_BYTES],
+        len,
+    )?;
+    hdr.copy_from_slice(&buf[..len]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_privacy_nonce() {
+        let mic: Vec<u8> = (0..16).collect();
+        let mut nonce = [0u8; crypto::AEAD_NONCE_LEN_BYTES];
+        get_privacy_nonce(0x1234, &mic, &mut nonce).unwrap();
+        assert_eq!(nonce, [0x12, 0x34, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(
+            get_privacy_nonce(0x1234, &mic[1..], &mut nonce),
+            Err(Error::Invalid)
+        );
+    }
+
+    #[test]
+    fn test_obfuscate_roundtrip() {
+        let mut privacy_key = [0u8; crypto::SYMM_KEY_LEN_BYTES];
+        get_privacy_key(&[0x5a; crypto::SYMM_KEY_LEN_BYTES], &mut privacy_key).unwrap();
+        let mic = [0xa5; crypto::AEAD_MIC_LEN_BYTES];
+
+        let plain = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut hdr = plain;
+        obfuscate_in_place(&privacy_key, 10, &mic, &mut hdr).unwrap();
+        assert_ne!(hdr, plain);
+
+        // A different session ID gives a different nonce
+        let mut other = plain;
+        obfuscate_in_place(&privacy_key, 11, &mic, &mut other).unwrap();
+        assert_ne!(hdr, other);
+
+        obfuscate_in_place(&privacy_key, 10, &mic, &mut hdr).unwrap();
+        assert_eq!(hdr, plain);
+    }
+}
@@ -15,12 +15,13 @@
  *    limitations under the License.
  */
 
-use boxslab::BoxSlab;
+use std::{any::Any, fmt, time::SystemTime};
 
 use crate::error::*;
+use log::error;
 
-use super::exchange::ExchangeCtx;
-use super::packet::PacketPool;
+use super::exchange::{ExchangeCtx, SessionTarget};
+use super::packet::Packet;
 
 const MAX_PROTOCOLS: usize = 4;
 
@@ -30,7 +31,7 @@ pub enum ResponseRequired {
     No,
 }
 pub struct ProtoDemux {
-    proto_id_handlers: [Option<Box<dyn HandleProto>>; MAX_PROTOCOLS],
+    proto_id_handlers: [Option<Box<dyn HandleProto + Send>>; MAX_PROTOCOLS],
 }
 
 /// This is the context in which a receive packet is being processed
@@ -38,21 +39,50 @@ pub struct ProtoCtx<'a> {
     /// This is the exchange context, that includes the exchange and the session
     pub exch_ctx: ExchangeCtx<'a>,
     /// This is the received buffer for this transaction
-    pub rx: BoxSlab<PacketPool>,
+    pub rx: Box<Packet<'static>>,
     /// This is the transmit buffer for this transaction
-    pub tx: BoxSlab<PacketPool>,
+    pub tx: Box<Packet<'static>>,
 }
 
 impl<'a> ProtoCtx<'a> {
     pub fn new(
         exch_ctx: ExchangeCtx<'a>,
-        rx: BoxSlab<PacketPool>,
-        tx: BoxSlab<PacketPool>,
+        rx: Box<Packet<'static>>,
+        tx: Box<Packet<'static>>,
     ) -> Self {
         Self { exch_ctx, rx, tx }
     }
 }
 
+/// A request to start a new exchange, as the initiator, with a protocol
+pub struct InitiateReq {
+    pub proto_id: usize,
+    /// The session on which the exchange is started
+    pub target: SessionTarget,
+    /// Protocol specific data, that is handed over to the protocol's initiate()
+    pub data: Box<dyn Any + Send>,
+}
+
+impl InitiateReq {
+    pub fn new(proto_id: usize, target: SessionTarget, data: Box<dyn Any + Send>) -> Self {
+        Self {
+            proto_id,
+            target,
+            data,
+        }
+    }
+}
+
+impl fmt::Debug for InitiateReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "InitiateReq {{ proto_id: {}, target: {:?} }}",
+            self.proto_id, self.target
+        )
+    }
+}
+
 pub trait HandleProto {
     fn handle_proto_id(&mut self, proto_ctx: &mut ProtoCtx) -> Result<ResponseRequired, Error>;
 
@@ -61,6 +91,32 @@ pub trait HandleProto {
     fn handle_session_event(&self) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Fill in the first message of a new exchange, on which we are the initiator
+    ///
+    /// The data is what came along in the InitiateReq.
+    fn initiate(
+        &mut self,
+        _data: Box<dyn Any + Send>,
+        _exch_ctx: &mut ExchangeCtx,
+        _tx: &mut Packet,
+    ) -> Result<(), Error> {
+        Err(Error::Invalid)
+    }
+
+    /// The next time at which this protocol has some timed work to do, like the expiry
+    /// of a timer
+    fn get_next_timeout(&self) -> Option<SystemTime> {
+        None
+    }
+
+    /// Perform the timed work that is due
+    fn handle_timeout(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// The session with this local session id was closed
+    fn handle_session_closed(&mut self, _local_sess_id: u16) {}
 }
 
 impl Default for ProtoDemux {
@@ -76,7 +132,7 @@ impl ProtoDemux {
         }
     }
 
-    pub fn register(&mut self, proto_id_handle: Box<dyn HandleProto>) -> Result<(), Error> {
+    pub fn register(&mut self, proto_id_handle: Box<dyn HandleProto + Send>) -> Result<(), Error> {
         let proto_id = proto_id_handle.get_proto_id();
         self.proto_id_handlers[proto_id] = Some(proto_id_handle);
         Ok(())
@@ -92,4 +148,44 @@ impl ProtoDemux {
             .ok_or(Error::NoHandler)?
             .handle_proto_id(proto_ctx);
     }
+
+    pub fn initiate(
+        &mut self,
+        proto_id: usize,
+        data: Box<dyn Any + Send>,
+        exch_ctx: &mut ExchangeCtx,
+        tx: &mut Packet,
+    ) -> Result<(), Error> {
+        if proto_id >= MAX_PROTOCOLS {
+            return Err(Error::Invalid);
+        }
+        self.proto_id_handlers[proto_id]
+            .as_mut()
+            .ok_or(Error::NoHandler)?
+            .initiate(data, exch_ctx, tx)
+    }
+
+    pub fn get_next_timeout(&self) -> Option<SystemTime> {
+        self.proto_id_handlers
+            .iter()
+            .flatten()
+            .filter_map(|h| h.get_next_timeout())
+            .min()
+    }
+
+    pub fn handle_session_closed(&mut self, local_sess_id: u16) {
+        for handler in self.proto_id_handlers.iter_mut().flatten() {
+            handler.handle_session_closed(local_sess_id);
+        }
+    }
+
+    pub fn handle_timeout(&mut self) {
+        for handler in self.proto_id_handlers.iter_mut().flatten() {
+            if matches!(handler.get_next_timeout(), Some(t) if t <= SystemTime::now()) {
+                if let Err(e) = handler.handle_timeout() {
+                    error!("Error in handling timeout {:?}", e);
+                }
+            }
+        }
+    }
 }
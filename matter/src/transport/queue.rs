@@ -15,50 +15,85 @@
  *    limitations under the License.
  */
 
-use std::sync::Once;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 
 use async_channel::{bounded, Receiver, Sender};
 
 use crate::error::Error;
 
-use super::session::CloneData;
+use super::{network::Address, proto_demux::InitiateReq, session::CloneData};
 
 #[derive(Debug)]
 pub enum Msg {
     Tx(),
     Rx(),
     NewSession(CloneData),
+    // A connection oriented transport lost its connection with this peer
+    ConnectionClosed(Address),
+    // The peer closed the session with this local session id
+    SessionClosed(u16),
+    // Close the session with this local session id, informing the peer
+    CloseSession(u16),
+    // The fabric with this index was removed, the sessions on it have to go
+    FabricRemoved(u8),
+    // The NOC of the fabric with this index changed, the sessions on it have to go, except
+    // the one with this local session id
+    FabricUpdated(u8, Option<u16>),
+    // Start a new exchange with a peer, as the initiator
+    Initiate(InitiateReq),
+    // Something in the data model changed, a report on a subscription may be due
+    DataChanged,
 }
 
+/// The queue of the work for the transport of a Matter object
+///
+/// The clones of a WorkQ send to the same transport.
 #[derive(Clone)]
 pub struct WorkQ {
     tx: Sender<Msg>,
+    // Whether a DataChanged is in the queue, that the transport hasn't picked up yet. The
+    // changes come in bursts, one wake-up is enough for all of them.
+    data_changed: Arc<AtomicBool>,
 }
 
-static mut G_WQ: Option<WorkQ> = None;
-static INIT: Once = Once::new();
-
 impl WorkQ {
-    pub fn init() -> Result<Receiver<Msg>, Error> {
+    /// A new queue, and the receiver that the transport picks the work up from
+    pub fn new() -> (WorkQ, Receiver<Msg>) {
         let (tx, rx) = bounded::<Msg>(3);
-        WorkQ::configure(tx);
-        Ok(rx)
+        let wq = WorkQ {
+            tx,
+            data_changed: Arc::new(AtomicBool::new(false)),
+        };
+        (wq, rx)
     }
 
-    fn configure(tx: Sender<Msg>) {
-        unsafe {
-            INIT.call_once(|| {
-                G_WQ = Some(WorkQ { tx });
-            });
-        }
+    pub fn sync_send(&self, msg: Msg) -> Result<(), Error> {
+        smol::block_on(self.send(msg))
     }
 
-    pub fn get() -> Result<WorkQ, Error> {
-        unsafe { G_WQ.as_ref().cloned().ok_or(Error::Invalid) }
+    /// Send without blocking, this fails if the queue is full
+    pub fn try_send(&self, msg: Msg) -> Result<(), Error> {
+        self.tx.try_send(msg).map_err(|_| Error::NoSpace)
     }
 
-    pub fn sync_send(&self, msg: Msg) -> Result<(), Error> {
-        smol::block_on(self.send(msg))
+    /// Inform the transport that something in the data model changed, unless it is yet to
+    /// pick up an earlier change
+    pub fn data_changed(&self) {
+        if self.data_changed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // If the queue is full, the transport is about to wake up anyway
+        if self.try_send(Msg::DataChanged).is_err() {
+            self.data_changed.store(false, Ordering::SeqCst);
+        }
+    }
+
+    /// The transport picked up the DataChanged, the changes after this need another one
+    pub fn data_change_handled(&self) {
+        self.data_changed.store(false, Ordering::SeqCst);
     }
 
     pub async fn send(&self, msg: Msg) -> Result<(), Error> {
@@ -19,22 +19,25 @@ use core::fmt;
 use std::{
     any::Any,
     ops::{Deref, DerefMut},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
 use crate::{
+    crypto,
     error::*,
-    transport::{plain_hdr, proto_hdr},
+    sys::MAX_PACKET_POOL_SIZE,
+    transport::{plain_hdr, privacy, proto_hdr},
     utils::writebuf::WriteBuf,
 };
-use boxslab::{BoxSlab, Slab};
 use colored::*;
 use log::{info, trace};
 use rand::Rng;
 
 use super::{
-    network::{Address, NetworkInterface},
-    packet::{Packet, PacketPool},
+    dedup::{CtrPolicy, RxCtrState},
+    mrp::MrpParams,
+    network::{Address, NetworkInterface, Readable},
+    packet::{Packet, PacketPool, MAX_RX_BUF_SIZE},
 };
 
 pub const MAX_CAT_IDS_PER_NOC: usize = 3;
@@ -84,9 +87,15 @@ pub struct Session {
     local_sess_id: u16,
     peer_sess_id: u16,
     msg_ctr: u32,
+    rx_ctr_state: RxCtrState,
     mode: SessionMode,
-    data: Option<Box<dyn Any>>,
+    data: Option<Box<dyn Any + Send>>,
     last_use: SystemTime,
+    // The last time we heard from the peer, used to decide the MRP interval
+    last_rx: SystemTime,
+    mrp_params: MrpParams,
+    // Obfuscate the message headers that we send
+    privacy: bool,
 }
 
 #[derive(Debug)]
@@ -94,6 +103,7 @@ pub struct CloneData {
     pub dec_key: [u8; MATTER_AES128_KEY_SIZE],
     pub enc_key: [u8; MATTER_AES128_KEY_SIZE],
     pub att_challenge: [u8; MATTER_AES128_KEY_SIZE],
+    pub mrp_params: MrpParams,
     local_sess_id: u16,
     peer_sess_id: u16,
     local_nodeid: u64,
@@ -114,6 +124,7 @@ impl CloneData {
             dec_key: [0; MATTER_AES128_KEY_SIZE],
             enc_key: [0; MATTER_AES128_KEY_SIZE],
             att_challenge: [0; MATTER_AES128_KEY_SIZE],
+            mrp_params: Default::default(),
             local_nodeid,
             peer_nodeid,
             peer_addr,
@@ -125,6 +136,7 @@ impl CloneData {
 }
 
 const MATTER_MSG_CTR_RANGE: u32 = 0x0fffffff;
+const MAX_OPERATIONAL_NODEID: u64 = 0xFFFF_FFEF_FFFF_FFFF;
 
 impl Session {
     pub fn new(peer_addr: Address, peer_nodeid: Option<u64>) -> Session {
@@ -138,9 +150,13 @@ impl Session {
             peer_sess_id: 0,
             local_sess_id: 0,
             msg_ctr: rand::thread_rng().gen_range(0..MATTER_MSG_CTR_RANGE),
+            rx_ctr_state: RxCtrState::new(CtrPolicy::Unencrypted),
             mode: SessionMode::PlainText,
             data: None,
             last_use: SystemTime::now(),
+            last_rx: SystemTime::now(),
+            mrp_params: Default::default(),
+            privacy: false,
         }
     }
 
@@ -156,13 +172,17 @@ impl Session {
             local_sess_id: clone_from.local_sess_id,
             peer_sess_id: clone_from.peer_sess_id,
             msg_ctr: rand::thread_rng().gen_range(0..MATTER_MSG_CTR_RANGE),
+            rx_ctr_state: RxCtrState::new(CtrPolicy::Encrypted),
             mode: clone_from.mode,
             data: None,
             last_use: SystemTime::now(),
+            last_rx: SystemTime::now(),
+            mrp_params: clone_from.mrp_params,
+            privacy: false,
         }
     }
 
-    pub fn set_data(&mut self, data: Box<dyn Any>) {
+    pub fn set_data(&mut self, data: Box<dyn Any + Send>) {
         self.data = Some(data);
     }
 
@@ -248,9 +268,54 @@ impl Session {
         &self.att_challenge
     }
 
+    pub fn get_mrp_params(&self) -> &MrpParams {
+        &self.mrp_params
+    }
+
+    pub fn set_mrp_params(&mut self, mrp_params: MrpParams) {
+        self.mrp_params = mrp_params;
+    }
+
+    /// The peer is considered active, if we heard from it within its active threshold
+    pub fn is_peer_active(&self) -> bool {
+        match self.last_rx.elapsed() {
+            Ok(elapsed) => elapsed < self.mrp_params.get_active_threshold(),
+            Err(_) => true,
+        }
+    }
+
+    /// The base interval to be used for retransmissions to this peer
+    pub fn get_retrans_interval(&self) -> Duration {
+        self.mrp_params.get_interval(self.is_peer_active())
+    }
+
+    /// Use message privacy for the messages that are sent on this session
+    ///
+    /// This is also turned on, once the peer sends us a message with privacy
+    pub fn set_privacy(&mut self, privacy: bool) {
+        self.privacy = privacy;
+    }
+
+    pub fn is_privacy(&self) -> bool {
+        self.privacy
+    }
+
+    /// Decrypt and decode the received message
+    ///
+    /// Returns Error::Duplicate, if a message with the same counter was already received
+    /// from the peer. The counter is checked only after the message is authenticated, so
+    /// that a forged message can't affect the counter state.
     pub fn recv(&mut self, proto_rx: &mut Packet) -> Result<(), Error> {
         self.last_use = SystemTime::now();
-        proto_rx.proto_decode(self.peer_nodeid.unwrap_or_default(), self.get_dec_key())
+        self.last_rx = self.last_use;
+        proto_rx.proto_decode(self.peer_nodeid.unwrap_or_default(), self.get_dec_key())?;
+        if self.rx_ctr_state.recv(proto_rx.plain.ctr) {
+            return Err(Error::Duplicate);
+        }
+        if proto_rx.plain.is_privacy() {
+            self.privacy = true;
+        }
+        Ok(())
     }
 
     pub fn pre_send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
@@ -258,6 +323,9 @@ impl Session {
         proto_tx.plain.ctr = self.get_msg_ctr();
         if self.is_encrypted() {
             proto_tx.plain.sess_type = plain_hdr::SessionType::Encrypted;
+            if self.privacy {
+                proto_tx.plain.set_privacy();
+            }
         }
         Ok(())
     }
@@ -278,11 +346,16 @@ impl Session {
             if let Some(d) = self.peer_nodeid {
                 proto_tx.plain.set_dest_u64(d);
             }
+            if self.local_nodeid != 0 {
+                // Our ephemeral node id, on a session that we initiated
+                proto_tx.plain.set_src_u64(self.local_nodeid);
+            }
         }
         let mut tmp_buf: [u8; plain_hdr::max_plain_hdr_len()] = [0; plain_hdr::max_plain_hdr_len()];
         let mut write_buf = WriteBuf::new(&mut tmp_buf[..], plain_hdr::max_plain_hdr_len());
         proto_tx.plain.encode(&mut write_buf)?;
-        let plain_hdr_bytes = write_buf.as_slice();
+        let plain_hdr_len = write_buf.as_borrow_slice().len();
+        let plain_hdr_bytes = &mut tmp_buf[..plain_hdr_len];
 
         trace!("unencrypted packet: {:x?}", proto_tx.as_borrow_slice());
         let ctr = proto_tx.plain.ctr;
@@ -295,6 +368,22 @@ impl Session {
                 proto_tx.get_writebuf()?,
                 e,
             )?;
+
+            if proto_tx.plain.is_privacy() {
+                // The header goes into the MIC as is, and is obfuscated only after that
+                let mut privacy_key = [0u8; crypto::SYMM_KEY_LEN_BYTES];
+                privacy::get_privacy_key(e, &mut privacy_key)?;
+                let obfuscated = plain_hdr_len - proto_tx.plain.obfuscated_len();
+                let sess_id = proto_tx.plain.sess_id;
+                let cipher_text = proto_tx.get_writebuf()?.as_borrow_slice();
+                let mic = &cipher_text[cipher_text.len() - crypto::AEAD_MIC_LEN_BYTES..];
+                privacy::obfuscate_in_place(
+                    &privacy_key,
+                    sess_id,
+                    mic,
+                    &mut plain_hdr_bytes[obfuscated..],
+                )?;
+            }
         }
 
         proto_tx.get_writebuf()?.prepend(plain_hdr_bytes)?;
@@ -319,11 +408,13 @@ impl fmt::Display for Session {
     }
 }
 
+// The default maximum number of sessions
 pub const MAX_SESSIONS: usize = 16;
 pub struct SessionMgr {
     next_sess_id: u16,
-    sessions: [Option<Session>; MAX_SESSIONS],
-    network: Option<Box<dyn NetworkInterface>>,
+    sessions: Vec<Option<Session>>,
+    network: Option<Box<dyn NetworkInterface + Send>>,
+    packet_pool: PacketPool,
 }
 
 impl Default for SessionMgr {
@@ -334,16 +425,32 @@ impl Default for SessionMgr {
 
 impl SessionMgr {
     pub fn new() -> SessionMgr {
+        SessionMgr::new_with(MAX_SESSIONS, PacketPool::new(MAX_PACKET_POOL_SIZE))
+    }
+
+    /// The packets, received or sent on the sessions, come from the `packet_pool`
+    pub fn new_with(max_sessions: usize, packet_pool: PacketPool) -> SessionMgr {
+        let mut sessions = Vec::new();
+        sessions.resize_with(max_sessions, || None);
         SessionMgr {
-            sessions: Default::default(),
+            sessions,
             next_sess_id: 1,
             network: None,
+            packet_pool,
         }
     }
 
+    pub fn packet_pool(&self) -> &PacketPool {
+        &self.packet_pool
+    }
+
+    pub fn max_sessions(&self) -> usize {
+        self.sessions.len()
+    }
+
     pub fn add_network_interface(
         &mut self,
-        interface: Box<dyn NetworkInterface>,
+        interface: Box<dyn NetworkInterface + Send>,
     ) -> Result<(), Error> {
         if self.network.is_none() {
             self.network = Some(interface);
@@ -383,8 +490,8 @@ impl SessionMgr {
     pub fn get_lru(&mut self) -> usize {
         let mut lru_index = 0;
         let mut lru_ts = SystemTime::now();
-        for i in 0..MAX_SESSIONS {
-            if let Some(s) = &self.sessions[i] {
+        for (i, s) in self.sessions.iter().enumerate() {
+            if let Some(s) = s {
                 if s.last_use < lru_ts {
                     lru_ts = s.last_use;
                     lru_index = i;
@@ -399,6 +506,15 @@ impl SessionMgr {
         self.add_session(session)
     }
 
+    /// Add an unsecured session, for a secure session establishment that we initiate
+    ///
+    /// We identify ourselves to the peer, with a random ephemeral node id
+    pub fn add_unsecured_initiator(&mut self, peer_addr: Address) -> Result<usize, Error> {
+        let mut session = Session::new(peer_addr, None);
+        session.local_nodeid = rand::thread_rng().gen_range(1..=MAX_OPERATIONAL_NODEID);
+        self.add_session(session)
+    }
+
     /// This assumes that the higher layer has taken care of doing anything required
     /// as per the spec before the session is erased
     pub fn remove(&mut self, idx: usize) {
@@ -447,13 +563,22 @@ impl SessionMgr {
     }
 
     pub fn get_with_id(&mut self, sess_id: u16) -> Option<SessionHandle> {
-        let index = self
-            .sessions
-            .iter_mut()
-            .position(|x| x.as_ref().map(|s| s.local_sess_id) == Some(sess_id))?;
+        let index = self.get_index_with_id(sess_id)?;
         Some(self.get_session_handle(index))
     }
 
+    pub fn get_index_with_id(&self, sess_id: u16) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|x| x.as_ref().map(|s| s.local_sess_id) == Some(sess_id))
+    }
+
+    pub fn get_with_peer_addr(&self, peer_addr: Address) -> Option<usize> {
+        self.sessions
+            .iter()
+            .position(|x| x.as_ref().map(|s| s.peer_addr) == Some(peer_addr))
+    }
+
     pub fn get_or_add(
         &mut self,
         sess_id: u16,
@@ -490,11 +615,18 @@ impl SessionMgr {
         Ok(sess_index)
     }
 
-    pub fn recv(&mut self) -> Result<(BoxSlab<PacketPool>, Option<usize>), Error> {
-        let mut rx =
-            Slab::<PacketPool>::try_new(Packet::new_rx()?).ok_or(Error::PacketPoolExhaust)?;
+    pub fn readable(&self) -> Result<Readable<'_>, Error> {
+        let network = self.network.as_ref().ok_or(Error::NoNetworkInterface)?;
+        Ok(network.readable())
+    }
 
+    pub fn recv(&mut self) -> Result<(Box<Packet<'static>>, Option<usize>), Error> {
         let network = self.network.as_ref().ok_or(Error::NoNetworkInterface)?;
+        // A large message gets a buffer of its own size
+        let len = network
+            .pending_len()
+            .map_or(MAX_RX_BUF_SIZE, |len| len.max(MAX_RX_BUF_SIZE));
+        let mut rx = Box::new(Packet::new_rx_with(&self.packet_pool, len)?);
 
         let (len, src) = network.recv(rx.as_borrow_slice())?;
         rx.get_parsebuf()?.set_len(len);
@@ -505,22 +637,33 @@ impl SessionMgr {
 
         // Read unencrypted packet header
         rx.plain_hdr_decode()?;
+        if rx.plain.is_privacy() {
+            // The rest of the header can be read only with the session's keys
+            let session = self
+                .sessions
+                .iter()
+                .flatten()
+                .find(|s| s.is_encrypted() && s.local_sess_id == rx.plain.sess_id)
+                .ok_or(Error::NoSession)?;
+            rx.plain_hdr_decode_privacy(session.get_dec_key().ok_or(Error::NoSession)?)?;
+        }
 
         // Get session
         let sess_handle = self.post_recv(&rx)?;
         Ok((rx, sess_handle))
     }
 
-    pub fn send(
-        &mut self,
-        sess_idx: usize,
-        mut proto_tx: BoxSlab<PacketPool>,
-    ) -> Result<(), Error> {
+    pub fn send(&mut self, sess_idx: usize, proto_tx: &mut Packet) -> Result<(), Error> {
         self.sessions[sess_idx]
             .as_mut()
             .ok_or(Error::NoSession)?
-            .do_send(&mut proto_tx)?;
+            .do_send(proto_tx)?;
+
+        self.transmit(proto_tx)
+    }
 
+    /// Send out an already encoded packet, as is
+    pub fn transmit(&self, proto_tx: &mut Packet) -> Result<(), Error> {
         let network = self.network.as_ref().ok_or(Error::NoNetworkInterface)?;
         let peer = proto_tx.peer;
         network.send(proto_tx.as_borrow_slice(), peer)?;
@@ -528,6 +671,13 @@ impl SessionMgr {
         Ok(())
     }
 
+    /// Close the connection to this peer, if it is on a connection oriented transport
+    pub fn close_connection(&self, peer: Address) {
+        if let Some(network) = self.network.as_ref() {
+            network.close(peer);
+        }
+    }
+
     pub fn get_session_handle(&mut self, sess_idx: usize) -> SessionHandle {
         SessionHandle {
             sess_mgr: self,
@@ -553,11 +703,15 @@ pub struct SessionHandle<'a> {
 }
 
 impl<'a> SessionHandle<'a> {
+    pub fn packet_pool(&self) -> &PacketPool {
+        self.sess_mgr.packet_pool()
+    }
+
     pub fn reserve_new_sess_id(&mut self) -> u16 {
         self.sess_mgr.get_next_sess_id()
     }
 
-    pub fn send(&mut self, proto_tx: BoxSlab<PacketPool>) -> Result<(), Error> {
+    pub fn send(&mut self, proto_tx: &mut Packet) -> Result<(), Error> {
         self.sess_mgr.send(self.sess_idx, proto_tx)
     }
 }
@@ -580,9 +734,12 @@ impl<'a> DerefMut for SessionHandle<'a> {
 #[cfg(test)]
 mod tests {
 
-    use crate::transport::network::Address;
+    use crate::transport::{
+        network::Address,
+        packet::{Packet, PacketPool},
+    };
 
-    use super::SessionMgr;
+    use super::{CloneData, Session, SessionMgr, SessionMode};
 
     #[test]
     fn test_next_sess_id_doesnt_reuse() {
@@ -610,4 +767,48 @@ mod tests {
         assert_eq!(sm.get_next_sess_id(), 65535);
         assert_eq!(sm.get_next_sess_id(), 2);
     }
+
+    #[test]
+    fn test_privacy_roundtrip() {
+        let mut clone_data = CloneData::new(1, 2, 20, 10, Address::default(), SessionMode::Pase);
+        clone_data.enc_key = [1; 16];
+        clone_data.dec_key = [2; 16];
+        let mut tx_sess = Session::clone(&clone_data);
+        tx_sess.set_privacy(true);
+
+        let mut clone_data = CloneData::new(2, 1, 10, 20, Address::default(), SessionMode::Pase);
+        clone_data.enc_key = [2; 16];
+        clone_data.dec_key = [1; 16];
+        let mut rx_sess = Session::clone(&clone_data);
+        assert!(!rx_sess.is_privacy());
+
+        let pool = PacketPool::new(2);
+        let mut tx = Packet::new_tx(&pool).unwrap();
+        tx.set_proto_id(1);
+        tx.set_proto_opcode(2);
+        tx.get_writebuf().unwrap().append(&[3, 4, 5]).unwrap();
+        tx_sess.pre_send(&mut tx).unwrap();
+        tx_sess.do_send(&mut tx).unwrap();
+        let ctr = tx.plain.ctr;
+
+        let mut rx = Packet::new_rx(&pool).unwrap();
+        let len = tx.as_borrow_slice().len();
+        rx.as_borrow_slice()[..len].copy_from_slice(tx.as_borrow_slice());
+        rx.get_parsebuf().unwrap().set_len(len);
+        // The counter isn't visible on the wire
+        assert_ne!(rx.as_borrow_slice()[4..8], ctr.to_le_bytes());
+
+        rx.plain_hdr_decode().unwrap();
+        assert!(rx.plain.is_privacy());
+        assert_eq!(rx.plain.sess_id, 20);
+        rx.plain_hdr_decode_privacy(rx_sess.get_dec_key().unwrap())
+            .unwrap();
+        assert_eq!(rx.plain.ctr, ctr);
+
+        rx_sess.recv(&mut rx).unwrap();
+        assert_eq!(rx.get_proto_opcode(), 2);
+        assert_eq!(rx.as_borrow_slice(), [3, 4, 5]);
+        // We reply with privacy too
+        assert!(rx_sess.is_privacy());
+    }
 }
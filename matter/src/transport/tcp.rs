@@ -0,0 +1,477 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{
+        IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener as StdTcpListener, TcpStream,
+    },
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use async_channel::{bounded, Receiver, Sender};
+use log::{error, info};
+
+use crate::error::*;
+
+use super::{
+    network::{Address, NetworkInterface, Readable},
+    packet::MAX_LARGE_MSG_SIZE,
+    queue::{Msg, WorkQ},
+    udp::UdpListener,
+};
+
+// Each message on a TCP connection is preceded by its length, as a 4-byte little-endian value
+const TCP_MSG_LEN_SIZE: usize = 4;
+
+// The number of received messages that may be pending, before the readers stop reading
+const MAX_TCP_RX_QUEUE: usize = 8;
+
+type Connections = Arc<Mutex<HashMap<SocketAddr, TcpStream>>>;
+
+/// Matter over TCP
+///
+/// A thread accepts incoming connections, and every connection gets a reader thread that
+/// extracts the messages from the stream. Connections beyond `max_conns` are refused. The
+/// received messages are then handed over through a channel. When a connection goes away,
+/// the transport manager is informed through the WorkQ, so any sessions on that
+/// connection can be cleaned up.
+///
+/// The messages may be as large as [MAX_LARGE_MSG_SIZE].
+pub struct TcpListener {
+    rx: Receiver<(Vec<u8>, SocketAddr)>,
+    // A message that was picked up from the channel while waiting for readability
+    pending: Mutex<Option<(Vec<u8>, SocketAddr)>>,
+    connections: Connections,
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl TcpListener {
+    /// The closed connections are reported to the transport through `wq`
+    pub fn new(port: u16, max_conns: usize, wq: WorkQ) -> Result<TcpListener, Error> {
+        let listener = StdTcpListener::bind((Ipv6Addr::UNSPECIFIED, port))?;
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = bounded(MAX_TCP_RX_QUEUE);
+        let connections: Connections = Default::default();
+        let stopped: Arc<AtomicBool> = Default::default();
+
+        let conns = connections.clone();
+        let stop = stopped.clone();
+        thread::Builder::new()
+            .name("matter-tcp".to_owned())
+            .spawn(move || TcpListener::accept_loop(listener, max_conns, tx, conns, stop, wq))?;
+
+        Ok(TcpListener {
+            rx,
+            pending: Mutex::new(None),
+            connections,
+            local_addr,
+            stopped,
+        })
+    }
+
+    fn accept_loop(
+        listener: StdTcpListener,
+        max_conns: usize,
+        tx: Sender<(Vec<u8>, SocketAddr)>,
+        connections: Connections,
+        stopped: Arc<AtomicBool>,
+        wq: WorkQ,
+    ) {
+        for stream in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            let result = stream.and_then(|stream| {
+                let peer = stream.peer_addr()?;
+                let reader = stream.try_clone()?;
+                Ok((stream, reader, peer))
+            });
+            let (stream, reader, peer) = match result {
+                Ok(r) => r,
+                Err(e) => {
+                    error!("Error accepting TCP connection: {:?}", e);
+                    continue;
+                }
+            };
+
+            {
+                let mut conns = connections.lock().unwrap();
+                if conns.len() >= max_conns {
+                    error!("Too many TCP connections, refusing {}", peer);
+                    let _ = stream.shutdown(Shutdown::Both);
+                    continue;
+                }
+                info!("New TCP connection from {}", peer);
+                conns.insert(peer, stream);
+            }
+            let tx = tx.clone();
+            let conns = connections.clone();
+            let wq = wq.clone();
+            if let Err(e) = thread::Builder::new()
+                .name("matter-tcp-conn".to_owned())
+                .spawn(move || TcpListener::read_loop(reader, peer, tx, conns, wq))
+            {
+                error!("Couldn't start reader for {}: {:?}", peer, e);
+                TcpListener::shutdown(&connections, peer);
+            }
+        }
+    }
+
+    fn read_loop(
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        tx: Sender<(Vec<u8>, SocketAddr)>,
+        connections: Connections,
+        wq: WorkQ,
+    ) {
+        loop {
+            match TcpListener::read_msg(&mut stream) {
+                Ok(msg) => {
+                    if smol::block_on(tx.send((msg, peer))).is_err() {
+                        // The listener went away
+                        break;
+                    }
+                }
+                Err(e) => {
+                    info!("TCP connection from {} closed: {:?}", peer, e);
+                    break;
+                }
+            }
+        }
+
+        TcpListener::shutdown(&connections, peer);
+        let _ = wq.sync_send(Msg::ConnectionClosed(Address::Tcp(peer)));
+    }
+
+    fn read_msg(stream: &mut TcpStream) -> Result<Vec<u8>, Error> {
+        let mut len = [0u8; TCP_MSG_LEN_SIZE];
+        stream.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_LARGE_MSG_SIZE {
+            // We can't hold this in our packet buffers, and there is no way to
+            // skip over it reliably either
+            error!("TCP message too large: {}", len);
+            return Err(Error::NoSpace);
+        }
+
+        let mut msg = vec![0u8; len];
+        stream.read_exact(&mut msg)?;
+        Ok(msg)
+    }
+
+    // The reader of the connection stops once it is shut down
+    fn shutdown(connections: &Connections, peer: SocketAddr) {
+        if let Some(stream) = connections.lock().unwrap().remove(&peer) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub async fn recv_async(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        let msg = self.recv_msg_async().await?;
+        self.deliver(msg, in_buf)
+    }
+
+    async fn recv_msg_async(&self) -> Result<(Vec<u8>, SocketAddr), Error> {
+        let pending = self.pending.lock().unwrap().take();
+        match pending {
+            Some(p) => Ok(p),
+            None => self.rx.recv().await.map_err(|_| Error::Network),
+        }
+    }
+
+    // A message that doesn't fit in the buffer is kept, for a recv with a buffer of the
+    // size that pending_len() reports
+    fn deliver(
+        &self,
+        (msg, peer): (Vec<u8>, SocketAddr),
+        in_buf: &mut [u8],
+    ) -> Result<(usize, Address), Error> {
+        if msg.len() > in_buf.len() {
+            *self.pending.lock().unwrap() = Some((msg, peer));
+            return Err(Error::NoSpace);
+        }
+        in_buf[..msg.len()].copy_from_slice(&msg);
+        Ok((msg.len(), Address::Tcp(peer)))
+    }
+
+    pub async fn readable_async(&self) -> Result<(), Error> {
+        if self.pending.lock().unwrap().is_some() {
+            return Ok(());
+        }
+        let msg = self.rx.recv().await.map_err(|_| Error::Network)?;
+        *self.pending.lock().unwrap() = Some(msg);
+        Ok(())
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        // Wake up the accept loop, so it notices that it has to stop, and releases
+        // the port
+        self.stopped.store(true, Ordering::SeqCst);
+        let mut wake_addr = self.local_addr;
+        if wake_addr.ip().is_unspecified() {
+            // Whichever loopback address the wildcard address accepts connections on
+            let loopback = match wake_addr {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            };
+            wake_addr.set_ip(loopback);
+        }
+        if let Err(e) = TcpStream::connect(wake_addr) {
+            error!(
+                "Couldn't wake up the TCP accept loop on {}: {:?}",
+                wake_addr, e
+            );
+        }
+
+        // The readers stop once their connections are shut down
+        for (_, stream) in self.connections.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl NetworkInterface for TcpListener {
+    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        smol::block_on(self.recv_async(in_buf))
+    }
+
+    fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error> {
+        let peer = match addr {
+            Address::Tcp(peer) => peer,
+            _ => return Err(Error::InvalidPeerAddr),
+        };
+
+        // Don't hold the lock while writing, a stalled peer shouldn't block the others
+        let mut stream = self
+            .connections
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .ok_or(Error::NotFound)?
+            .try_clone()?;
+        let len = (out_buf.len() as u32).to_le_bytes();
+        stream.write_all(&len)?;
+        stream.write_all(out_buf)?;
+        Ok(out_buf.len())
+    }
+
+    fn readable(&self) -> Readable<'_> {
+        Box::pin(self.readable_async())
+    }
+
+    fn pending_len(&self) -> Option<usize> {
+        self.pending
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(msg, _)| msg.len())
+    }
+
+    fn close(&self, addr: Address) {
+        if let Address::Tcp(peer) = addr {
+            info!("Closing TCP connection to {}", peer);
+            TcpListener::shutdown(&self.connections, peer);
+        }
+    }
+}
+
+/// The network interface for Matter over IP
+///
+/// Messages are received on both, UDP and TCP, and sent out on the transport that the
+/// peer's address belongs to. There may be at most `max_conns` TCP connections at a time.
+pub struct IpListener {
+    udp: UdpListener,
+    tcp: TcpListener,
+}
+
+impl IpListener {
+    pub fn new(port: u16, max_conns: usize, wq: WorkQ) -> Result<IpListener, Error> {
+        Ok(IpListener {
+            udp: UdpListener::new(port)?,
+            tcp: TcpListener::new(port, max_conns, wq)?,
+        })
+    }
+}
+
+// What IpListener received, on either of the transports
+enum Received {
+    Udp(Result<(usize, Address), Error>),
+    Tcp(Result<(Vec<u8>, SocketAddr), Error>),
+}
+
+impl NetworkInterface for IpListener {
+    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        // Both futures need the buffer, but only one of them completes. So a TCP message
+        // is copied to the buffer once it is received
+        let received = smol::block_on(smol::future::or(
+            async { Received::Udp(self.udp.recv_async(in_buf).await) },
+            async { Received::Tcp(self.tcp.recv_msg_async().await) },
+        ));
+        match received {
+            Received::Udp(result) => result,
+            Received::Tcp(result) => self.tcp.deliver(result?, in_buf),
+        }
+    }
+
+    fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error> {
+        match addr {
+            Address::Udp(_) => self.udp.send(out_buf, addr),
+            Address::Tcp(_) => self.tcp.send(out_buf, addr),
+        }
+    }
+
+    fn readable(&self) -> Readable<'_> {
+        Box::pin(smol::future::or(
+            self.udp.readable_async(),
+            self.tcp.readable_async(),
+        ))
+    }
+
+    fn pending_len(&self) -> Option<usize> {
+        self.tcp.pending_len()
+    }
+
+    fn close(&self, addr: Address) {
+        self.tcp.close(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{Ipv4Addr, Ipv6Addr, TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        error::Error,
+        transport::{
+            network::{Address, NetworkInterface},
+            packet::MAX_RX_BUF_SIZE,
+            queue::{Msg, WorkQ},
+        },
+    };
+
+    #[test]
+    fn test_read_msg() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        client.write_all(&[3, 0, 0, 0, 1, 2, 3]).unwrap();
+        client.write_all(&[0, 0, 0, 0]).unwrap();
+        // Larger than a UDP message can be
+        let large = [0xAA; 4000];
+        client
+            .write_all(&(large.len() as u32).to_le_bytes())
+            .unwrap();
+        client.write_all(&large).unwrap();
+        // Larger than what our buffers can hold
+        client.write_all(&[0, 0, 1, 0]).unwrap();
+
+        assert_eq!(
+            super::TcpListener::read_msg(&mut server).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(super::TcpListener::read_msg(&mut server)
+            .unwrap()
+            .is_empty());
+        assert_eq!(super::TcpListener::read_msg(&mut server).unwrap(), large);
+        assert_eq!(
+            super::TcpListener::read_msg(&mut server),
+            Err(Error::NoSpace)
+        );
+    }
+
+    #[test]
+    fn test_max_connections() {
+        let listener = super::TcpListener::new(0, 1, WorkQ::new().0).unwrap();
+        let addr = (Ipv4Addr::LOCALHOST, listener.local_addr.port());
+        let _first = TcpStream::connect(addr).unwrap();
+        while listener.connections.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The second one is closed right away
+        let mut second = TcpStream::connect(addr).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(second.read(&mut buf).unwrap(), 0);
+        assert_eq!(listener.connections.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_large_msg() {
+        let (wq, rx_q) = WorkQ::new();
+        let listener = super::TcpListener::new(0, 1, wq).unwrap();
+        let addr = (Ipv4Addr::LOCALHOST, listener.local_addr.port());
+        let mut client = TcpStream::connect(addr).unwrap();
+        let large = [0xAA; 4000];
+        client
+            .write_all(&(large.len() as u32).to_le_bytes())
+            .unwrap();
+        client.write_all(&large).unwrap();
+
+        // A buffer that is too small doesn't lose the message, but learns its length
+        smol::block_on(listener.readable_async()).unwrap();
+        let mut buf = [0u8; MAX_RX_BUF_SIZE];
+        assert_eq!(
+            smol::block_on(listener.recv_async(&mut buf)),
+            Err(Error::NoSpace)
+        );
+        assert_eq!(listener.pending_len(), Some(large.len()));
+        let mut buf = vec![0u8; large.len()];
+        let (len, _) = smol::block_on(listener.recv_async(&mut buf)).unwrap();
+        assert_eq!(&buf[..len], large);
+
+        // Closing the connection stops its reader
+        let peer = *listener.connections.lock().unwrap().keys().next().unwrap();
+        listener.close(Address::Tcp(peer));
+        assert!(listener.connections.lock().unwrap().is_empty());
+        let mut buf = [0u8; 4];
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        assert!(matches!(
+            smol::block_on(rx_q.recv()),
+            Ok(Msg::ConnectionClosed(Address::Tcp(p))) if p == peer
+        ));
+    }
+
+    #[test]
+    fn test_drop_releases_port() {
+        let listener = super::TcpListener::new(0, 1, WorkQ::new().0).unwrap();
+        let port = listener.local_addr.port();
+        drop(listener);
+
+        // The accept loop is woken up, and closes the listening socket on its way out
+        let mut retries = 100;
+        while TcpListener::bind((Ipv6Addr::UNSPECIFIED, port)).is_err() {
+            retries -= 1;
+            assert!(retries > 0, "Port {} is still in use", port);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
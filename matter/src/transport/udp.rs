@@ -15,15 +15,17 @@
  *    limitations under the License.
  */
 
+use std::net::{Ipv6Addr, UdpSocket};
+
 use crate::error::*;
-use smol::net::{Ipv6Addr, UdpSocket};
+use smol::Async;
 
-use super::network::{Address, NetworkInterface};
+use super::network::{Address, NetworkInterface, Readable};
 
 // We could get rid of the smol here, but keeping it around in case we have to process
 // any other events in this thread's context
 pub struct UdpListener {
-    socket: UdpSocket,
+    socket: Async<UdpSocket>,
 }
 
 // Currently matches with the one in connectedhomeip repo
@@ -33,25 +35,40 @@ pub const MAX_RX_BUF_SIZE: usize = 1583;
 pub const MATTER_PORT: u16 = 5540;
 
 impl UdpListener {
-    pub fn new() -> Result<UdpListener, Error> {
+    pub fn new(port: u16) -> Result<UdpListener, Error> {
         Ok(UdpListener {
-            socket: smol::block_on(UdpSocket::bind((Ipv6Addr::UNSPECIFIED, MATTER_PORT)))?,
+            socket: Async::<UdpSocket>::bind((Ipv6Addr::UNSPECIFIED, port))?,
         })
     }
 }
 
-impl NetworkInterface for UdpListener {
-    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
-        let (size, addr) = smol::block_on(self.socket.recv_from(in_buf)).map_err(|e| {
+impl UdpListener {
+    pub async fn recv_async(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        let (size, addr) = self.socket.recv_from(in_buf).await.map_err(|e| {
             println!("Error on the network: {:?}", e);
             Error::Network
         })?;
         Ok((size, Address::Udp(addr)))
     }
 
+    pub async fn readable_async(&self) -> Result<(), Error> {
+        Ok(self.socket.readable().await?)
+    }
+}
+
+impl NetworkInterface for UdpListener {
+    fn recv(&self, in_buf: &mut [u8]) -> Result<(usize, Address), Error> {
+        smol::block_on(self.recv_async(in_buf))
+    }
+
     fn send(&self, out_buf: &[u8], addr: Address) -> Result<usize, Error> {
         match addr {
             Address::Udp(addr) => Ok(smol::block_on(self.socket.send_to(out_buf, addr))?),
+            _ => Err(Error::InvalidPeerAddr),
         }
     }
+
+    fn readable(&self) -> Readable<'_> {
+        Box::pin(self.readable_async())
+    }
 }
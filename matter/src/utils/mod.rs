@@ -16,4 +16,6 @@
  */
 
 pub mod parsebuf;
+#[cfg(test)]
+pub mod test_utils;
 pub mod writebuf;
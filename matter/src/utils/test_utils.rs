@@ -0,0 +1,41 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::sync::{Arc, Mutex};
+
+use tempfile::TempDir;
+
+use crate::{fabric::FabricMgr, sys::Psm};
+
+/// Storage in a directory of its own, so that the tests don't see each other's data
+///
+/// The directory is removed once the returned [TempDir] is dropped, so it has to outlive
+/// the users of the storage.
+pub fn temp_psm() -> (Arc<Mutex<Psm>>, TempDir) {
+    let dir = tempfile::Builder::new()
+        .prefix("matter-test-")
+        .tempdir()
+        .unwrap();
+    let psm = Psm::new(dir.path().to_str().unwrap()).unwrap();
+    (Arc::new(Mutex::new(psm)), dir)
+}
+
+/// A FabricMgr, with storage of its own, see [temp_psm]
+pub fn temp_fabric_mgr() -> (Arc<FabricMgr>, TempDir) {
+    let (psm, dir) = temp_psm();
+    (Arc::new(FabricMgr::new_with_psm(psm).unwrap()), dir)
+}
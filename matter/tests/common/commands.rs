@@ -18,24 +18,83 @@
 use matter::{
     data_model::objects::EncodeValue,
     interaction_model::{
-        messages::ib::{CmdPath, CmdStatus, InvResp},
-        messages::msg,
+        core::OpCode,
+        messages::ib::{CmdData, CmdPath, CmdStatus, InvResp},
+        messages::msg::{self, InvReq, TimedReq},
     },
+    tlv::{self, FromTLV, TLVArray, ToTLV},
+    transport::exchange::{self, Exchange},
 };
 
+use crate::common::im_engine::{ImEngine, ImInput};
+
 pub enum ExpectedInvResp {
-    Cmd(CmdPath, u8),
+    Cmd(CmdPath, u8, Option<u16>),
     Status(CmdStatus),
 }
 
+/// Invoke the command, and run the closure on its response
+///
+/// A timed invoke is preceded by a Timed Request, on the same exchange.
+pub fn invoke(
+    engine: &mut ImEngine,
+    path: CmdPath,
+    data: &dyn ToTLV,
+    timed: bool,
+    f: impl FnOnce(InvResp),
+) {
+    if timed {
+        engine.exch = Some(Exchange::new(1, 0, exchange::Role::Responder));
+        let mut out_buf = [0u8; 400];
+        let timed_req = TimedReq { timeout: 400 };
+        engine.process(
+            &ImInput::new(OpCode::TimedRequest, &timed_req),
+            &mut out_buf,
+        );
+    }
+    let input = [CmdData::new(path, EncodeValue::Value(data))];
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(timed),
+        inv_requests: Some(TLVArray::Slice(&input)),
+    };
+    let mut out_buf = [0u8; 1024];
+    let (out_code, out_buf) =
+        engine.process(&ImInput::new(OpCode::InvokeRequest, &req), &mut out_buf);
+    engine.exch = None;
+    assert_eq!(out_code, OpCode::InvokeResponse as u8);
+    tlv::print_tlv_list(out_buf);
+    let root = tlv::get_root_node_struct(out_buf).unwrap();
+    let resp = msg::InvResp::from_tlv(&root).unwrap();
+    let mut responses = resp.inv_responses.unwrap().iter();
+    f(responses.next().unwrap());
+    assert!(responses.next().is_none());
+}
+
+/// Invoke the command, and return the status of its response
+pub fn invoke_status(
+    engine: &mut ImEngine,
+    path: CmdPath,
+    data: &dyn ToTLV,
+    timed: bool,
+) -> CmdStatus {
+    let mut status = None;
+    invoke(engine, path, data, timed, |resp| match resp {
+        InvResp::Status(s) => status = Some(s),
+        _ => panic!("Invalid response, expected InvResp::Status"),
+    });
+    status.unwrap()
+}
+
 pub fn assert_inv_response(resp: &msg::InvResp, expected: &[ExpectedInvResp]) {
     let mut index = 0;
     for inv_response in resp.inv_responses.unwrap().iter() {
         println!("Validating index {}", index);
         match expected[index] {
-            ExpectedInvResp::Cmd(e_c, e_d) => match inv_response {
+            ExpectedInvResp::Cmd(e_c, e_d, e_ref) => match inv_response {
                 InvResp::Cmd(c) => {
                     assert_eq!(e_c, c.path);
+                    assert_eq!(e_ref, c.command_ref);
                     match c.data {
                         EncodeValue::Tlv(t) => {
                             assert_eq!(e_d, t.find_tag(0).unwrap().u8().unwrap())
@@ -67,6 +126,9 @@ macro_rules! cmd_data {
     ($path:ident, $data:literal) => {
         CmdData::new($path, EncodeValue::Value(&($data as u32)))
     };
+    ($path:ident, $data:literal, $command_ref:literal) => {
+        CmdData::new($path, EncodeValue::Value(&($data as u32))).set_command_ref($command_ref)
+    };
 }
 
 #[macro_export]
@@ -81,6 +143,17 @@ macro_rules! echo_req {
             EncodeValue::Value(&($data as u32)),
         )
     };
+    ($endpoint:literal, $data:literal, $command_ref:literal) => {
+        CmdData::new(
+            CmdPath::new(
+                Some($endpoint),
+                Some(echo_cluster::ID),
+                Some(echo_cluster::Commands::EchoReq as u16),
+            ),
+            EncodeValue::Value(&($data as u32)),
+        )
+        .set_command_ref($command_ref)
+    };
 }
 
 #[macro_export]
@@ -93,6 +166,18 @@ macro_rules! echo_resp {
                 Some(echo_cluster::Commands::EchoResp as u16),
             ),
             $data,
+            None,
+        )
+    };
+    ($endpoint:literal, $data:literal, $command_ref:literal) => {
+        ExpectedInvResp::Cmd(
+            CmdPath::new(
+                Some($endpoint),
+                Some(echo_cluster::ID),
+                Some(echo_cluster::Commands::EchoResp as u16),
+            ),
+            $data,
+            Some($command_ref),
         )
     };
 }
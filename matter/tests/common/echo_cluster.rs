@@ -26,7 +26,7 @@ use matter::{
     interaction_model::{
         command::CommandReq,
         core::IMStatusCode,
-        messages::ib::{self, attr_list_write, ListOperation},
+        messages::ib::{self, ListOperation},
     },
     tlv::{TLVElement, TLVWriter, TagType, ToTLV},
 };
@@ -114,16 +114,15 @@ impl ClusterType for EchoCluster {
         }
     }
 
-    fn write_attribute(
+    fn write_list_attribute(
         &mut self,
+        op: &ListOperation,
         attr: &AttrDetails,
         data: &TLVElement,
     ) -> Result<(), IMStatusCode> {
         match num::FromPrimitive::from_u16(attr.attr_id) {
-            Some(Attributes::AttWriteList) => {
-                attr_list_write(attr, data, |op, data| self.write_attr_list(&op, data))
-            }
-            _ => self.base.write_attribute_from_tlv(attr.attr_id, data),
+            Some(Attributes::AttWriteList) => self.write_attr_list(op, data),
+            _ => Err(IMStatusCode::UnsupportedWrite),
         }
     }
 
@@ -150,10 +149,10 @@ impl ClusterType for EchoCluster {
                     let _ = t.end_container();
                 };
 
-                let invoke_resp = ib::InvResp::Cmd(ib::CmdData::new(
-                    echo_response,
-                    EncodeValue::Closure(&cmd_data),
-                ));
+                let invoke_resp = ib::InvResp::Cmd(ib::CmdData {
+                    command_ref: cmd_req.command_ref,
+                    ..ib::CmdData::new(echo_response, EncodeValue::Closure(&cmd_data))
+                });
                 let _ = invoke_resp.to_tlv(cmd_req.resp, TagType::Anonymous);
                 cmd_req.trans.complete();
             }
@@ -199,7 +198,7 @@ impl EchoCluster {
             Attributes::AttWriteList as u16,
             AttrValue::Custom,
             Access::WRITE | Access::NEED_ADMIN,
-            Quality::NONE,
+            Quality::LIST,
         ))?;
         Ok(c)
     }
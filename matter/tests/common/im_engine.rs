@@ -15,28 +15,30 @@
  *    limitations under the License.
  */
 
-use crate::common::echo_cluster;
-use boxslab::Slab;
+use crate::common::{echo_cluster, storage::temp_fabric_mgr};
+use async_channel::Receiver;
 use matter::{
     acl::{AclEntry, AclMgr, AuthMode},
     data_model::{
         cluster_basic_information::BasicInfoConfig,
-        core::DataModel,
+        core::{DataModel, DataModelConfig},
         device_types::device_type_add_on_off_light,
         objects::Privilege,
         sdm::dev_att::{DataType, DevAttDataFetcher},
     },
     error::Error,
     fabric::FabricMgr,
-    interaction_model::{core::OpCode, InteractionModel},
+    interaction_model::{
+        command::MAX_PATHS_PER_INVOKE, core::OpCode, InteractionModel, SubsReportReq,
+    },
     secure_channel::pake::PaseMgr,
     tlv::{TLVWriter, TagType, ToTLV},
     transport::packet::Packet,
     transport::{
         exchange::{self, Exchange, ExchangeCtx},
         network::Address,
-        packet::PacketPool,
         proto_demux::ProtoCtx,
+        queue::{Msg, WorkQ},
         session::{CloneData, NocCatIds, SessionMgr, SessionMode},
     },
     transport::{proto_demux::HandleProto, session::CaseDetails},
@@ -46,6 +48,7 @@ use std::{
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
 };
+use tempfile::TempDir;
 
 pub struct DummyDevAtt {}
 impl DevAttDataFetcher for DummyDevAtt {
@@ -58,11 +61,19 @@ impl DevAttDataFetcher for DummyDevAtt {
 pub struct ImEngine {
     pub dm: DataModel,
     pub acl_mgr: Arc<AclMgr>,
+    pub fabric_mgr: Arc<FabricMgr>,
     pub im: Box<InteractionModel>,
+    // What the data model hands over to the transport
+    pub rx_q: Receiver<Msg>,
+    // The storage of the fabrics, if the engine created them
+    _storage: Option<TempDir>,
     // By default, a new exchange is created for every run, if you wish to instead using a specific
     // exchange, set this variable. This is helpful in situations where you have to run multiple
     // actions in the same transaction (exchange)
     pub exch: Option<Exchange>,
+    // Likewise, a new session is created for every run, unless this is set. The session
+    // data, like that of a CSRRequest, only lasts as long as the session.
+    pub sess: Option<(SessionMgr, usize)>,
 }
 
 pub struct ImInput<'a> {
@@ -95,6 +106,14 @@ impl<'a> ImInput<'a> {
 impl ImEngine {
     /// Create the interaction model engine
     pub fn new() -> Self {
+        let (fabric_mgr, dir) = temp_fabric_mgr();
+        let mut engine = Self::new_with_fabric_mgr(fabric_mgr);
+        engine._storage = Some(dir);
+        engine
+    }
+
+    /// Create the interaction model engine, with the fabrics of this fabric manager
+    pub fn new_with_fabric_mgr(fabric_mgr: Arc<FabricMgr>) -> Self {
         let dev_det = BasicInfoConfig {
             vid: 10,
             pid: 11,
@@ -106,15 +125,24 @@ impl ImEngine {
         };
 
         let dev_att = Box::new(DummyDevAtt {});
-        let fabric_mgr = Arc::new(FabricMgr::new().unwrap());
-        let acl_mgr = Arc::new(AclMgr::new_with(false).unwrap());
-        let pase_mgr = PaseMgr::new();
+        let (wq, rx_q) = WorkQ::new();
+        let acl_mgr = Arc::new(AclMgr::new());
+        let pase_mgr = PaseMgr::new(wq.clone());
         acl_mgr.erase_all();
         let mut default_acl = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
         // Only allow the standard peer node id of the IM Engine
         default_acl.add_subject(IM_ENGINE_PEER_ID).unwrap();
         acl_mgr.add(default_acl).unwrap();
-        let dm = DataModel::new(dev_det, dev_att, fabric_mgr, acl_mgr.clone(), pase_mgr).unwrap();
+        let dm = DataModel::new(
+            dev_det,
+            dev_att,
+            fabric_mgr.clone(),
+            acl_mgr.clone(),
+            pase_mgr,
+            DataModelConfig::default(),
+            wq,
+        )
+        .unwrap();
 
         {
             let mut d = dm.node.write().unwrap();
@@ -125,40 +153,55 @@ impl ImEngine {
                 .unwrap();
         }
 
-        let im = Box::new(InteractionModel::new(Box::new(dm.clone())));
+        let im = Box::new(InteractionModel::new(
+            Box::new(dm.clone()),
+            MAX_PATHS_PER_INVOKE,
+        ));
 
         Self {
             dm,
             acl_mgr,
+            fabric_mgr,
             im,
+            rx_q,
+            _storage: None,
             exch: None,
+            sess: None,
         }
     }
 
-    /// Run a transaction through the interaction model engine
-    pub fn process<'a>(&mut self, input: &ImInput, data_out: &'a mut [u8]) -> (u8, &'a mut [u8]) {
-        let mut new_exch = Exchange::new(1, 0, exchange::Role::Responder);
-        // Choose whether to use a new exchange, or use the one from the ImEngine configuration
-        let exch = self.exch.as_mut().unwrap_or(&mut new_exch);
-
+    /// The session with the peer, on which the transactions are run
+    pub fn new_sess_mgr(peer_id: u64, cat_ids: &NocCatIds) -> (SessionMgr, usize) {
         let mut sess_mgr: SessionMgr = Default::default();
 
         let clone_data = CloneData::new(
             123456,
-            input.peer_id,
+            peer_id,
             10,
             30,
             Address::Udp(SocketAddr::new(
                 std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
                 5542,
             )),
-            SessionMode::Case(CaseDetails::new(1, &input.cat_ids)),
+            SessionMode::Case(CaseDetails::new(1, cat_ids)),
         );
         let sess_idx = sess_mgr.clone_session(&clone_data).unwrap();
-        let sess = sess_mgr.get_session_handle(sess_idx);
+        (sess_mgr, sess_idx)
+    }
+
+    /// Run a transaction through the interaction model engine
+    pub fn process<'a>(&mut self, input: &ImInput, data_out: &'a mut [u8]) -> (u8, &'a mut [u8]) {
+        let mut new_exch = Exchange::new(1, 0, exchange::Role::Responder);
+        // Choose whether to use a new exchange, or use the one from the ImEngine configuration
+        let exch = self.exch.as_mut().unwrap_or(&mut new_exch);
+
+        let mut new_sess = Self::new_sess_mgr(input.peer_id, &input.cat_ids);
+        let (sess_mgr, sess_idx) = self.sess.as_mut().unwrap_or(&mut new_sess);
+        let pool = sess_mgr.packet_pool().clone();
+        let sess = sess_mgr.get_session_handle(*sess_idx);
         let exch_ctx = ExchangeCtx { exch, sess };
-        let mut rx = Slab::<PacketPool>::try_new(Packet::new_rx().unwrap()).unwrap();
-        let tx = Slab::<PacketPool>::try_new(Packet::new_tx().unwrap()).unwrap();
+        let mut rx = Box::new(Packet::new_rx(&pool).unwrap());
+        let tx = Box::new(Packet::new_tx(&pool).unwrap());
         // Create fake rx packet
         rx.set_proto_id(0x01);
         rx.set_proto_opcode(input.action as u8);
@@ -186,6 +229,24 @@ impl ImEngine {
         let response = ctx.tx.get_proto_opcode();
         (response, &mut data_out[..out_data_len])
     }
+
+    /// Start a report on a subscription, as the transport does once the report is due
+    pub fn report<'a>(&mut self, subs_id: u32, data_out: &'a mut [u8]) -> (u8, &'a mut [u8]) {
+        let mut new_exch = Exchange::new(1, 0, exchange::Role::Initiator);
+        let exch = self.exch.as_mut().unwrap_or(&mut new_exch);
+
+        let (mut sess_mgr, sess_idx) = Self::new_sess_mgr(IM_ENGINE_PEER_ID, &Default::default());
+        let mut tx = Packet::new_tx(sess_mgr.packet_pool()).unwrap();
+        let sess = sess_mgr.get_session_handle(sess_idx);
+        let mut exch_ctx = ExchangeCtx { exch, sess };
+
+        self.im
+            .initiate(Box::new(SubsReportReq { subs_id }), &mut exch_ctx, &mut tx)
+            .unwrap();
+        let out_data_len = tx.as_borrow_slice().len();
+        data_out[..out_data_len].copy_from_slice(tx.as_borrow_slice());
+        (tx.get_proto_opcode(), &mut data_out[..out_data_len])
+    }
 }
 
 // Create an Interaction Model, Data Model and run a rx/tx transaction through it
@@ -19,3 +19,4 @@ pub mod attributes;
 pub mod commands;
 pub mod echo_cluster;
 pub mod im_engine;
+pub mod storage;
@@ -0,0 +1,36 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use std::sync::{Arc, Mutex};
+
+use matter::{fabric::FabricMgr, sys::Psm};
+use tempfile::TempDir;
+
+/// A FabricMgr, with storage in a directory of its own, so that the tests don't see each
+/// other's fabrics
+///
+/// The directory is removed once the returned [TempDir] is dropped, so it has to outlive
+/// the FabricMgr.
+pub fn temp_fabric_mgr() -> (Arc<FabricMgr>, TempDir) {
+    let dir = tempfile::Builder::new()
+        .prefix("matter-test-")
+        .tempdir()
+        .unwrap();
+    let psm = Psm::new(dir.path().to_str().unwrap()).unwrap();
+    let fabric_mgr = FabricMgr::new_with_psm(Arc::new(Mutex::new(psm))).unwrap();
+    (Arc::new(fabric_mgr), dir)
+}
@@ -29,8 +29,11 @@ use matter::{
         },
         messages::{msg, GenericPath},
     },
-    tlv::{self, ElementType, FromTLV, TLVArray, TLVElement, TLVWriter, TagType},
-    transport::session::NocCatIds,
+    tlv::{self, ElementType, FromTLV, Nullable, TLVArray, TLVElement, TLVWriter, TagType},
+    transport::{
+        exchange::{self, Exchange},
+        session::NocCatIds,
+    },
 };
 
 use crate::{
@@ -82,10 +85,19 @@ fn handle_write_reqs(
     input: &[AttrData],
     expected: &[AttrStatus],
 ) {
-    let mut out_buf = [0u8; 400];
     let write_req = WriteReq::new(false, input);
+    handle_write_req_msg(im, peer_node_id, peer_cat_ids, &write_req, expected);
+}
 
-    let mut input = ImInput::new(OpCode::WriteRequest, &write_req);
+fn handle_write_req_msg(
+    im: &mut ImEngine,
+    peer_node_id: u64,
+    peer_cat_ids: Option<&NocCatIds>,
+    write_req: &WriteReq,
+    expected: &[AttrStatus],
+) {
+    let mut out_buf = [0u8; 400];
+    let mut input = ImInput::new(OpCode::WriteRequest, write_req);
     input.set_peer_node_id(peer_node_id);
     if let Some(cat_ids) = peer_cat_ids {
         input.set_cat_ids(cat_ids);
@@ -734,3 +746,152 @@ fn test_write_data_ver() {
 
     assert_eq!(initial_data_ver + 1, new_data_ver);
 }
+
+fn read_acl_data_ver(im: &ImEngine) -> u32 {
+    let node = im.dm.node.read().unwrap();
+    let acl = node.get_cluster(0, access_control::ID).unwrap();
+
+    acl.base().get_dataver()
+}
+
+#[test]
+/// A list replace followed by an append in the same write request
+/// - the append isn't checked against the data version again
+/// - only the entries of the accessing fabric are replaced
+/// - an append on its own is checked against the data version
+fn write_acl_replace_and_append() {
+    let _ = env_logger::try_init();
+    let peer = 98765;
+    let mut im = ImEngine::new();
+
+    // An entry of some other fabric, which must survive the replace
+    let mut other_fabric = AclEntry::new(2, Privilege::ADMIN, AuthMode::Case);
+    other_fabric.add_subject(peer).unwrap();
+    im.acl_mgr.add(other_fabric).unwrap();
+
+    let mut admin = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
+    admin.add_subject(peer).unwrap();
+    im.acl_mgr.add(admin).unwrap();
+
+    let mut view = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+    view.add_subject(peer + 1).unwrap();
+
+    let acl_att = GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        Some(access_control::Attributes::Acl as u32),
+    );
+    let mut append_path = AttrPath::new(&acl_att);
+    append_path.list_index = Some(Nullable::Null);
+    let data_ver = read_acl_data_ver(&im);
+
+    // Test 1: Replace the list, and append to it
+    let replace_list = [admin];
+    let input = &[
+        AttrData::new(
+            Some(data_ver),
+            AttrPath::new(&acl_att),
+            EncodeValue::Value(&replace_list),
+        ),
+        AttrData::new(Some(data_ver), append_path, EncodeValue::Value(&view)),
+    ];
+    handle_write_reqs(
+        &mut im,
+        peer,
+        None,
+        input,
+        &[
+            AttrStatus::new(&acl_att, IMStatusCode::Success, 0),
+            AttrStatus::new(&acl_att, IMStatusCode::Success, 0),
+        ],
+    );
+
+    let mut entries = Vec::new();
+    im.acl_mgr.for_each_acl(|e| entries.push(*e)).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert!(entries.contains(&other_fabric));
+    assert!(entries.contains(&admin));
+    assert!(entries.contains(&view));
+
+    // Test 2: An append on its own, with the stale data version
+    let input = &[AttrData::new(
+        Some(data_ver),
+        append_path,
+        EncodeValue::Value(&view),
+    )];
+    handle_write_reqs(
+        &mut im,
+        peer,
+        None,
+        input,
+        &[AttrStatus::new(
+            &acl_att,
+            IMStatusCode::DataVersionMismatch,
+            0,
+        )],
+    );
+}
+
+#[test]
+/// A list replace in one chunk of a write request, followed by an append in the next
+/// - the exchange stays on between the chunks
+/// - the append continues the replace, as if they were in the same message
+fn write_acl_chunked() {
+    let _ = env_logger::try_init();
+    let peer = 98765;
+    let mut im = ImEngine::new();
+    // Use the same exchange for all the chunks
+    im.exch = Some(Exchange::new(1, 0, exchange::Role::Responder));
+
+    let mut admin = AclEntry::new(1, Privilege::ADMIN, AuthMode::Case);
+    admin.add_subject(peer).unwrap();
+    im.acl_mgr.add(admin).unwrap();
+
+    let mut view = AclEntry::new(1, Privilege::VIEW, AuthMode::Case);
+    view.add_subject(peer + 1).unwrap();
+
+    let acl_att = GenericPath::new(
+        Some(0),
+        Some(access_control::ID),
+        Some(access_control::Attributes::Acl as u32),
+    );
+    let mut append_path = AttrPath::new(&acl_att);
+    append_path.list_index = Some(Nullable::Null);
+    let data_ver = read_acl_data_ver(&im);
+
+    // Chunk 1: Replace the list
+    let replace_list = [admin];
+    let input = [AttrData::new(
+        Some(data_ver),
+        AttrPath::new(&acl_att),
+        EncodeValue::Value(&replace_list),
+    )];
+    let write_req = WriteReq::new(false, &input).set_more_chunked();
+    handle_write_req_msg(
+        &mut im,
+        peer,
+        None,
+        &write_req,
+        &[AttrStatus::new(&acl_att, IMStatusCode::Success, 0)],
+    );
+    assert!(!im.exch.as_ref().unwrap().is_data_none());
+
+    // Chunk 2: Append to it
+    let input = [AttrData::new(
+        Some(data_ver),
+        append_path,
+        EncodeValue::Value(&view),
+    )];
+    let write_req = WriteReq::new(false, &input);
+    handle_write_req_msg(
+        &mut im,
+        peer,
+        None,
+        &write_req,
+        &[AttrStatus::new(&acl_att, IMStatusCode::Success, 0)],
+    );
+
+    let mut entries = Vec::new();
+    im.acl_mgr.for_each_acl(|e| entries.push(*e)).unwrap();
+    assert_eq!(entries, [admin, view]);
+}
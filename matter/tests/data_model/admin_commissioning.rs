@@ -0,0 +1,230 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use matter::{
+    data_model::{
+        objects::EncodeValue,
+        sdm::admin_commissioning::{
+            self as adm_comm, OpenBasicCommWindowReq, OpenCommWindowReq, StatusCode, WindowStatus,
+        },
+    },
+    error::Error,
+    interaction_model::{
+        core::{IMStatusCode, OpCode},
+        messages::{
+            ib::{AttrPath, AttrResp, CmdPath, CmdStatus},
+            msg::{ReadReq, ReportDataMsg},
+            GenericPath,
+        },
+    },
+    tlv::{self, FromTLV, OctetStr, TLVWriter, TagType, ToTLV},
+};
+
+use crate::common::{
+    commands::invoke_status,
+    im_engine::{ImEngine, ImInput},
+};
+
+// The RevokeCommissioning command has no fields
+#[derive(ToTLV)]
+struct RevokeCommReq {}
+
+fn cmd_path(cmd: adm_comm::Commands) -> CmdPath {
+    CmdPath::new(Some(0), Some(adm_comm::ID), Some(cmd as u16))
+}
+
+fn failure(path: CmdPath, status: StatusCode) -> CmdStatus {
+    CmdStatus::new(path, IMStatusCode::Failure, status as u16)
+}
+
+fn read_window_status(engine: &mut ImEngine) -> u8 {
+    let path = GenericPath::new(
+        Some(0),
+        Some(adm_comm::ID),
+        Some(adm_comm::Attributes::WindowStatus as u32),
+    );
+    let input = [AttrPath::new(&path)];
+    let read_req = ReadReq::new(true).set_attr_requests(&input);
+    let mut out_buf = [0u8; 400];
+    let (_, out_buf) = engine.process(&ImInput::new(OpCode::ReadRequest, &read_req), &mut out_buf);
+    let root = tlv::get_root_node_struct(out_buf).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    let data = report_data.attr_reports.unwrap().iter().next().unwrap();
+    match data {
+        AttrResp::Data(d) => match d.data {
+            EncodeValue::Tlv(t) => t.u8().unwrap(),
+            _ => panic!("Incorrect data type"),
+        },
+        _ => panic!("Invalid response, expected AttrResp::Data"),
+    }
+}
+
+#[test]
+fn test_revoke_comm_no_window() {
+    // Revoking, when no window is open, fails with WindowNotOpen
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+
+    assert_eq!(
+        read_window_status(&mut engine),
+        WindowStatus::WindowNotOpen as u8
+    );
+    let revoke = cmd_path(adm_comm::Commands::RevokeComm);
+    let status = invoke_status(&mut engine, revoke, &RevokeCommReq {}, false);
+    assert_eq!(status, failure(revoke, StatusCode::WindowNotOpen));
+}
+
+#[test]
+fn test_open_comm_window_invalid_params() {
+    // 4 Open Commissioning Window requests
+    // - timeout is too short - InvalidCommand
+    // - timeout is too long - InvalidCommand
+    // - iterations is too few - PAKEParameterError
+    // - salt is too short - PAKEParameterError
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let verifier = [0u8; 97];
+    let salt = [0u8; 16];
+    let req = |timeout, iterations, salt| OpenCommWindowReq {
+        timeout,
+        verifier: OctetStr(&verifier),
+        discriminator: 250,
+        iterations,
+        salt: OctetStr(salt),
+    };
+    let open_comm = cmd_path(adm_comm::Commands::OpenCommWindow);
+    let invalid_command = CmdStatus::new(open_comm, IMStatusCode::InvalidCommand, 0);
+
+    let status = invoke_status(&mut engine, open_comm, &req(10, 1000, &salt), true);
+    assert_eq!(status, invalid_command);
+    let status = invoke_status(&mut engine, open_comm, &req(1000, 1000, &salt), true);
+    assert_eq!(status, invalid_command);
+    let status = invoke_status(&mut engine, open_comm, &req(180, 10, &salt), true);
+    assert_eq!(status, failure(open_comm, StatusCode::PAKEParameterError));
+    let status = invoke_status(&mut engine, open_comm, &req(180, 1000, &salt[..8]), true);
+    assert_eq!(status, failure(open_comm, StatusCode::PAKEParameterError));
+
+    // None of them opened a window
+    assert_eq!(
+        read_window_status(&mut engine),
+        WindowStatus::WindowNotOpen as u8
+    );
+}
+
+#[test]
+fn test_open_basic_comm_window_invalid_timeout() {
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let open_basic = cmd_path(adm_comm::Commands::OpenBasicCommWindow);
+
+    let status = invoke_status(
+        &mut engine,
+        open_basic,
+        &OpenBasicCommWindowReq { timeout: 901 },
+        true,
+    );
+    assert_eq!(
+        status,
+        CmdStatus::new(open_basic, IMStatusCode::InvalidCommand, 0)
+    );
+}
+
+#[test]
+fn test_comm_window_lifecycle() {
+    // Open an enhanced window, another one is Busy, and revoke it
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let verifier = [0u8; 97];
+    let salt = [0u8; 16];
+    let req = OpenCommWindowReq {
+        timeout: 180,
+        verifier: OctetStr(&verifier),
+        discriminator: 250,
+        iterations: 1000,
+        salt: OctetStr(&salt),
+    };
+    let open_comm = cmd_path(adm_comm::Commands::OpenCommWindow);
+    let open_basic = cmd_path(adm_comm::Commands::OpenBasicCommWindow);
+
+    assert_eq!(
+        invoke_status(&mut engine, open_comm, &req, true),
+        CmdStatus::new(open_comm, IMStatusCode::Success, 0)
+    );
+    assert_eq!(
+        read_window_status(&mut engine),
+        WindowStatus::EnhancedWindowOpen as u8
+    );
+    assert_eq!(
+        invoke_status(&mut engine, open_comm, &req, true),
+        failure(open_comm, StatusCode::Busy)
+    );
+    assert_eq!(
+        invoke_status(
+            &mut engine,
+            open_basic,
+            &OpenBasicCommWindowReq { timeout: 180 },
+            true
+        ),
+        failure(open_basic, StatusCode::Busy)
+    );
+
+    let revoke = cmd_path(adm_comm::Commands::RevokeComm);
+    assert_eq!(
+        invoke_status(&mut engine, revoke, &RevokeCommReq {}, false),
+        CmdStatus::new(revoke, IMStatusCode::Success, 0)
+    );
+    assert_eq!(
+        read_window_status(&mut engine),
+        WindowStatus::WindowNotOpen as u8
+    );
+}
+
+#[test]
+fn test_open_comm_window_needs_timed_invoke_status() {
+    // Both the windows can only be opened through a timed invoke
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let verifier = [0u8; 97];
+    let salt = [0u8; 16];
+    let req = OpenCommWindowReq {
+        timeout: 180,
+        verifier: OctetStr(&verifier),
+        discriminator: 250,
+        iterations: 1000,
+        salt: OctetStr(&salt),
+    };
+    let open_comm = cmd_path(adm_comm::Commands::OpenCommWindow);
+    let open_basic = cmd_path(adm_comm::Commands::OpenBasicCommWindow);
+
+    assert_eq!(
+        invoke_status(&mut engine, open_comm, &req, false),
+        CmdStatus::new(open_comm, IMStatusCode::NeedsTimedInteraction, 0)
+    );
+    assert_eq!(
+        invoke_status(
+            &mut engine,
+            open_basic,
+            &OpenBasicCommWindowReq { timeout: 180 },
+            false
+        ),
+        CmdStatus::new(open_basic, IMStatusCode::NeedsTimedInteraction, 0)
+    );
+    assert_eq!(
+        read_window_status(&mut engine),
+        WindowStatus::WindowNotOpen as u8
+    );
+}
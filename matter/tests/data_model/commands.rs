@@ -24,11 +24,12 @@ use crate::{
 use matter::{
     data_model::{cluster_on_off, objects::EncodeValue},
     interaction_model::{
+        command::MAX_PATHS_PER_INVOKE,
         core::{IMStatusCode, OpCode},
         messages::{
             ib::{CmdData, CmdPath, CmdStatus},
             msg,
-            msg::InvReq,
+            msg::{InvReq, StatusResp},
         },
     },
     tlv::{self, FromTLV, TLVArray},
@@ -50,6 +51,22 @@ fn handle_commands(input: &[CmdData], expected: &[ExpectedInvResp]) {
     assert_inv_response(&resp, expected)
 }
 
+// Helper for handling Invoke Command sequences that are rejected as a whole
+fn handle_invalid_commands(input: &[CmdData]) {
+    let mut out_buf = [0u8; 400];
+    let req = InvReq {
+        suppress_response: Some(false),
+        timed_request: Some(false),
+        inv_requests: Some(TLVArray::Slice(input)),
+    };
+
+    let (_, out_code, out_buf) = im_engine(OpCode::InvokeRequest, &req, &mut out_buf);
+    assert_eq!(out_code, OpCode::StatusResponse as u8);
+    let root = tlv::get_root_node_struct(out_buf).unwrap();
+    let status_resp = StatusResp::from_tlv(&root).unwrap();
+    assert_eq!(status_resp.status, IMStatusCode::InvalidAction);
+}
+
 #[test]
 fn test_invoke_cmds_success() {
     // 2 echo Requests
@@ -57,19 +74,68 @@ fn test_invoke_cmds_success() {
     // - another on endpoint 1 with data 10
     let _ = env_logger::try_init();
 
-    let input = &[echo_req!(0, 5), echo_req!(1, 10)];
-    let expected = &[echo_resp!(0, 10), echo_resp!(1, 30)];
+    let input = &[echo_req!(0, 5, 1), echo_req!(1, 10, 2)];
+    let expected = &[echo_resp!(0, 10, 1), echo_resp!(1, 30, 2)];
+    handle_commands(input, expected);
+}
+
+#[test]
+fn test_invoke_cmd_command_ref() {
+    // A single command may have a CommandRef too, and its response carries it back
+    let _ = env_logger::try_init();
+
+    let input = &[echo_req!(0, 5, 7)];
+    let expected = &[echo_resp!(0, 10, 7)];
+    handle_commands(input, expected);
+
+    let invalid_command = CmdPath::new(Some(0), Some(echo_cluster::ID), Some(0x1234));
+    let input = &[cmd_data!(invalid_command, 5, 7)];
+    let expected = &[ExpectedInvResp::Status(
+        CmdStatus::new(invalid_command, IMStatusCode::UnsupportedCommand, 0).set_command_ref(7),
+    )];
     handle_commands(input, expected);
 }
 
+#[test]
+fn test_invoke_cmds_invalid_batch() {
+    // A batch of commands is rejected as a whole, if
+    // - a command doesn't have a CommandRef
+    // - two commands have the same CommandRef
+    // - two commands have the same path
+    // - a command has a wildcard path
+    // - it has more commands than MaxPathsPerInvoke
+    let _ = env_logger::try_init();
+
+    handle_invalid_commands(&[echo_req!(0, 5, 1), echo_req!(1, 10)]);
+    handle_invalid_commands(&[echo_req!(0, 5, 1), echo_req!(1, 10, 1)]);
+    handle_invalid_commands(&[echo_req!(0, 5, 1), echo_req!(0, 10, 2)]);
+
+    let wc_path = CmdPath::new(
+        None,
+        Some(echo_cluster::ID),
+        Some(echo_cluster::Commands::EchoReq as u16),
+    );
+    handle_invalid_commands(&[echo_req!(0, 5, 1), cmd_data!(wc_path, 10, 2)]);
+
+    let paths: Vec<CmdPath> = (0..=MAX_PATHS_PER_INVOKE)
+        .map(|i| CmdPath::new(Some(0), Some(echo_cluster::ID), Some(i)))
+        .collect();
+    let input: Vec<CmdData> = paths
+        .iter()
+        .zip(1..)
+        .map(|(path, command_ref)| {
+            CmdData::new(*path, EncodeValue::Value(&5u32)).set_command_ref(command_ref)
+        })
+        .collect();
+    handle_invalid_commands(&input);
+}
+
 #[test]
 fn test_invoke_cmds_unsupported_fields() {
-    // 5 commands
+    // 3 commands
     // - endpoint doesn't exist - UnsupportedEndpoint
     // - cluster doesn't exist - UnsupportedCluster
-    // - cluster doesn't exist and endpoint is wildcard - UnsupportedCluster
     // - command doesn't exist - UnsupportedCommand
-    // - command doesn't exist and endpoint is wildcard - UnsupportedCommand
     let _ = env_logger::try_init();
 
     let invalid_endpoint = CmdPath::new(
@@ -82,41 +148,45 @@ fn test_invoke_cmds_unsupported_fields() {
         Some(0x1234),
         Some(echo_cluster::Commands::EchoReq as u16),
     );
-    let invalid_cluster_wc_endpoint = CmdPath::new(
-        None,
-        Some(0x1234),
-        Some(echo_cluster::Commands::EchoReq as u16),
-    );
     let invalid_command = CmdPath::new(Some(0), Some(echo_cluster::ID), Some(0x1234));
-    let invalid_command_wc_endpoint = CmdPath::new(None, Some(echo_cluster::ID), Some(0x1234));
     let input = &[
-        cmd_data!(invalid_endpoint, 5),
-        cmd_data!(invalid_cluster, 5),
-        cmd_data!(invalid_cluster_wc_endpoint, 5),
-        cmd_data!(invalid_command, 5),
-        cmd_data!(invalid_command_wc_endpoint, 5),
+        cmd_data!(invalid_endpoint, 5, 1),
+        cmd_data!(invalid_cluster, 5, 2),
+        cmd_data!(invalid_command, 5, 3),
     ];
 
     let expected = &[
-        ExpectedInvResp::Status(CmdStatus::new(
-            invalid_endpoint,
-            IMStatusCode::UnsupportedEndpoint,
-            0,
-        )),
-        ExpectedInvResp::Status(CmdStatus::new(
-            invalid_cluster,
-            IMStatusCode::UnsupportedCluster,
-            0,
-        )),
-        ExpectedInvResp::Status(CmdStatus::new(
-            invalid_command,
-            IMStatusCode::UnsupportedCommand,
-            0,
-        )),
+        ExpectedInvResp::Status(
+            CmdStatus::new(invalid_endpoint, IMStatusCode::UnsupportedEndpoint, 0)
+                .set_command_ref(1),
+        ),
+        ExpectedInvResp::Status(
+            CmdStatus::new(invalid_cluster, IMStatusCode::UnsupportedCluster, 0).set_command_ref(2),
+        ),
+        ExpectedInvResp::Status(
+            CmdStatus::new(invalid_command, IMStatusCode::UnsupportedCommand, 0).set_command_ref(3),
+        ),
     ];
     handle_commands(input, expected);
 }
 
+#[test]
+fn test_invoke_cmd_wc_endpoint_unsupported_fields() {
+    // 2 commands, each on its own, since a batch can't have wildcard paths
+    // - cluster doesn't exist and endpoint is wildcard - Silently ignore
+    // - command doesn't exist and endpoint is wildcard - Silently ignore
+    let _ = env_logger::try_init();
+
+    let invalid_cluster_wc_endpoint = CmdPath::new(
+        None,
+        Some(0x1234),
+        Some(echo_cluster::Commands::EchoReq as u16),
+    );
+    let invalid_command_wc_endpoint = CmdPath::new(None, Some(echo_cluster::ID), Some(0x1234));
+    handle_commands(&[cmd_data!(invalid_cluster_wc_endpoint, 5)], &[]);
+    handle_commands(&[cmd_data!(invalid_command_wc_endpoint, 5)], &[]);
+}
+
 #[test]
 fn test_invoke_cmd_wc_endpoint_all_have_clusters() {
     // 1 echo Request with wildcard endpoint
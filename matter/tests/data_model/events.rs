@@ -0,0 +1,148 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use matter::{
+    data_model::{cluster_basic_information as basic_info, objects::EventPriority},
+    interaction_model::{
+        core::{IMStatusCode, OpCode},
+        messages::GenericPath,
+        messages::{
+            ib::{EventFilter, EventPath, EventResp},
+            msg::{ReadReq, ReportDataMsg},
+        },
+    },
+    tlv::{self, FromTLV},
+};
+
+use crate::common::im_engine::{ImEngine, ImInput};
+
+// Read the events on the paths, starting from the event number event_min
+fn read_events<'a>(
+    engine: &mut ImEngine,
+    input: &[EventPath],
+    event_min: u64,
+    out_buf: &'a mut [u8],
+    f: impl FnMut(EventResp),
+) -> ReportDataMsg<'a> {
+    let filters = [EventFilter::new(event_min)];
+    let read_req = ReadReq::new(true)
+        .set_event_requests(input)
+        .set_event_filters(&filters);
+    let (out_code, out_buf) =
+        engine.process(&ImInput::new(OpCode::ReadRequest, &read_req), out_buf);
+    assert_eq!(out_code, OpCode::ReportData as u8);
+    tlv::print_tlv_list(out_buf);
+    let root = tlv::get_root_node_struct(out_buf).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    report_data.event_reports.unwrap().iter().for_each(f);
+    report_data
+}
+
+#[test]
+fn test_read_event_success() {
+    // Emit an event, and read it back
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let number = basic_info::emit_leave(&engine.dm.get_change_log(), 5).unwrap();
+
+    let leave = GenericPath::new(
+        Some(0),
+        Some(basic_info::ID),
+        Some(basic_info::Events::Leave as u32),
+    );
+    let mut out_buf = [0u8; 400];
+    let mut found = false;
+    let report_data = read_events(
+        &mut engine,
+        &[EventPath::new(&leave)],
+        number,
+        &mut out_buf,
+        |e| {
+            let d = match e {
+                EventResp::Data(d) => d,
+                _ => panic!("Invalid response, expected EventResp::Data"),
+            };
+            assert_eq!(d.path.to_gp(), leave);
+            assert_eq!(d.event_number, number);
+            assert_eq!(d.priority, EventPriority::Info as u8);
+            assert!(d.system_ts.is_some());
+            found = true;
+        },
+    );
+    assert!(found);
+    assert!(report_data.attr_reports.is_none());
+}
+
+#[test]
+fn test_read_event_filtered() {
+    // Events before the event_min of the filter are not reported
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let number = basic_info::emit_leave(&engine.dm.get_change_log(), 6).unwrap();
+
+    let wc_cluster = GenericPath::new(Some(0), Some(basic_info::ID), None);
+    let mut out_buf = [0u8; 400];
+    read_events(
+        &mut engine,
+        &[EventPath::new(&wc_cluster)],
+        number + 1,
+        &mut out_buf,
+        |e| {
+            let d = match e {
+                EventResp::Data(d) => d,
+                _ => panic!("Invalid response, expected EventResp::Data"),
+            };
+            assert!(d.event_number > number);
+        },
+    );
+}
+
+#[test]
+fn test_read_event_unsupported_fields() {
+    // 3 reads
+    // - endpoint doesn't exist - UnsupportedEndpoint
+    // - event doesn't exist - UnsupportedEvent
+    // - event doesn't exist and endpoint is wildcard - Silently ignore
+    let _ = env_logger::try_init();
+
+    let invalid_endpoint = GenericPath::new(
+        Some(2),
+        Some(basic_info::ID),
+        Some(basic_info::Events::Leave as u32),
+    );
+    let invalid_event = GenericPath::new(Some(0), Some(basic_info::ID), Some(0x1234));
+    let wc_endpoint_invalid_event = GenericPath::new(None, Some(basic_info::ID), Some(0x1234));
+    let input = [
+        EventPath::new(&invalid_endpoint),
+        EventPath::new(&invalid_event),
+        EventPath::new(&wc_endpoint_invalid_event),
+    ];
+
+    let mut out_buf = [0u8; 400];
+    let mut received = Vec::new();
+    read_events(&mut ImEngine::new(), &input, 0, &mut out_buf, |e| match e {
+        EventResp::Status(s) => received.push((s.path.to_gp(), s.status.status)),
+        EventResp::Data(_) => panic!("Invalid response, expected EventResp::Status"),
+    });
+    assert_eq!(
+        received,
+        [
+            (invalid_endpoint, IMStatusCode::UnsupportedEndpoint),
+            (invalid_event, IMStatusCode::UnsupportedEvent),
+        ]
+    );
+}
@@ -89,6 +89,8 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(0, 40, basic_info::Attributes::SwVer, dont_care),
         attr_data!(0, 40, basic_info::Attributes::SwVerString, dont_care),
         attr_data!(0, 40, basic_info::Attributes::SerialNo, dont_care),
+        attr_data!(0, 40, basic_info::Attributes::MaxPathsPerInvoke, dont_care),
+        attr_data!(0, 40, GlobalElements::EventList, dont_care),
         attr_data!(0, 48, GlobalElements::FeatureMap, dont_care),
         attr_data!(0, 48, GlobalElements::AttributeList, dont_care),
         attr_data!(0, 48, gen_comm::Attributes::BreadCrumb, dont_care),
@@ -120,17 +122,18 @@ fn wildcard_read_resp(part: u8) -> Vec<AttrResp<'static>> {
         attr_data!(0, 31, acl::Attributes::SubjectsPerEntry, dont_care),
         attr_data!(0, 31, acl::Attributes::TargetsPerEntry, dont_care),
         attr_data!(0, 31, acl::Attributes::EntriesPerFabric, dont_care),
+        attr_data!(0, 31, GlobalElements::EventList, dont_care),
         attr_data!(0, echo::ID, GlobalElements::FeatureMap, dont_care),
         attr_data!(0, echo::ID, GlobalElements::AttributeList, dont_care),
         attr_data!(0, echo::ID, echo::Attributes::Att1, dont_care),
         attr_data!(0, echo::ID, echo::Attributes::Att2, dont_care),
         attr_data!(0, echo::ID, echo::Attributes::AttCustom, dont_care),
         attr_data!(1, 29, GlobalElements::FeatureMap, dont_care),
-        attr_data!(1, 29, GlobalElements::AttributeList, dont_care),
-        attr_data!(1, 29, descriptor::Attributes::DeviceTypeList, dont_care),
     ];
 
     let part2 = vec![
+        attr_data!(1, 29, GlobalElements::AttributeList, dont_care),
+        attr_data!(1, 29, descriptor::Attributes::DeviceTypeList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::ServerList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::PartsList, dont_care),
         attr_data!(1, 29, descriptor::Attributes::ClientList, dont_care),
@@ -195,6 +198,7 @@ fn test_long_read_subscription_success() {
     let read_all = [AttrPath::new(&wc_path)];
     let subs_req = SubscribeReq::new(true, 1, 20).set_attr_requests(&read_all);
     let expected_part1 = wildcard_read_resp(1);
+    let expected_part2 = wildcard_read_resp(2);
     let (out_code, out_data) = lr.process(OpCode::SubscribeRequest, &subs_req, &mut output);
     let root = tlv::get_root_node_struct(out_data).unwrap();
     let report_data = ReportDataMsg::from_tlv(&root).unwrap();
@@ -206,7 +210,6 @@ fn test_long_read_subscription_success() {
     let status_report = StatusResp {
         status: IMStatusCode::Success,
     };
-    let expected_part2 = wildcard_read_resp(2);
     let (out_code, out_data) = lr.process(OpCode::StatusResponse, &status_report, &mut output);
     let root = tlv::get_root_node_struct(out_data).unwrap();
     let report_data = ReportDataMsg::from_tlv(&root).unwrap();
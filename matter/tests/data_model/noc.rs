@@ -0,0 +1,455 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use matter::{
+    cert::{Cert, CertBuilder, CertIssuer},
+    crypto::{CryptoKeyPair, KeyPair, EC_POINT_LEN_BYTES},
+    data_model::{
+        objects::{AttrValue, EncodeValue},
+        sdm::{general_commissioning as gen_comm, noc},
+    },
+    error::Error,
+    fabric::Fabric,
+    interaction_model::{
+        core::IMStatusCode,
+        messages::ib::{CmdPath, CmdStatus, InvResp},
+    },
+    tlv::{self, OctetStr, TLVWriter, TagType, ToTLV, UtfStr},
+    transport::queue::Msg,
+};
+use tempfile::TempDir;
+
+use crate::common::{
+    commands::invoke,
+    im_engine::{ImEngine, IM_ENGINE_PEER_ID},
+    storage::temp_fabric_mgr,
+};
+
+// The fabric id, and the node id of the device on it
+const FABRIC_ID: u64 = 5;
+const NODE_ID: u64 = 0x1122;
+// The local session id of the ImEngine's session
+const LOCAL_SESS_ID: u16 = 30;
+
+#[derive(ToTLV)]
+struct ArmFailSafeReq {
+    expiry_len: u16,
+    bread_crumb: u64,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct UpdateNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddNocReq<'a> {
+    noc_value: OctetStr<'a>,
+    icac_value: Option<OctetStr<'a>>,
+    ipk_value: OctetStr<'a>,
+    case_admin_subject: u64,
+    vendor_id: u16,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct AddTrustedRootReq<'a> {
+    root_ca_value: OctetStr<'a>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct CsrReq<'a> {
+    nonce: OctetStr<'a>,
+    for_update_noc: Option<bool>,
+}
+
+#[derive(ToTLV)]
+#[tlvargs(lifetime = "'a")]
+struct UpdateFabricLabelReq<'a> {
+    label: UtfStr<'a>,
+}
+
+fn noc_path(cmd: noc::Commands) -> CmdPath {
+    CmdPath::new(Some(0), Some(noc::ID), Some(cmd as u16))
+}
+
+fn assert_status(resp: InvResp, path: CmdPath, status: IMStatusCode) {
+    match resp {
+        InvResp::Status(s) => assert_eq!(s, CmdStatus::new(path, status, 0)),
+        _ => panic!("Invalid response, expected InvResp::Status"),
+    }
+}
+
+fn arm_failsafe(engine: &mut ImEngine) {
+    arm_failsafe_for(engine, 60);
+}
+
+// An expiry_len of 0 expires the fail-safe
+fn arm_failsafe_for(engine: &mut ImEngine, expiry_len: u16) {
+    arm_failsafe_with(engine, expiry_len, 0);
+}
+
+fn arm_failsafe_with(engine: &mut ImEngine, expiry_len: u16, bread_crumb: u64) {
+    let path = CmdPath::new(
+        Some(0),
+        Some(gen_comm::ID),
+        Some(gen_comm::Commands::ArmFailsafe as u16),
+    );
+    let req = ArmFailSafeReq {
+        expiry_len,
+        bread_crumb,
+    };
+    invoke(engine, path, &req, false, |resp| match resp {
+        InvResp::Cmd(c) => match c.data {
+            // ErrorCode OK
+            EncodeValue::Tlv(t) => assert_eq!(t.find_tag(0).unwrap().u8().unwrap(), 0),
+            _ => panic!("Incorrect CmdDataType"),
+        },
+        _ => panic!("Invalid response, expected InvResp::Cmd"),
+    });
+}
+
+fn bread_crumb(engine: &ImEngine) -> u64 {
+    let node = engine.dm.node.read().unwrap();
+    let cluster = node.get_cluster(0, gen_comm::ID).unwrap();
+    match cluster
+        .base()
+        .read_attribute_raw(gen_comm::Attributes::BreadCrumb as u16)
+    {
+        Ok(AttrValue::Uint64(bread_crumb)) => *bread_crumb,
+        _ => panic!("Invalid BreadCrumb"),
+    }
+}
+
+#[test]
+fn test_failsafe_bread_crumb() {
+    // The BreadCrumb is set by the ArmFailSafe, and goes back to 0 on the expiry
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    assert_eq!(bread_crumb(&engine), 0);
+    arm_failsafe_with(&mut engine, 60, 0x1_0000_0005);
+    assert_eq!(bread_crumb(&engine), 0x1_0000_0005);
+    arm_failsafe_for(&mut engine, 0);
+    assert_eq!(bread_crumb(&engine), 0);
+}
+
+#[test]
+fn test_update_noc_failsafe_required() {
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let path = noc_path(noc::Commands::UpdateNOC);
+    let req = UpdateNocReq {
+        noc_value: OctetStr(&[0x15, 0x18]),
+        icac_value: None,
+    };
+    invoke(&mut engine, path, &req, false, |resp| {
+        assert_status(resp, path, IMStatusCode::FailSafeRequired)
+    });
+}
+
+#[test]
+fn test_update_noc_missing_csr() {
+    // Under an armed fail-safe, but without a CSRRequest before it
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    arm_failsafe(&mut engine);
+
+    let path = noc_path(noc::Commands::UpdateNOC);
+    let req = UpdateNocReq {
+        noc_value: OctetStr(&[0x15, 0x18]),
+        icac_value: None,
+    };
+    invoke(&mut engine, path, &req, false, |resp| match resp {
+        InvResp::Cmd(c) => {
+            assert_eq!(c.path, noc_path(noc::Commands::NOCResp));
+            match c.data {
+                EncodeValue::Tlv(t) => {
+                    // StatusCode MissingCsr, on the fabric of the session
+                    assert_eq!(t.find_tag(0).unwrap().u8().unwrap(), 4);
+                    assert_eq!(t.find_tag(1).unwrap().u8().unwrap(), 1);
+                }
+                _ => panic!("Incorrect CmdDataType"),
+            }
+        }
+        _ => panic!("Invalid response, expected InvResp::Cmd"),
+    });
+}
+
+#[test]
+fn test_update_fabric_label_too_long() {
+    let _ = env_logger::try_init();
+    let mut engine = ImEngine::new();
+    let path = noc_path(noc::Commands::UpdateFabricLabel);
+    let req = UpdateFabricLabelReq {
+        label: UtfStr(&[b'a'; 33]),
+    };
+    invoke(&mut engine, path, &req, false, |resp| {
+        assert_status(resp, path, IMStatusCode::ConstraintError)
+    });
+}
+
+fn pub_key(key_pair: &KeyPair) -> Vec<u8> {
+    let mut pub_key = [0u8; EC_POINT_LEN_BYTES];
+    let len = key_pair.get_public_key(&mut pub_key).unwrap();
+    pub_key[..len].to_vec()
+}
+
+fn cert_tlv(cert: &Cert) -> Vec<u8> {
+    let mut buf = [0u8; 1024];
+    let len = cert.as_tlv(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+// An ImEngine with storage of its own, and a fabric at the index of its session, with
+// the NOC from this root CA. The session lasts across the invokes.
+fn engine_on_fabric(root: &CertIssuer) -> (ImEngine, TempDir) {
+    let (fabric_mgr, dir) = temp_fabric_mgr();
+
+    let key_pair = KeyPair::new().unwrap();
+    let noc = root
+        .issue(CertBuilder::noc(NODE_ID), &pub_key(&key_pair))
+        .unwrap();
+    let root_ca = Cert::new(&cert_tlv(root.get_cert())).unwrap();
+    let fabric = Fabric::new(key_pair, root_ca, None, noc, &[0u8; 16], 10).unwrap();
+    assert_eq!(fabric_mgr.add(fabric).unwrap(), 1);
+
+    let mut engine = ImEngine::new_with_fabric_mgr(fabric_mgr);
+    engine.sess = Some(ImEngine::new_sess_mgr(
+        IM_ENGINE_PEER_ID,
+        &Default::default(),
+    ));
+    (engine, dir)
+}
+
+fn noc_hash(engine: &ImEngine) -> Vec<u8> {
+    let fabric = engine.fabric_mgr.get_fabric(1).unwrap();
+    (*fabric).as_ref().unwrap().get_noc_hash().unwrap().to_vec()
+}
+
+// The public key in the CSR of the CSRResponse
+fn csr_request(engine: &mut ImEngine, for_update_noc: bool) -> Vec<u8> {
+    let path = noc_path(noc::Commands::CSRReq);
+    let req = CsrReq {
+        nonce: OctetStr(&[0xab; 32]),
+        for_update_noc: Some(for_update_noc),
+    };
+    let mut pub_key = Vec::new();
+    invoke(engine, path, &req, false, |resp| match resp {
+        InvResp::Cmd(c) => match c.data {
+            EncodeValue::Tlv(t) => {
+                let elements = t.find_tag(0).unwrap().slice().unwrap();
+                let elements = tlv::get_root_node_struct(elements).unwrap();
+                let csr = elements.find_tag(1).unwrap().slice().unwrap();
+                // The P-256 SubjectPublicKeyInfo, up to the uncompressed point
+                let spki: [u8; 26] = [
+                    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01,
+                    0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+                ];
+                let start = csr.windows(spki.len()).position(|w| w == spki).unwrap();
+                let start = start + spki.len();
+                pub_key = csr[start..start + EC_POINT_LEN_BYTES].to_vec();
+            }
+            _ => panic!("Incorrect CmdDataType"),
+        },
+        _ => panic!("Invalid response, expected InvResp::Cmd"),
+    });
+    pub_key
+}
+
+// Send the UpdateNOC, and return the StatusCode of its NOCResponse
+fn update_noc(engine: &mut ImEngine, noc: &Cert) -> u8 {
+    let path = noc_path(noc::Commands::UpdateNOC);
+    let noc = cert_tlv(noc);
+    let req = UpdateNocReq {
+        noc_value: OctetStr(&noc),
+        icac_value: None,
+    };
+    let mut status = 0;
+    invoke(engine, path, &req, false, |resp| match resp {
+        InvResp::Cmd(c) => match c.data {
+            EncodeValue::Tlv(t) => {
+                status = t.find_tag(0).unwrap().u8().unwrap();
+                assert_eq!(t.find_tag(1).unwrap().u8().unwrap(), 1);
+            }
+            _ => panic!("Incorrect CmdDataType"),
+        },
+        _ => panic!("Invalid response, expected InvResp::Cmd"),
+    });
+    status
+}
+
+// The NOCResponse StatusCodes
+const STATUS_OK: u8 = 0;
+const STATUS_INVALID_NOC: u8 = 3;
+
+#[test]
+fn test_update_noc() {
+    // The NOC is replaced, and the other sessions on the fabric are closed, except this
+    // one
+    let _ = env_logger::try_init();
+    let root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+    let prev_hash = noc_hash(&engine);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, true);
+    let noc = root.issue(CertBuilder::noc(NODE_ID), &pub_key).unwrap();
+    assert_eq!(update_noc(&mut engine, &noc), STATUS_OK);
+    assert_ne!(noc_hash(&engine), prev_hash);
+
+    let mut fabric_updated = false;
+    while let Ok(msg) = engine.rx_q.try_recv() {
+        fabric_updated |= matches!(msg, Msg::FabricUpdated(1, Some(LOCAL_SESS_ID)));
+    }
+    assert!(fabric_updated);
+}
+
+#[test]
+fn test_update_noc_other_root() {
+    // The NOC doesn't chain to the root of the fabric
+    let _ = env_logger::try_init();
+    let root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+    let prev_hash = noc_hash(&engine);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, true);
+    let other_root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let noc = other_root
+        .issue(CertBuilder::noc(NODE_ID), &pub_key)
+        .unwrap();
+    assert_eq!(update_noc(&mut engine, &noc), STATUS_INVALID_NOC);
+    assert_eq!(noc_hash(&engine), prev_hash);
+}
+
+#[test]
+fn test_update_noc_other_fabric_id() {
+    // The NOC chains to the root of the fabric, but is for another fabric id
+    let _ = env_logger::try_init();
+    let root_key = KeyPair::new().unwrap();
+    let mut priv_key = [0u8; 32];
+    let len = root_key.get_private_key(&mut priv_key).unwrap();
+    let same_key = KeyPair::new_from_components(&pub_key(&root_key), &priv_key[..len]).unwrap();
+    let root = CertIssuer::new_root(root_key, CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+    let prev_hash = noc_hash(&engine);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, true);
+    let other_fabric = CertIssuer::new_root(same_key, CertBuilder::rcac(1), FABRIC_ID + 1).unwrap();
+    let noc = other_fabric
+        .issue(CertBuilder::noc(NODE_ID), &pub_key)
+        .unwrap();
+    assert_eq!(update_noc(&mut engine, &noc), STATUS_INVALID_NOC);
+    assert_eq!(noc_hash(&engine), prev_hash);
+}
+
+#[test]
+fn test_update_noc_rollback() {
+    // The previous NOC is put back, once the fail-safe expires
+    let _ = env_logger::try_init();
+    let root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+    let prev_hash = noc_hash(&engine);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, true);
+    let noc = root.issue(CertBuilder::noc(NODE_ID), &pub_key).unwrap();
+    assert_eq!(update_noc(&mut engine, &noc), STATUS_OK);
+    assert_ne!(noc_hash(&engine), prev_hash);
+
+    arm_failsafe_for(&mut engine, 0);
+    assert_eq!(noc_hash(&engine), prev_hash);
+}
+
+#[test]
+fn test_update_noc_csr_not_for_update() {
+    // The CSR was requested for an AddNOC
+    let _ = env_logger::try_init();
+    let root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, false);
+    let noc = root.issue(CertBuilder::noc(NODE_ID), &pub_key).unwrap();
+    let path = noc_path(noc::Commands::UpdateNOC);
+    let noc = cert_tlv(&noc);
+    let req = UpdateNocReq {
+        noc_value: OctetStr(&noc),
+        icac_value: None,
+    };
+    invoke(&mut engine, path, &req, false, |resp| {
+        assert_status(resp, path, IMStatusCode::ConstraintError)
+    });
+}
+
+#[test]
+fn test_add_noc_other_root() {
+    // The NOC doesn't chain to the trusted root from AddTrustedRootCert
+    let _ = env_logger::try_init();
+    let root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(1), FABRIC_ID).unwrap();
+    let (mut engine, _dir) = engine_on_fabric(&root);
+
+    arm_failsafe(&mut engine);
+    let pub_key = csr_request(&mut engine, false);
+    let other_root =
+        CertIssuer::new_root(KeyPair::new().unwrap(), CertBuilder::rcac(2), FABRIC_ID + 1).unwrap();
+    let path = noc_path(noc::Commands::AddTrustedRootCert);
+    let root_ca = cert_tlv(other_root.get_cert());
+    let req = AddTrustedRootReq {
+        root_ca_value: OctetStr(&root_ca),
+    };
+    invoke(&mut engine, path, &req, false, |resp| {
+        assert_status(resp, path, IMStatusCode::Success)
+    });
+
+    let noc = root.issue(CertBuilder::noc(NODE_ID), &pub_key).unwrap();
+    let noc = cert_tlv(&noc);
+    let req = AddNocReq {
+        noc_value: OctetStr(&noc),
+        icac_value: None,
+        ipk_value: OctetStr(&[0u8; 16]),
+        case_admin_subject: IM_ENGINE_PEER_ID,
+        vendor_id: 10,
+    };
+    invoke(
+        &mut engine,
+        noc_path(noc::Commands::AddNOC),
+        &req,
+        false,
+        |resp| match resp {
+            InvResp::Cmd(c) => match c.data {
+                EncodeValue::Tlv(t) => {
+                    assert_eq!(t.find_tag(0).unwrap().u8().unwrap(), STATUS_INVALID_NOC)
+                }
+                _ => panic!("Incorrect CmdDataType"),
+            },
+            _ => panic!("Invalid response, expected InvResp::Cmd"),
+        },
+    );
+    assert!(engine.fabric_mgr.get_fabric(2).unwrap().is_none());
+}
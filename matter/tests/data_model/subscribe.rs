@@ -0,0 +1,143 @@
+/*
+ *
+ *    Copyright (c) 2020-2022 Project CHIP Authors
+ *
+ *    Licensed under the Apache License, Version 2.0 (the "License");
+ *    you may not use this file except in compliance with the License.
+ *    You may obtain a copy of the License at
+ *
+ *        http://www.apache.org/licenses/LICENSE-2.0
+ *
+ *    Unless required by applicable law or agreed to in writing, software
+ *    distributed under the License is distributed on an "AS IS" BASIS,
+ *    WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ *    See the License for the specific language governing permissions and
+ *    limitations under the License.
+ */
+
+use matter::{
+    data_model::{
+        cluster_on_off as onoff,
+        objects::{AttrValue, EncodeValue},
+    },
+    interaction_model::{
+        core::{IMStatusCode, OpCode},
+        messages::{
+            ib::{AttrData, AttrPath, AttrResp},
+            msg::{ReportDataMsg, StatusResp, SubscribeReq, SubscribeResp},
+            GenericPath,
+        },
+        InteractionConsumer,
+    },
+    tlv::{self, ElementType, FromTLV, TLVElement, TagType},
+    transport::{
+        exchange::{self, Exchange},
+        udp::MAX_RX_BUF_SIZE,
+    },
+};
+
+use crate::{
+    attr_data,
+    common::{
+        attributes::*,
+        im_engine::{ImEngine, ImInput},
+    },
+};
+
+const SUCCESS: StatusResp = StatusResp {
+    status: IMStatusCode::Success,
+};
+
+// Subscribe to the OnOff attribute, and return the subscription ID
+fn subscribe_on_off(im: &mut ImEngine) -> u32 {
+    let mut out_buf = [0u8; MAX_RX_BUF_SIZE];
+    let path = GenericPath::new(
+        Some(1),
+        Some(onoff::ID),
+        Some(onoff::Attributes::OnOff as u32),
+    );
+    let attr_paths = [AttrPath::new(&path)];
+    let subs_req = SubscribeReq::new(true, 0, 30).set_attr_requests(&attr_paths);
+
+    let input = ImInput::new(OpCode::SubscribeRequest, &subs_req);
+    let (out_code, out_data) = im.process(&input, &mut out_buf);
+    assert_eq!(out_code, OpCode::ReportData as u8);
+    let root = tlv::get_root_node_struct(out_data).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    assert_eq!(report_data.attr_reports.unwrap().iter().count(), 1);
+
+    let input = ImInput::new(OpCode::StatusResponse, &SUCCESS);
+    let (out_code, out_data) = im.process(&input, &mut out_buf);
+    assert_eq!(out_code, OpCode::SubscriptResponse as u8);
+    let root = tlv::get_root_node_struct(out_data).unwrap();
+    let subs_resp = SubscribeResp::from_tlv(&root).unwrap();
+    assert_eq!(subs_resp.max_int, 30);
+    subs_resp.subs_id
+}
+
+// Get a report on the subscription, check it against the expected attributes, and
+// acknowledge it
+fn report(im: &mut ImEngine, subs_id: u32, expected: &[AttrResp]) {
+    let mut out_buf = [0u8; MAX_RX_BUF_SIZE];
+    let (out_code, out_data) = im.report(subs_id, &mut out_buf);
+    assert_eq!(out_code, OpCode::ReportData as u8);
+    let root = tlv::get_root_node_struct(out_data).unwrap();
+    let report_data = ReportDataMsg::from_tlv(&root).unwrap();
+    assert_eq!(report_data.subscription_id, Some(subs_id));
+    // The report must be acknowledged
+    assert_eq!(report_data.suppress_response, None);
+    assert_attr_report(&report_data, expected);
+
+    let input = ImInput::new(OpCode::StatusResponse, &SUCCESS);
+    let (out_code, _) = im.process(&input, &mut out_buf);
+    assert_eq!(out_code, OpCode::Reserved as u8);
+}
+
+#[test]
+fn test_subscription_reports_changes() {
+    let _ = env_logger::try_init();
+    let mut im = ImEngine::new();
+    // Use the same exchange for all parts of the transaction
+    im.exch = Some(Exchange::new(1, 0, exchange::Role::Responder));
+    let subs_id = subscribe_on_off(&mut im);
+    assert!(im.dm.get_next_timeout().is_some());
+
+    // Nothing changed, this is an empty keep-alive report
+    report(&mut im, subs_id, &[]);
+
+    // Only what changed gets reported
+    {
+        let mut node = im.dm.node.write().unwrap();
+        let c = node.get_cluster_mut(1, onoff::ID).unwrap();
+        c.base_mut()
+            .write_attribute_raw(onoff::Attributes::OnOff as u16, AttrValue::Bool(true))
+            .unwrap();
+    }
+    let expected = [attr_data!(
+        1,
+        onoff::ID,
+        onoff::Attributes::OnOff,
+        ElementType::True
+    )];
+    report(&mut im, subs_id, &expected);
+    report(&mut im, subs_id, &[]);
+}
+
+#[test]
+fn test_subscription_ends_on_error_status() {
+    let _ = env_logger::try_init();
+    let mut im = ImEngine::new();
+    im.exch = Some(Exchange::new(1, 0, exchange::Role::Responder));
+    let subs_id = subscribe_on_off(&mut im);
+
+    let mut out_buf = [0u8; MAX_RX_BUF_SIZE];
+    im.report(subs_id, &mut out_buf);
+    let status = StatusResp {
+        status: IMStatusCode::InvalidSubscription,
+    };
+    let input = ImInput::new(OpCode::StatusResponse, &status);
+    let (out_code, _) = im.process(&input, &mut out_buf);
+    assert_eq!(out_code, OpCode::Reserved as u8);
+    // The subscription is gone, there is nothing more to report
+    assert_eq!(im.dm.get_next_timeout(), None);
+}
@@ -233,8 +233,8 @@ fn test_timed_cmd_success() {
     // A timed request that works
     let _ = env_logger::try_init();
 
-    let input = &[echo_req!(0, 5), echo_req!(1, 10)];
-    let expected = &[echo_resp!(0, 10), echo_resp!(1, 30)];
+    let input = &[echo_req!(0, 5, 1), echo_req!(1, 10, 2)];
+    let expected = &[echo_resp!(0, 10, 1), echo_resp!(1, 30, 2)];
     handle_timed_commands(
         input,
         &TimedInvResponse::TransactionSuccess(expected),
@@ -249,7 +249,7 @@ fn test_timed_cmd_timeout() {
     // A timed request that is executed after t imeout
     let _ = env_logger::try_init();
 
-    let input = &[echo_req!(0, 5), echo_req!(1, 10)];
+    let input = &[echo_req!(0, 5, 1), echo_req!(1, 10, 2)];
     handle_timed_commands(
         input,
         &TimedInvResponse::TransactionError(IMStatusCode::Timeout),
@@ -264,7 +264,7 @@ fn test_timed_cmd_timedout_mismatch() {
     // A timed request with timeout mismatch
     let _ = env_logger::try_init();
 
-    let input = &[echo_req!(0, 5), echo_req!(1, 10)];
+    let input = &[echo_req!(0, 5, 1), echo_req!(1, 10, 2)];
     handle_timed_commands(
         input,
         &TimedInvResponse::TransactionError(IMStatusCode::TimedRequestMisMatch),
@@ -273,7 +273,7 @@ fn test_timed_cmd_timedout_mismatch() {
         false,
     );
 
-    let input = &[echo_req!(0, 5), echo_req!(1, 10)];
+    let input = &[echo_req!(0, 5, 1), echo_req!(1, 10, 2)];
     handle_timed_commands(
         input,
         &TimedInvResponse::TransactionError(IMStatusCode::TimedRequestMisMatch),
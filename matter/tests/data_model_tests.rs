@@ -19,9 +19,13 @@ mod common;
 
 mod data_model {
     mod acl_and_dataver;
+    mod admin_commissioning;
     mod attribute_lists;
     mod attributes;
     mod commands;
+    mod events;
     mod long_reads;
+    mod noc;
+    mod subscribe;
     mod timed_requests;
 }